@@ -0,0 +1,514 @@
+use crate::config;
+use crate::presets;
+use crate::types::DnsPreset;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use log::{info, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const SOURCE_FILE: &str = "catalog_source.json";
+/// Последняя принятая версия каталога и её подпись
+const CATALOG_FILE: &str = "preset_catalog.toml";
+const SIGNATURE_FILE: &str = "preset_catalog.toml.sig";
+/// Версия, действовавшая до последнего обновления, — для сравнения
+const PREVIOUS_FILE: &str = "preset_catalog.previous.toml";
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_CATALOG_SIZE: usize = 1024 * 1024;
+
+/// Откуда загружать обновления каталога пресетов
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogSource {
+    /// Адрес файла каталога (https, или http на loopback для проверки)
+    pub url: Option<String>,
+    /// Адрес отделённой подписи; по умолчанию `<url>.sig`
+    pub signature_url: Option<String>,
+    /// Закреплённый открытый ключ Ed25519 в base64
+    pub public_key: Option<String>,
+}
+
+impl CatalogSource {
+    pub fn load() -> Result<Self> {
+        config::load_json(SOURCE_FILE)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.validate()?;
+        config::save_json(SOURCE_FILE, self)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for url in self.url.iter().chain(&self.signature_url) {
+            check_url(url)?;
+        }
+        if let Some(key) = &self.public_key {
+            parse_public_key(key)?;
+        }
+        if self.url.is_some() && self.public_key.is_none() {
+            bail!("A public key is required to verify catalog updates");
+        }
+        Ok(())
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.url.is_some() && self.public_key.is_some()
+    }
+
+    fn signature_url(&self, url: &str) -> String {
+        self.signature_url.clone().unwrap_or_else(|| format!("{}.sig", url))
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey> {
+        parse_public_key(self.public_key.as_deref().context("No public key configured for the preset catalog")?)
+    }
+}
+
+/// Каталог, прошедший проверку подписи и всех записей
+pub struct VerifiedCatalog {
+    pub content: String,
+    pub signature: String,
+    pub version: u64,
+    pub presets: Vec<DnsPreset>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresetChange {
+    pub id: String,
+    /// Изменившиеся поля пресета
+    pub fields: Vec<String>,
+}
+
+/// Разница между двумя версиями каталога
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CatalogDiff {
+    pub from_version: u64,
+    pub to_version: u64,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<PresetChange>,
+}
+
+impl CatalogDiff {
+    pub fn between(from: (u64, &[DnsPreset]), to: (u64, &[DnsPreset])) -> Self {
+        let (from_version, old) = from;
+        let (to_version, new) = to;
+        let mut diff = Self {
+            from_version,
+            to_version,
+            ..Self::default()
+        };
+
+        for preset in new {
+            let Some(previous) = old.iter().find(|p| p.id == preset.id) else {
+                diff.added.push(preset.id.clone());
+                continue;
+            };
+            let fields = changed_fields(previous, preset);
+            if !fields.is_empty() {
+                diff.changed.push(PresetChange {
+                    id: preset.id.clone(),
+                    fields,
+                });
+            }
+        }
+        diff.removed = old
+            .iter()
+            .filter(|p| !new.iter().any(|n| n.id == p.id))
+            .map(|p| p.id.clone())
+            .collect();
+        diff
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogUpdate {
+    /// Загружена более новая версия
+    pub updated: bool,
+    /// Версия каталога, действующая после проверки
+    pub version: u64,
+    pub diff: CatalogDiff,
+}
+
+/// Обновление каталога пресетов с подписанного адреса
+pub struct CatalogUpdater;
+
+impl CatalogUpdater {
+    /// Загрузить каталог и подпись, проверить подпись ключом и все записи
+    pub async fn fetch(url: &str, signature_url: &str, key: &VerifyingKey) -> Result<VerifiedCatalog> {
+        check_url(url)?;
+        check_url(signature_url)?;
+
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()?;
+        let content = Self::download(&client, url).await.context("Failed to download the preset catalog")?;
+        let signature = Self::download(&client, signature_url)
+            .await
+            .context("Failed to download the preset catalog signature")?;
+        let signature = String::from_utf8(signature).context("Catalog signature is not text")?;
+
+        verify(&content, &signature, key)?;
+        let content = String::from_utf8(content).context("Preset catalog is not UTF-8")?;
+        let (version, presets) = presets::parse_strict("downloaded preset catalog", &content)?;
+
+        Ok(VerifiedCatalog {
+            content,
+            signature: signature.trim().to_string(),
+            version,
+            presets,
+        })
+    }
+
+    /// Загрузить каталог по настройкам и принять его, если он новее текущего
+    pub async fn update() -> Result<CatalogUpdate> {
+        let source = CatalogSource::load()?;
+        let url = source.url.clone().context("No preset catalog URL configured")?;
+        let key = source.verifying_key()?;
+
+        let catalog = Self::fetch(&url, &source.signature_url(&url), &key).await?;
+        Self::install(catalog)
+    }
+
+    /// Сохранить проверенный каталог, если его версия больше действующей
+    pub fn install(catalog: VerifiedCatalog) -> Result<CatalogUpdate> {
+        let (current_content, current_version, current) = Self::active()?;
+        if catalog.version <= current_version {
+            info!(
+                "📋 Preset catalog is up to date (version {}, offered {})",
+                current_version, catalog.version
+            );
+            return Ok(CatalogUpdate {
+                updated: false,
+                version: current_version,
+                diff: CatalogDiff {
+                    from_version: current_version,
+                    to_version: current_version,
+                    ..CatalogDiff::default()
+                },
+            });
+        }
+
+        config::save_file(PREVIOUS_FILE, current_content.as_bytes())?;
+        config::save_file(SIGNATURE_FILE, catalog.signature.as_bytes())?;
+        config::save_file(CATALOG_FILE, catalog.content.as_bytes())?;
+        presets::reload();
+
+        info!("📥 Preset catalog updated: version {} → {}", current_version, catalog.version);
+        Ok(CatalogUpdate {
+            updated: true,
+            version: catalog.version,
+            diff: CatalogDiff::between((current_version, &current), (catalog.version, &catalog.presets)),
+        })
+    }
+
+    /// Что изменилось в последнем обновлении относительно предыдущей версии
+    pub fn diff() -> Result<CatalogDiff> {
+        let previous = match fs::read_to_string(config::app_dir()?.join(PREVIOUS_FILE)) {
+            Ok(content) => content,
+            Err(_) => presets::builtin_catalog().to_string(),
+        };
+        let (from_version, old) = presets::parse_strict("previous preset catalog", &previous)?;
+        let (_, to_version, new) = Self::active()?;
+        Ok(CatalogDiff::between((from_version, &old), (to_version, &new)))
+    }
+
+    /// Сохранённый каталог, если его подпись верна и он новее встроенного.
+    /// Иначе действует встроенный каталог.
+    pub fn stored() -> Option<String> {
+        Self::read_stored().unwrap_or_else(|e| {
+            warn!("⚠️  Ignoring downloaded preset catalog: {:#}", e);
+            None
+        })
+    }
+
+    fn read_stored() -> Result<Option<String>> {
+        let dir = config::app_dir()?;
+        let path = dir.join(CATALOG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(&path).with_context(|| format!("Failed to read {}", CATALOG_FILE))?;
+        let signature = fs::read_to_string(dir.join(SIGNATURE_FILE))
+            .with_context(|| format!("Failed to read {}", SIGNATURE_FILE))?;
+        verify(&content, &signature, &CatalogSource::load()?.verifying_key()?)?;
+
+        let content = String::from_utf8(content).context("Preset catalog is not UTF-8")?;
+        let (version, _) = presets::parse_strict(CATALOG_FILE, &content)?;
+        let (builtin, _) = presets::parse_strict("built-in preset catalog", presets::builtin_catalog())?;
+        // Новый выпуск приложения может принести каталог новее загруженного
+        if version <= builtin {
+            return Ok(None);
+        }
+        Ok(Some(content))
+    }
+
+    /// Действующий каталог без пользовательских правок: текст, версия и пресеты
+    fn active() -> Result<(String, u64, Vec<DnsPreset>)> {
+        let content = Self::stored().unwrap_or_else(|| presets::builtin_catalog().to_string());
+        let (version, presets) = presets::parse_strict("active preset catalog", &content)?;
+        Ok((content, version, presets))
+    }
+
+    async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+        let body = client.get(url).send().await?.error_for_status()?.bytes().await?;
+        if body.len() > MAX_CATALOG_SIZE {
+            bail!("{} is larger than {} bytes", url, MAX_CATALOG_SIZE);
+        }
+        Ok(body.to_vec())
+    }
+}
+
+/// Проверить отделённую подпись Ed25519 (64 байта в base64)
+pub fn verify(content: &[u8], signature: &str, key: &VerifyingKey) -> Result<()> {
+    let signature = STANDARD
+        .decode(signature.trim())
+        .context("Catalog signature is not valid base64")?;
+    let signature = Signature::from_slice(&signature).context("Catalog signature must be 64 bytes")?;
+    key.verify_strict(content, &signature)
+        .context("Preset catalog signature does not match the pinned key")
+}
+
+pub fn parse_public_key(key: &str) -> Result<VerifyingKey> {
+    let bytes = STANDARD.decode(key.trim()).context("Catalog public key is not valid base64")?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Catalog public key must be 32 bytes"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid catalog public key")
+}
+
+/// Каталог загружается только по https; http допустим для loopback
+fn check_url(url: &str) -> Result<()> {
+    let parsed = Url::parse(url).with_context(|| format!("Invalid catalog URL: {}", url))?;
+    let loopback = match parsed.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false),
+        None => false,
+    };
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => bail!("Catalog URL must use https: {}", url),
+    }
+}
+
+fn changed_fields(old: &DnsPreset, new: &DnsPreset) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    new.iter()
+        .filter(|(field, value)| old.get(*field) != Some(*value))
+        .map(|(field, _)| field.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const TEST_PRESET: &str = r##"
+[[preset]]
+id = "catalog-test"
+name = "Catalog Test"
+description = "Preset added by a catalog update"
+category = "privacy"
+servers_ipv4 = ["192.0.2.53"]
+icon = "🧪"
+color = "#123456"
+"##;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn builtin_version() -> u64 {
+        presets::parse_strict("built-in preset catalog", presets::builtin_catalog()).unwrap().0
+    }
+
+    /// Встроенный каталог с другой версией и одним добавленным пресетом
+    fn catalog(version: u64) -> String {
+        let builtin = presets::builtin_catalog();
+        let current = format!("version = {}", builtin_version());
+        assert!(builtin.contains(&current));
+        format!("{}{}", builtin.replacen(&current, &format!("version = {}", version), 1), TEST_PRESET)
+    }
+
+    fn sign(content: &str, key: &SigningKey) -> String {
+        STANDARD.encode(key.sign(content.as_bytes()).to_bytes())
+    }
+
+    /// HTTP-сервер на 127.0.0.1, отдающий файлы по пути
+    async fn serve(files: HashMap<String, String>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let files = Arc::new(files);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let files = files.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let read = stream.read(&mut chunk).await.unwrap();
+                        if read == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&chunk[..read]);
+                    }
+                    let request = String::from_utf8_lossy(&request).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or_default();
+                    let response = match files.get(path) {
+                        Some(body) => format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        ),
+                        None => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Настроить источник каталога на локальный сервер с этими файлами
+    async fn configure(files: HashMap<String, String>) -> String {
+        let addr = serve(files).await;
+        let url = format!("http://{}/catalog.toml", addr);
+        CatalogSource {
+            url: Some(url.clone()),
+            signature_url: None,
+            public_key: Some(STANDARD.encode(signing_key().verifying_key().to_bytes())),
+        }
+        .save()
+        .unwrap();
+        url
+    }
+
+    /// Установка каталога меняет общий для всех потоков список пресетов, поэтому
+    /// такие тесты идут по очереди, а в конце список собирается из пустого
+    /// временного каталога, а не из настроек пользователя
+    struct CatalogInstall {
+        _serial: tokio::sync::MutexGuard<'static, ()>,
+    }
+
+    impl CatalogInstall {
+        async fn begin() -> Self {
+            static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+            Self {
+                _serial: SERIAL.lock().await,
+            }
+        }
+    }
+
+    impl Drop for CatalogInstall {
+        fn drop(&mut self) {
+            let _empty = config::use_temp_app_dir();
+            presets::reload();
+        }
+    }
+
+    fn signed_files(content: &str, signature: String) -> HashMap<String, String> {
+        HashMap::from([
+            ("/catalog.toml".to_string(), content.to_string()),
+            ("/catalog.toml.sig".to_string(), signature),
+        ])
+    }
+
+    #[tokio::test]
+    async fn signed_update_is_installed() {
+        let _install = CatalogInstall::begin().await;
+        let dir = config::use_temp_app_dir();
+        let content = catalog(builtin_version() + 1);
+        configure(signed_files(&content, sign(&content, &signing_key()))).await;
+
+        let update = CatalogUpdater::update().await.unwrap();
+        assert!(update.updated);
+        assert_eq!(update.version, builtin_version() + 1);
+        assert_eq!(update.diff.added, vec!["catalog-test"]);
+        assert!(update.diff.removed.is_empty() && update.diff.changed.is_empty());
+        assert_eq!(CatalogUpdater::stored().as_deref(), Some(content.as_str()));
+        assert!(dir.path().join(PREVIOUS_FILE).exists());
+        assert!(presets::get_preset_by_id("catalog-test").is_some());
+    }
+
+    #[tokio::test]
+    async fn bad_signature_is_rejected() {
+        let _dir = config::use_temp_app_dir();
+        let content = catalog(builtin_version() + 1);
+        // Подпись другим ключом
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        configure(signed_files(&content, sign(&content, &other_key))).await;
+        assert!(CatalogUpdater::update().await.is_err());
+
+        // Подпись того же ключа, но под другим содержимым
+        let tampered = content.replace("192.0.2.53", "192.0.2.54");
+        configure(signed_files(&tampered, sign(&content, &signing_key()))).await;
+        assert!(CatalogUpdater::update().await.is_err());
+
+        assert!(CatalogUpdater::stored().is_none());
+    }
+
+    #[tokio::test]
+    async fn rollback_is_rejected() {
+        let _install = CatalogInstall::begin().await;
+        let _dir = config::use_temp_app_dir();
+        let newer = catalog(builtin_version() + 2);
+        configure(signed_files(&newer, sign(&newer, &signing_key()))).await;
+        assert!(CatalogUpdater::update().await.unwrap().updated);
+
+        // Старая, но правильно подписанная версия не заменяет новую
+        let older = catalog(builtin_version() + 1);
+        configure(signed_files(&older, sign(&older, &signing_key()))).await;
+        let update = CatalogUpdater::update().await.unwrap();
+        assert!(!update.updated);
+        assert_eq!(update.version, builtin_version() + 2);
+        assert_eq!(CatalogUpdater::stored().as_deref(), Some(newer.as_str()));
+
+        // Сохранённый каталог не старше встроенного не действует
+        let stale = catalog(builtin_version());
+        config::save_file(CATALOG_FILE, stale.as_bytes()).unwrap();
+        config::save_file(SIGNATURE_FILE, sign(&stale, &signing_key()).as_bytes()).unwrap();
+        assert!(CatalogUpdater::stored().is_none());
+    }
+
+    #[tokio::test]
+    async fn non_https_url_is_rejected() {
+        let key = signing_key().verifying_key();
+        for url in ["http://example.com/catalog.toml", "ftp://127.0.0.1/catalog.toml", "file:///etc/passwd"] {
+            assert!(check_url(url).is_err(), "{}", url);
+            assert!(CatalogUpdater::fetch(url, "https://example.com/catalog.toml.sig", &key).await.is_err());
+        }
+        for url in ["https://example.com/catalog.toml", "http://127.0.0.1:8080/c.toml", "http://[::1]/c.toml", "http://localhost/c.toml"] {
+            assert!(check_url(url).is_ok(), "{}", url);
+        }
+
+        let source = CatalogSource {
+            url: Some("http://192.168.1.10/catalog.toml".to_string()),
+            signature_url: None,
+            public_key: Some(STANDARD.encode(key.to_bytes())),
+        };
+        assert!(source.validate().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+#[cfg(test)]
+thread_local! {
+    /// Временный каталог приложения для тестов этого потока, см. [`use_temp_app_dir`]
+    static TEST_APP_DIR: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Каталог приложения в системной папке конфигурации
+pub fn app_dir() -> Result<PathBuf> {
+    #[cfg(test)]
+    if let Some(dir) = TEST_APP_DIR.with(|dir| dir.borrow().clone()) {
+        return Ok(dir);
+    }
+
+    let config_dir = dirs::config_dir()
+        .context("Failed to get config directory")?;
+
+    let app_dir = config_dir.join("dns-changer");
+
+    if !app_dir.exists() {
+        fs::create_dir_all(&app_dir)?;
+    }
+
+    Ok(app_dir)
+}
+
+/// Загрузить JSON-файл из каталога приложения (или значение по умолчанию)
+pub fn load_json<T: DeserializeOwned + Default>(file_name: &str) -> Result<T> {
+    let file_path = app_dir()?.join(file_name);
+
+    if !file_path.exists() {
+        return Ok(T::default());
+    }
+
+    let content = fs::read_to_string(&file_path)
+        .with_context(|| format!("Failed to read {}", file_name))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", file_name))
+}
+
+/// Сохранить значение как JSON в каталог приложения
+pub fn save_json<T: Serialize + ?Sized>(file_name: &str, value: &T) -> Result<()> {
+    let content = serde_json::to_string_pretty(value)
+        .with_context(|| format!("Failed to serialize {}", file_name))?;
+
+    save_file(file_name, content.as_bytes())
+}
+
+/// Записать файл в каталог приложения. Файл пишется рядом и переименовывается,
+/// так что сбой посреди записи не оставит его обрезанным.
+pub fn save_file(file_name: &str, content: &[u8]) -> Result<()> {
+    let file_path = app_dir()?.join(file_name);
+    let temp_path = file_path.with_file_name(format!("{}.tmp", file_name));
+
+    fs::write(&temp_path, content)
+        .with_context(|| format!("Failed to write {}", file_name))?;
+    fs::rename(&temp_path, &file_path)
+        .with_context(|| format!("Failed to replace {}", file_name))?;
+
+    Ok(())
+}
+
+/// Пустой временный каталог приложения; действует в текущем потоке, пока жив
+#[cfg(test)]
+pub struct TempAppDir {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TempAppDir {
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempAppDir {
+    fn drop(&mut self) {
+        TEST_APP_DIR.with(|dir| *dir.borrow_mut() = None);
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Направить файлы приложения текущего потока во временный каталог, чтобы
+/// тесты не трогали настройки пользователя
+#[cfg(test)]
+pub fn use_temp_app_dir() -> TempAppDir {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+        "dns-changer-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).expect("Failed to create a temporary app directory");
+    TEST_APP_DIR.with(|dir| *dir.borrow_mut() = Some(path.clone()));
+    TempAppDir { path }
+}
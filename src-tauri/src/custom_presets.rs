@@ -1,85 +1,145 @@
+use crate::config;
+use crate::presets;
 use crate::types::DnsPreset;
+use crate::validation;
 use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::fs;
 use std::path::PathBuf;
 
+const PRESETS_FILE: &str = "custom_presets.json";
+
+/// Текущая версия формата файла. Версия 1 — голый массив пресетов без обёртки.
+const FORMAT_VERSION: u64 = 2;
+
+type Migration = fn(Value) -> Result<Value>;
+
+/// `MIGRATIONS[n]` переводит файл версии `n + 1` в версию `n + 2`
+const MIGRATIONS: [Migration; 1] = [migrate_v1_to_v2];
+
+#[derive(Deserialize)]
+struct PresetsFile {
+    presets: Vec<DnsPreset>,
+}
+
+#[derive(Serialize)]
+struct PresetsFileRef<'a> {
+    version: u64,
+    presets: &'a [DnsPreset],
+}
+
 pub struct CustomPresetsManager;
 
 impl CustomPresetsManager {
     /// Получить путь к файлу с кастомными пресетами
     fn get_presets_file_path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .context("Failed to get config directory")?;
-        
-        let app_dir = config_dir.join("dns-changer");
-        
-        // Создаём директорию если её нет
-        if !app_dir.exists() {
-            fs::create_dir_all(&app_dir)?;
-        }
-        
-        Ok(app_dir.join("custom_presets.json"))
+        Ok(config::app_dir()?.join(PRESETS_FILE))
     }
-    
-    /// Загрузить кастомные пресеты
+
+    /// Загрузить кастомные пресеты. Файл старого формата переводится в текущий
+    /// по цепочке миграций, перед каждым шагом рядом сохраняется копия.
     pub fn load_custom_presets() -> Result<Vec<DnsPreset>> {
         let file_path = Self::get_presets_file_path()?;
-        
+
         if !file_path.exists() {
             return Ok(Vec::new());
         }
-        
+
         let content = fs::read_to_string(&file_path)
             .context("Failed to read custom presets file")?;
-        
-        let presets: Vec<DnsPreset> = serde_json::from_str(&content)
+
+        let mut document: Value = serde_json::from_str(&content)
+            .context("Failed to parse custom presets")?;
+        let mut version = file_version(&document)?;
+        if version > FORMAT_VERSION {
+            warn!("⚠️  {} was written by a newer version (format {}), unknown fields are ignored", PRESETS_FILE, version);
+        }
+
+        let migrated = version < FORMAT_VERSION;
+        let mut text = content;
+        while version < FORMAT_VERSION {
+            config::save_file(&backup_name(version), text.as_bytes())
+                .context("Failed to back up custom presets before migration")?;
+            document = MIGRATIONS[(version - 1) as usize](document)
+                .with_context(|| format!("Failed to migrate custom presets from format {}", version))?;
+            version += 1;
+            text = serde_json::to_string_pretty(&document)?;
+            info!("📦 Migrated custom presets to format {}", version);
+        }
+
+        let file: PresetsFile = serde_json::from_value(document)
             .context("Failed to parse custom presets")?;
-        
-        Ok(presets)
+        if migrated {
+            Self::save_custom_presets(&file.presets)?;
+        }
+
+        Ok(file.presets)
     }
-    
+
     /// Сохранить кастомные пресеты
     pub fn save_custom_presets(presets: &[DnsPreset]) -> Result<()> {
+        // Файл более новой версии перезапись лишила бы неизвестных нам полей
+        if let Some(version) = Self::stored_version()? {
+            if version > FORMAT_VERSION {
+                anyhow::bail!("{} was written by a newer version of the app and can't be changed", PRESETS_FILE);
+            }
+        }
+
+        config::save_json(
+            PRESETS_FILE,
+            &PresetsFileRef {
+                version: FORMAT_VERSION,
+                presets,
+            },
+        )
+        .context("Failed to write custom presets file")
+    }
+
+    fn stored_version() -> Result<Option<u64>> {
         let file_path = Self::get_presets_file_path()?;
-        
-        let content = serde_json::to_string_pretty(presets)
-            .context("Failed to serialize custom presets")?;
-        
-        fs::write(&file_path, content)
-            .context("Failed to write custom presets file")?;
-        
-        Ok(())
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&file_path)
+            .context("Failed to read custom presets file")?;
+        let document: Value = serde_json::from_str(&content)
+            .context("Failed to parse custom presets")?;
+        file_version(&document).map(Some)
     }
-    
+
     /// Добавить новый кастомный пресет
     pub fn add_custom_preset(preset: DnsPreset) -> Result<()> {
         let mut presets = Self::load_custom_presets()?;
-        
+
         // Проверяем, что ID уникален
         if presets.iter().any(|p| p.id == preset.id) {
             anyhow::bail!("Preset with this ID already exists");
         }
-        
+        validation::validate_against(&preset, &presets::get_default_presets(), &presets)?;
+
         presets.push(preset);
         Self::save_custom_presets(&presets)?;
-        
+
         Ok(())
     }
-    
+
     /// Удалить кастомный пресет по ID
     pub fn delete_custom_preset(id: &str) -> Result<()> {
         let mut presets = Self::load_custom_presets()?;
-        
+
         presets.retain(|p| p.id != id);
         Self::save_custom_presets(&presets)?;
-        
+
         Ok(())
     }
-    
+
     /// Обновить кастомный пресет
     pub fn update_custom_preset(preset: DnsPreset) -> Result<()> {
         let mut presets = Self::load_custom_presets()?;
-        
+        validation::validate_against(&preset, &presets::get_default_presets(), &presets)?;
+
         if let Some(existing) = presets.iter_mut().find(|p| p.id == preset.id) {
             *existing = preset;
             Self::save_custom_presets(&presets)?;
@@ -88,4 +148,113 @@ impl CustomPresetsManager {
             anyhow::bail!("Preset not found");
         }
     }
-}
\ No newline at end of file
+}
+
+/// Версия формата: массив — версия 1, объект — поле `version`
+fn file_version(document: &Value) -> Result<u64> {
+    match document {
+        Value::Array(_) => Ok(1),
+        Value::Object(object) => match object.get("version").and_then(Value::as_u64) {
+            Some(version) if version >= 1 => Ok(version),
+            _ => anyhow::bail!("{} has no valid format version", PRESETS_FILE),
+        },
+        _ => anyhow::bail!("{} is neither a preset list nor a versioned file", PRESETS_FILE),
+    }
+}
+
+fn backup_name(version: u64) -> String {
+    format!("custom_presets.v{}.bak.json", version)
+}
+
+/// Голый массив → обёртка с версией. Поля, которых не было в ранних версиях
+/// приложения, получают значения по умолчанию.
+fn migrate_v1_to_v2(document: Value) -> Result<Value> {
+    let Value::Array(presets) = document else {
+        anyhow::bail!("format 1 must be a list of presets");
+    };
+
+    let presets = presets
+        .into_iter()
+        .map(|preset| {
+            let Value::Object(mut preset) = preset else {
+                anyhow::bail!("preset entry is not an object");
+            };
+            fill_defaults(&mut preset);
+            Ok(Value::Object(preset))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(json!({ "version": 2, "presets": presets }))
+}
+
+fn fill_defaults(preset: &mut Map<String, Value>) {
+    let has_doh = preset.get("doh_template").is_some_and(|v| !v.is_null());
+    let has_dot = preset.get("dot_hostname").is_some_and(|v| !v.is_null());
+
+    for (field, default) in [
+        ("description", json!("")),
+        ("category", json!("custom")),
+        ("servers_ipv6", json!([])),
+        ("doh_template", Value::Null),
+        ("dot_hostname", Value::Null),
+        ("supports_doh", json!(has_doh)),
+        ("supports_dot", json!(has_dot)),
+        ("icon", json!("⚙️")),
+        ("color", json!("")),
+        ("website", Value::Null),
+    ] {
+        preset.entry(field).or_insert(default);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_list_is_migrated_with_a_backup() {
+        let dir = config::use_temp_app_dir();
+        let v1 = r#"[{"id": "custom-1", "name": "Home", "servers_ipv4": ["192.0.2.1"], "doh_template": "https://dns.example/dns-query"}]"#;
+        fs::write(dir.path().join(PRESETS_FILE), v1).unwrap();
+
+        let presets = CustomPresetsManager::load_custom_presets().unwrap();
+
+        assert_eq!(presets.len(), 1);
+        assert_eq!(presets[0].category, "custom");
+        assert!(presets[0].supports_doh);
+        assert!(!presets[0].supports_dot);
+        assert!(presets[0].servers_ipv6.is_empty());
+
+        let stored: Value = serde_json::from_str(&fs::read_to_string(dir.path().join(PRESETS_FILE)).unwrap()).unwrap();
+        assert_eq!(stored["version"], json!(FORMAT_VERSION));
+        assert_eq!(fs::read_to_string(dir.path().join(backup_name(1))).unwrap(), v1);
+
+        // Повторная загрузка уже ничего не мигрирует
+        assert_eq!(CustomPresetsManager::load_custom_presets().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn newer_files_are_read_but_not_overwritten() {
+        let dir = config::use_temp_app_dir();
+        let v1 = r#"[{"id": "custom-1", "name": "Home", "servers_ipv4": ["192.0.2.1"]}]"#;
+        fs::write(dir.path().join(PRESETS_FILE), v1).unwrap();
+        let mut presets = CustomPresetsManager::load_custom_presets().unwrap();
+
+        let newer = json!({ "version": FORMAT_VERSION + 1, "presets": presets, "future_field": true });
+        fs::write(dir.path().join(PRESETS_FILE), newer.to_string()).unwrap();
+
+        assert_eq!(CustomPresetsManager::load_custom_presets().unwrap().len(), 1);
+        presets[0].name = "Renamed".to_string();
+        assert!(CustomPresetsManager::save_custom_presets(&presets).is_err());
+    }
+
+    #[test]
+    fn unknown_layouts_are_rejected() {
+        let dir = config::use_temp_app_dir();
+
+        fs::write(dir.path().join(PRESETS_FILE), r#"{"presets": []}"#).unwrap();
+        assert!(CustomPresetsManager::load_custom_presets().is_err());
+        fs::write(dir.path().join(PRESETS_FILE), "42").unwrap();
+        assert!(CustomPresetsManager::load_custom_presets().is_err());
+    }
+}
@@ -174,7 +174,7 @@ impl DnsManager {
 
     #[cfg(not(windows))]
     pub fn get_current_dns(&self, _adapter_name: &str) -> Result<DnsConfiguration> {
-        anyhml::bail!("This function is only supported on Windows")
+        anyhow::bail!("This function is only supported on Windows")
     }
 
     fn parse_dns_servers(&self, output: &str) -> Vec<String> {
@@ -438,7 +438,6 @@ impl DnsManager {
         _ipv4_servers: Vec<String>,
         _ipv6_servers: Vec<String>,
         _doh_template: Option<String>,
-        _dot_hostname: Option<String>,
     ) -> Result<()> {
         anyhow::bail!("This function is only supported on Windows")
     }
@@ -0,0 +1,310 @@
+use crate::dns::DnsManager;
+use crate::types::{BenchmarkRun, DnsConfiguration, ExportFormat};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Machine and adapter details written at the top of every export
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportContext {
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    pub app_version: String,
+    pub hostname: Option<String>,
+    pub os: String,
+    pub adapter: Option<String>,
+    pub adapter_dns: Option<DnsConfiguration>,
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    context: &'a ExportContext,
+    runs: &'a [BenchmarkRun],
+}
+
+pub struct ResultsExporter;
+
+impl ResultsExporter {
+    /// Собрать контекст машины и адаптера для заголовка
+    pub fn collect_context(adapter_name: Option<&str>) -> ExportContext {
+        let adapter_dns = adapter_name.and_then(|name| {
+            DnsManager::new()
+                .get_current_dns(name)
+                .map_err(|e| debug!("Could not read DNS for export context: {}", e))
+                .ok()
+        });
+
+        ExportContext {
+            generated_at: chrono::Utc::now(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: Self::hostname(),
+            os: format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH),
+            adapter: adapter_name.map(String::from),
+            adapter_dns,
+        }
+    }
+
+    fn hostname() -> Option<String> {
+        std::env::var("COMPUTERNAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .ok()
+            .filter(|h| !h.is_empty())
+    }
+
+    /// Экспортировать результаты тестов в файл
+    pub fn export_to_file(
+        runs: &[BenchmarkRun],
+        format: ExportFormat,
+        path: &Path,
+        context: &ExportContext,
+    ) -> Result<()> {
+        info!("💾 Exporting {} benchmark run(s) to {}", runs.len(), path.display());
+
+        let content = match format {
+            ExportFormat::Csv => Self::to_csv(runs, context),
+            ExportFormat::Json => Self::to_json(runs, context)?,
+            ExportFormat::Markdown => Self::to_markdown(runs, context),
+        };
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        fs::write(path, content).context("Failed to write export file")?;
+
+        Ok(())
+    }
+
+    fn context_lines(context: &ExportContext) -> Vec<(&'static str, String)> {
+        let mut lines = vec![
+            ("Generated", context.generated_at.to_rfc3339()),
+            ("App version", context.app_version.clone()),
+            ("Host", context.hostname.clone().unwrap_or_else(|| "unknown".to_string())),
+            ("OS", context.os.clone()),
+        ];
+
+        if let Some(ref adapter) = context.adapter {
+            lines.push(("Adapter", adapter.clone()));
+        }
+
+        if let Some(ref dns) = context.adapter_dns {
+            let servers: Vec<&str> = [&dns.primary, &dns.secondary, &dns.primary_ipv6, &dns.secondary_ipv6]
+                .iter()
+                .filter_map(|s| s.as_deref())
+                .collect();
+            lines.push(("Adapter DNS", if servers.is_empty() { "-".to_string() } else { servers.join(", ") }));
+            lines.push(("DHCP", dns.is_dhcp.to_string()));
+            lines.push(("DoH", dns.doh_enabled.to_string()));
+        }
+
+        lines
+    }
+
+    fn to_csv(runs: &[BenchmarkRun], context: &ExportContext) -> String {
+        let mut out = String::new();
+
+        for (key, value) in Self::context_lines(context) {
+            // Комментарий занимает одну строку
+            out.push_str(&format!("# {}: {}\n", key, value.replace(['\n', '\r'], " ")));
+        }
+
+        out.push_str("timestamp,preset_id,server,protocol,samples,successes,min_ms,avg_ms,median_ms,max_ms,jitter_ms\n");
+
+        for run in runs {
+            let fields = [
+                run.timestamp.to_rfc3339(),
+                run.preset_id.clone().unwrap_or_default(),
+                run.server.clone(),
+                run.protocol.clone(),
+                run.stats.samples.to_string(),
+                run.stats.successes.to_string(),
+                Self::format_ms(run.stats.min_ms),
+                Self::format_ms(run.stats.avg_ms),
+                Self::format_ms(run.stats.median_ms),
+                Self::format_ms(run.stats.max_ms),
+                Self::format_ms(run.stats.jitter_ms),
+            ];
+
+            let row: Vec<String> = fields.iter().map(|f| Self::escape_csv(f)).collect();
+            out.push_str(&row.join(","));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn to_json(runs: &[BenchmarkRun], context: &ExportContext) -> Result<String> {
+        serde_json::to_string_pretty(&JsonExport { context, runs })
+            .context("Failed to serialize benchmark results")
+    }
+
+    fn to_markdown(runs: &[BenchmarkRun], context: &ExportContext) -> String {
+        let mut out = String::from("# DNS benchmark results\n\n");
+
+        for (key, value) in Self::context_lines(context) {
+            out.push_str(&format!("- **{}:** {}\n", key, Self::escape_markdown(&value)));
+        }
+
+        out.push_str("\n| Timestamp | Preset | Server | Protocol | Success | Min (ms) | Avg (ms) | Median (ms) | Max (ms) | Jitter (ms) |\n");
+        out.push_str("|---|---|---|---|---|---:|---:|---:|---:|---:|\n");
+
+        for run in runs {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {}/{} | {} | {} | {} | {} | {} |\n",
+                run.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                Self::escape_markdown(run.preset_id.as_deref().unwrap_or("-")),
+                Self::escape_markdown(&run.server),
+                Self::escape_markdown(&run.protocol),
+                run.stats.successes,
+                run.stats.samples,
+                Self::format_ms_or_dash(run.stats.min_ms),
+                Self::format_ms_or_dash(run.stats.avg_ms),
+                Self::format_ms_or_dash(run.stats.median_ms),
+                Self::format_ms_or_dash(run.stats.max_ms),
+                Self::format_ms_or_dash(run.stats.jitter_ms),
+            ));
+        }
+
+        out
+    }
+
+    fn format_ms(value: Option<f64>) -> String {
+        value.map(|v| format!("{:.2}", v)).unwrap_or_default()
+    }
+
+    fn format_ms_or_dash(value: Option<f64>) -> String {
+        value.map(|v| format!("{:.2}", v)).unwrap_or_else(|| "-".to_string())
+    }
+
+    fn escape_csv(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// Ячейка таблицы: `|` экранируется, перевод строки разорвал бы строку таблицы
+    fn escape_markdown(value: &str) -> String {
+        value.replace('|', "\\|").replace("\r\n", " ").replace(['\n', '\r'], " ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BenchmarkStats;
+    use chrono::TimeZone;
+
+    fn context() -> ExportContext {
+        ExportContext {
+            generated_at: chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap(),
+            app_version: "1.2.3".to_string(),
+            hostname: Some("desk\nlab".to_string()),
+            os: "windows (x86_64)".to_string(),
+            adapter: Some("Wi-Fi | 2".to_string()),
+            adapter_dns: Some(DnsConfiguration {
+                primary: Some("1.1.1.1".to_string()),
+                secondary: None,
+                primary_ipv6: Some("2606:4700:4700::1111".to_string()),
+                secondary_ipv6: None,
+                doh_enabled: true,
+                doh_template: None,
+                dot_hostname: None,
+                is_dhcp: false,
+                original_primary: None,
+                original_secondary: None,
+                original_primary_ipv6: None,
+                original_secondary_ipv6: None,
+            }),
+        }
+    }
+
+    fn run(preset_id: Option<&str>, server: &str, avg_ms: Option<f64>) -> BenchmarkRun {
+        BenchmarkRun {
+            preset_id: preset_id.map(String::from),
+            server: server.to_string(),
+            protocol: "udp".to_string(),
+            stats: BenchmarkStats {
+                samples: 5,
+                successes: if avg_ms.is_some() { 4 } else { 0 },
+                min_ms: avg_ms.map(|v| v - 1.0),
+                avg_ms,
+                median_ms: avg_ms,
+                max_ms: avg_ms.map(|v| v + 1.0),
+                jitter_ms: avg_ms.map(|_| 0.5),
+            },
+            timestamp: chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(ResultsExporter::escape_csv("1.1.1.1"), "1.1.1.1");
+        assert_eq!(ResultsExporter::escape_csv("a,b"), "\"a,b\"");
+        assert_eq!(ResultsExporter::escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(ResultsExporter::escape_csv("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(ResultsExporter::escape_csv("line\rbreak"), "\"line\rbreak\"");
+    }
+
+    #[test]
+    fn csv_has_a_commented_header_and_one_row_per_run() {
+        let runs = [run(Some("cloudflare"), "1.1.1.1", Some(12.345)), run(None, "dns,\"odd\"", None)];
+
+        let csv = ResultsExporter::to_csv(&runs, &context());
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            &lines[..8],
+            [
+                "# Generated: 2024-05-01T12:30:00+00:00",
+                "# App version: 1.2.3",
+                "# Host: desk lab",
+                "# OS: windows (x86_64)",
+                "# Adapter: Wi-Fi | 2",
+                "# Adapter DNS: 1.1.1.1, 2606:4700:4700::1111",
+                "# DHCP: false",
+                "# DoH: true",
+            ]
+        );
+        assert_eq!(
+            lines[8],
+            "timestamp,preset_id,server,protocol,samples,successes,min_ms,avg_ms,median_ms,max_ms,jitter_ms"
+        );
+        assert_eq!(lines[9], "2024-05-01T12:00:00+00:00,cloudflare,1.1.1.1,udp,5,4,11.35,12.35,12.35,13.35,0.50");
+        assert_eq!(lines[10], "2024-05-01T12:00:00+00:00,,\"dns,\"\"odd\"\"\",udp,5,0,,,,,");
+        assert_eq!(lines.len(), 11);
+    }
+
+    #[test]
+    fn markdown_escapes_pipes_and_line_breaks() {
+        let mut context = context();
+        context.adapter_dns = None;
+        let runs = [run(Some("a|b"), "line\nbreak", None)];
+
+        let markdown = ResultsExporter::to_markdown(&runs, &context);
+
+        assert!(markdown.starts_with("# DNS benchmark results\n\n- **Generated:** 2024-05-01T12:30:00+00:00\n"));
+        assert!(markdown.contains("- **Host:** desk lab\n"));
+        assert!(markdown.contains("- **Adapter:** Wi-Fi \\| 2\n"));
+        assert!(!markdown.contains("Adapter DNS"));
+        let row = markdown.lines().last().unwrap();
+        assert_eq!(row, "| 2024-05-01 12:00:00 | a\\|b | line break | udp | 0/5 | - | - | - | - | - |");
+        // В строке таблицы ровно 11 неэкранированных разделителей
+        assert_eq!(row.replace("\\|", "").matches('|').count(), 11);
+    }
+
+    #[test]
+    fn json_contains_the_context_and_runs() {
+        let json = ResultsExporter::to_json(&[run(None, "9.9.9.9", Some(20.0))], &context()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value["context"]["app_version"], "1.2.3");
+        assert_eq!(value["context"]["adapter_dns"]["primary"], "1.1.1.1");
+        assert_eq!(value["runs"][0]["server"], "9.9.9.9");
+        assert_eq!(value["runs"][0]["stats"]["avg_ms"], 20.0);
+    }
+}
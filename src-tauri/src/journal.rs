@@ -0,0 +1,344 @@
+use crate::config;
+use crate::dns::DnsManager;
+use crate::types::DnsConfiguration;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+const JOURNAL_FILE: &str = "applied_state.json";
+
+/// Аргумент командной строки, с которым приложение запускается как сторож
+pub const WATCHDOG_ARG: &str = "--watchdog";
+
+/// Файл-замок журнала: журнал меняют и приложение, и сторожевой процесс
+const LOCK_FILE: &str = "applied_state.lock";
+/// Сколько ждать, пока другой процесс освободит журнал
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Замок старше этого оставлен упавшим процессом
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// DNS адаптера до того, как его направили на локальный прокси
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedDns {
+    pub adapter: String,
+    pub is_dhcp: bool,
+    pub ipv4_servers: Vec<String>,
+    pub ipv6_servers: Vec<String>,
+    pub doh_template: Option<String>,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl SavedDns {
+    fn from_configuration(adapter: &str, current: &DnsConfiguration) -> Self {
+        let ipv4_servers: Vec<String> = [&current.primary, &current.secondary]
+            .into_iter()
+            .flatten()
+            .filter(|s| !is_loopback(s))
+            .cloned()
+            .collect();
+        let ipv6_servers: Vec<String> = [&current.primary_ipv6, &current.secondary_ipv6]
+            .into_iter()
+            .flatten()
+            .filter(|s| !is_loopback(s))
+            .cloned()
+            .collect();
+
+        Self {
+            adapter: adapter.to_string(),
+            is_dhcp: current.is_dhcp,
+            ipv4_servers,
+            ipv6_servers,
+            doh_template: current.doh_template.clone(),
+            saved_at: Utc::now(),
+        }
+    }
+
+    /// Вернуть адаптеру сохранённые настройки
+    fn restore(&self, dns_manager: &DnsManager) -> Result<()> {
+        // Сброс убирает и IPv6-адрес прокси, который set_dns без IPv6 не трогает
+        dns_manager.reset_to_dhcp(&self.adapter)?;
+        // Адаптер, уже смотревший только на прокси, остаётся на DHCP
+        let has_servers = !self.ipv4_servers.is_empty() || !self.ipv6_servers.is_empty();
+        if !self.is_dhcp && has_servers {
+            dns_manager.set_dns(
+                &self.adapter,
+                self.ipv4_servers.clone(),
+                self.ipv6_servers.clone(),
+                self.doh_template.clone(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JournalFile {
+    adapters: Vec<SavedDns>,
+}
+
+/// Журнал применённого состояния: пока адаптер направлен на прокси, здесь лежат
+/// его прежние DNS. Непустой журнал при запуске означает аварийное завершение.
+pub struct RestoreJournal;
+
+impl RestoreJournal {
+    /// Запомнить DNS адаптера перед переключением на прокси. Если адаптер уже
+    /// в журнале, сохраняется самое первое состояние. Возвращает `true`, если
+    /// запись создана этим вызовом.
+    pub fn record(adapter: &str, current: &DnsConfiguration) -> Result<bool> {
+        let _lock = JournalLock::acquire()?;
+        let mut journal = Self::load()?;
+        if journal.adapters.iter().any(|saved| saved.adapter == adapter) {
+            return Ok(false);
+        }
+
+        journal.adapters.push(SavedDns::from_configuration(adapter, current));
+        config::save_json(JOURNAL_FILE, &journal).context("Failed to write the restore journal")?;
+        Ok(true)
+    }
+
+    /// Адаптер больше не смотрит на прокси
+    pub fn forget(adapter: &str) -> Result<()> {
+        let _lock = JournalLock::acquire()?;
+        let mut journal = Self::load()?;
+        let before = journal.adapters.len();
+        journal.adapters.retain(|saved| saved.adapter != adapter);
+        if journal.adapters.len() == before {
+            return Ok(());
+        }
+        config::save_json(JOURNAL_FILE, &journal)
+    }
+
+    pub fn pending() -> Result<Vec<SavedDns>> {
+        let _lock = JournalLock::acquire()?;
+        Ok(Self::load()?.adapters)
+    }
+
+    /// Вернуть всем адаптерам из журнала прежние DNS. Адаптеры, которые не удалось
+    /// восстановить, остаются в журнале до следующей попытки.
+    pub fn restore_all() -> usize {
+        let dns_manager = DnsManager::new();
+        Self::restore_with(|saved| saved.restore(&dns_manager))
+    }
+
+    /// Восстановление и удаление из журнала идут под одним замком, чтобы сторож
+    /// и приложение не восстановили один адаптер дважды
+    fn restore_with(restore: impl Fn(&SavedDns) -> Result<()>) -> usize {
+        let journal = JournalLock::acquire().and_then(|lock| Ok((lock, Self::load()?)));
+        let (_lock, mut journal) = match journal {
+            Ok(journal) => journal,
+            Err(e) => {
+                error!("❌ Failed to read the restore journal: {}", e);
+                return 0;
+            }
+        };
+
+        let before = journal.adapters.len();
+        journal.adapters.retain(|saved| {
+            info!("🩹 Restoring previous DNS of {}", saved.adapter);
+            match restore(saved) {
+                Ok(()) => false,
+                Err(e) => {
+                    error!("❌ Failed to restore DNS of {}: {}", saved.adapter, e);
+                    true
+                }
+            }
+        });
+
+        let restored = before - journal.adapters.len();
+        if restored > 0 {
+            if let Err(e) = config::save_json(JOURNAL_FILE, &journal) {
+                warn!("⚠️  Failed to update the restore journal: {}", e);
+            }
+        }
+        restored
+    }
+
+    /// Запустить сторожевой процесс. Он ждёт закрытия stdin, что происходит при
+    /// любом завершении приложения, и восстанавливает DNS, если журнал не пуст.
+    /// Процесс живёт, пока жив возвращённый `Child` с открытым stdin.
+    pub fn spawn_watchdog() -> Result<Child> {
+        let exe = std::env::current_exe().context("Failed to locate the application executable")?;
+        let mut command = Command::new(exe);
+        command
+            .arg(WATCHDOG_ARG)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        #[cfg(windows)]
+        command.creation_flags(CREATE_NO_WINDOW);
+
+        command.spawn().context("Failed to start the DNS watchdog")
+    }
+
+    /// Тело сторожевого процесса
+    pub fn run_watchdog() {
+        use std::io::Read;
+
+        // read_to_end возвращается, только когда родитель закрыл канал или умер
+        let mut sink = Vec::new();
+        let _ = std::io::stdin().read_to_end(&mut sink);
+
+        match Self::pending() {
+            Ok(pending) if !pending.is_empty() => {
+                warn!("⚠️  DNS Switcher exited without restoring {} adapter(s)", pending.len());
+                Self::restore_all();
+            }
+            Ok(_) => {}
+            Err(e) => error!("❌ Failed to read the restore journal: {}", e),
+        }
+    }
+
+    fn load() -> Result<JournalFile> {
+        config::load_json(JOURNAL_FILE)
+    }
+}
+
+/// Блокировка журнала, общая для приложения и сторожа. Файл-замок создаётся
+/// атомарно (`create_new`) и удаляется при освобождении.
+struct JournalLock {
+    path: PathBuf,
+}
+
+impl JournalLock {
+    fn acquire() -> Result<Self> {
+        let path = config::app_dir()?.join(LOCK_FILE);
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if Self::is_stale(&path) {
+                        warn!("⚠️  Removing a stale restore journal lock");
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        anyhow::bail!("The restore journal is locked by another process");
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e).context("Failed to lock the restore journal"),
+            }
+        }
+    }
+
+    fn is_stale(path: &Path) -> bool {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > LOCK_STALE_AFTER)
+    }
+}
+
+impl Drop for JournalLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_loopback(server: &str) -> bool {
+    server.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration(ipv4: &[&str], ipv6: &[&str], is_dhcp: bool) -> DnsConfiguration {
+        let server = |list: &[&str], i: usize| list.get(i).map(|s| s.to_string());
+        DnsConfiguration {
+            primary: server(ipv4, 0),
+            secondary: server(ipv4, 1),
+            primary_ipv6: server(ipv6, 0),
+            secondary_ipv6: server(ipv6, 1),
+            doh_enabled: false,
+            doh_template: None,
+            dot_hostname: None,
+            is_dhcp,
+            original_primary: None,
+            original_secondary: None,
+            original_primary_ipv6: None,
+            original_secondary_ipv6: None,
+        }
+    }
+
+    fn adapters() -> Vec<String> {
+        RestoreJournal::pending()
+            .unwrap()
+            .into_iter()
+            .map(|saved| saved.adapter)
+            .collect()
+    }
+
+    #[test]
+    fn record_keeps_the_first_state_and_forget_removes_it() {
+        let _dir = config::use_temp_app_dir();
+
+        assert!(RestoreJournal::record("Ethernet", &configuration(&["192.168.1.1"], &[], false)).unwrap());
+        // Повторная запись не затирает состояние до прокси адресом самого прокси
+        assert!(!RestoreJournal::record("Ethernet", &configuration(&["127.0.0.1"], &[], false)).unwrap());
+        assert!(RestoreJournal::record("Wi-Fi", &configuration(&[], &[], true)).unwrap());
+
+        let pending = RestoreJournal::pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].ipv4_servers, ["192.168.1.1"]);
+        assert!(!pending[0].is_dhcp);
+        assert!(pending[1].is_dhcp);
+
+        RestoreJournal::forget("Ethernet").unwrap();
+        RestoreJournal::forget("Missing").unwrap();
+        assert_eq!(adapters(), ["Wi-Fi"]);
+    }
+
+    #[test]
+    fn loopback_servers_are_not_saved() {
+        let _dir = config::use_temp_app_dir();
+        let current = configuration(&["127.0.0.1"], &["2001:db8::53", "::1"], false);
+
+        RestoreJournal::record("Ethernet", &current).unwrap();
+
+        let saved = &RestoreJournal::pending().unwrap()[0];
+        assert!(saved.ipv4_servers.is_empty());
+        assert_eq!(saved.ipv6_servers, ["2001:db8::53"]);
+        assert!(!saved.is_dhcp);
+    }
+
+    #[test]
+    fn restore_forgets_only_restored_adapters_under_the_lock() {
+        let dir = config::use_temp_app_dir();
+        RestoreJournal::record("Ethernet", &configuration(&["192.168.1.1"], &[], false)).unwrap();
+        RestoreJournal::record("Wi-Fi", &configuration(&[], &[], true)).unwrap();
+        let lock = dir.path().join(LOCK_FILE);
+
+        let restored = RestoreJournal::restore_with(|saved| {
+            // Журнал заперт на всё время восстановления
+            assert!(lock.exists());
+            if saved.adapter == "Wi-Fi" {
+                anyhow::bail!("adapter is disconnected");
+            }
+            Ok(())
+        });
+
+        assert_eq!(restored, 1);
+        assert!(!lock.exists());
+        // Невосстановленный адаптер ждёт следующей попытки
+        assert_eq!(adapters(), ["Wi-Fi"]);
+        assert_eq!(RestoreJournal::restore_with(|_| Ok(())), 1);
+        assert!(adapters().is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use tauri::{Emitter, Manager, Runtime, State};
-use log::{error, info};
+use log::{error, info, warn};
 use std::sync::{Arc, Mutex};
 
 mod dns;
@@ -11,11 +11,40 @@ mod types;
 mod custom_presets;
 mod tray;
 mod mini_window;
+mod export;
+mod config;
+mod proxy;
+mod stamp;
+mod journal;
+mod catalog;
+mod validation;
+mod verification;
+mod preset_query;
+mod preset_params;
+mod preset_stamps;
+mod preset_import;
 
 use dns::DnsManager;
 use network::NetworkManager;
-use types::{DnsConfiguration, DnsPreset, DnsTestResult, NetworkAdapter, WindowsVersion};
+use types::{
+    BenchmarkRun, DnsConfiguration, DnsPreset, DnsTestResult, ExportFormat, NetworkAdapter,
+    WindowsVersion,
+};
 use custom_presets::CustomPresetsManager;
+use export::ResultsExporter;
+use journal::RestoreJournal;
+use validation::ValidationErrors;
+use verification::{PresetSaveResult, VerificationReport};
+use preset_query::PresetQuery;
+use preset_params::{ParameterValues, PresetParameters};
+use preset_stamps::StampImport;
+use preset_import::{ImportFormat, ImportPreview, ImportResult};
+use catalog::{CatalogDiff, CatalogSource, CatalogUpdate, CatalogUpdater};
+pub use journal::WATCHDOG_ARG;
+use proxy::{
+    BlocklistSettings, BlocklistSource, BlocklistStatus, CacheStats, ForwardingRule, LocalRecord,
+    ProxyConfig, ProxyService, ProxyStatus, QueryLogPage, QueryStats, RecordsManager, RulesManager,
+};
 
 /// Shared state for selected adapter
 pub struct AppState {
@@ -95,14 +124,50 @@ async fn get_custom_presets() -> Result<Vec<DnsPreset>, String> {
         })
 }
 
+/// Ошибки в полях возвращаются в итоге сохранения, а не строкой
+fn save_result(
+    saved: anyhow::Result<()>,
+    report: Option<VerificationReport>,
+    action: &str,
+) -> Result<PresetSaveResult, String> {
+    match saved {
+        Ok(()) => Ok(PresetSaveResult::saved(report)),
+        Err(e) => match e.downcast::<ValidationErrors>() {
+            Ok(errors) => {
+                warn!("⚠️  Custom preset has invalid fields: {}", errors);
+                Ok(PresetSaveResult::rejected(report, errors))
+            }
+            Err(e) => {
+                error!("❌ Failed to {} custom preset: {}", action, e);
+                Err(e.to_string())
+            }
+        },
+    }
+}
+
 #[tauri::command]
-async fn add_custom_preset(preset: DnsPreset) -> Result<(), String> {
+async fn add_custom_preset(
+    mut preset: DnsPreset,
+    verify: Option<bool>,
+    save_anyway: Option<bool>,
+    proxy: State<'_, ProxyService>,
+) -> Result<PresetSaveResult, String> {
     info!("➕ Adding custom preset: {}", preset.name);
-    CustomPresetsManager::add_custom_preset(preset)
-        .map_err(|e| {
-            error!("❌ Failed to add custom preset: {}", e);
-            e.to_string()
-        })
+    let report = match verification::check_before_save(&mut preset, None, verify, save_anyway, &proxy).await {
+        Ok(report) => report,
+        Err(rejected) => return Ok(rejected),
+    };
+
+    save_result(CustomPresetsManager::add_custom_preset(preset), report, "add")
+}
+
+/// Проверить пресет без сохранения: ошибки по полям для формы
+#[tauri::command]
+async fn validate_preset(preset: DnsPreset) -> Result<ValidationErrors, String> {
+    let custom = CustomPresetsManager::load_custom_presets().map_err(|e| e.to_string())?;
+    Ok(validation::validate_against(&preset, &presets::get_default_presets(), &custom)
+        .err()
+        .unwrap_or_default())
 }
 
 #[tauri::command]
@@ -116,13 +181,24 @@ async fn delete_custom_preset(id: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn update_custom_preset(preset: DnsPreset) -> Result<(), String> {
+async fn update_custom_preset(
+    mut preset: DnsPreset,
+    verify: Option<bool>,
+    save_anyway: Option<bool>,
+    proxy: State<'_, ProxyService>,
+) -> Result<PresetSaveResult, String> {
     info!("✏️ Updating custom preset: {}", preset.name);
-    CustomPresetsManager::update_custom_preset(preset)
-        .map_err(|e| {
-            error!("❌ Failed to update custom preset: {}", e);
-            e.to_string()
-        })
+    let previous = CustomPresetsManager::load_custom_presets()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|p| p.id == preset.id);
+    let checked = verification::check_before_save(&mut preset, previous.as_ref(), verify, save_anyway, &proxy).await;
+    let report = match checked {
+        Ok(report) => report,
+        Err(rejected) => return Ok(rejected),
+    };
+
+    save_result(CustomPresetsManager::update_custom_preset(preset), report, "update")
 }
 
 #[tauri::command]
@@ -159,10 +235,81 @@ async fn set_dns(
     ipv4_servers: Vec<String>,
     ipv6_servers: Vec<String>,
     doh_template: Option<String>,
-    _dot_hostname: Option<String>,
+    dot_hostname: Option<String>,
+    stamp: Option<String>,
+    preset_id: Option<String>,
+    proxy: State<'_, ProxyService>,
 ) -> Result<(), String> {
     info!("🔧 Setting DNS for adapter: {}", adapter_name);
     let dns_manager = DnsManager::new();
+
+    // У пресетов с параметрами в адресах стоят `{name}` — подставляем личные значения
+    let (doh_template, dot_hostname) = match preset_id.as_deref() {
+        Some(id) => PresetParameters::apply(id, doh_template, dot_hostname).map_err(|e| {
+            error!("❌ Failed to apply preset parameters: {}", e);
+            e.to_string()
+        })?,
+        None => (doh_template, dot_hostname),
+    };
+
+    let has_encrypted = doh_template.is_some() || dot_hostname.is_some() || stamp.is_some();
+    // Стампы (DNSCrypt и др.) Windows не понимает — их обслуживает только прокси
+    let native_doh = stamp.is_none()
+        && dns_manager
+            .get_windows_version()
+            .map(|v| v.supports_doh)
+            .unwrap_or(false);
+
+    if proxy.should_route(has_encrypted, native_doh) {
+        info!("🛰️  Routing {} through the local DNS proxy", adapter_name);
+        // Прежние DNS записываются до переключения, чтобы вернуть их после сбоя
+        let recorded = match dns_manager.get_current_dns(&adapter_name) {
+            Ok(current) => record_restore_state(&adapter_name, current).await,
+            Err(e) => Err(e),
+        }
+        .map_err(|e| {
+            error!("❌ Failed to save the current DNS before routing: {}", e);
+            e.to_string()
+        })?;
+
+        let routed = proxy
+            .route_adapter(
+                &adapter_name,
+                &ipv4_servers,
+                &ipv6_servers,
+                doh_template.as_deref(),
+                dot_hostname.as_deref(),
+                stamp.as_deref(),
+            )
+            .await;
+        let (proxy_ipv4, proxy_ipv6) = match routed {
+            Ok(servers) => servers,
+            Err(e) => {
+                // Адаптер не переключён — запись, созданная для него, больше не нужна
+                if recorded {
+                    forget_restore_state(&adapter_name).await;
+                }
+                error!("❌ Failed to start local DNS proxy: {}", e);
+                return Err(e.to_string());
+            }
+        };
+
+        let result = dns_manager.set_dns(&adapter_name, proxy_ipv4, proxy_ipv6, None);
+        if result.is_err() {
+            proxy.release_adapter(&adapter_name).await;
+            if recorded {
+                forget_restore_state(&adapter_name).await;
+            }
+        }
+        result.map_err(|e| {
+            error!("❌ Failed to set DNS: {}", e);
+            e.to_string()
+        })?;
+        proxy.set_active_preset(&adapter_name, preset_id.as_deref());
+        return Ok(());
+    }
+
+    proxy.release_adapter(&adapter_name).await;
     dns_manager
         .set_dns(
             &adapter_name,
@@ -173,30 +320,81 @@ async fn set_dns(
         .map_err(|e| {
             error!("❌ Failed to set DNS: {}", e);
             e.to_string()
-        })
+        })?;
+    forget_restore_state(&adapter_name).await;
+    proxy.set_active_preset(&adapter_name, preset_id.as_deref());
+    Ok(())
+}
+
+/// Записать прежние DNS адаптера в журнал. Замок журнала может ждать другой
+/// процесс, поэтому запись идёт в пуле блокирующих потоков
+async fn record_restore_state(adapter_name: &str, current: DnsConfiguration) -> anyhow::Result<bool> {
+    let adapter_name = adapter_name.to_string();
+    tokio::task::spawn_blocking(move || RestoreJournal::record(&adapter_name, &current)).await?
+}
+
+/// Адаптер больше не направлен на прокси — восстанавливать его после сбоя не нужно
+async fn forget_restore_state(adapter_name: &str) {
+    let adapter_name = adapter_name.to_string();
+    let forgotten = tokio::task::spawn_blocking(move || RestoreJournal::forget(&adapter_name))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|forgotten| forgotten);
+    if let Err(e) = forgotten {
+        warn!("⚠️  Failed to update the restore journal: {}", e);
+    }
 }
 
 #[tauri::command]
-async fn reset_dns(adapter_name: String) -> Result<(), String> {
+async fn reset_dns(adapter_name: String, proxy: State<'_, ProxyService>) -> Result<(), String> {
     info!("🔄 Resetting DNS for adapter: {}", adapter_name);
     let dns_manager = DnsManager::new();
     dns_manager.reset_to_dhcp(&adapter_name).map_err(|e| {
         error!("❌ Failed to reset DNS: {}", e);
         e.to_string()
+    })?;
+    proxy.release_adapter(&adapter_name).await;
+    forget_restore_state(&adapter_name).await;
+    proxy.set_active_preset(&adapter_name, None);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_proxy_config(proxy: State<'_, ProxyService>) -> Result<ProxyConfig, String> {
+    Ok(proxy.config())
+}
+
+#[tauri::command]
+async fn update_proxy_config(config: ProxyConfig, proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("⚙️  Updating local DNS proxy settings...");
+    proxy.update_config(config).await.map_err(|e| {
+        error!("❌ Failed to update proxy settings: {}", e);
+        e.to_string()
     })
 }
 
 #[tauri::command]
-async fn test_dns(server: String) -> Result<DnsTestResult, String> {
+async fn get_proxy_status(proxy: State<'_, ProxyService>) -> Result<ProxyStatus, String> {
+    Ok(proxy.status().await)
+}
+
+#[tauri::command]
+async fn test_dns(server: String, proxy: State<'_, ProxyService>) -> Result<DnsTestResult, String> {
     info!("🔍 Testing DNS server: {}", server);
+    // DoH/DoT/ODoH/DNSCrypt-адреса и стампы проверяются через транспорт прокси
+    if server.contains("://") {
+        return Ok(proxy.probe_upstream(&server).await);
+    }
     let dns_manager = DnsManager::new();
-    dns_manager
+    let result = dns_manager
         .test_dns(&server)
         .await
         .map_err(|e| {
             error!("❌ DNS test failed: {}", e);
             e.to_string()
-        })
+        })?;
+    proxy.record_probe(&result);
+    Ok(result)
 }
 
 #[tauri::command]
@@ -211,6 +409,129 @@ async fn get_preset_by_id(id: String) -> Result<Option<DnsPreset>, String> {
     Ok(presets::get_preset_by_id(&id))
 }
 
+/// Встроенные и пользовательские пресеты, отобранные по возможностям
+#[tauri::command]
+async fn query_presets(query: PresetQuery) -> Result<Vec<DnsPreset>, String> {
+    info!("🔍 Querying presets...");
+    query.run().map_err(|e| {
+        error!("❌ Failed to query presets: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn get_preset_parameters(preset_id: String) -> Result<ParameterValues, String> {
+    PresetParameters::get(&preset_id).map_err(|e| {
+        error!("❌ Failed to load preset parameters: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn set_preset_parameters(preset_id: String, values: ParameterValues) -> Result<(), String> {
+    info!("🔑 Saving parameters for preset: {}", preset_id);
+    PresetParameters::set(&preset_id, values).map_err(|e| {
+        error!("❌ Failed to save preset parameters: {}", e);
+        e.to_string()
+    })
+}
+
+/// Пресет в виде DNS-стампов (`sdns://`), по одному на адрес и протокол
+#[tauri::command]
+async fn export_preset_stamps(preset_id: String) -> Result<Vec<String>, String> {
+    info!("📤 Exporting preset {} as DNS stamps", preset_id);
+    proxy::find_preset(&preset_id)
+        .ok_or_else(|| format!("Preset {} not found", preset_id))
+        .and_then(|preset| {
+            preset_stamps::to_stamps(&preset).map_err(|e| {
+                error!("❌ Failed to export DNS stamps: {}", e);
+                e.to_string()
+            })
+        })
+}
+
+/// Создать пользовательские пресеты из стампов: текст или файл со списком
+#[tauri::command]
+async fn import_preset_stamps(text: Option<String>, path: Option<String>) -> Result<StampImport, String> {
+    info!("📥 Importing presets from DNS stamps...");
+    let result = match (text, path) {
+        (_, Some(path)) => preset_stamps::import_file(std::path::Path::new(&path)),
+        (Some(text), None) => preset_stamps::import(&text),
+        (None, None) => return Err("Nothing to import".to_string()),
+    };
+    result.map_err(|e| {
+        error!("❌ Failed to import DNS stamps: {}", e);
+        e.to_string()
+    })
+}
+
+/// Разобрать список серверов dnscrypt-proxy или AdGuard Home и показать
+/// совпадения с имеющимися пресетами; ничего не сохраняется
+#[tauri::command]
+async fn preview_preset_import(
+    format: Option<ImportFormat>,
+    text: Option<String>,
+    path: Option<String>,
+) -> Result<ImportPreview, String> {
+    info!("🔎 Previewing preset import...");
+    let result = match (text, path) {
+        (_, Some(path)) => preset_import::preview_file(std::path::Path::new(&path), format),
+        (Some(text), None) => match format {
+            Some(format) => preset_import::preview(format, &text),
+            None => return Err("Choose the format of the pasted list".to_string()),
+        },
+        (None, None) => return Err("Nothing to import".to_string()),
+    };
+    result.map_err(|e| {
+        error!("❌ Failed to read the resolver list: {}", e);
+        e.to_string()
+    })
+}
+
+/// Сохранить выбранные после предпросмотра пресеты
+#[tauri::command]
+async fn import_presets(presets: Vec<DnsPreset>) -> Result<ImportResult, String> {
+    info!("📥 Importing {} presets...", presets.len());
+    preset_import::commit(presets).map_err(|e| {
+        error!("❌ Failed to import presets: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn get_catalog_source() -> Result<CatalogSource, String> {
+    CatalogSource::load().map_err(|e| {
+        error!("❌ Failed to load catalog source: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn set_catalog_source(source: CatalogSource) -> Result<(), String> {
+    info!("💾 Saving preset catalog source...");
+    source.save().map_err(|e| {
+        error!("❌ Failed to save catalog source: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn update_preset_catalog() -> Result<CatalogUpdate, String> {
+    info!("🔄 Checking for preset catalog updates...");
+    CatalogUpdater::update().await.map_err(|e| {
+        error!("❌ Preset catalog update failed: {:#}", e);
+        format!("{:#}", e)
+    })
+}
+
+#[tauri::command]
+async fn get_preset_catalog_diff() -> Result<CatalogDiff, String> {
+    CatalogUpdater::diff().map_err(|e| {
+        error!("❌ Failed to compare preset catalogs: {}", e);
+        e.to_string()
+    })
+}
+
 #[tauri::command]
 async fn flush_dns_cache() -> Result<(), String> {
     info!("🧹 Flushing DNS cache...");
@@ -231,6 +552,206 @@ async fn get_windows_version() -> Result<WindowsVersion, String> {
     })
 }
 
+#[tauri::command]
+async fn export_test_results(
+    runs: Vec<BenchmarkRun>,
+    format: ExportFormat,
+    path: String,
+    adapter_name: Option<String>,
+) -> Result<(), String> {
+    info!("💾 Exporting test results as {:?}...", format);
+    let context = ResultsExporter::collect_context(adapter_name.as_deref());
+    ResultsExporter::export_to_file(&runs, format, std::path::Path::new(&path), &context)
+        .map_err(|e| {
+            error!("❌ Failed to export test results: {}", e);
+            e.to_string()
+        })
+}
+
+#[tauri::command]
+async fn get_proxy_cache_stats(proxy: State<'_, ProxyService>) -> Result<CacheStats, String> {
+    Ok(proxy.cache_stats())
+}
+
+#[tauri::command]
+async fn clear_proxy_cache(proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("🧹 Clearing local DNS proxy cache...");
+    proxy.clear_cache();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_forwarding_rules() -> Result<Vec<ForwardingRule>, String> {
+    RulesManager::load_rules().map_err(|e| {
+        error!("❌ Failed to load forwarding rules: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn add_forwarding_rule(
+    rule: ForwardingRule,
+    proxy: State<'_, ProxyService>,
+) -> Result<ForwardingRule, String> {
+    info!("➕ Adding forwarding rule for {}", rule.pattern);
+    let rule = RulesManager::add_rule(rule).map_err(|e| {
+        error!("❌ Failed to add forwarding rule: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_rules().await;
+    Ok(rule)
+}
+
+#[tauri::command]
+async fn update_forwarding_rule(
+    rule: ForwardingRule,
+    proxy: State<'_, ProxyService>,
+) -> Result<(), String> {
+    info!("✏️ Updating forwarding rule: {}", rule.id);
+    RulesManager::update_rule(rule).map_err(|e| {
+        error!("❌ Failed to update forwarding rule: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_rules().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_forwarding_rule(id: String, proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("🗑️ Deleting forwarding rule: {}", id);
+    RulesManager::delete_rule(&id).map_err(|e| {
+        error!("❌ Failed to delete forwarding rule: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_rules().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_local_records() -> Result<Vec<LocalRecord>, String> {
+    RecordsManager::load_records().map_err(|e| {
+        error!("❌ Failed to load local records: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn add_local_record(
+    record: LocalRecord,
+    proxy: State<'_, ProxyService>,
+) -> Result<LocalRecord, String> {
+    info!("➕ Adding local record: {}", record.name);
+    let record = RecordsManager::add_record(record).map_err(|e| {
+        error!("❌ Failed to add local record: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_records();
+    Ok(record)
+}
+
+#[tauri::command]
+async fn update_local_record(
+    record: LocalRecord,
+    proxy: State<'_, ProxyService>,
+) -> Result<(), String> {
+    info!("✏️ Updating local record: {}", record.id);
+    RecordsManager::update_record(record).map_err(|e| {
+        error!("❌ Failed to update local record: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_records();
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_local_record(id: String, proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("🗑️ Deleting local record: {}", id);
+    RecordsManager::delete_record(&id).map_err(|e| {
+        error!("❌ Failed to delete local record: {}", e);
+        e.to_string()
+    })?;
+    proxy.reload_records();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_query_log(
+    offset: usize,
+    limit: usize,
+    search: Option<String>,
+    proxy: State<'_, ProxyService>,
+) -> Result<QueryLogPage, String> {
+    Ok(proxy.query_log(offset, limit, search.as_deref()))
+}
+
+#[tauri::command]
+async fn get_query_stats(top: Option<usize>, proxy: State<'_, ProxyService>) -> Result<QueryStats, String> {
+    Ok(proxy.query_stats(top.unwrap_or(10)))
+}
+
+#[tauri::command]
+async fn clear_query_log(proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("🧹 Clearing query log...");
+    proxy.clear_query_log().map_err(|e| {
+        error!("❌ Failed to clear query log: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn get_blocklist_settings(proxy: State<'_, ProxyService>) -> Result<BlocklistSettings, String> {
+    Ok(proxy.blocklists().settings())
+}
+
+#[tauri::command]
+async fn update_blocklist_settings(
+    settings: BlocklistSettings,
+    proxy: State<'_, ProxyService>,
+) -> Result<(), String> {
+    info!("💾 Updating blocklist settings...");
+    proxy.blocklists().update_settings(settings).await.map_err(|e| {
+        error!("❌ Failed to update blocklist settings: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn add_blocklist_source(
+    source: BlocklistSource,
+    proxy: State<'_, ProxyService>,
+) -> Result<BlocklistSource, String> {
+    info!("➕ Adding blocklist: {}", source.name);
+    proxy.blocklists().add_source(source).await.map_err(|e| {
+        error!("❌ Failed to add blocklist: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn delete_blocklist_source(id: String, proxy: State<'_, ProxyService>) -> Result<(), String> {
+    info!("🗑️ Deleting blocklist: {}", id);
+    proxy.blocklists().delete_source(&id).await.map_err(|e| {
+        error!("❌ Failed to delete blocklist: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn refresh_blocklists(proxy: State<'_, ProxyService>) -> Result<BlocklistStatus, String> {
+    info!("🔄 Refreshing blocklists...");
+    let blocklists = proxy.blocklists();
+    blocklists.refresh(true).await.map_err(|e| {
+        error!("❌ Failed to refresh blocklists: {}", e);
+        e.to_string()
+    })?;
+    Ok(blocklists.status())
+}
+
+#[tauri::command]
+async fn get_blocklist_status(proxy: State<'_, ProxyService>) -> Result<BlocklistStatus, String> {
+    Ok(proxy.blocklists().status())
+}
+
 /// Initialize the logger with custom formatting
 fn init_logger() {
     use env_logger::Builder;
@@ -260,6 +781,12 @@ fn init_logger() {
         .init();
 }
 
+/// Сторожевой процесс: восстанавливает DNS адаптеров, если приложение упало
+pub fn run_watchdog() {
+    init_logger();
+    RestoreJournal::run_watchdog();
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Initialize logger
@@ -273,8 +800,27 @@ pub fn run() {
         .manage(AppState {
             selected_adapter: Arc::new(Mutex::new(None)),
         })
+        .manage(ProxyService::new())
         .setup(|app| {
             info!("🔧 Setting up application...");
+
+            // Непустой журнал означает, что прошлый запуск завершился аварийно
+            if RestoreJournal::pending().map(|p| !p.is_empty()).unwrap_or(false) {
+                warn!("⚠️  Unclean shutdown detected, restoring adapters routed through the proxy");
+                let restored = RestoreJournal::restore_all();
+                info!("🩹 Restored DNS of {} adapter(s)", restored);
+            }
+
+            // Сторож живёт, пока открыт его stdin, то есть пока жив этот процесс
+            match RestoreJournal::spawn_watchdog() {
+                Ok(watchdog) => {
+                    app.manage(Mutex::new(watchdog));
+                }
+                Err(e) => warn!("⚠️  DNS watchdog is not running: {}", e),
+            }
+
+            // Каталог пресетов разбирается сразу, чтобы ошибки в нём попали в лог при запуске
+            presets::init();
             
             // Setup tray menu event handler
             if let Some(tray) = app.tray_by_id("main-tray") {
@@ -309,6 +855,56 @@ pub fn run() {
                 });
             }
             
+            // Загрузка блок-листов и обновление подписок по расписанию
+            let blocklists = app.state::<ProxyService>().blocklists();
+            tauri::async_runtime::spawn(blocklists.run_scheduler());
+
+            // Прокси для локальной сети, если доступ из сети включён в настройках
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = app_handle.state::<ProxyService>().apply_server().await {
+                    warn!("⚠️  Local DNS proxy is not running for the LAN: {}", e);
+                }
+            });
+
+            // Эндпоинт метрик, если он включён в настройках
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = app_handle.state::<ProxyService>().apply_metrics().await {
+                    warn!("⚠️  Metrics endpoint is not running: {}", e);
+                }
+            });
+
+            // Обновление каталога пресетов, если задан его адрес
+            if CatalogSource::load().map(|source| source.is_configured()).unwrap_or(false) {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    match CatalogUpdater::update().await {
+                        Ok(update) if update.updated => {
+                            let _ = app_handle.emit("preset-catalog-updated", update);
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("⚠️  Preset catalog update failed: {:#}", e),
+                    }
+                });
+            }
+
+            // Живой поток журнала запросов для фронтенда
+            let mut queries = app.state::<ProxyService>().subscribe_queries();
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                use tokio::sync::broadcast::error::RecvError;
+                loop {
+                    match queries.recv().await {
+                        Ok(entry) => {
+                            let _ = app_handle.emit("proxy-query", entry);
+                        }
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+
             info!("✅ Application setup complete");
             Ok(())
         })
@@ -320,12 +916,47 @@ pub fn run() {
             test_dns,
             get_dns_presets,
             get_preset_by_id,
+            query_presets,
+            get_preset_parameters,
+            set_preset_parameters,
+            export_preset_stamps,
+            import_preset_stamps,
+            preview_preset_import,
+            import_presets,
+            get_catalog_source,
+            set_catalog_source,
+            update_preset_catalog,
+            get_preset_catalog_diff,
             flush_dns_cache,
             get_windows_version,
+            export_test_results,
+            get_proxy_config,
+            update_proxy_config,
+            get_proxy_status,
+            get_proxy_cache_stats,
+            clear_proxy_cache,
+            get_forwarding_rules,
+            add_forwarding_rule,
+            update_forwarding_rule,
+            delete_forwarding_rule,
+            get_local_records,
+            add_local_record,
+            update_local_record,
+            delete_local_record,
+            get_query_log,
+            get_query_stats,
+            clear_query_log,
+            get_blocklist_settings,
+            update_blocklist_settings,
+            add_blocklist_source,
+            delete_blocklist_source,
+            refresh_blocklists,
+            get_blocklist_status,
             get_custom_presets,
             add_custom_preset,
             delete_custom_preset,
             update_custom_preset,
+            validate_preset,
             toggle_mini_window,
             set_mini_always_on_top,
             update_tray_tooltip,
@@ -333,6 +964,15 @@ pub fn run() {
             get_selected_adapter,
             set_selected_adapter,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // При обычном выходе адаптеры возвращаются к прежним DNS сразу
+                let restored = RestoreJournal::restore_all();
+                if restored > 0 {
+                    info!("🩹 Restored DNS of {} adapter(s) on exit", restored);
+                }
+            }
+        });
 }
\ No newline at end of file
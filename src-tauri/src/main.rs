@@ -2,5 +2,9 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
-    dns_switcher::run()
+    if std::env::args().nth(1).as_deref() == Some(dns_switcher::WATCHDOG_ARG) {
+        dns_switcher::run_watchdog()
+    } else {
+        dns_switcher::run()
+    }
 }
\ No newline at end of file
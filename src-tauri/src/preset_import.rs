@@ -0,0 +1,657 @@
+use crate::custom_presets::CustomPresetsManager;
+use crate::preset_stamps::{self, slug, unique_id};
+use crate::presets;
+use crate::proxy::strip_uri_template;
+use crate::stamp::{self, DnsStamp};
+use crate::types::DnsPreset;
+use anyhow::{Context, Result};
+use log::info;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use yaml_rust2::{Yaml, YamlLoader};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// `dnscrypt-proxy.toml`: стампы из `[static]`
+    DnscryptProxy,
+    /// `public-resolvers.md` и другие списки dnscrypt-proxy
+    PublicResolvers,
+    /// `AdGuardHome.yaml`: `dns.upstream_dns`
+    AdguardHome,
+}
+
+impl ImportFormat {
+    /// Формат по расширению файла
+    pub fn detect(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(Self::DnscryptProxy),
+            "md" => Some(Self::PublicResolvers),
+            "yaml" | "yml" => Some(Self::AdguardHome),
+            _ => None,
+        }
+    }
+
+    fn id_prefix(self) -> &'static str {
+        match self {
+            Self::DnscryptProxy => "dnscrypt",
+            Self::PublicResolvers => "resolvers",
+            Self::AdguardHome => "adguard",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    Name,
+    /// Совпадает хотя бы один обычный DNS-адрес
+    Server,
+    DohTemplate,
+    DotHostname,
+    Stamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateSource {
+    BuiltIn,
+    Custom,
+    /// Встречается раньше в том же импорте
+    Import,
+}
+
+/// Пресет, с которым совпадает импортируемый
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub id: String,
+    pub name: String,
+    pub source: DuplicateSource,
+    pub reasons: Vec<DuplicateReason>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportCandidate {
+    pub preset: DnsPreset,
+    pub duplicates: Vec<Duplicate>,
+}
+
+/// Запись, которую не удалось разобрать или сохранить
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportIssue {
+    /// Имя сервера или строка апстрима
+    pub entry: String,
+    pub message: String,
+}
+
+/// Что будет импортировано; ничего ещё не сохранено
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub format: ImportFormat,
+    pub candidates: Vec<ImportCandidate>,
+    pub skipped: Vec<ImportIssue>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportResult {
+    pub imported: Vec<DnsPreset>,
+    pub errors: Vec<ImportIssue>,
+}
+
+/// Сервер из списка: имя, описание и его стампы
+struct ImportEntry {
+    name: Option<String>,
+    description: Option<String>,
+    stamps: Vec<String>,
+    /// Как запись выглядела в исходном файле — для сообщений
+    source: String,
+}
+
+/// Разобрать список серверов и сравнить его со встроенными и пользовательскими пресетами
+pub fn preview(format: ImportFormat, content: &str) -> Result<ImportPreview> {
+    let mut skipped = Vec::new();
+    let entries = match format {
+        ImportFormat::DnscryptProxy => parse_dnscrypt_proxy(content)?,
+        ImportFormat::PublicResolvers => parse_public_resolvers(content, &mut skipped),
+        ImportFormat::AdguardHome => parse_adguard_home(content, &mut skipped)?,
+    };
+
+    let builtin = presets::get_default_presets();
+    let custom = CustomPresetsManager::load_custom_presets()?;
+    let mut candidates: Vec<ImportCandidate> = Vec::new();
+
+    for entry in entries {
+        let preset = match entry_to_preset(format, &entry) {
+            Ok(preset) => preset,
+            Err(e) => {
+                skipped.push(ImportIssue {
+                    entry: entry.source,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let mut duplicates = find_duplicates(&preset, &builtin, DuplicateSource::BuiltIn);
+        duplicates.extend(find_duplicates(&preset, &custom, DuplicateSource::Custom));
+        let earlier: Vec<DnsPreset> = candidates.iter().map(|c| c.preset.clone()).collect();
+        duplicates.extend(find_duplicates(&preset, &earlier, DuplicateSource::Import));
+        candidates.push(ImportCandidate { preset, duplicates });
+    }
+
+    if candidates.is_empty() && skipped.is_empty() {
+        anyhow::bail!("No DNS servers found to import");
+    }
+    Ok(ImportPreview {
+        format,
+        candidates,
+        skipped,
+    })
+}
+
+pub fn preview_file(path: &Path, format: Option<ImportFormat>) -> Result<ImportPreview> {
+    let format = format
+        .or_else(|| ImportFormat::detect(path))
+        .with_context(|| format!("Can't tell the format of {}", path.display()))?;
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    preview(format, &content)
+}
+
+/// Сохранить выбранные из предпросмотра пресеты как пользовательские
+pub fn commit(presets: Vec<DnsPreset>) -> Result<ImportResult> {
+    let mut existing: Vec<String> = presets::get_default_presets().into_iter().map(|p| p.id).collect();
+    existing.extend(CustomPresetsManager::load_custom_presets()?.into_iter().map(|p| p.id));
+
+    let mut result = ImportResult::default();
+    for mut preset in presets {
+        preset.id = unique_id(&preset.id, &existing);
+        match CustomPresetsManager::add_custom_preset(preset.clone()) {
+            Ok(()) => {
+                existing.push(preset.id.clone());
+                result.imported.push(preset);
+            }
+            Err(e) => result.errors.push(ImportIssue {
+                entry: preset.name,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    info!("📥 Imported {} presets, {} failed", result.imported.len(), result.errors.len());
+    Ok(result)
+}
+
+/// `[static.'name']` с полем `stamp`
+fn parse_dnscrypt_proxy(content: &str) -> Result<Vec<ImportEntry>> {
+    let config: toml::Table = toml::from_str(content).context("Failed to parse dnscrypt-proxy.toml")?;
+    let servers = config
+        .get("static")
+        .and_then(toml::Value::as_table)
+        .context("dnscrypt-proxy.toml has no [static] servers")?;
+
+    Ok(servers
+        .iter()
+        .map(|(name, server)| ImportEntry {
+            name: Some(name.clone()),
+            description: None,
+            stamps: server
+                .get("stamp")
+                .and_then(toml::Value::as_str)
+                .map(|stamp| vec![stamp.to_string()])
+                .unwrap_or_default(),
+            source: name.clone(),
+        })
+        .collect())
+}
+
+/// Markdown-список dnscrypt-proxy: `## имя`, описание и строки со стампами
+fn parse_public_resolvers(content: &str, skipped: &mut Vec<ImportIssue>) -> Vec<ImportEntry> {
+    let mut entries: Vec<ImportEntry> = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("## ") {
+            entries.push(ImportEntry {
+                name: Some(name.trim().to_string()),
+                description: None,
+                stamps: Vec::new(),
+                source: name.trim().to_string(),
+            });
+            continue;
+        }
+        // Текст до первого сервера — заголовок списка
+        let Some(entry) = entries.last_mut() else {
+            continue;
+        };
+        if stamp::is_stamp(line) {
+            entry.stamps.push(line.to_string());
+        } else if !line.is_empty() && !line.starts_with('#') && entry.stamps.is_empty() {
+            // Описание — первый абзац
+            match &mut entry.description {
+                Some(description) => {
+                    description.push(' ');
+                    description.push_str(line);
+                }
+                None => entry.description = Some(line.to_string()),
+            }
+        }
+    }
+
+    entries.retain(|entry| {
+        if entry.stamps.is_empty() {
+            skipped.push(ImportIssue {
+                entry: entry.source.clone(),
+                message: "no DNS stamp in this entry".to_string(),
+            });
+        }
+        !entry.stamps.is_empty()
+    });
+    entries
+}
+
+/// `dns.upstream_dns` из AdGuardHome.yaml; каждый апстрим — отдельный сервер
+fn parse_adguard_home(content: &str, skipped: &mut Vec<ImportIssue>) -> Result<Vec<ImportEntry>> {
+    let documents = YamlLoader::load_from_str(content).context("Failed to parse AdGuard Home YAML")?;
+    let document = documents.first().context("AdGuard Home YAML is empty")?;
+    let upstreams = match &document["dns"]["upstream_dns"] {
+        Yaml::Array(upstreams) => upstreams,
+        _ => match &document["upstream_dns"] {
+            Yaml::Array(upstreams) => upstreams,
+            _ => anyhow::bail!("AdGuard Home YAML has no dns.upstream_dns list"),
+        },
+    };
+
+    let mut entries = Vec::new();
+    for upstream in upstreams.iter().filter_map(Yaml::as_str).map(str::trim) {
+        if upstream.is_empty() || upstream.starts_with('#') {
+            continue;
+        }
+        match adguard_upstream_to_stamp(upstream) {
+            Ok(stamp) => entries.push(ImportEntry {
+                name: None,
+                description: Some(format!("AdGuard Home upstream {}", upstream)),
+                stamps: vec![stamp],
+                source: upstream.to_string(),
+            }),
+            Err(e) => skipped.push(ImportIssue {
+                entry: upstream.to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+    Ok(entries)
+}
+
+/// Апстрим AdGuard Home (`https://`, `h3://`, `tls://`, `udp://`, `tcp://`,
+/// голый адрес или `sdns://`) в виде стампа; `quic://` прокси не обслуживает
+fn adguard_upstream_to_stamp(upstream: &str) -> Result<String> {
+    if stamp::is_stamp(upstream) {
+        return Ok(upstream.to_string());
+    }
+    if upstream.starts_with("[/") {
+        anyhow::bail!("domain-specific upstreams are not imported");
+    }
+
+    let (scheme, rest) = upstream.split_once("://").unwrap_or(("udp", upstream));
+    let props = Default::default();
+    let stamp = match scheme {
+        "https" | "h3" => {
+            let url = Url::parse(&format!("https://{}", rest)).context("invalid DoH upstream")?;
+            let host = url.host_str().context("DoH upstream has no host")?;
+            let mut hostname = host.to_string();
+            if let Some(port) = url.port() {
+                hostname = format!("{}:{}", hostname, port);
+            }
+            let mut path = strip_uri_template(url.path());
+            if let Some(query) = url.query() {
+                path = format!("{}?{}", path, query);
+            }
+            DnsStamp::DoH {
+                props,
+                addr: ip_host(host).unwrap_or_default(),
+                hashes: Vec::new(),
+                hostname,
+                path,
+                bootstrap: Vec::new(),
+            }
+        }
+        "tls" => {
+            let hostname = rest.trim_end_matches('/').to_string();
+            let host = hostname.rsplit_once(':').map_or(hostname.as_str(), |(host, _)| host);
+            let addr = ip_host(host).map(|_| hostname.clone()).unwrap_or_default();
+            DnsStamp::DoT { props, addr, hashes: Vec::new(), hostname, bootstrap: Vec::new() }
+        }
+        "quic" => {
+            anyhow::bail!("DNS-over-QUIC upstreams are not supported: the local DNS proxy can't connect to DoQ servers")
+        }
+        "udp" | "tcp" => DnsStamp::Plain {
+            props,
+            addr: rest.trim_end_matches('/').to_string(),
+        },
+        other => anyhow::bail!("unsupported upstream scheme '{}'", other),
+    };
+    stamp.encode()
+}
+
+/// Хост, если это IP-адрес (в том числе `[ipv6]`)
+fn ip_host(host: &str) -> Option<String> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| ip.to_string())
+}
+
+/// Первый стамп задаёт пресет; обычные адреса остальных стампов добавляются к нему
+fn entry_to_preset(format: ImportFormat, entry: &ImportEntry) -> Result<DnsPreset> {
+    let (first, rest) = entry.stamps.split_first().context("no DNS stamp in this entry")?;
+    let mut preset = preset_stamps::from_stamp(first, entry.name.as_deref())?;
+
+    for other in rest {
+        let other = preset_stamps::from_stamp(other, None)?;
+        for server in other.servers_ipv4 {
+            if !preset.servers_ipv4.contains(&server) {
+                preset.servers_ipv4.push(server);
+            }
+        }
+        for server in other.servers_ipv6 {
+            if !preset.servers_ipv6.contains(&server) {
+                preset.servers_ipv6.push(server);
+            }
+        }
+    }
+
+    preset.id = format!("{}-{}", format.id_prefix(), slug(&preset.name));
+    if let Some(description) = &entry.description {
+        preset.description = description.clone();
+    }
+    Ok(preset)
+}
+
+fn find_duplicates(preset: &DnsPreset, others: &[DnsPreset], source: DuplicateSource) -> Vec<Duplicate> {
+    others
+        .iter()
+        .filter_map(|other| {
+            let mut reasons = Vec::new();
+            if other.name.trim().eq_ignore_ascii_case(preset.name.trim()) {
+                reasons.push(DuplicateReason::Name);
+            }
+            if servers(preset).any(|ip| servers(other).any(|other| other == ip)) {
+                reasons.push(DuplicateReason::Server);
+            }
+            if let (Some(a), Some(b)) = (&preset.doh_template, &other.doh_template) {
+                if normalize_doh(a) == normalize_doh(b) {
+                    reasons.push(DuplicateReason::DohTemplate);
+                }
+            }
+            if let (Some(a), Some(b)) = (&preset.dot_hostname, &other.dot_hostname) {
+                if a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.')) {
+                    reasons.push(DuplicateReason::DotHostname);
+                }
+            }
+            if let (Some(a), Some(b)) = (&preset.stamp, &other.stamp) {
+                if a.trim() == b.trim() {
+                    reasons.push(DuplicateReason::Stamp);
+                }
+            }
+
+            (!reasons.is_empty()).then(|| Duplicate {
+                id: other.id.clone(),
+                name: other.name.clone(),
+                source,
+                reasons,
+            })
+        })
+        .collect()
+}
+
+fn servers(preset: &DnsPreset) -> impl Iterator<Item = IpAddr> + '_ {
+    preset
+        .servers_ipv4
+        .iter()
+        .chain(&preset.servers_ipv6)
+        .filter_map(|server| server.trim().parse().ok())
+}
+
+fn normalize_doh(template: &str) -> String {
+    strip_uri_template(template).trim_end_matches('/').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::types::test_preset;
+
+    const CLOUDFLARE_DOH: &str = "sdns://AgcAAAAAAAAABzEuMC4wLjEAEmRucy5jbG91ZGZsYXJlLmNvbQovZG5zLXF1ZXJ5";
+    const PLAIN_1111: &str = "sdns://AAcAAAAAAAAABzEuMS4xLjE";
+
+    #[test]
+    fn public_resolvers_entries_keep_name_description_and_stamps() {
+        let content = format!(
+            "# public-resolvers\n\nList header.\n\n## example\n\nFirst line of\ndescription.\n\n{}\n{}\n\n## no-stamp\n\nNothing here\n",
+            CLOUDFLARE_DOH, PLAIN_1111
+        );
+        let mut skipped = Vec::new();
+        let entries = parse_public_resolvers(&content, &mut skipped);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name.as_deref(), Some("example"));
+        assert_eq!(entries[0].description.as_deref(), Some("First line of description."));
+        assert_eq!(entries[0].stamps, vec![CLOUDFLARE_DOH, PLAIN_1111]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].entry, "no-stamp");
+
+        let preset = entry_to_preset(ImportFormat::PublicResolvers, &entries[0]).unwrap();
+        assert_eq!(preset.id, "resolvers-example");
+        assert_eq!(preset.servers_ipv4, vec!["1.0.0.1", "1.1.1.1"]);
+        assert_eq!(preset.doh_template.as_deref(), Some("https://dns.cloudflare.com/dns-query"));
+    }
+
+    #[test]
+    fn adguard_upstreams_become_stamps_and_unsupported_ones_are_skipped() {
+        let content = "dns:\n  upstream_dns:\n    - https://dns10.quad9.net/dns-query\n    - tls://1.1.1.1\n    - 9.9.9.9:5353\n    - '[/lan/]192.168.1.1'\n    - quic://dns.adguard-dns.com\n    - gopher://example\n    - '# comment'\n";
+        let mut skipped = Vec::new();
+        let entries = parse_adguard_home(content, &mut skipped).unwrap();
+
+        let stamps: Vec<DnsStamp> = entries.iter().map(|e| DnsStamp::parse(&e.stamps[0]).unwrap()).collect();
+        assert!(matches!(&stamps[0], DnsStamp::DoH { hostname, path, .. } if hostname == "dns10.quad9.net" && path == "/dns-query"));
+        assert!(matches!(&stamps[1], DnsStamp::DoT { addr, hostname, .. } if addr == "1.1.1.1" && hostname == "1.1.1.1"));
+        assert!(matches!(&stamps[2], DnsStamp::Plain { addr, .. } if addr == "9.9.9.9:5353"));
+        assert_eq!(stamps.len(), 3);
+
+        let skipped: Vec<&str> = skipped.iter().map(|issue| issue.entry.as_str()).collect();
+        assert_eq!(skipped, vec!["[/lan/]192.168.1.1", "quic://dns.adguard-dns.com", "gopher://example"]);
+    }
+
+    #[test]
+    fn adguard_accepts_top_level_list_and_requires_one() {
+        let mut skipped = Vec::new();
+        let entries = parse_adguard_home("upstream_dns:\n  - 8.8.8.8\n", &mut skipped).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(parse_adguard_home("dns: {}\n", &mut skipped).is_err());
+    }
+
+    #[test]
+    fn doq_stamp_entry_is_rejected() {
+        let doq = DnsStamp::DoQ {
+            props: Default::default(),
+            addr: "94.140.14.14".to_string(),
+            hashes: Vec::new(),
+            hostname: "dns.adguard-dns.com".to_string(),
+            bootstrap: Vec::new(),
+        };
+        let entry = ImportEntry {
+            name: Some("AdGuard DoQ".to_string()),
+            description: None,
+            stamps: vec![doq.encode().unwrap()],
+            source: "AdGuard DoQ".to_string(),
+        };
+        let error = entry_to_preset(ImportFormat::PublicResolvers, &entry).unwrap_err();
+        assert!(error.to_string().contains("DNS-over-QUIC"), "{}", error);
+    }
+
+    /// Пресет, который ни с чем не совпадает, кроме того, что в нём изменено
+    fn unrelated(id: &str, server: &str) -> DnsPreset {
+        DnsPreset {
+            servers_ipv4: vec![server.to_string()],
+            doh_template: None,
+            ..test_preset(id, id)
+        }
+    }
+
+    fn reasons(duplicates: &[Duplicate], id: &str) -> Vec<DuplicateReason> {
+        duplicates
+            .iter()
+            .find(|d| d.id == id)
+            .map(|d| d.reasons.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn find_duplicates_reports_every_reason() {
+        let stamp = DnsStamp::Plain {
+            props: Default::default(),
+            addr: "192.0.2.1:5353".to_string(),
+        }
+        .encode()
+        .unwrap();
+        let preset = DnsPreset {
+            servers_ipv6: vec!["2001:db8::1".to_string()],
+            dot_hostname: Some("dot.example".to_string()),
+            stamp: Some(stamp.clone()),
+            ..test_preset("imported", "Example DNS")
+        };
+        let others = vec![
+            DnsPreset {
+                name: " example dns ".to_string(),
+                ..unrelated("name", "198.51.100.1")
+            },
+            // Адреса сравниваются как IP, а не как строки
+            DnsPreset {
+                servers_ipv6: vec!["2001:DB8:0::1".to_string()],
+                ..unrelated("server", "198.51.100.2")
+            },
+            DnsPreset {
+                doh_template: Some("HTTPS://DNS.example/dns-query/".to_string()),
+                ..unrelated("doh", "198.51.100.3")
+            },
+            DnsPreset {
+                dot_hostname: Some("DOT.example.".to_string()),
+                ..unrelated("dot", "198.51.100.4")
+            },
+            DnsPreset {
+                stamp: Some(format!(" {}\n", stamp)),
+                ..unrelated("stamp", "198.51.100.5")
+            },
+            DnsPreset {
+                id: "same".to_string(),
+                ..preset.clone()
+            },
+            DnsPreset {
+                doh_template: Some("https://other.example/dns-query".to_string()),
+                dot_hostname: Some("other.example".to_string()),
+                ..unrelated("other", "198.51.100.6")
+            },
+        ];
+
+        let duplicates = find_duplicates(&preset, &others, DuplicateSource::Custom);
+
+        let ids: Vec<&str> = duplicates.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["name", "server", "doh", "dot", "stamp", "same"]);
+        assert!(duplicates.iter().all(|d| d.source == DuplicateSource::Custom));
+        assert_eq!(duplicates[0].name, " example dns ");
+        assert_eq!(reasons(&duplicates, "server"), [DuplicateReason::Server]);
+        assert_eq!(reasons(&duplicates, "doh"), [DuplicateReason::DohTemplate]);
+        assert_eq!(reasons(&duplicates, "dot"), [DuplicateReason::DotHostname]);
+        assert_eq!(reasons(&duplicates, "stamp"), [DuplicateReason::Stamp]);
+        assert_eq!(
+            reasons(&duplicates, "same"),
+            [
+                DuplicateReason::Name,
+                DuplicateReason::Server,
+                DuplicateReason::DohTemplate,
+                DuplicateReason::DotHostname,
+                DuplicateReason::Stamp,
+            ]
+        );
+    }
+
+    #[test]
+    fn dnscrypt_proxy_static_servers_are_previewed_with_duplicates_from_every_source() {
+        let _dir = config::use_temp_app_dir();
+        let router = DnsStamp::Plain {
+            props: Default::default(),
+            addr: "192.0.2.53".to_string(),
+        }
+        .encode()
+        .unwrap();
+        let router_doh = DnsStamp::DoH {
+            props: Default::default(),
+            addr: "192.0.2.53".to_string(),
+            hashes: Vec::new(),
+            hostname: "router.example".to_string(),
+            path: "/dns-query".to_string(),
+            bootstrap: Vec::new(),
+        }
+        .encode()
+        .unwrap();
+        CustomPresetsManager::add_custom_preset(DnsPreset {
+            servers_ipv4: vec!["192.0.2.53".to_string()],
+            ..test_preset("home", "Home-Router")
+        })
+        .unwrap();
+
+        // Фрагмент настоящего dnscrypt-proxy.toml: всё, кроме [static], пропускается
+        let content = format!(
+            "listen_addresses = ['127.0.0.1:53']\n\
+             server_names = ['cloudflare-copy', 'home-router']\n\
+             \n\
+             [sources.public-resolvers]\n\
+             urls = ['https://download.dnscrypt.info/resolvers-list/v3/public-resolvers.md']\n\
+             cache_file = 'public-resolvers.md'\n\
+             \n\
+             [static]\n\
+             \x20 [static.'cloudflare-copy']\n\
+             \x20 stamp = '{}'\n\
+             \n\
+             \x20 [static.'home-router']\n\
+             \x20 stamp = '{}'\n\
+             \n\
+             \x20 [static.'home-router-doh']\n\
+             \x20 stamp = '{}'\n\
+             \n\
+             \x20 [static.'no-stamp']\n\
+             \x20 addr = '192.0.2.54'\n",
+            CLOUDFLARE_DOH, router, router_doh
+        );
+
+        let result = preview(ImportFormat::DnscryptProxy, &content).unwrap();
+
+        let ids: Vec<&str> = result.candidates.iter().map(|c| c.preset.id.as_str()).collect();
+        assert_eq!(ids, ["dnscrypt-cloudflare-copy", "dnscrypt-home-router", "dnscrypt-home-router-doh"]);
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(result.skipped[0].entry, "no-stamp");
+
+        let cloudflare = &result.candidates[0].duplicates;
+        assert!(cloudflare.iter().all(|d| d.source == DuplicateSource::BuiltIn));
+        assert_eq!(reasons(cloudflare, "cloudflare"), [DuplicateReason::Server]);
+
+        let home = &result.candidates[1].duplicates;
+        assert_eq!(home.len(), 1);
+        assert_eq!(home[0].source, DuplicateSource::Custom);
+        assert_eq!(home[0].reasons, [DuplicateReason::Name, DuplicateReason::Server]);
+
+        let home_doh = &result.candidates[2].duplicates;
+        let sources: Vec<(DuplicateSource, &str)> = home_doh.iter().map(|d| (d.source, d.id.as_str())).collect();
+        assert_eq!(
+            sources,
+            [(DuplicateSource::Custom, "home"), (DuplicateSource::Import, "dnscrypt-home-router")]
+        );
+
+        assert!(preview(ImportFormat::DnscryptProxy, "server_names = []\n").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::config;
+use crate::proxy::find_preset;
+use crate::types::{DnsPreset, PresetParameter};
+use crate::validation;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+const PARAMETERS_FILE: &str = "preset_parameters.json";
+
+/// Значения параметров одного пресета по имени параметра
+pub type ParameterValues = BTreeMap<String, String>;
+
+/// Значения параметров пресетов, заданные пользователем
+pub struct PresetParameters;
+
+impl PresetParameters {
+    fn load() -> Result<BTreeMap<String, ParameterValues>> {
+        config::load_json(PARAMETERS_FILE)
+    }
+
+    pub fn get(preset_id: &str) -> Result<ParameterValues> {
+        Ok(Self::load()?.remove(preset_id).unwrap_or_default())
+    }
+
+    /// Сохранить значения, проверив их по описанию параметров пресета
+    pub fn set(preset_id: &str, values: ParameterValues) -> Result<()> {
+        let preset = find_preset(preset_id).with_context(|| format!("Preset {} not found", preset_id))?;
+
+        for (name, value) in &values {
+            let parameter = preset
+                .parameters
+                .iter()
+                .find(|p| &p.name == name)
+                .with_context(|| format!("Preset {} has no parameter '{}'", preset.name, name))?;
+            check_value(parameter, value)?;
+        }
+
+        let mut all = Self::load()?;
+        if values.is_empty() {
+            all.remove(preset_id);
+        } else {
+            all.insert(preset_id.to_string(), values);
+        }
+        config::save_json(PARAMETERS_FILE, &all)
+    }
+
+    /// Пресет с подставленными значениями параметров
+    pub fn resolve(preset: &DnsPreset) -> Result<DnsPreset> {
+        let mut resolved = preset.clone();
+        if preset.parameters.is_empty() {
+            return Ok(resolved);
+        }
+
+        let values = Self::get(&preset.id)?;
+        resolved.doh_template = preset
+            .doh_template
+            .as_deref()
+            .map(|template| substitute_doh_template(preset, template, &values))
+            .transpose()?;
+        resolved.dot_hostname = preset
+            .dot_hostname
+            .as_deref()
+            .map(|hostname| substitute_dot_hostname(preset, hostname, &values))
+            .transpose()?;
+        Ok(resolved)
+    }
+
+    /// Подставить параметры пресета `preset_id` в адреса, пришедшие от интерфейса
+    pub fn apply(
+        preset_id: &str,
+        doh_template: Option<String>,
+        dot_hostname: Option<String>,
+    ) -> Result<(Option<String>, Option<String>)> {
+        let Some(preset) = find_preset(preset_id).filter(|p| !p.parameters.is_empty()) else {
+            return Ok((doh_template, dot_hostname));
+        };
+
+        let values = Self::get(preset_id)?;
+        let doh_template = doh_template
+            .map(|t| substitute_doh_template(&preset, &t, &values))
+            .transpose()?;
+        let dot_hostname = dot_hostname
+            .map(|h| substitute_dot_hostname(&preset, &h, &values))
+            .transpose()?;
+        Ok((doh_template, dot_hostname))
+    }
+}
+
+/// Значение должно целиком соответствовать шаблону параметра
+pub fn check_value(parameter: &PresetParameter, value: &str) -> Result<()> {
+    let pattern = Regex::new(&format!("^(?:{})$", parameter.pattern))
+        .with_context(|| format!("Invalid pattern for parameter '{}'", parameter.name))?;
+    if !pattern.is_match(value) {
+        anyhow::bail!("'{}' is not a valid {}", value, parameter.label);
+    }
+    Ok(())
+}
+
+/// Шаблон DoH с подставленными параметрами. Значения кодируются как часть URL,
+/// а результат проверяется заново, так что значение не может подменить хост или путь
+pub fn substitute_doh_template(preset: &DnsPreset, template: &str, values: &ParameterValues) -> Result<String> {
+    let url = substitute(preset, template, values, percent_encode)?;
+    validation::check_doh_template(&url, &[])
+        .map_err(|e| anyhow::anyhow!("{} gives an invalid DoH template: {}", preset.name, e))?;
+    Ok(url)
+}
+
+/// Имя DoT с подставленными параметрами; результат должен остаться именем хоста
+pub fn substitute_dot_hostname(preset: &DnsPreset, hostname: &str, values: &ParameterValues) -> Result<String> {
+    let hostname = substitute(preset, hostname, values, str::to_string)?;
+    if !validation::is_hostname(&hostname) {
+        anyhow::bail!("{} gives an invalid DoT hostname '{}'", preset.name, hostname);
+    }
+    Ok(hostname)
+}
+
+/// Заменить `{name}` значениями; у каждого использованного параметра должно быть значение
+fn substitute(
+    preset: &DnsPreset,
+    template: &str,
+    values: &ParameterValues,
+    encode: fn(&str) -> String,
+) -> Result<String> {
+    let mut result = template.to_string();
+    for parameter in &preset.parameters {
+        let placeholder = format!("{{{}}}", parameter.name);
+        if !result.contains(&placeholder) {
+            continue;
+        }
+        let value = values
+            .get(&parameter.name)
+            .with_context(|| format!("Set {} for {} before applying it", parameter.label, preset.name))?;
+        check_value(parameter, value)?;
+        result = result.replace(&placeholder, &encode(value));
+    }
+    Ok(result)
+}
+
+/// Процентное кодирование всего, кроме незарезервированных символов RFC 3986
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::test_preset;
+
+    /// Пресет с параметром `id`, шаблон которого пропускает любое значение
+    fn preset(doh_template: &str, dot_hostname: &str) -> DnsPreset {
+        DnsPreset {
+            servers_ipv4: Vec::new(),
+            doh_template: Some(doh_template.to_string()),
+            dot_hostname: Some(dot_hostname.to_string()),
+            supports_dot: true,
+            parameters: vec![PresetParameter {
+                name: "id".to_string(),
+                label: "Profile ID".to_string(),
+                description: String::new(),
+                pattern: ".+".to_string(),
+                example: None,
+            }],
+            ..test_preset("profile", "Profile DNS")
+        }
+    }
+
+    fn values(id: &str) -> ParameterValues {
+        [("id".to_string(), id.to_string())].into()
+    }
+
+    #[test]
+    fn doh_values_are_percent_encoded() {
+        let preset = preset("https://dns.example/{id}{?dns}", "{id}.dns.example");
+
+        let url = substitute_doh_template(&preset, preset.doh_template.as_deref().unwrap(), &values("a/b?c#d")).unwrap();
+        assert_eq!(url, "https://dns.example/a%2Fb%3Fc%23d{?dns}");
+    }
+
+    #[test]
+    fn values_that_break_the_address_are_rejected() {
+        let preset = preset("https://{id}.dns.example/dns-query", "{id}.dns.example");
+
+        // `#` в имени хоста делает URL недействительным
+        assert!(substitute_doh_template(&preset, preset.doh_template.as_deref().unwrap(), &values("evil#")).is_err());
+        assert!(substitute_dot_hostname(&preset, preset.dot_hostname.as_deref().unwrap(), &values("evil.com/x")).is_err());
+
+        let hostname = substitute_dot_hostname(&preset, preset.dot_hostname.as_deref().unwrap(), &values("abc123")).unwrap();
+        assert_eq!(hostname, "abc123.dns.example");
+    }
+
+    #[test]
+    fn missing_value_names_the_parameter() {
+        let preset = preset("https://dns.example/{id}", "{id}.dns.example");
+
+        let err = substitute_doh_template(&preset, preset.doh_template.as_deref().unwrap(), &ParameterValues::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Profile ID"), "{}", err);
+    }
+}
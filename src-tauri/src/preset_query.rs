@@ -0,0 +1,397 @@
+use crate::custom_presets::CustomPresetsManager;
+use crate::presets;
+use crate::stamp::{self, DnsStamp};
+use crate::types::{DnsPreset, FilteringType, LoggingPolicy, Protocol};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Страны Евросоюза; в запросе их заменяет код `EU`
+const EU_COUNTRIES: [&str; 27] = [
+    "AT", "BE", "BG", "CY", "CZ", "DE", "DK", "EE", "ES", "FI", "FR", "GR", "HR", "HU", "IE", "IT", "LT", "LU", "LV",
+    "MT", "NL", "PL", "PT", "RO", "SE", "SI", "SK",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresetSource {
+    #[default]
+    All,
+    BuiltIn,
+    Custom,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresetSort {
+    #[default]
+    Name,
+    Category,
+    /// Сначала без журналов, без ECS и с проверкой DNSSEC
+    Privacy,
+    Jurisdiction,
+}
+
+/// Отбор пресетов по возможностям. Пустой список или `None` — без ограничения;
+/// пресет с неизвестным значением под заданное условие не попадает.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetQuery {
+    /// Подстрока id, имени или описания
+    pub text: Option<String>,
+    pub source: PresetSource,
+    /// Любая из категорий
+    pub categories: Vec<String>,
+    /// Все перечисленные протоколы
+    pub protocols: Vec<Protocol>,
+    /// Любая из политик
+    pub logging: Vec<LoggingPolicy>,
+    pub dnssec: Option<bool>,
+    pub ecs: Option<bool>,
+    /// Любой из видов фильтрации
+    pub filtering: Vec<FilteringType>,
+    /// Любая из стран; `EU` — любая страна Евросоюза
+    pub jurisdictions: Vec<String>,
+    pub anycast: Option<bool>,
+    pub sort: PresetSort,
+    pub descending: bool,
+}
+
+impl PresetQuery {
+    /// Отобрать и упорядочить встроенные и пользовательские пресеты
+    pub fn run(&self) -> Result<Vec<DnsPreset>> {
+        let mut presets = Vec::new();
+        if self.source != PresetSource::Custom {
+            presets.extend(presets::get_default_presets());
+        }
+        if self.source != PresetSource::BuiltIn {
+            presets.extend(CustomPresetsManager::load_custom_presets()?);
+        }
+        Ok(self.apply(presets))
+    }
+
+    pub fn apply(&self, presets: Vec<DnsPreset>) -> Vec<DnsPreset> {
+        let mut presets: Vec<DnsPreset> = presets.into_iter().filter(|preset| self.matches(preset)).collect();
+        presets.sort_by(|a, b| {
+            let order = match self.sort {
+                PresetSort::Name => Ordering::Equal,
+                PresetSort::Category => a.category.cmp(&b.category),
+                PresetSort::Privacy => privacy_rank(a).cmp(&privacy_rank(b)),
+                PresetSort::Jurisdiction => {
+                    // Без страны — в конце
+                    let key = |p: &DnsPreset| (p.capabilities.jurisdiction.is_none(), p.capabilities.jurisdiction.clone());
+                    key(a).cmp(&key(b))
+                }
+            };
+            order.then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        if self.descending {
+            presets.reverse();
+        }
+        presets
+    }
+
+    pub fn matches(&self, preset: &DnsPreset) -> bool {
+        let capabilities = &preset.capabilities;
+
+        if let Some(text) = self.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+            let text = text.to_lowercase();
+            if ![&preset.id, &preset.name, &preset.description]
+                .iter()
+                .any(|field| field.to_lowercase().contains(&text))
+            {
+                return false;
+            }
+        }
+        if !self.categories.is_empty() && !self.categories.contains(&preset.category) {
+            return false;
+        }
+        if !self.protocols.is_empty() {
+            let supported = protocols(preset);
+            if !self.protocols.iter().all(|protocol| supported.contains(protocol)) {
+                return false;
+            }
+        }
+        if !self.logging.is_empty() && !self.logging.contains(&capabilities.logging) {
+            return false;
+        }
+        if !self.filtering.is_empty() && !self.filtering.contains(&capabilities.filtering) {
+            return false;
+        }
+        let flags = [
+            (self.dnssec, capabilities.dnssec),
+            (self.ecs, capabilities.ecs),
+            (self.anycast, capabilities.anycast),
+        ];
+        if flags.iter().any(|(wanted, actual)| wanted.is_some() && wanted != actual) {
+            return false;
+        }
+        if !self.jurisdictions.is_empty() {
+            let Some(country) = capabilities.jurisdiction.as_deref() else {
+                return false;
+            };
+            let in_eu = EU_COUNTRIES.contains(&country);
+            if !self
+                .jurisdictions
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(country) || (in_eu && wanted.eq_ignore_ascii_case("EU")))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Протоколы пресета: по адресам, DoH/DoT-настройкам и стампу
+pub fn protocols(preset: &DnsPreset) -> Vec<Protocol> {
+    let mut protocols = Vec::new();
+    let mut add = |protocol| {
+        if !protocols.contains(&protocol) {
+            protocols.push(protocol);
+        }
+    };
+
+    if !preset.servers_ipv4.is_empty() || !preset.servers_ipv6.is_empty() {
+        add(Protocol::Do53);
+    }
+    if preset.supports_doh {
+        add(Protocol::Doh);
+    }
+    if preset.supports_dot {
+        add(Protocol::Dot);
+    }
+    if let Some(Ok(stamp)) = preset.stamp.as_deref().filter(|s| stamp::is_stamp(s)).map(DnsStamp::parse) {
+        match stamp {
+            DnsStamp::Plain { .. } => add(Protocol::Do53),
+            DnsStamp::DnsCrypt { .. } => add(Protocol::Dnscrypt),
+            DnsStamp::DoH { .. } => add(Protocol::Doh),
+            DnsStamp::DoT { .. } => add(Protocol::Dot),
+            DnsStamp::DoQ { .. } => add(Protocol::Doq),
+            DnsStamp::ODoHTarget { .. } => add(Protocol::Odoh),
+            DnsStamp::DnsCryptRelay { .. } | DnsStamp::ODoHRelay { .. } => {}
+        }
+    }
+    protocols
+}
+
+/// Меньше — приватнее: журналы, затем ECS, затем DNSSEC
+fn privacy_rank(preset: &DnsPreset) -> (u8, u8, u8) {
+    let capabilities = &preset.capabilities;
+    let logging = match capabilities.logging {
+        LoggingPolicy::None => 0,
+        LoggingPolicy::Anonymized => 1,
+        LoggingPolicy::Temporary => 2,
+        LoggingPolicy::Unknown => 3,
+        LoggingPolicy::Full => 4,
+    };
+    let ecs = match capabilities.ecs {
+        Some(false) => 0,
+        None => 1,
+        Some(true) => 2,
+    };
+    let dnssec = match capabilities.dnssec {
+        Some(true) => 0,
+        None => 1,
+        Some(false) => 2,
+    };
+    (logging, ecs, dnssec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{test_preset, PresetCapabilities};
+
+    fn preset(id: &str, capabilities: PresetCapabilities) -> DnsPreset {
+        DnsPreset {
+            capabilities,
+            ..test_preset(id, &id.to_uppercase())
+        }
+    }
+
+    fn country(code: &str) -> PresetCapabilities {
+        PresetCapabilities {
+            jurisdiction: Some(code.to_string()),
+            ..PresetCapabilities::default()
+        }
+    }
+
+    fn ids(presets: &[DnsPreset]) -> Vec<&str> {
+        presets.iter().map(|p| p.id.as_str()).collect()
+    }
+
+    #[test]
+    fn eu_matches_any_member_state() {
+        let presets = vec![
+            preset("de", country("DE")),
+            preset("se", country("SE")),
+            preset("ch", country("CH")),
+            preset("us", country("US")),
+            preset("unknown", PresetCapabilities::default()),
+        ];
+        let query = |jurisdictions: &[&str]| PresetQuery {
+            jurisdictions: jurisdictions.iter().map(|c| c.to_string()).collect(),
+            ..PresetQuery::default()
+        };
+
+        assert_eq!(ids(&query(&["EU"]).apply(presets.clone())), ["de", "se"]);
+        assert_eq!(ids(&query(&["eu", "ch"]).apply(presets.clone())), ["ch", "de", "se"]);
+        assert_eq!(ids(&query(&["DE"]).apply(presets.clone())), ["de"]);
+        assert_eq!(query(&[]).apply(presets).len(), 5);
+    }
+
+    #[test]
+    fn every_listed_protocol_is_required() {
+        let plain = DnsPreset {
+            doh_template: None,
+            supports_doh: false,
+            ..test_preset("plain", "Plain")
+        };
+        let doh = test_preset("doh", "DoH");
+        let both = DnsPreset {
+            dot_hostname: Some("dns.example".to_string()),
+            supports_dot: true,
+            ..test_preset("both", "Both")
+        };
+        let dnscrypt = DnsPreset {
+            servers_ipv4: Vec::new(),
+            doh_template: None,
+            supports_doh: false,
+            stamp: Some(
+                DnsStamp::DnsCrypt {
+                    props: Default::default(),
+                    addr: "192.0.2.1".to_string(),
+                    public_key: [1; 32],
+                    provider_name: "2.dnscrypt-cert.example".to_string(),
+                }
+                .encode()
+                .unwrap(),
+            ),
+            ..test_preset("dnscrypt", "DNSCrypt")
+        };
+        assert_eq!(protocols(&both), [Protocol::Do53, Protocol::Doh, Protocol::Dot]);
+        assert_eq!(protocols(&dnscrypt), [Protocol::Dnscrypt]);
+
+        let presets = vec![plain, doh, both, dnscrypt];
+        let query = |protocols: Vec<Protocol>| PresetQuery {
+            protocols,
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&query(vec![Protocol::Doh]).apply(presets.clone())), ["both", "doh"]);
+        assert_eq!(ids(&query(vec![Protocol::Doh, Protocol::Dot]).apply(presets.clone())), ["both"]);
+        assert_eq!(ids(&query(vec![Protocol::Do53]).apply(presets.clone())), ["both", "doh", "plain"]);
+        assert_eq!(ids(&query(vec![Protocol::Dnscrypt]).apply(presets)), ["dnscrypt"]);
+    }
+
+    #[test]
+    fn flags_require_a_known_matching_value() {
+        let presets = vec![
+            preset(
+                "secure",
+                PresetCapabilities {
+                    dnssec: Some(true),
+                    ecs: Some(false),
+                    anycast: Some(true),
+                    ..PresetCapabilities::default()
+                },
+            ),
+            preset(
+                "insecure",
+                PresetCapabilities {
+                    dnssec: Some(false),
+                    ecs: Some(true),
+                    ..PresetCapabilities::default()
+                },
+            ),
+            preset("unknown", PresetCapabilities::default()),
+        ];
+
+        let dnssec = PresetQuery {
+            dnssec: Some(true),
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&dnssec.apply(presets.clone())), ["secure"]);
+        let no_dnssec = PresetQuery {
+            dnssec: Some(false),
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&no_dnssec.apply(presets.clone())), ["insecure"]);
+        let combined = PresetQuery {
+            ecs: Some(false),
+            anycast: Some(false),
+            ..PresetQuery::default()
+        };
+        assert!(combined.apply(presets.clone()).is_empty());
+
+        let text = PresetQuery {
+            text: Some("  SEC ".to_string()),
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&text.apply(presets)), ["insecure", "secure"]);
+    }
+
+    #[test]
+    fn privacy_sort_puts_no_logs_first_then_ecs_then_dnssec() {
+        let with = |logging, ecs, dnssec| PresetCapabilities {
+            logging,
+            ecs,
+            dnssec,
+            ..PresetCapabilities::default()
+        };
+        let presets = vec![
+            preset("full", with(LoggingPolicy::Full, Some(false), Some(true))),
+            preset("unknown", with(LoggingPolicy::Unknown, None, None)),
+            preset("temporary", with(LoggingPolicy::Temporary, Some(false), Some(true))),
+            preset("none-ecs", with(LoggingPolicy::None, Some(true), Some(true))),
+            preset("anonymized", with(LoggingPolicy::Anonymized, Some(false), Some(true))),
+            preset("none-unsigned", with(LoggingPolicy::None, Some(false), Some(false))),
+            preset("none-best", with(LoggingPolicy::None, Some(false), Some(true))),
+        ];
+        let mut query = PresetQuery {
+            sort: PresetSort::Privacy,
+            ..PresetQuery::default()
+        };
+
+        let expected = [
+            "none-best",
+            "none-unsigned",
+            "none-ecs",
+            "anonymized",
+            "temporary",
+            "unknown",
+            "full",
+        ];
+        assert_eq!(ids(&query.apply(presets.clone())), expected);
+
+        query.descending = true;
+        let mut reversed = expected;
+        reversed.reverse();
+        assert_eq!(ids(&query.apply(presets)), reversed);
+    }
+
+    #[test]
+    fn name_and_jurisdiction_sorts_can_be_descending() {
+        let presets = vec![
+            preset("b", country("CH")),
+            preset("c", PresetCapabilities::default()),
+            preset("a", country("US")),
+        ];
+
+        assert_eq!(ids(&PresetQuery::default().apply(presets.clone())), ["a", "b", "c"]);
+        let descending = PresetQuery {
+            descending: true,
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&descending.apply(presets.clone())), ["c", "b", "a"]);
+
+        // Без страны — в конце, при обратном порядке — в начале
+        let mut by_country = PresetQuery {
+            sort: PresetSort::Jurisdiction,
+            ..PresetQuery::default()
+        };
+        assert_eq!(ids(&by_country.apply(presets.clone())), ["b", "a", "c"]);
+        by_country.descending = true;
+        assert_eq!(ids(&by_country.apply(presets)), ["c", "a", "b"]);
+    }
+}
@@ -0,0 +1,497 @@
+use crate::custom_presets::CustomPresetsManager;
+use crate::preset_params::PresetParameters;
+use crate::presets;
+use crate::proxy::strip_uri_template;
+use crate::stamp::{self, DnsStamp, StampProps};
+use crate::types::{DnsPreset, FilteringType, LoggingPolicy, PresetCapabilities};
+use anyhow::{Context, Result};
+use log::info;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// Строка списка стампов, которую не удалось импортировать
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StampImportError {
+    /// Номер строки, начиная с 1
+    pub line: usize,
+    pub stamp: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StampImport {
+    pub imported: Vec<DnsPreset>,
+    pub errors: Vec<StampImportError>,
+}
+
+/// Стампы пресета: по одному на каждый адрес и протокол. Сохранённый в пресете
+/// стамп идёт первым и заменяет собой сгенерированный стамп того же протокола
+/// (в нём могут быть хэши сертификатов).
+pub fn to_stamps(preset: &DnsPreset) -> Result<Vec<String>> {
+    let preset = PresetParameters::resolve(preset)?;
+    let props = props_of(&preset.capabilities);
+    let mut stamps = Vec::new();
+
+    let own = match preset.stamp.as_deref().filter(|s| stamp::is_stamp(s)) {
+        Some(own) => {
+            stamps.push(own.trim().to_string());
+            Some(DnsStamp::parse(own)?)
+        }
+        None => None,
+    };
+
+    for server in preset.servers_ipv4.iter().chain(&preset.servers_ipv6) {
+        let ip: IpAddr = server.trim().parse().with_context(|| format!("'{}' is not an IP address", server))?;
+        let addr = if ip.is_ipv6() { format!("[{}]", ip) } else { ip.to_string() };
+        stamps.push(DnsStamp::Plain { props, addr }.encode()?);
+    }
+
+    // Адрес сервера в стампе не указываем: имя разрешается через обычные DNS пресета
+    let bootstrap: Vec<String> = preset.servers_ipv4.iter().map(|s| s.trim().to_string()).collect();
+    if let Some(template) = preset.doh_template.as_deref() {
+        if !matches!(own, Some(DnsStamp::DoH { .. })) {
+            let url = Url::parse(&strip_uri_template(template))
+                .with_context(|| format!("'{}' is not a valid DoH template", template))?;
+            let mut hostname = url.host_str().context("DoH template has no host")?.to_string();
+            if let Some(port) = url.port() {
+                hostname = format!("{}:{}", hostname, port);
+            }
+            let mut path = url.path().to_string();
+            if let Some(query) = url.query() {
+                path = format!("{}?{}", path, query);
+            }
+            stamps.push(
+                DnsStamp::DoH {
+                    props,
+                    addr: String::new(),
+                    hashes: Vec::new(),
+                    hostname,
+                    path,
+                    bootstrap: bootstrap.clone(),
+                }
+                .encode()?,
+            );
+        }
+    }
+    if let Some(hostname) = preset.dot_hostname.as_deref() {
+        if !matches!(own, Some(DnsStamp::DoT { .. })) {
+            stamps.push(
+                DnsStamp::DoT {
+                    props,
+                    addr: String::new(),
+                    hashes: Vec::new(),
+                    hostname: hostname.to_string(),
+                    bootstrap,
+                }
+                .encode()?,
+            );
+        }
+    }
+
+    Ok(stamps)
+}
+
+/// Пресет из стампа. Обычные адреса, DoH-шаблон и DoT-хост заполняются из
+/// стампа; сам стамп сохраняется, если без него что-то потеряется: хэши,
+/// нестандартный порт, DNSCrypt/ODoH или отсутствие IPv4-адреса. Стампы,
+/// которые прокси обслужить не может (DoQ, релеи), отклоняются.
+pub fn from_stamp(value: &str, name: Option<&str>) -> Result<DnsPreset> {
+    let parsed = DnsStamp::parse(value)?;
+    parsed.check_upstream()?;
+    let props = parsed.props();
+
+    let mut preset = DnsPreset {
+        id: String::new(),
+        name: String::new(),
+        description: "Imported from a DNS stamp".to_string(),
+        category: "custom".to_string(),
+        servers_ipv4: Vec::new(),
+        servers_ipv6: Vec::new(),
+        doh_template: None,
+        dot_hostname: None,
+        supports_doh: false,
+        supports_dot: false,
+        icon: "⚙️".to_string(),
+        color: String::new(),
+        website: None,
+        stamp: None,
+        verification: None,
+        capabilities: PresetCapabilities {
+            dnssec: props.dnssec.then_some(true),
+            logging: if props.no_logs { LoggingPolicy::None } else { LoggingPolicy::Unknown },
+            filtering: if props.no_filter { FilteringType::None } else { FilteringType::Unknown },
+            ..PresetCapabilities::default()
+        },
+        parameters: Vec::new(),
+    };
+
+    let (addr, hostname, keep_stamp, default_port) = match &parsed {
+        DnsStamp::Plain { addr, .. } => (addr.as_str(), None, false, 53),
+        DnsStamp::DoH { addr, hashes, hostname, path, .. } => {
+            preset.doh_template = Some(format!("https://{}{}", hostname, path));
+            (addr.as_str(), Some(hostname.as_str()), !hashes.is_empty(), 443)
+        }
+        DnsStamp::DoT { addr, hashes, hostname, .. } => {
+            let (host, port) = split_host_port(hostname);
+            preset.dot_hostname = Some(host.to_string());
+            (addr.as_str(), Some(host), !hashes.is_empty() || port.is_some(), 853)
+        }
+        DnsStamp::DnsCrypt { addr, provider_name, .. } => (addr.as_str(), Some(provider_name.as_str()), true, 443),
+        DnsStamp::ODoHTarget { hostname, .. } => ("", Some(hostname.as_str()), true, 443),
+        // Отклонены check_upstream выше
+        DnsStamp::DoQ { .. } | DnsStamp::DnsCryptRelay { .. } | DnsStamp::ODoHRelay { .. } => {
+            anyhow::bail!("This kind of DNS stamp can't be used as a preset")
+        }
+    };
+
+    let address = parse_addr(addr).with_context(|| format!("'{}' is not a valid server address", addr))?;
+    // DNSCrypt-серверы не отвечают на обычные запросы — в обычные DNS их адреса не берём
+    let answers_plain = matches!(parsed, DnsStamp::Plain { .. } | DnsStamp::DoH { .. } | DnsStamp::DoT { .. });
+    let mut keep_stamp = keep_stamp;
+    if let Some((ip, port)) = address.filter(|_| answers_plain) {
+        if port.is_some_and(|port| port != default_port) {
+            keep_stamp = true;
+        }
+        if ip.is_ipv6() {
+            preset.servers_ipv6.push(ip.to_string());
+        } else {
+            preset.servers_ipv4.push(ip.to_string());
+        }
+    }
+    if keep_stamp || preset.servers_ipv4.is_empty() {
+        preset.stamp = Some(parsed.encode()?);
+    }
+    preset.supports_doh = preset.doh_template.is_some();
+    preset.supports_dot = preset.dot_hostname.is_some();
+
+    let fallback = hostname
+        .map(str::to_string)
+        .or_else(|| address.map(|(ip, _)| ip.to_string()))
+        .unwrap_or_else(|| "DNS stamp".to_string());
+    preset.name = name.map(str::trim).filter(|n| !n.is_empty()).map(str::to_string).unwrap_or(fallback);
+    preset.id = format!("stamp-{}", slug(&preset.name));
+    Ok(preset)
+}
+
+/// Импорт стампов в пользовательские пресеты. Текст — один или несколько
+/// стампов либо список: по строке на стамп, перед стампом может стоять имя,
+/// строки с `#` пропускаются.
+pub fn import(text: &str) -> Result<StampImport> {
+    let mut existing: Vec<String> = presets::get_default_presets().into_iter().map(|p| p.id).collect();
+    existing.extend(CustomPresetsManager::load_custom_presets()?.into_iter().map(|p| p.id));
+
+    let mut result = StampImport::default();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let Some(start) = line.find("sdns://") else {
+            continue;
+        };
+        let stamps: Vec<&str> = line[start..].split_whitespace().filter(|s| stamp::is_stamp(s)).collect();
+        // Имя берём, только если в строке один стамп
+        let name = line[..start].trim().trim_end_matches(['=', ':']).trim().trim_matches(['"', '\'']);
+        let name = (stamps.len() == 1).then_some(name);
+
+        for value in stamps {
+            let imported = from_stamp(value, name).and_then(|mut preset| {
+                preset.id = unique_id(&preset.id, &existing);
+                CustomPresetsManager::add_custom_preset(preset.clone())?;
+                Ok(preset)
+            });
+            match imported {
+                Ok(preset) => {
+                    existing.push(preset.id.clone());
+                    result.imported.push(preset);
+                }
+                Err(e) => result.errors.push(StampImportError {
+                    line: index + 1,
+                    stamp: value.to_string(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    if result.imported.is_empty() && result.errors.is_empty() {
+        anyhow::bail!("No DNS stamps (sdns://…) found");
+    }
+    info!("📥 Imported {} presets from DNS stamps, {} failed", result.imported.len(), result.errors.len());
+    Ok(result)
+}
+
+pub fn import_file(path: &Path) -> Result<StampImport> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    import(&text)
+}
+
+/// Адрес из стампа: `ip`, `ip:port`, `[ipv6]` или `[ipv6]:port`; пустой — нет адреса
+fn parse_addr(addr: &str) -> Result<Option<(IpAddr, Option<u16>)>> {
+    let addr = addr.trim();
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(socket) = addr.parse::<SocketAddr>() {
+        return Ok(Some((socket.ip(), Some(socket.port()))));
+    }
+    let ip = addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()?;
+    Ok(Some((ip, None)))
+}
+
+fn split_host_port(hostname: &str) -> (&str, Option<&str>) {
+    match hostname.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => (host, Some(port)),
+        _ => (hostname, None),
+    }
+}
+
+fn props_of(capabilities: &PresetCapabilities) -> StampProps {
+    StampProps {
+        dnssec: capabilities.dnssec == Some(true),
+        no_logs: capabilities.logging == LoggingPolicy::None,
+        no_filter: capabilities.filtering == FilteringType::None,
+    }
+}
+
+pub fn slug(name: &str) -> String {
+    let slug: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+    if slug.is_empty() {
+        "preset".to_string()
+    } else {
+        slug
+    }
+}
+
+pub fn unique_id(id: &str, existing: &[String]) -> String {
+    let mut candidate = id.to_string();
+    let mut counter = 2;
+    while existing.contains(&candidate) {
+        candidate = format!("{}-{}", id, counter);
+        counter += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::types::test_preset;
+
+    fn props() -> StampProps {
+        StampProps {
+            dnssec: true,
+            no_logs: true,
+            no_filter: false,
+        }
+    }
+
+    fn doh(addr: &str, hashes: Vec<Vec<u8>>, hostname: &str) -> DnsStamp {
+        DnsStamp::DoH {
+            props: props(),
+            addr: addr.to_string(),
+            hashes,
+            hostname: hostname.to_string(),
+            path: "/dns-query".to_string(),
+            bootstrap: Vec::new(),
+        }
+    }
+
+    fn dot(addr: &str, hostname: &str) -> DnsStamp {
+        DnsStamp::DoT {
+            props: props(),
+            addr: addr.to_string(),
+            hashes: Vec::new(),
+            hostname: hostname.to_string(),
+            bootstrap: Vec::new(),
+        }
+    }
+
+    fn plain(addr: &str) -> String {
+        DnsStamp::Plain {
+            props: props(),
+            addr: addr.to_string(),
+        }
+        .encode()
+        .unwrap()
+    }
+
+    fn parsed(stamps: &[String]) -> Vec<DnsStamp> {
+        stamps.iter().map(|s| DnsStamp::parse(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn to_stamps_covers_every_address_and_protocol() {
+        let mut preset = test_preset("home", "Home");
+        preset.servers_ipv6 = vec!["2001:db8::1".to_string()];
+        preset.doh_template = Some("https://dns.example:8443/dns-query?profile=kids{&dns}".to_string());
+        preset.dot_hostname = Some("dot.example".to_string());
+        preset.capabilities.dnssec = Some(true);
+        preset.capabilities.logging = LoggingPolicy::None;
+
+        let stamps = parsed(&to_stamps(&preset).unwrap());
+
+        assert_eq!(stamps.len(), 4);
+        assert_eq!(stamps[0], DnsStamp::Plain { props: props(), addr: "192.0.2.1".to_string() });
+        // IPv6 в стампе записывается в скобках
+        assert_eq!(stamps[1], DnsStamp::Plain { props: props(), addr: "[2001:db8::1]".to_string() });
+        assert_eq!(
+            stamps[2],
+            DnsStamp::DoH {
+                props: props(),
+                addr: String::new(),
+                hashes: Vec::new(),
+                hostname: "dns.example:8443".to_string(),
+                path: "/dns-query?profile=kids".to_string(),
+                bootstrap: vec!["192.0.2.1".to_string()],
+            }
+        );
+        assert_eq!(
+            stamps[3],
+            DnsStamp::DoT {
+                props: props(),
+                addr: String::new(),
+                hashes: Vec::new(),
+                hostname: "dot.example".to_string(),
+                bootstrap: vec!["192.0.2.1".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn to_stamps_puts_the_stored_stamp_first_instead_of_a_generated_one() {
+        let stored = doh("192.0.2.1", vec![vec![0xab; 32]], "dns.example").encode().unwrap();
+        let mut preset = test_preset("pinned", "Pinned");
+        preset.stamp = Some(format!(" {} ", stored));
+        preset.dot_hostname = Some("dot.example".to_string());
+
+        let stamps = to_stamps(&preset).unwrap();
+
+        assert_eq!(stamps[0], stored);
+        let kinds = parsed(&stamps);
+        assert_eq!(kinds.iter().filter(|s| matches!(s, DnsStamp::DoH { .. })).count(), 1);
+        assert!(matches!(kinds.last(), Some(DnsStamp::DoT { .. })));
+        let bad = DnsPreset {
+            servers_ipv4: vec!["dns.example".to_string()],
+            ..test_preset("bad", "Bad")
+        };
+        assert!(to_stamps(&bad).is_err());
+    }
+
+    #[test]
+    fn from_stamp_keeps_the_stamp_only_when_needed() {
+        // Обычный DNS на стандартном порту полностью описывается адресом
+        let preset = from_stamp(&plain("192.0.2.1"), None).unwrap();
+        assert_eq!(preset.servers_ipv4, ["192.0.2.1"]);
+        assert_eq!(preset.stamp, None);
+        assert_eq!((preset.name.as_str(), preset.id.as_str()), ("192.0.2.1", "stamp-192-0-2-1"));
+        assert_eq!(preset.capabilities.dnssec, Some(true));
+        assert_eq!(preset.capabilities.logging, LoggingPolicy::None);
+        assert_eq!(preset.capabilities.filtering, FilteringType::Unknown);
+
+        // Нестандартный порт теряется без стампа
+        let preset = from_stamp(&plain("192.0.2.1:5353"), None).unwrap();
+        assert!(preset.stamp.is_some());
+        let preset = from_stamp(&plain("[2001:db8::1]:53"), None).unwrap();
+        assert_eq!(preset.servers_ipv6, ["2001:db8::1"]);
+        // Без IPv4-адреса пресет не смог бы работать без стампа
+        assert!(preset.stamp.is_some());
+
+        let stamp = doh("192.0.2.1:443", Vec::new(), "dns.example").encode().unwrap();
+        let preset = from_stamp(&stamp, Some(" My DoH ")).unwrap();
+        assert_eq!(preset.doh_template.as_deref(), Some("https://dns.example/dns-query"));
+        assert!(preset.supports_doh && !preset.supports_dot);
+        assert_eq!(preset.stamp, None);
+        assert_eq!((preset.name.as_str(), preset.id.as_str()), ("My DoH", "stamp-my-doh"));
+
+        let pinned = from_stamp(&doh("192.0.2.1", vec![vec![1; 32]], "dns.example").encode().unwrap(), None).unwrap();
+        assert!(pinned.stamp.is_some());
+        assert_eq!(pinned.name, "dns.example");
+
+        let preset = from_stamp(&dot("192.0.2.1", "dot.example:8853").encode().unwrap(), None).unwrap();
+        assert_eq!(preset.dot_hostname.as_deref(), Some("dot.example"));
+        assert!(preset.stamp.is_some());
+        let preset = from_stamp(&dot("192.0.2.1:853", "dot.example").encode().unwrap(), None).unwrap();
+        assert_eq!(preset.stamp, None);
+    }
+
+    #[test]
+    fn from_stamp_keeps_dnscrypt_addresses_out_of_plain_servers() {
+        let stamp = DnsStamp::DnsCrypt {
+            props: props(),
+            addr: "192.0.2.1:8443".to_string(),
+            public_key: [5; 32],
+            provider_name: "2.dnscrypt-cert.example".to_string(),
+        }
+        .encode()
+        .unwrap();
+
+        let preset = from_stamp(&stamp, None).unwrap();
+        assert!(preset.servers_ipv4.is_empty());
+        assert_eq!(preset.stamp.as_deref(), Some(stamp.as_str()));
+        assert_eq!(preset.name, "2.dnscrypt-cert.example");
+
+        let doq = DnsStamp::DoQ {
+            props: props(),
+            addr: "192.0.2.1".to_string(),
+            hashes: Vec::new(),
+            hostname: "doq.example".to_string(),
+            bootstrap: Vec::new(),
+        };
+        assert!(from_stamp(&doq.encode().unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn import_reads_names_and_reports_bad_lines() {
+        let _dir = config::use_temp_app_dir();
+        let text = format!(
+            "# Resolvers\n\
+             \"Home DNS\" = {}\n\
+             \n\
+             // two on one line, no name\n\
+             {} {}\n\
+             broken: sdns://AAAA\n\
+             Office: {}\n",
+            plain("192.0.2.10"),
+            plain("192.0.2.11"),
+            doh("", Vec::new(), "two.example").encode().unwrap(),
+            plain("192.0.2.12"),
+        );
+
+        let result = import(&text).unwrap();
+
+        let names: Vec<&str> = result.imported.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Home DNS", "192.0.2.11", "two.example", "Office"]);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!((result.errors[0].line, result.errors[0].stamp.as_str()), (6, "sdns://AAAA"));
+        assert_eq!(CustomPresetsManager::load_custom_presets().unwrap().len(), 4);
+
+        // Повторный импорт того же адреса под другим именем получает новый ID
+        let again = import(&format!("Home DNS 2 {}", plain("192.0.2.10"))).unwrap();
+        assert_eq!(again.imported[0].id, "stamp-home-dns-2");
+        // Имя уже занято — ошибка попадает в отчёт с номером строки
+        let again = import(&format!("# again\nOffice {}", plain("192.0.2.13"))).unwrap();
+        assert!(again.imported.is_empty());
+        assert_eq!(again.errors[0].line, 2);
+
+        assert!(import("# nothing here\nplain text").is_err());
+    }
+
+    #[test]
+    fn ids_are_slugged_and_made_unique() {
+        assert_eq!(slug("  My  DNS (Home) "), "my-dns-home");
+        assert_eq!(slug("Ωμέγα"), "preset");
+        let existing = vec!["stamp-a".to_string(), "stamp-a-2".to_string()];
+        assert_eq!(unique_id("stamp-a", &existing), "stamp-a-3");
+        assert_eq!(unique_id("stamp-b", &existing), "stamp-b");
+    }
+}
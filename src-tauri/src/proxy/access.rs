@@ -0,0 +1,349 @@
+use super::rules::find_preset;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+/// Больше клиентов не отслеживаем: полные корзины удаляются, остальные ждут очистки
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanConfig {
+    /// Отвечать устройствам локальной сети, а не только этому компьютеру
+    pub enabled: bool,
+    /// Адреса интерфейсов, на которых прокси слушает для сети (порт общий с прокси)
+    pub listen_addresses: Vec<IpAddr>,
+    /// Сети, клиентам из которых разрешено пользоваться прокси, в нотации CIDR
+    pub allowed_networks: Vec<String>,
+    /// Запросов в секунду от одного клиента сети; 0 — без ограничения
+    pub rate_limit_qps: u32,
+    /// Сколько запросов клиент может отправить залпом сверх среднего темпа
+    pub rate_limit_burst: u32,
+    /// Отвечать REFUSED на запросы ANY из сети — защита от усиления (RFC 8482)
+    pub refuse_any: bool,
+    /// Пресет, через который прокси отвечает сети, пока на него не направлен
+    /// ни один адаптер этого компьютера
+    pub preset_id: Option<String>,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_addresses: Vec::new(),
+            allowed_networks: ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7", "fe80::/10"]
+                .iter()
+                .map(|net| net.to_string())
+                .collect(),
+            rate_limit_qps: 50,
+            rate_limit_burst: 200,
+            refuse_any: true,
+            preset_id: None,
+        }
+    }
+}
+
+impl LanConfig {
+    /// Проверить сети и пресет до сохранения настроек
+    pub fn validate(&self) -> Result<()> {
+        if let Some(id) = &self.preset_id {
+            if find_preset(id).is_none() {
+                anyhow::bail!("Preset {} for LAN clients not found", id);
+            }
+        }
+        self.networks().map(|_| ())
+    }
+
+    fn networks(&self) -> Result<Vec<IpNet>> {
+        self.allowed_networks
+            .iter()
+            .map(|net| {
+                let net = net.trim();
+                // Одиночный адрес допустим как сеть из одного хоста
+                net.parse::<IpNet>()
+                    .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("Invalid network in LAN allowlist: {}", net))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessStats {
+    /// Запросы от клиентов вне разрешённых сетей
+    pub denied: u64,
+    /// Запросы, отброшенные ограничением частоты
+    pub rate_limited: u64,
+    /// Запросы ANY, получившие REFUSED
+    pub refused_any: u64,
+}
+
+/// Решение о запросе клиента
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Запрос с этого компьютера
+    Local,
+    /// Клиент локальной сети из разрешённых сетей
+    Lan,
+    /// Клиент не входит в разрешённые сети
+    Denied,
+    /// Клиент превысил лимит запросов
+    RateLimited,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Доступ клиентов к прокси: loopback разрешён всегда, остальные — только
+/// при включённом доступе из сети, из разрешённых сетей и в пределах лимита
+pub struct AccessControl {
+    config: RwLock<(LanConfig, Vec<IpNet>)>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    denied: AtomicU64,
+    rate_limited: AtomicU64,
+    refused_any: AtomicU64,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        Self {
+            config: RwLock::new((LanConfig::default(), Vec::new())),
+            buckets: Mutex::new(HashMap::new()),
+            denied: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            refused_any: AtomicU64::new(0),
+        }
+    }
+
+    pub fn configure(&self, config: LanConfig) {
+        let networks = config.networks().unwrap_or_default();
+        if let Ok(mut current) = self.config.write() {
+            *current = (config, networks);
+        }
+        if let Ok(mut buckets) = self.buckets.lock() {
+            buckets.clear();
+        }
+    }
+
+    /// Можно ли обслужить запрос клиента
+    pub fn admit(&self, client: IpAddr) -> Admission {
+        let client = canonical(client);
+        if client.is_loopback() {
+            return Admission::Local;
+        }
+
+        let Ok(guard) = self.config.read() else {
+            return Admission::Denied;
+        };
+        let (config, networks) = &*guard;
+        if !config.enabled || !networks.iter().any(|net| net.contains(&client)) {
+            self.denied.fetch_add(1, Ordering::Relaxed);
+            return Admission::Denied;
+        }
+
+        if config.rate_limit_qps > 0 && !self.take_token(client, config) {
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Admission::RateLimited;
+        }
+
+        Admission::Lan
+    }
+
+    /// Нужно ли отказать клиенту сети в запросе ANY
+    pub fn refuses_any(&self) -> bool {
+        self.config.read().map(|c| c.0.refuse_any).unwrap_or(true)
+    }
+
+    pub fn any_refused(&self) {
+        self.refused_any.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> AccessStats {
+        AccessStats {
+            denied: self.denied.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            refused_any: self.refused_any.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Корзина токенов: пополняется на `rate_limit_qps` в секунду до `rate_limit_burst`
+    fn take_token(&self, client: IpAddr, config: &LanConfig) -> bool {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return true;
+        };
+
+        let rate = config.rate_limit_qps as f64;
+        let capacity = config.rate_limit_burst.max(config.rate_limit_qps) as f64;
+        let now = Instant::now();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity);
+            // Все клиенты активны — вытесняем того, кто дольше всех не присылал запросов
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                let oldest = buckets.iter().min_by_key(|(_, bucket)| bucket.updated).map(|(ip, _)| *ip);
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+/// IPv4-адрес клиента, пришедшего на двухстековый сокет как `::ffff:a.b.c.d`
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::proxy::blocklist::Blocklists;
+    use crate::proxy::cache::CacheConfig;
+    use crate::proxy::querylog::QueryLogConfig;
+    use crate::proxy::resolver::ProxyResolver;
+    use hickory_proto::op::{Message, Query, ResponseCode};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn lan(allowed: &[&str], qps: u32, burst: u32) -> AccessControl {
+        let access = AccessControl::new();
+        access.configure(LanConfig {
+            enabled: true,
+            allowed_networks: allowed.iter().map(|net| net.to_string()).collect(),
+            rate_limit_qps: qps,
+            rate_limit_burst: burst,
+            ..LanConfig::default()
+        });
+        access
+    }
+
+    #[test]
+    fn only_allowed_networks_are_admitted() {
+        let access = lan(&["192.168.1.0/24", "10.0.0.5", "fd00::/8"], 0, 0);
+
+        assert_eq!(access.admit(ip("192.168.1.20")), Admission::Lan);
+        assert_eq!(access.admit(ip("10.0.0.5")), Admission::Lan);
+        assert_eq!(access.admit(ip("fd00::1")), Admission::Lan);
+        // Клиент IPv4 на двухстековом сокете
+        assert_eq!(access.admit(ip("::ffff:192.168.1.7")), Admission::Lan);
+        assert_eq!(access.admit(ip("192.168.2.1")), Admission::Denied);
+        assert_eq!(access.admit(ip("10.0.0.6")), Admission::Denied);
+        assert_eq!(access.admit(ip("127.0.0.1")), Admission::Local);
+        assert_eq!(access.stats().denied, 2);
+
+        access.configure(LanConfig {
+            enabled: false,
+            ..LanConfig::default()
+        });
+        assert_eq!(access.admit(ip("192.168.1.20")), Admission::Denied);
+        assert_eq!(access.admit(ip("::1")), Admission::Local);
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        let config = LanConfig {
+            allowed_networks: vec!["192.168.1.0/24".to_string(), "300.1.1.0/8".to_string()],
+            ..LanConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(LanConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let access = lan(&["192.168.0.0/16"], 2, 2);
+        let client = ip("192.168.1.20");
+
+        assert_eq!(access.admit(client), Admission::Lan);
+        assert_eq!(access.admit(client), Admission::Lan);
+        assert_eq!(access.admit(client), Admission::RateLimited);
+        // Другой клиент расходует свою корзину
+        assert_eq!(access.admit(ip("192.168.1.21")), Admission::Lan);
+
+        // Два запроса в секунду: за 600 мс набирается один токен
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(access.admit(client), Admission::Lan);
+        assert_eq!(access.admit(client), Admission::RateLimited);
+        assert_eq!(access.stats().rate_limited, 2);
+    }
+
+    #[test]
+    fn tracked_clients_are_capped() {
+        let access = lan(&["10.0.0.0/8"], 1, 5);
+        let client = |n: usize| IpAddr::from([10, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+
+        for n in 0..MAX_TRACKED_CLIENTS {
+            assert_eq!(access.admit(client(n)), Admission::Lan);
+        }
+        assert_eq!(access.buckets.lock().unwrap().len(), MAX_TRACKED_CLIENTS);
+
+        // Новый клиент вытесняет того, кто дольше всех молчал
+        assert_eq!(access.admit(client(MAX_TRACKED_CLIENTS)), Admission::Lan);
+        let buckets = access.buckets.lock().unwrap();
+        assert!(buckets.len() <= MAX_TRACKED_CLIENTS);
+        assert!(buckets.contains_key(&client(MAX_TRACKED_CLIENTS)));
+        assert!(!buckets.contains_key(&client(0)));
+    }
+
+    #[tokio::test]
+    async fn any_from_lan_is_refused() {
+        let _dir = config::use_temp_app_dir();
+        let resolver = Arc::new(ProxyResolver::new(
+            Duration::from_millis(100),
+            CacheConfig::default(),
+            QueryLogConfig::default(),
+            Arc::new(Blocklists::new()),
+        ));
+        resolver.set_lan(LanConfig {
+            enabled: true,
+            ..LanConfig::default()
+        });
+
+        let mut request = Message::new();
+        request
+            .set_id(0x0a11)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii("example.com.").unwrap(), RecordType::ANY));
+        let packet = request.to_vec().unwrap();
+        let code = |response: Vec<u8>| Message::from_vec(&response).unwrap().response_code();
+
+        let from_lan: SocketAddr = "192.168.1.20:40000".parse().unwrap();
+        assert_eq!(code(resolver.handle(&packet, from_lan).await.unwrap()), ResponseCode::Refused);
+        assert_eq!(resolver.access().stats().refused_any, 1);
+
+        // С этого компьютера ANY пересылается как обычно; апстримов нет — SERVFAIL
+        let local: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        assert_eq!(code(resolver.handle(&packet, local).await.unwrap()), ResponseCode::ServFail);
+        assert_eq!(resolver.access().stats().refused_any, 1);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use hickory_proto::rr::Name;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const SETTINGS_FILE: &str = "blocklists.json";
+const CACHE_DIR: &str = "blocklists";
+/// Как часто планировщик проверяет, не пора ли обновить подписки
+const SCHEDULER_TICK: Duration = Duration::from_secs(600);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Имена из hosts-файлов, которые никогда не блокируются
+const IGNORED_HOSTS: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "0.0.0.0",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockResponse {
+    Nxdomain,
+    /// `0.0.0.0` для A и `::` для AAAA, пустой ответ для остальных типов
+    NullIp,
+    Refused,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocklistSource {
+    pub id: String,
+    pub name: String,
+    /// Local file path or http(s) subscription URL
+    pub location: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub last_updated: Option<DateTime<Utc>>,
+    /// Сколько уникальных доменов блокировки добавил этот список
+    #[serde(default)]
+    pub entries: usize,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl BlocklistSource {
+    fn is_remote(&self) -> bool {
+        self.location.starts_with("http://") || self.location.starts_with("https://")
+    }
+
+    /// Файл, из которого читается список: сам путь или кэш загруженной подписки
+    fn local_path(&self) -> Result<PathBuf> {
+        if self.is_remote() {
+            Ok(cache_dir()?.join(format!("{}.txt", self.id)))
+        } else {
+            Ok(PathBuf::from(self.location.trim_start_matches("file://")))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlocklistSettings {
+    pub enabled: bool,
+    pub response: BlockResponse,
+    pub refresh_interval_hours: u32,
+    pub sources: Vec<BlocklistSource>,
+    /// Домены (с поддоменами), которые не блокируются никогда
+    pub allowlist: Vec<String>,
+}
+
+impl Default for BlocklistSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            response: BlockResponse::Nxdomain,
+            refresh_interval_hours: 24,
+            sources: Vec::new(),
+            allowlist: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocklistStatus {
+    pub enabled: bool,
+    pub domains: usize,
+    pub blocked_queries: u64,
+    pub sources: Vec<BlocklistSource>,
+}
+
+/// Множество доменов с проверкой по суффиксам. Хранятся только 64-битные
+/// хэши имён, так что список из миллиона записей занимает ~16 МБ, а проверка
+/// стоит по одному поиску в хэш-таблице на метку имени.
+#[derive(Default)]
+struct DomainMatcher {
+    blocked: HashSet<u64>,
+    allowed: HashSet<u64>,
+}
+
+impl DomainMatcher {
+    fn matches(set: &HashSet<u64>, name: &str) -> bool {
+        let mut suffix = name;
+        loop {
+            if set.contains(&hash_domain(suffix)) {
+                return true;
+            }
+            match suffix.find('.') {
+                Some(dot) => suffix = &suffix[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    fn is_blocked(&self, name: &str) -> bool {
+        !self.blocked.is_empty()
+            && Self::matches(&self.blocked, name)
+            && !Self::matches(&self.allowed, name)
+    }
+}
+
+enum ListEntry<'a> {
+    Block(&'a str),
+    Allow(&'a str),
+}
+
+/// Подписки на блок-листы и сопоставление запросов с ними
+pub struct Blocklists {
+    settings: RwLock<BlocklistSettings>,
+    matcher: RwLock<Arc<DomainMatcher>>,
+    blocked_queries: AtomicU64,
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+impl Blocklists {
+    pub fn new() -> Self {
+        let settings: BlocklistSettings = config::load_json(SETTINGS_FILE).unwrap_or_else(|e| {
+            warn!("⚠️  Failed to load blocklist settings, using defaults: {}", e);
+            BlocklistSettings::default()
+        });
+
+        Self {
+            settings: RwLock::new(settings),
+            matcher: RwLock::new(Arc::new(DomainMatcher::default())),
+            blocked_queries: AtomicU64::new(0),
+            refresh_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn settings(&self) -> BlocklistSettings {
+        self.settings.read().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    pub fn response(&self) -> BlockResponse {
+        self.settings
+            .read()
+            .map(|s| s.response)
+            .unwrap_or(BlockResponse::Nxdomain)
+    }
+
+    /// Проверить имя запроса; заблокированные запросы учитываются в статистике
+    pub fn is_blocked(&self, name: &Name) -> bool {
+        if !self.settings.read().map(|s| s.enabled).unwrap_or(false) {
+            return false;
+        }
+
+        let name = name.to_lowercase().to_ascii();
+        let name = name.trim_end_matches('.');
+        let matcher = self.matcher.read().map(|m| m.clone()).unwrap_or_default();
+
+        let blocked = matcher.is_blocked(name);
+        if blocked {
+            self.blocked_queries.fetch_add(1, Ordering::Relaxed);
+        }
+        blocked
+    }
+
+    pub fn status(&self) -> BlocklistStatus {
+        let settings = self.settings();
+        BlocklistStatus {
+            enabled: settings.enabled,
+            domains: self.matcher.read().map(|m| m.blocked.len()).unwrap_or(0),
+            blocked_queries: self.blocked_queries.load(Ordering::Relaxed),
+            sources: settings.sources,
+        }
+    }
+
+    /// Сохранить настройки и пересобрать сопоставитель
+    pub async fn update_settings(&self, settings: BlocklistSettings) -> Result<()> {
+        for source in &settings.sources {
+            Self::validate(source)?;
+        }
+        self.store(settings)?;
+        self.refresh(false).await
+    }
+
+    /// Добавить источник; пустой ID генерируется автоматически
+    pub async fn add_source(&self, mut source: BlocklistSource) -> Result<BlocklistSource> {
+        Self::validate(&source)?;
+        let mut settings = self.settings();
+
+        if source.id.is_empty() {
+            source.id = format!("list-{}", Utc::now().timestamp_millis());
+        }
+
+        if settings.sources.iter().any(|s| s.id == source.id) {
+            anyhow::bail!("Blocklist with this ID already exists");
+        }
+
+        settings.sources.push(source.clone());
+        self.store(settings)?;
+        self.refresh(false).await?;
+
+        Ok(self
+            .settings()
+            .sources
+            .into_iter()
+            .find(|s| s.id == source.id)
+            .unwrap_or(source))
+    }
+
+    /// Удалить источник вместе с кэшем загруженной подписки
+    pub async fn delete_source(&self, id: &str) -> Result<()> {
+        let mut settings = self.settings();
+        if let Some(source) = settings.sources.iter().find(|s| s.id == id) {
+            if source.is_remote() {
+                let _ = fs::remove_file(source.local_path()?);
+            }
+        }
+        settings.sources.retain(|s| s.id != id);
+        self.store(settings)?;
+        self.refresh(false).await
+    }
+
+    /// Загрузить просроченные подписки (или все при `force`) и пересобрать
+    /// сопоставитель из всех включённых источников
+    pub async fn refresh(&self, force: bool) -> Result<()> {
+        let _guard = self.refresh_lock.lock().await;
+        let mut settings = self.settings();
+        let max_age = chrono::Duration::hours(settings.refresh_interval_hours.max(1) as i64);
+
+        for source in settings.sources.iter_mut().filter(|s| s.enabled && s.is_remote()) {
+            let cached = source.local_path().map(|p| p.exists()).unwrap_or(false);
+            let stale = source
+                .last_updated
+                .map(|updated| Utc::now() - updated >= max_age)
+                .unwrap_or(true);
+
+            if !(force || stale || !cached) {
+                continue;
+            }
+
+            match Self::download(source).await {
+                Ok(()) => {
+                    source.last_updated = Some(Utc::now());
+                    source.last_error = None;
+                }
+                Err(e) => {
+                    warn!("⚠️  Failed to update blocklist {}: {:#}", source.name, e);
+                    source.last_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        let sources = settings.sources.clone();
+        let allowlist = settings.allowlist.clone();
+        let (matcher, counts) = tokio::task::spawn_blocking(move || Self::build(&sources, &allowlist))
+            .await
+            .context("Blocklist build task failed")?;
+
+        for (source, (entries, error)) in settings.sources.iter_mut().zip(counts) {
+            source.entries = entries;
+            if error.is_some() {
+                source.last_error = error;
+            }
+        }
+
+        info!("🚫 Blocklists loaded: {} domains", matcher.blocked.len());
+        if let Ok(mut current) = self.matcher.write() {
+            *current = Arc::new(matcher);
+        }
+
+        // Источники могли измениться, пока шла загрузка: переносим только метаданные
+        let mut current = self.settings();
+        for source in current.sources.iter_mut() {
+            if let Some(updated) = settings.sources.iter().find(|s| s.id == source.id) {
+                source.last_updated = updated.last_updated;
+                source.entries = updated.entries;
+                source.last_error = updated.last_error.clone();
+            }
+        }
+        self.store(current)
+    }
+
+    /// Фоновое обновление подписок по расписанию
+    pub async fn run_scheduler(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.refresh(false).await {
+                warn!("⚠️  Blocklist refresh failed: {:#}", e);
+            }
+            tokio::time::sleep(SCHEDULER_TICK).await;
+        }
+    }
+
+    fn store(&self, settings: BlocklistSettings) -> Result<()> {
+        config::save_json(SETTINGS_FILE, &settings)?;
+        if let Ok(mut current) = self.settings.write() {
+            *current = settings;
+        }
+        Ok(())
+    }
+
+    fn validate(source: &BlocklistSource) -> Result<()> {
+        if source.location.trim().is_empty() {
+            anyhow::bail!("Blocklist location is required");
+        }
+        if source.is_remote() {
+            reqwest::Url::parse(&source.location).context("Invalid blocklist URL")?;
+        }
+        Ok(())
+    }
+
+    async fn download(source: &BlocklistSource) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()?;
+
+        let body = client
+            .get(&source.location)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let path = source.local_path()?;
+        // Пишем через временный файл, чтобы не оставить обрезанный список
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &body).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path)?;
+
+        info!("📥 Downloaded blocklist {} ({} bytes)", source.name, body.len());
+        Ok(())
+    }
+
+    /// Собрать сопоставитель; для каждого источника — число записей и ошибка чтения
+    fn build(
+        sources: &[BlocklistSource],
+        allowlist: &[String],
+    ) -> (DomainMatcher, Vec<(usize, Option<String>)>) {
+        let mut matcher = DomainMatcher::default();
+        let mut counts = Vec::with_capacity(sources.len());
+
+        for domain in allowlist {
+            if let Some(domain) = normalize_domain(domain) {
+                matcher.allowed.insert(hash_domain(&domain));
+            }
+        }
+
+        for source in sources {
+            if !source.enabled {
+                counts.push((source.entries, None));
+                continue;
+            }
+
+            let content = match source
+                .local_path()
+                .and_then(|path| fs::read(&path).with_context(|| format!("Failed to read {}", path.display())))
+            {
+                Ok(content) => content,
+                Err(e) => {
+                    counts.push((0, Some(format!("{:#}", e))));
+                    continue;
+                }
+            };
+
+            // Считаются только новые домены блокировки: повторы, правила `@@`
+            // и домены из предыдущих списков не увеличивают число записей
+            let mut entries = 0;
+            for line in String::from_utf8_lossy(&content).lines() {
+                parse_line(line, |entry| match entry {
+                    ListEntry::Block(domain) => {
+                        if let Some(domain) = normalize_domain(domain) {
+                            if matcher.blocked.insert(hash_domain(&domain)) {
+                                entries += 1;
+                            }
+                        }
+                    }
+                    ListEntry::Allow(domain) => {
+                        if let Some(domain) = normalize_domain(domain) {
+                            matcher.allowed.insert(hash_domain(&domain));
+                        }
+                    }
+                });
+            }
+            counts.push((entries, None));
+        }
+
+        matcher.blocked.shrink_to_fit();
+        (matcher, counts)
+    }
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    let dir = config::app_dir()?.join(CACHE_DIR);
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+    }
+    Ok(dir)
+}
+
+fn hash_domain(domain: &str) -> u64 {
+    // SipHash с нулевыми ключами: хэши одинаковы между сборками множества
+    let mut hasher = DefaultHasher::new();
+    domain.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+
+    let valid = !domain.is_empty()
+        && domain.len() <= 253
+        && !IGNORED_HOSTS.contains(&domain.as_str())
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+
+    valid.then_some(domain)
+}
+
+/// Разобрать строку списка в формате hosts, adblock (`||domain^`) или
+/// простого списка доменов
+fn parse_line<'a>(line: &'a str, mut emit: impl FnMut(ListEntry<'a>)) {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['#', '!', '[']) {
+        return;
+    }
+
+    if let Some(rule) = line.strip_prefix("@@||") {
+        if let Some(domain) = adblock_domain(rule) {
+            emit(ListEntry::Allow(domain));
+        }
+        return;
+    }
+
+    if let Some(rule) = line.strip_prefix("||") {
+        if let Some(domain) = adblock_domain(rule) {
+            emit(ListEntry::Block(domain));
+        }
+        return;
+    }
+
+    let line = line.split('#').next().unwrap_or_default();
+    let mut tokens = line.split_whitespace();
+    let Some(first) = tokens.next() else {
+        return;
+    };
+
+    if first.parse::<IpAddr>().is_ok() {
+        // hosts: `0.0.0.0 ads.example tracker.example`
+        tokens.for_each(|host| emit(ListEntry::Block(host)));
+    } else if tokens.next().is_none() {
+        emit(ListEntry::Block(first));
+    }
+}
+
+/// Домен из правила `||domain^[$important]`; правила с другими модификаторами
+/// или путями не относятся к DNS и пропускаются
+fn adblock_domain(rule: &str) -> Option<&str> {
+    let (pattern, modifiers) = rule.split_once('$').unwrap_or((rule, ""));
+    if !modifiers.is_empty() && modifiers != "important" {
+        return None;
+    }
+
+    let domain = pattern.strip_suffix('^').unwrap_or(pattern);
+    if domain.contains(['/', '*', '^', '|', ':']) {
+        return None;
+    }
+    Some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(id: &str, location: &str) -> BlocklistSource {
+        BlocklistSource {
+            id: id.to_string(),
+            name: id.to_string(),
+            location: location.to_string(),
+            enabled: true,
+            last_updated: None,
+            entries: 0,
+            last_error: None,
+        }
+    }
+
+    /// Сопоставитель из одного списка с заданным содержимым
+    fn matcher(content: &str, allowlist: &[&str]) -> (DomainMatcher, Vec<(usize, Option<String>)>) {
+        let dir = config::use_temp_app_dir();
+        let list = dir.path().join("list.txt");
+        fs::write(&list, content).unwrap();
+        let allowlist: Vec<String> = allowlist.iter().map(|d| d.to_string()).collect();
+        Blocklists::build(&[source("list", &list.to_string_lossy())], &allowlist)
+    }
+
+    #[test]
+    fn hosts_adblock_and_plain_entries_block_subdomains() {
+        let content = "\
+# hosts
+0.0.0.0 ads.example tracker.example # trailing comment
+127.0.0.1 localhost
+! adblock
+||Metrics.Example^
+||cdn.example^$third-party
+||paths.example/banner^
+plain.example
+0.0.0.0 ADS.example
+@@||allowed.example^
+";
+        let (matcher, counts) = matcher(content, &[]);
+
+        assert_eq!(counts, vec![(4, None)]);
+        for name in ["ads.example", "x.ads.example", "tracker.example", "metrics.example", "a.b.plain.example"] {
+            assert!(matcher.is_blocked(name), "{}", name);
+        }
+        for name in ["example", "notads.example", "localhost", "cdn.example", "paths.example"] {
+            assert!(!matcher.is_blocked(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn allow_rules_and_allowlist_win_over_blocks() {
+        let content = "||example.com^\n@@||good.example.com^\n";
+        let (matcher, counts) = matcher(content, &["Kept.Example.com."]);
+
+        assert_eq!(counts, vec![(1, None)]);
+        assert!(matcher.is_blocked("ads.example.com"));
+        assert!(!matcher.is_blocked("good.example.com"));
+        assert!(!matcher.is_blocked("cdn.good.example.com"));
+        assert!(!matcher.is_blocked("kept.example.com"));
+    }
+
+    #[test]
+    fn missing_lists_report_an_error_and_disabled_lists_keep_their_count() {
+        let dir = config::use_temp_app_dir();
+        let missing = source("missing", &dir.path().join("nope.txt").to_string_lossy());
+        let mut disabled = source("disabled", "unused.txt");
+        disabled.enabled = false;
+        disabled.entries = 7;
+
+        let (matcher, counts) = Blocklists::build(&[missing, disabled], &[]);
+
+        assert!(matcher.blocked.is_empty());
+        assert_eq!(counts[0].0, 0);
+        assert!(counts[0].1.is_some());
+        assert_eq!(counts[1], (7, None));
+    }
+
+    #[test]
+    fn domains_repeated_across_lists_are_counted_once() {
+        let dir = config::use_temp_app_dir();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        fs::write(&first, "a.example\nb.example\n").unwrap();
+        fs::write(&second, "b.example\nc.example\n").unwrap();
+
+        let sources = [
+            source("first", &first.to_string_lossy()),
+            source("second", &second.to_string_lossy()),
+        ];
+        let (_, counts) = Blocklists::build(&sources, &[]);
+
+        assert_eq!(counts, vec![(2, None), (1, None)]);
+    }
+
+    #[test]
+    fn a_million_entries_build_and_match() {
+        const SIZE: usize = 1_000_000;
+        let mut content = String::with_capacity(SIZE * 32);
+        for i in 0..SIZE {
+            // Формы записей чередуются, как в настоящих сборных списках
+            match i % 3 {
+                0 => content.push_str(&format!("0.0.0.0 host{}.ads.example\n", i)),
+                1 => content.push_str(&format!("||host{}.ads.example^\n", i)),
+                _ => content.push_str(&format!("host{}.ads.example\n", i)),
+            }
+        }
+
+        let (matcher, counts) = matcher(&content, &["host7.ads.example"]);
+
+        assert_eq!(counts, vec![(SIZE, None)]);
+        for i in (0..SIZE).step_by(9973).filter(|i| *i != 7) {
+            assert!(matcher.is_blocked(&format!("host{}.ads.example", i)), "{}", i);
+            assert!(matcher.is_blocked(&format!("cdn.host{}.ads.example", i)), "{}", i);
+            assert!(!matcher.is_blocked(&format!("host{}.other.example", i)), "{}", i);
+        }
+        assert!(!matcher.is_blocked("ads.example"));
+        assert!(!matcher.is_blocked("host7.ads.example"));
+        assert!(!matcher.is_blocked(&format!("host{}.ads.example", SIZE)));
+    }
+}
@@ -0,0 +1,607 @@
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{DNSClass, RData, Record, RecordType};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Максимальное число записей в LRU
+    pub capacity: usize,
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    /// Верхняя граница TTL для NXDOMAIN/NODATA (RFC 2308)
+    pub negative_max_ttl: u32,
+    pub prefetch: bool,
+    /// Prefetch starts once less than this share of the TTL is left
+    pub prefetch_window_percent: u8,
+    /// Только записи, запрошенные хотя бы столько раз, обновляются заранее
+    pub prefetch_min_hits: u32,
+    /// Отдавать просроченные ответы, если апстрим не отвечает (RFC 8767)
+    pub serve_stale: bool,
+    /// Сколько секунд после истечения TTL запись ещё можно отдать
+    pub stale_max_age_secs: u32,
+    /// TTL просроченных ответов; RFC 8767 рекомендует 30 секунд
+    pub stale_answer_ttl: u32,
+    /// Сколько ждать апстрим, прежде чем ответить клиенту просроченной записью
+    pub stale_client_timeout_ms: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: 4096,
+            min_ttl: 0,
+            max_ttl: 86400,
+            negative_max_ttl: 900,
+            prefetch: true,
+            prefetch_window_percent: 10,
+            prefetch_min_hits: 3,
+            serve_stale: true,
+            stale_max_age_secs: 86400,
+            stale_answer_ttl: 30,
+            stale_client_timeout_ms: 1800,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub prefetches: u64,
+    /// Сколько раз клиенту был отдан просроченный ответ
+    pub stale_served: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    name: String,
+    record_type: RecordType,
+    class: DNSClass,
+    dnssec_ok: bool,
+}
+
+impl CacheKey {
+    pub fn from_request(request: &Message) -> Option<Self> {
+        let query = request.query()?;
+        Some(Self {
+            name: query.name().to_lowercase().to_ascii(),
+            record_type: query.query_type(),
+            class: query.query_class(),
+            dnssec_ok: request.extensions().as_ref().map(|e| e.dnssec_ok()).unwrap_or(false),
+        })
+    }
+}
+
+struct CacheEntry {
+    response: Message,
+    stored_at: Instant,
+    ttl: u32,
+    hits: u32,
+    prefetching: bool,
+}
+
+/// Answer served from the cache
+pub struct CachedAnswer {
+    pub response: Vec<u8>,
+    /// Запись популярна и скоро истечёт — стоит обновить её в фоне
+    pub prefetch: bool,
+}
+
+/// Просроченный ответ, который можно отдать, если апстрим не успеет
+pub struct StaleAnswer {
+    pub response: Vec<u8>,
+    /// Сколько ждать свежий ответ, прежде чем отдать этот
+    pub client_timeout: Duration,
+}
+
+/// LRU-кэш ответов прокси с учётом TTL
+pub struct ResponseCache {
+    config: Mutex<CacheConfig>,
+    entries: Mutex<LruCache<CacheKey, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    prefetches: AtomicU64,
+    stale_served: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        let capacity = Self::capacity_of(&config);
+        Self {
+            config: Mutex::new(config),
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            prefetches: AtomicU64::new(0),
+            stale_served: AtomicU64::new(0),
+        }
+    }
+
+    fn capacity_of(config: &CacheConfig) -> NonZeroUsize {
+        NonZeroUsize::new(config.capacity).unwrap_or(NonZeroUsize::MIN)
+    }
+
+    fn config(&self) -> CacheConfig {
+        self.config.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    pub fn configure(&self, config: CacheConfig) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if config.enabled {
+                entries.resize(Self::capacity_of(&config));
+            } else {
+                entries.clear();
+            }
+        }
+        if let Ok(mut current) = self.config.lock() {
+            *current = config;
+        }
+    }
+
+    /// Найти ответ для запроса; TTL записей уменьшаются на время хранения
+    pub fn get(&self, request: &Message) -> Option<CachedAnswer> {
+        let config = self.config();
+        if !config.enabled {
+            return None;
+        }
+
+        let key = CacheKey::from_request(request)?;
+        let mut entries = self.entries.lock().ok()?;
+
+        let elapsed = match entries.peek(&key) {
+            Some(entry) => entry.stored_at.elapsed().as_secs() as u32,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+
+        let entry = entries.get_mut(&key)?;
+        if elapsed >= entry.ttl {
+            // Просроченная запись остаётся в кэше на случай, если апстрим не ответит
+            if !Self::within_stale_window(entry, elapsed, &config) {
+                entries.pop(&key);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        entry.hits += 1;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        let ttl = entry.ttl;
+        let remaining = ttl - elapsed;
+        let window = (ttl as u64 * config.prefetch_window_percent as u64 / 100) as u32;
+        let prefetch = config.prefetch
+            && !entry.prefetching
+            && entry.hits >= config.prefetch_min_hits
+            && remaining <= window.max(1);
+
+        if prefetch {
+            entry.prefetching = true;
+            self.prefetches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut response = entry.response.clone();
+        drop(entries);
+
+        response
+            .set_id(request.id())
+            .set_recursion_desired(request.recursion_desired());
+        *response.queries_mut() = request.queries().to_vec();
+        Self::age_records(&mut response, ttl, elapsed, &config);
+
+        Some(CachedAnswer {
+            response: response.to_vec().ok()?,
+            prefetch,
+        })
+    }
+
+    /// Просроченный ответ для запроса (RFC 8767), все TTL заменены на `stale_answer_ttl`
+    pub fn get_stale(&self, request: &Message) -> Option<StaleAnswer> {
+        let config = self.config();
+        if !config.enabled || !config.serve_stale {
+            return None;
+        }
+
+        let key = CacheKey::from_request(request)?;
+        let mut response = {
+            let entries = self.entries.lock().ok()?;
+            let entry = entries.peek(&key)?;
+            let elapsed = entry.stored_at.elapsed().as_secs() as u32;
+            if elapsed < entry.ttl || !Self::within_stale_window(entry, elapsed, &config) {
+                return None;
+            }
+            entry.response.clone()
+        };
+
+        response
+            .set_id(request.id())
+            .set_recursion_desired(request.recursion_desired());
+        *response.queries_mut() = request.queries().to_vec();
+        let expire = |records: &mut Vec<Record>| {
+            for record in records.iter_mut() {
+                record.set_ttl(config.stale_answer_ttl);
+            }
+        };
+        expire(response.answers_mut());
+        expire(response.name_servers_mut());
+        expire(response.additionals_mut());
+
+        Some(StaleAnswer {
+            response: response.to_vec().ok()?,
+            client_timeout: Duration::from_millis(config.stale_client_timeout_ms),
+        })
+    }
+
+    /// Учесть в статистике, что клиенту ушёл просроченный ответ
+    pub fn stale_served(&self) {
+        self.stale_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn within_stale_window(entry: &CacheEntry, elapsed: u32, config: &CacheConfig) -> bool {
+        config.serve_stale && elapsed < entry.ttl.saturating_add(config.stale_max_age_secs)
+    }
+
+    /// Сохранить ответ апстрима. Ошибки, кроме NXDOMAIN, и обрезанные ответы не кэшируются.
+    pub fn insert(&self, request: &Message, response: &[u8]) {
+        let config = self.config();
+        if !config.enabled {
+            return;
+        }
+
+        let (Some(key), Ok(message)) = (CacheKey::from_request(request), Message::from_vec(response)) else {
+            return;
+        };
+
+        if message.truncated() {
+            return;
+        }
+
+        let ttl = match Self::cacheable_ttl(&message, &config) {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.put(
+                key,
+                CacheEntry {
+                    response: message,
+                    stored_at: Instant::now(),
+                    ttl,
+                    hits: 0,
+                    prefetching: false,
+                },
+            );
+        }
+    }
+
+    /// Снять флаг prefetch, если обновление не удалось
+    pub fn prefetch_failed(&self, request: &Message) {
+        let Some(key) = CacheKey::from_request(request) else {
+            return;
+        };
+        if let Ok(mut entries) = self.entries.lock() {
+            if let Some(entry) = entries.peek_mut(&key) {
+                entry.prefetching = false;
+            }
+        }
+    }
+
+    pub fn clear_entries(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    /// Состарить все записи, как будто они сохранены на `by` раньше
+    #[cfg(test)]
+    pub(super) fn age_entries(&self, by: Duration) {
+        if let Ok(mut entries) = self.entries.lock() {
+            for (_, entry) in entries.iter_mut() {
+                entry.stored_at -= by;
+            }
+        }
+    }
+
+    /// Очистить кэш и сбросить статистику
+    pub fn clear(&self) {
+        self.clear_entries();
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.prefetches.store(0, Ordering::Relaxed);
+        self.stale_served.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let config = self.config();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+
+        CacheStats {
+            enabled: config.enabled,
+            capacity: config.capacity,
+            entries: self.entries.lock().map(|e| e.len()).unwrap_or(0),
+            hits,
+            misses,
+            prefetches: self.prefetches.load(Ordering::Relaxed),
+            stale_served: self.stale_served.load(Ordering::Relaxed),
+            hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        }
+    }
+
+    /// TTL записи кэша: минимальный TTL ответа для положительных ответов,
+    /// min(SOA TTL, SOA MINIMUM) для отрицательных (RFC 2308 §5)
+    fn cacheable_ttl(message: &Message, config: &CacheConfig) -> Option<u32> {
+        if Self::is_negative(message)? {
+            let soa_ttl = message.name_servers().iter().find_map(|record| match record.data() {
+                Some(RData::SOA(soa)) => Some(record.ttl().min(soa.minimum())),
+                _ => None,
+            })?;
+            return Some(soa_ttl.min(config.negative_max_ttl));
+        }
+
+        message
+            .answers()
+            .iter()
+            .map(|record| Self::clamp_ttl(record.ttl(), config))
+            .min()
+    }
+
+    /// NXDOMAIN или NODATA; `None` для ответов, которые не кэшируются
+    fn is_negative(message: &Message) -> Option<bool> {
+        match message.response_code() {
+            ResponseCode::NXDomain => Some(true),
+            ResponseCode::NoError => Some(message.answers().is_empty()),
+            _ => None,
+        }
+    }
+
+    fn clamp_ttl(ttl: u32, config: &CacheConfig) -> u32 {
+        ttl.max(config.min_ttl).min(config.max_ttl.max(config.min_ttl))
+    }
+
+    /// Уменьшить TTL на время хранения. В отрицательном ответе TTL не больше
+    /// отрицательного TTL записи кэша, иначе клиент закэширует SOA дольше (RFC 2308 §5)
+    fn age_records(message: &mut Message, entry_ttl: u32, elapsed: u32, config: &CacheConfig) {
+        let negative = Self::is_negative(message).unwrap_or(false);
+        let age = |records: &mut Vec<Record>| {
+            for record in records.iter_mut() {
+                let ttl = if negative {
+                    record.ttl().min(entry_ttl)
+                } else {
+                    Self::clamp_ttl(record.ttl(), config)
+                };
+                record.set_ttl(ttl.saturating_sub(elapsed));
+            }
+        };
+
+        age(message.answers_mut());
+        age(message.name_servers_mut());
+        age(message.additionals_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::rdata::{A, SOA};
+    use hickory_proto::rr::Name;
+
+    fn request(id: u16) -> Message {
+        query(id, "missing.example.")
+    }
+
+    fn query(id: u16, name: &str) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(id)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        message
+    }
+
+    /// Положительный ответ с одной A-записью
+    fn answer(name: &str, ttl: u32) -> Vec<u8> {
+        let mut message = query(1, name);
+        message.set_message_type(MessageType::Response).add_answer(Record::from_rdata(
+            Name::from_ascii(name).unwrap(),
+            ttl,
+            RData::A(A::new(192, 0, 2, 1)),
+        ));
+        message.to_vec().unwrap()
+    }
+
+    /// Отрицательный ответ с SOA в authority: TTL записи и поле MINIMUM
+    fn negative(code: ResponseCode, soa_ttl: u32, minimum: u32) -> Message {
+        let zone = Name::from_ascii("example.").unwrap();
+        let soa = SOA::new(zone.clone(), zone.clone(), 1, 7200, 900, 86400, minimum);
+        let mut message = request(1);
+        message
+            .set_message_type(MessageType::Response)
+            .set_response_code(code)
+            .add_name_server(Record::from_rdata(zone, soa_ttl, RData::SOA(soa)));
+        message
+    }
+
+    #[test]
+    fn negative_ttl_is_the_smaller_of_soa_ttl_and_minimum() {
+        let config = CacheConfig::default();
+
+        assert_eq!(ResponseCache::cacheable_ttl(&negative(ResponseCode::NXDomain, 3600, 300), &config), Some(300));
+        assert_eq!(ResponseCache::cacheable_ttl(&negative(ResponseCode::NXDomain, 120, 300), &config), Some(120));
+        // NODATA: NOERROR без ответов
+        assert_eq!(ResponseCache::cacheable_ttl(&negative(ResponseCode::NoError, 3600, 600), &config), Some(600));
+    }
+
+    #[test]
+    fn negative_ttl_is_capped_and_needs_a_soa() {
+        let config = CacheConfig {
+            negative_max_ttl: 60,
+            ..CacheConfig::default()
+        };
+
+        assert_eq!(ResponseCache::cacheable_ttl(&negative(ResponseCode::NXDomain, 3600, 3600), &config), Some(60));
+
+        let mut without_soa = request(1);
+        without_soa.set_response_code(ResponseCode::NXDomain);
+        assert_eq!(ResponseCache::cacheable_ttl(&without_soa, &config), None);
+        assert_eq!(ResponseCache::cacheable_ttl(&negative(ResponseCode::ServFail, 3600, 300), &config), None);
+    }
+
+    #[test]
+    fn cached_nxdomain_is_served_for_a_new_request() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        cache.insert(&request(1), &negative(ResponseCode::NXDomain, 3600, 300).to_vec().unwrap());
+
+        let answer = cache.get(&request(2)).unwrap();
+        let response = Message::from_vec(&answer.response).unwrap();
+
+        assert_eq!(response.id(), 2);
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        // SOA отдаётся с отрицательным TTL, а не со своим собственным
+        assert_eq!(response.name_servers()[0].ttl(), 300);
+    }
+
+    #[test]
+    fn popular_entries_near_expiry_are_prefetched_once() {
+        let cache = ResponseCache::new(CacheConfig {
+            prefetch_window_percent: 100,
+            prefetch_min_hits: 2,
+            ..CacheConfig::default()
+        });
+        cache.insert(&query(1, "popular.example."), &answer("popular.example.", 60));
+
+        // Первое обращение ещё не делает запись популярной
+        assert!(!cache.get(&query(2, "popular.example.")).unwrap().prefetch);
+        assert!(cache.get(&query(3, "popular.example.")).unwrap().prefetch);
+        // Пока обновление идёт, второе не запускается
+        assert!(!cache.get(&query(4, "popular.example.")).unwrap().prefetch);
+
+        cache.prefetch_failed(&query(5, "popular.example."));
+        assert!(cache.get(&query(6, "popular.example.")).unwrap().prefetch);
+        assert_eq!(cache.stats().prefetches, 2);
+    }
+
+    #[test]
+    fn prefetch_can_be_disabled() {
+        let cache = ResponseCache::new(CacheConfig {
+            prefetch: false,
+            prefetch_window_percent: 100,
+            prefetch_min_hits: 0,
+            ..CacheConfig::default()
+        });
+        cache.insert(&query(1, "popular.example."), &answer("popular.example.", 60));
+
+        assert!(!cache.get(&query(2, "popular.example.")).unwrap().prefetch);
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let cache = ResponseCache::new(CacheConfig {
+            capacity: 2,
+            ..CacheConfig::default()
+        });
+        cache.insert(&query(1, "a.example."), &answer("a.example.", 60));
+        cache.insert(&query(1, "b.example."), &answer("b.example.", 60));
+        // Обращение к a делает самой старой запись b
+        assert!(cache.get(&query(2, "a.example.")).is_some());
+        cache.insert(&query(1, "c.example."), &answer("c.example.", 60));
+
+        assert!(cache.get(&query(3, "a.example.")).is_some());
+        assert!(cache.get(&query(3, "b.example.")).is_none());
+        assert!(cache.get(&query(3, "c.example.")).is_some());
+        assert_eq!(cache.stats().entries, 2);
+
+        // Уменьшение ёмкости вытесняет лишние записи
+        cache.configure(CacheConfig {
+            capacity: 1,
+            ..CacheConfig::default()
+        });
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get(&query(4, "c.example.")).is_some());
+    }
+
+    #[test]
+    fn expired_entries_are_served_stale_only_within_the_window() {
+        let cache = ResponseCache::new(CacheConfig {
+            stale_max_age_secs: 60,
+            stale_answer_ttl: 5,
+            stale_client_timeout_ms: 250,
+            ..CacheConfig::default()
+        });
+        let mut response = Message::from_vec(&answer("old.example.", 60)).unwrap();
+        response.add_additional(Record::from_rdata(
+            Name::from_ascii("ns.old.example.").unwrap(),
+            3600,
+            RData::A(A::new(192, 0, 2, 53)),
+        ));
+        cache.insert(&query(1, "old.example."), &response.to_vec().unwrap());
+
+        // Пока запись свежая, просроченный ответ не нужен
+        assert!(cache.get_stale(&query(2, "old.example.")).is_none());
+
+        cache.age_entries(Duration::from_secs(90));
+        assert!(cache.get(&query(3, "old.example.")).is_none());
+        let stale = cache.get_stale(&query(4, "old.example.")).unwrap();
+        assert_eq!(stale.client_timeout, Duration::from_millis(250));
+        let response = Message::from_vec(&stale.response).unwrap();
+        assert_eq!(response.id(), 4);
+        let ttls: Vec<u32> = response.answers().iter().chain(response.additionals()).map(|r| r.ttl()).collect();
+        assert_eq!(ttls, [5, 5]);
+        // Отдача ещё не значит, что клиент получил этот ответ — это учитывает резолвер
+        assert_eq!(cache.stats().stale_served, 0);
+
+        // За пределами окна запись не отдаётся и удаляется при обращении
+        cache.age_entries(Duration::from_secs(40));
+        assert!(cache.get_stale(&query(5, "old.example.")).is_none());
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get(&query(6, "old.example.")).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped_when_serve_stale_is_off() {
+        let cache = ResponseCache::new(CacheConfig {
+            serve_stale: false,
+            ..CacheConfig::default()
+        });
+        cache.insert(&query(1, "old.example."), &answer("old.example.", 60));
+        cache.age_entries(Duration::from_secs(90));
+
+        assert!(cache.get_stale(&query(2, "old.example.")).is_none());
+        assert!(cache.get(&query(3, "old.example.")).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn positive_ttl_is_the_smallest_clamped_answer_ttl() {
+        let config = CacheConfig {
+            min_ttl: 30,
+            max_ttl: 600,
+            ..CacheConfig::default()
+        };
+        let name = Name::from_ascii("missing.example.").unwrap();
+        let mut message = request(1);
+        message
+            .add_answer(Record::from_rdata(name.clone(), 5, RData::A(A::new(192, 0, 2, 1))))
+            .add_answer(Record::from_rdata(name, 3600, RData::A(A::new(192, 0, 2, 2))));
+
+        assert_eq!(ResponseCache::cacheable_ttl(&message, &config), Some(30));
+    }
+}
@@ -0,0 +1,587 @@
+use super::upstream::{self, exchange_stream};
+use anyhow::{Context, Result};
+use crypto_box::aead::{Aead, OsRng};
+use crypto_box::{ChaChaBox, Nonce, PublicKey, SalsaBox, SecretKey};
+use ed25519_dalek::{Signature, VerifyingKey};
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use log::{debug, info};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpStream, UdpSocket};
+
+const CERT_MAGIC: &[u8; 4] = b"DNSC";
+const RESOLVER_MAGIC: &[u8; 8] = b"r6fnvWj8";
+const CERT_LEN: usize = 124;
+/// Сертификат подписан начиная с ключа резолвера (после magic, версий и подписи)
+const CERT_SIGNED_OFFSET: usize = 72;
+
+const HALF_NONCE_LEN: usize = 12;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Минимальный размер UDP-запроса: резолвер не отвечает по UDP больше, чем получил
+const MIN_UDP_QUERY_LEN: usize = 256;
+const PADDING_BLOCK: usize = 64;
+
+const CERT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Сертификат перечитывается не реже раза в час, даже если ещё действителен
+const CERT_REFRESH: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EsVersion {
+    XSalsa20Poly1305,
+    XChaCha20Poly1305,
+}
+
+struct Certificate {
+    version: EsVersion,
+    resolver_pk: [u8; 32],
+    client_magic: [u8; 8],
+    serial: u32,
+    ts_start: u32,
+    ts_end: u32,
+}
+
+impl Certificate {
+    /// Разобрать сертификат из TXT-записи и проверить подпись ключом провайдера
+    fn parse(data: &[u8], provider_key: &VerifyingKey) -> Result<Self> {
+        if data.len() < CERT_LEN || &data[..4] != CERT_MAGIC {
+            anyhow::bail!("Not a DNSCrypt certificate");
+        }
+
+        let version = match u16::from_be_bytes([data[4], data[5]]) {
+            1 => EsVersion::XSalsa20Poly1305,
+            2 => EsVersion::XChaCha20Poly1305,
+            other => anyhow::bail!("Unsupported DNSCrypt es-version {}", other),
+        };
+
+        let signature = Signature::from_bytes(data[8..CERT_SIGNED_OFFSET].try_into()?);
+        provider_key
+            .verify_strict(&data[CERT_SIGNED_OFFSET..], &signature)
+            .context("DNSCrypt certificate signature is invalid")?;
+
+        let u32_at = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap_or_default());
+
+        Ok(Self {
+            version,
+            resolver_pk: data[72..104].try_into()?,
+            client_magic: data[104..112].try_into()?,
+            serial: u32_at(112),
+            ts_start: u32_at(116),
+            ts_end: u32_at(120),
+        })
+    }
+}
+
+enum Cipher {
+    Salsa(SalsaBox),
+    ChaCha(ChaChaBox),
+}
+
+impl Cipher {
+    fn new(version: EsVersion, public_key: &PublicKey, secret: &SecretKey) -> Self {
+        match version {
+            EsVersion::XSalsa20Poly1305 => Self::Salsa(SalsaBox::new(public_key, secret)),
+            EsVersion::XChaCha20Poly1305 => Self::ChaCha(ChaChaBox::new(public_key, secret)),
+        }
+    }
+
+    fn seal(&self, nonce: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            Self::Salsa(cipher) => cipher.encrypt(nonce, plaintext),
+            Self::ChaCha(cipher) => cipher.encrypt(nonce, plaintext),
+        }
+        .map_err(|_| anyhow::anyhow!("DNSCrypt encryption failed"))
+    }
+
+    fn open(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            Self::Salsa(cipher) => cipher.decrypt(nonce, ciphertext),
+            Self::ChaCha(cipher) => cipher.decrypt(nonce, ciphertext),
+        }
+        .map_err(|_| anyhow::anyhow!("DNSCrypt response failed to decrypt"))
+    }
+}
+
+/// ISO/IEC 7816-4: 0x80 и нули до длины не меньше `min_len`, кратной 64
+fn pad(data: &[u8], min_len: usize) -> Vec<u8> {
+    let len = (data.len() + 1).max(min_len);
+    let padded_len = len + (PADDING_BLOCK - len % PADDING_BLOCK) % PADDING_BLOCK;
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(data);
+    padded.push(0x80);
+    padded.resize(padded_len, 0);
+    padded
+}
+
+fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>> {
+    let end = padded
+        .iter()
+        .rposition(|&b| b != 0)
+        .filter(|&idx| padded[idx] == 0x80)
+        .context("DNSCrypt response has invalid padding")?;
+    padded.truncate(end);
+    Ok(padded)
+}
+
+/// Ключи, согласованные по текущему сертификату резолвера
+struct Session {
+    cipher: Cipher,
+    client_pk: [u8; 32],
+    client_magic: [u8; 8],
+    valid_until: u64,
+    fetched_at: Instant,
+}
+
+impl Session {
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < CERT_REFRESH && unix_now() < self.valid_until
+    }
+
+    /// Зашифровать запрос; возвращает пакет и nonce, с которым его нужно сверить в ответе
+    fn encrypt(&self, query: &[u8], min_len: usize) -> Result<(Vec<u8>, [u8; NONCE_LEN])> {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..HALF_NONCE_LEN].copy_from_slice(&rand::random::<[u8; HALF_NONCE_LEN]>());
+        let sealed = self.cipher.seal(&nonce, &pad(query, min_len))?;
+
+        let mut packet = Vec::with_capacity(8 + 32 + HALF_NONCE_LEN + sealed.len());
+        packet.extend_from_slice(&self.client_magic);
+        packet.extend_from_slice(&self.client_pk);
+        packet.extend_from_slice(&nonce[..HALF_NONCE_LEN]);
+        packet.extend_from_slice(&sealed);
+
+        Ok((packet, nonce))
+    }
+
+    fn decrypt(&self, response: &[u8], nonce: &[u8; NONCE_LEN]) -> Result<Vec<u8>> {
+        let header_len = RESOLVER_MAGIC.len() + NONCE_LEN;
+        if response.len() < header_len + TAG_LEN || &response[..RESOLVER_MAGIC.len()] != RESOLVER_MAGIC {
+            anyhow::bail!("Not a DNSCrypt response");
+        }
+
+        let server_nonce = &response[RESOLVER_MAGIC.len()..header_len];
+        if server_nonce[..HALF_NONCE_LEN] != nonce[..HALF_NONCE_LEN] {
+            anyhow::bail!("DNSCrypt response nonce mismatch");
+        }
+
+        unpad(self.cipher.open(server_nonce, &response[header_len..])?)
+    }
+}
+
+/// Клиент DNSCrypt v2: получает подписанный сертификат резолвера и шифрует
+/// запросы X25519-XSalsa20Poly1305 или X25519-XChaCha20Poly1305
+pub struct DnsCryptClient {
+    addr: SocketAddr,
+    provider_name: String,
+    provider_key: VerifyingKey,
+    session: Mutex<Option<Arc<Session>>>,
+}
+
+impl DnsCryptClient {
+    pub fn new(addr: SocketAddr, provider_name: &str, public_key: &[u8; 32]) -> Result<Self> {
+        let provider_key = VerifyingKey::from_bytes(public_key).context("Invalid DNSCrypt provider key")?;
+
+        Ok(Self {
+            addr,
+            provider_name: provider_name.trim_end_matches('.').to_string(),
+            provider_key,
+            session: Mutex::new(None),
+        })
+    }
+
+    pub fn provider_name(&self) -> &str {
+        &self.provider_name
+    }
+
+    pub async fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let session = self.session().await?;
+
+        let result = self.exchange_with(&session, query).await;
+        if result.is_err() {
+            // Резолвер мог сменить ключ — следующий запрос перечитает сертификат
+            if let Ok(mut current) = self.session.lock() {
+                *current = None;
+            }
+        }
+        result
+    }
+
+    async fn exchange_with(&self, session: &Session, query: &[u8]) -> Result<Vec<u8>> {
+        let (packet, nonce) = session.encrypt(query, MIN_UDP_QUERY_LEN)?;
+        let response = session.decrypt(&exchange_udp(self.addr, &packet, &nonce).await?, &nonce)?;
+
+        if response.len() > 2 && response[2] & 0x02 != 0 {
+            debug!("Truncated DNSCrypt answer from {}, retrying over TCP", self.addr);
+            let (packet, nonce) = session.encrypt(query, 0)?;
+            let mut stream = TcpStream::connect(self.addr)
+                .await
+                .with_context(|| format!("Failed to connect to {}", self.addr))?;
+            return session.decrypt(&exchange_stream(&mut stream, &packet).await?, &nonce);
+        }
+
+        Ok(response)
+    }
+
+    async fn session(&self) -> Result<Arc<Session>> {
+        let cached = self.session.lock().ok().and_then(|s| s.clone());
+        if let Some(session) = cached.filter(|s| s.is_fresh()) {
+            return Ok(session);
+        }
+
+        let session = Arc::new(self.fetch_session().await?);
+        if let Ok(mut current) = self.session.lock() {
+            *current = Some(session.clone());
+        }
+        Ok(session)
+    }
+
+    /// Запросить TXT-сертификаты провайдера и выбрать действующий с наибольшим серийным номером
+    async fn fetch_session(&self) -> Result<Session> {
+        let mut name = Name::from_ascii(&self.provider_name).context("Invalid DNSCrypt provider name")?;
+        name.set_fqdn(true);
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_recursion_desired(true)
+            .add_query(Query::query(name, RecordType::TXT));
+
+        let response = tokio::time::timeout(CERT_TIMEOUT, upstream::exchange_udp(self.addr, &message.to_vec()?))
+            .await
+            .context("DNSCrypt certificate request timed out")?
+            .context("Failed to fetch DNSCrypt certificate")?;
+
+        let now = unix_now();
+        let certificate = Message::from_vec(&response)?
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::TXT(txt)) => Some(txt.txt_data().concat()),
+                _ => None,
+            })
+            .filter_map(|data| match Certificate::parse(&data, &self.provider_key) {
+                Ok(cert) => Some(cert),
+                Err(e) => {
+                    debug!("Skipping certificate from {}: {:#}", self.provider_name, e);
+                    None
+                }
+            })
+            .filter(|cert| u64::from(cert.ts_start) <= now && now < u64::from(cert.ts_end))
+            .max_by_key(|cert| (cert.serial, cert.version))
+            .with_context(|| format!("No valid DNSCrypt certificate for {}", self.provider_name))?;
+
+        info!(
+            "🔐 DNSCrypt certificate for {}: serial {}, {:?}",
+            self.provider_name, certificate.serial, certificate.version
+        );
+
+        let secret = SecretKey::generate(&mut OsRng);
+        Ok(Session {
+            cipher: Cipher::new(certificate.version, &PublicKey::from(certificate.resolver_pk), &secret),
+            client_pk: *secret.public_key().as_bytes(),
+            client_magic: certificate.client_magic,
+            valid_until: u64::from(certificate.ts_end),
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+/// Отправить зашифрованный запрос по UDP; ответы с чужим nonce отбрасываются
+async fn exchange_udp(addr: SocketAddr, packet: &[u8], nonce: &[u8; NONCE_LEN]) -> Result<Vec<u8>> {
+    let bind_addr: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+
+    let socket = UdpSocket::bind(bind_addr).await
+        .context("Failed to bind UDP socket")?;
+    socket.connect(addr).await?;
+    socket.send(packet).await?;
+
+    let nonce_range = RESOLVER_MAGIC.len()..RESOLVER_MAGIC.len() + HALF_NONCE_LEN;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if len >= nonce_range.end && buf[nonce_range.clone()] == nonce[..HALF_NONCE_LEN] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use hickory_proto::op::MessageType;
+    use hickory_proto::rr::rdata::{A, TXT};
+    use hickory_proto::rr::Record;
+    use std::ops::Range;
+
+    /// Ключи и сообщение из тестов crypto_box в NaCl
+    const ALICE_SECRET_KEY: &str = "68f208412d8dd5db9d0c6d18512e86f0ec75665ab841372d57b042b27ef89d4c";
+    const ALICE_PUBLIC_KEY: &str = "ac3a70ba35df3c3fae427a7c72021d68f2c1e044040b75f17313c0c8b5d4241d";
+    const BOB_SECRET_KEY: &str = "b581fb5ae182a16f603f39270d4e3b95bc008310b727a11dd4e784a0044d461b";
+    const BOB_PUBLIC_KEY: &str = "e8980c86e032f1eb2975052e8d65bddd15c3b59641174ec9678a53789d92c754";
+    const NONCE: &str = "69696ee955b62b73cd62bda875fc73d68219e0036b7a0b37";
+    const PLAINTEXT: &str = "be075fc53c81f2d5cf141316ebeb0c7b5228c52a4c62cbd44b66849b64244ffce5ecbaaf33bd751a1ac728d45e6c61296cdc3c01233561f41db66cce314adb310e3be8250c46f06dceea3a7fa1348057e2f6556ad6b1318a024a838f21af1fde048977eb48f59ffd4924ca1c60902e52f0a089bc76897040e082f937763848645e0705";
+    const SALSA_CIPHERTEXT: &str = "c03f27d188ef650cd12936913137bb17ed4c98c2648939e2e1d2e855470a7b8c632cabfd5ab3b3c2d313dc8c9ecf5da173e1f9c318cdef1dced6d2519e695085e6b5c401a2bd5331442986c7076d412625497c4cb2fd94c6f103961033b2c930d7e82e0341f29d3879bd6ab9d881ea3a1f365d634e653c6e171aac7fc1e76934d23be6f04a54010808dbf0f9bd30f63b68d026";
+    const CHACHA_CIPHERTEXT: &str = "0cd5ed093de698c8e410d0d451df2f5283057376b947b9b7392b956e5d675f309218acce8cf85f6cf6a9e2e09ef8c5b0f97c661ee21b1b3418be566692634056a92b4034d5d0cf14c52420a488b7f0da0c5740dfc6b85397d3a8f679e84303e8d3f8b048abdb2dd79183b0a62683a1bc2a527fc9b82c5ffac4a684bcfeadfdcd28930b2dbe597f4716a658ccfca5b44049e06c";
+
+    const PROVIDER: &str = "2.dnscrypt-cert.test";
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(value: &str) -> [u8; 32] {
+        hex(value).try_into().unwrap()
+    }
+
+    fn provider_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn resolver_secret() -> SecretKey {
+        SecretKey::from([9; 32])
+    }
+
+    /// Клиентская метка сертификата несёт его версию, чтобы подставной резолвер знал шифр
+    fn client_magic(version: u16) -> [u8; 8] {
+        let mut magic = *b"client-0";
+        magic[7] += version as u8;
+        magic
+    }
+
+    fn certificate(version: u16, serial: u32, valid: Range<u32>) -> Vec<u8> {
+        let mut signed = resolver_secret().public_key().as_bytes().to_vec();
+        signed.extend_from_slice(&client_magic(version));
+        signed.extend_from_slice(&serial.to_be_bytes());
+        signed.extend_from_slice(&valid.start.to_be_bytes());
+        signed.extend_from_slice(&valid.end.to_be_bytes());
+
+        let mut cert = CERT_MAGIC.to_vec();
+        cert.extend_from_slice(&version.to_be_bytes());
+        cert.extend_from_slice(&[0, 0]);
+        cert.extend_from_slice(&provider_key().sign(&signed).to_bytes());
+        cert.extend_from_slice(&signed);
+        cert
+    }
+
+    fn current() -> Range<u32> {
+        let now = unix_now() as u32;
+        now - 100..now + 1000
+    }
+
+    /// Подставной резолвер DNSCrypt на 127.0.0.1: отдаёт сертификаты по TXT-запросу
+    /// и отвечает на зашифрованные запросы A-записью 192.0.2.53
+    async fn resolver(certificates: Vec<Vec<u8>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let packet = &buf[..len];
+                let version = certificates
+                    .iter()
+                    .find(|cert| cert[104..112] == packet[..8])
+                    .map(|cert| u16::from_be_bytes([cert[4], cert[5]]));
+                let response = match version {
+                    Some(version) => answer(packet, version),
+                    None => certificate_answer(packet, &certificates),
+                };
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        addr
+    }
+
+    fn certificate_answer(packet: &[u8], certificates: &[Vec<u8>]) -> Vec<u8> {
+        let mut response = Message::from_vec(packet).unwrap();
+        let name = response.queries()[0].name().clone();
+        assert_eq!(name.to_ascii(), format!("{}.", PROVIDER));
+        response.set_message_type(MessageType::Response);
+        for cert in certificates {
+            response.add_answer(Record::from_rdata(name.clone(), 300, RData::TXT(TXT::from_bytes(vec![cert]))));
+        }
+        response.to_vec().unwrap()
+    }
+
+    fn answer(packet: &[u8], version: u16) -> Vec<u8> {
+        let es_version = if version == 2 { EsVersion::XChaCha20Poly1305 } else { EsVersion::XSalsa20Poly1305 };
+        let client_pk = PublicKey::from(<[u8; 32]>::try_from(&packet[8..40]).unwrap());
+        let cipher = Cipher::new(es_version, &client_pk, &resolver_secret());
+
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..HALF_NONCE_LEN].copy_from_slice(&packet[40..52]);
+        let padded = cipher.open(&nonce, &packet[52..]).unwrap();
+        // UDP-запрос дополнен хотя бы до 256 байт
+        assert!(padded.len() >= MIN_UDP_QUERY_LEN);
+        assert_eq!(padded.len() % PADDING_BLOCK, 0);
+
+        let mut response = Message::from_vec(&unpad(padded).unwrap()).unwrap();
+        let name = response.queries()[0].name().clone();
+        response
+            .set_message_type(MessageType::Response)
+            .add_answer(Record::from_rdata(name, 300, RData::A(A::new(192, 0, 2, 53))));
+
+        nonce[HALF_NONCE_LEN..].copy_from_slice(&[3; HALF_NONCE_LEN]);
+        let mut out = RESOLVER_MAGIC.to_vec();
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&cipher.seal(&nonce, &pad(&response.to_vec().unwrap(), 0)).unwrap());
+        out
+    }
+
+    fn client(addr: SocketAddr) -> DnsCryptClient {
+        DnsCryptClient::new(addr, PROVIDER, &provider_key().verifying_key().to_bytes()).unwrap()
+    }
+
+    fn query() -> Vec<u8> {
+        let mut message = Message::new();
+        message
+            .set_id(42)
+            .add_query(Query::query(Name::from_ascii("example.com.").unwrap(), RecordType::A));
+        message.to_vec().unwrap()
+    }
+
+    #[test]
+    fn certificate_fields_are_read_after_the_signature_check() {
+        let key = provider_key().verifying_key();
+        let cert = Certificate::parse(&certificate(2, 7, 1_700_000_000..1_800_000_000), &key).unwrap();
+
+        assert_eq!(cert.version, EsVersion::XChaCha20Poly1305);
+        assert_eq!(cert.resolver_pk, *resolver_secret().public_key().as_bytes());
+        assert_eq!(cert.client_magic, *b"client-2");
+        assert_eq!((cert.serial, cert.ts_start, cert.ts_end), (7, 1_700_000_000, 1_800_000_000));
+        assert_eq!(Certificate::parse(&certificate(1, 7, current()), &key).unwrap().version, EsVersion::XSalsa20Poly1305);
+    }
+
+    #[test]
+    fn tampered_or_foreign_certificates_are_rejected() {
+        let key = provider_key().verifying_key();
+        let cert = certificate(2, 7, current());
+
+        // Любой подписанный байт, включая срок действия, защищён подписью
+        for offset in [CERT_SIGNED_OFFSET, 104, 112, CERT_LEN - 1] {
+            let mut tampered = cert.clone();
+            tampered[offset] ^= 1;
+            assert!(Certificate::parse(&tampered, &key).is_err(), "{}", offset);
+        }
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(Certificate::parse(&cert, &other).is_err());
+        assert!(Certificate::parse(&cert[..CERT_LEN - 1], &key).is_err());
+        assert!(Certificate::parse(&certificate(3, 7, current()), &key).is_err());
+
+        let mut magic = cert.clone();
+        magic[..4].copy_from_slice(b"DNSX");
+        assert!(Certificate::parse(&magic, &key).is_err());
+    }
+
+    #[test]
+    fn boxes_match_the_nacl_vectors() {
+        let nonce = hex(NONCE);
+        let alice = SecretKey::from(key(ALICE_SECRET_KEY));
+        let bob = SecretKey::from(key(BOB_SECRET_KEY));
+
+        for (version, ciphertext) in [
+            (EsVersion::XSalsa20Poly1305, SALSA_CIPHERTEXT),
+            (EsVersion::XChaCha20Poly1305, CHACHA_CIPHERTEXT),
+        ] {
+            let sender = Cipher::new(version, &PublicKey::from(key(BOB_PUBLIC_KEY)), &alice);
+            assert_eq!(sender.seal(&nonce, &hex(PLAINTEXT)).unwrap(), hex(ciphertext), "{:?}", version);
+
+            let receiver = Cipher::new(version, &PublicKey::from(key(ALICE_PUBLIC_KEY)), &bob);
+            assert_eq!(receiver.open(&nonce, &hex(ciphertext)).unwrap(), hex(PLAINTEXT), "{:?}", version);
+        }
+
+        // Шифр выбирается по версии: вектор одного не открывается другим
+        let chacha = Cipher::new(EsVersion::XChaCha20Poly1305, &PublicKey::from(key(ALICE_PUBLIC_KEY)), &bob);
+        assert!(chacha.open(&nonce, &hex(SALSA_CIPHERTEXT)).is_err());
+    }
+
+    #[test]
+    fn padding_follows_iso_7816_4() {
+        let padded = pad(&[0xaa; 10], MIN_UDP_QUERY_LEN);
+        assert_eq!(padded.len(), 256);
+        assert_eq!(&padded[..11], [[0xaa; 10].as_slice(), &[0x80]].concat());
+        assert!(padded[11..].iter().all(|&b| b == 0));
+
+        assert_eq!(pad(&[1; 63], 0).len(), 64);
+        assert_eq!(pad(&[1; 64], 0).len(), 128);
+        assert_eq!(pad(&[1; 300], MIN_UDP_QUERY_LEN).len(), 320);
+
+        // Байты 0x80 и 0x00 в самом сообщении сохраняются
+        for data in [vec![], vec![0x80], vec![1, 0, 0], vec![0x80, 0]] {
+            assert_eq!(unpad(pad(&data, 0)).unwrap(), data);
+        }
+        assert!(unpad(vec![0; 64]).is_err());
+        assert!(unpad(vec![1, 2, 3, 0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_round_trip_through_both_ciphers() {
+        for version in [1, 2] {
+            let addr = resolver(vec![certificate(version, 1, current())]).await;
+            let client = client(addr);
+
+            let response = Message::from_vec(&client.exchange(&query()).await.unwrap()).unwrap();
+            assert_eq!(response.id(), 42);
+            assert_eq!(response.answers()[0].data(), Some(&RData::A(A::new(192, 0, 2, 53))));
+        }
+    }
+
+    #[tokio::test]
+    async fn newest_valid_certificate_and_stronger_cipher_are_chosen() {
+        let now = unix_now() as u32;
+        let addr = resolver(vec![
+            certificate(2, 1, current()),
+            certificate(1, 2, current()),
+            // Новее, но уже истёк
+            certificate(2, 3, now - 1000..now - 1),
+        ])
+        .await;
+        let session = client(addr).fetch_session().await.unwrap();
+        assert!(matches!(session.cipher, Cipher::Salsa(_)));
+        assert_eq!(session.client_magic, client_magic(1));
+
+        // При равных серийных номерах XChaCha20 предпочтительнее
+        let addr = resolver(vec![certificate(1, 2, current()), certificate(2, 2, current())]).await;
+        let session = client(addr).fetch_session().await.unwrap();
+        assert!(matches!(session.cipher, Cipher::ChaCha(_)));
+    }
+
+    #[tokio::test]
+    async fn responses_for_another_query_are_rejected() {
+        let addr = resolver(vec![certificate(2, 1, current())]).await;
+        let session = client(addr).fetch_session().await.unwrap();
+
+        let (packet, nonce) = session.encrypt(&query(), MIN_UDP_QUERY_LEN).unwrap();
+        let response = answer(&packet, 2);
+        assert!(session.decrypt(&response, &nonce).is_ok());
+
+        let mut other_nonce = nonce;
+        other_nonce[0] ^= 1;
+        assert!(session.decrypt(&response, &other_nonce).is_err());
+
+        let mut corrupted = response.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(session.decrypt(&corrupted, &nonce).is_err());
+        assert!(session.decrypt(&response[..RESOLVER_MAGIC.len() + NONCE_LEN], &nonce).is_err());
+    }
+}
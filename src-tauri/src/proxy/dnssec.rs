@@ -0,0 +1,1112 @@
+use super::resolver::ProxyResolver;
+use anyhow::{Context, Result};
+use hickory_proto::op::{Edns, Message, ResponseCode};
+use hickory_proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY, DS, NSEC, NSEC3, RRSIG};
+use hickory_proto::rr::dnssec::{Algorithm, DigestType, Verifier};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Якоря доверия корневой зоны: KSK-2017 и KSK-2024 (https://data.iana.org/root-anchors/)
+const ROOT_ANCHORS: [&str; 2] = [
+    ". 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+    ". 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+];
+/// Проверенные ключи и разрезы зон хранятся не дольше часа
+const MAX_STEP_TTL: u32 = 3600;
+/// Зоны с большим числом итераций NSEC3 считаются неподписанными (RFC 9276 §3.2)
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+/// Размер UDP-буфера для ответов с подписями
+const DNSSEC_PAYLOAD: u16 = 1232;
+/// DNAME (RFC 6672) не входит в перечень типов hickory
+const DNAME_TYPE: u16 = 39;
+const BASE32HEX: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnssecConfig {
+    /// Проверять подписи самостоятельно, не доверяя биту AD апстрима
+    pub enabled: bool,
+    /// Дополнительные якоря доверия в формате DS: `corp.example. 12345 13 2 <hex>`
+    pub trust_anchors: Vec<String>,
+}
+
+impl DnssecConfig {
+    /// Проверить якоря доверия до сохранения настроек
+    pub fn validate(&self) -> Result<()> {
+        for anchor in &self.trust_anchors {
+            parse_anchor(anchor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Якорь доверия в виде DS-записи ключа зоны
+fn parse_anchor(anchor: &str) -> Result<(Name, DS)> {
+    let fields: Vec<&str> = anchor.split_whitespace().collect();
+    let [zone, key_tag, algorithm, digest_type, digest] = fields[..] else {
+        anyhow::bail!("Trust anchor must look like '<zone> <key tag> <algorithm> <digest type> <digest>': {}", anchor);
+    };
+
+    let mut zone = Name::from_utf8(zone).with_context(|| format!("Invalid trust anchor zone: {}", zone))?;
+    zone.set_fqdn(true);
+    let digest_type = DigestType::from_u8(digest_type.parse().context("Invalid trust anchor digest type")?)
+        .map_err(|e| anyhow::anyhow!("Unsupported trust anchor digest type: {}", e))?;
+
+    Ok((
+        zone,
+        DS::new(
+            key_tag.parse().context("Invalid trust anchor key tag")?,
+            Algorithm::from_u8(algorithm.parse().context("Invalid trust anchor algorithm")?),
+            digest_type,
+            decode_hex(digest).context("Trust anchor digest must be hex")?,
+        ),
+    ))
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Состояние зоны в цепочке доверия
+#[derive(Clone)]
+enum ZoneState {
+    /// Ключи зоны подтверждены цепочкой от якоря
+    Secure(Arc<Vec<DNSKEY>>),
+    /// Делегирование без DS: ниже подписи не проверяются
+    Insecure,
+}
+
+/// Что выяснилось об имени при спуске от якоря
+#[derive(Clone)]
+enum Step {
+    Zone(ZoneState),
+    /// Имя лежит внутри родительской зоны
+    NotACut,
+    /// Имя не существует или является псевдонимом — ниже разрезов нет
+    End,
+}
+
+/// Проверяющий DNSSEC-валидатор: строит цепочку доверия от якоря до зоны,
+/// подписавшей ответ, и проверяет подписи и доказательства отсутствия
+pub struct DnssecValidator {
+    anchors: Vec<(Name, DS)>,
+    steps: Mutex<HashMap<Name, (Step, Instant)>>,
+}
+
+impl DnssecValidator {
+    pub fn new(config: &DnssecConfig) -> Result<Self> {
+        let anchors = ROOT_ANCHORS
+            .iter()
+            .copied()
+            .chain(config.trust_anchors.iter().map(String::as_str))
+            .map(parse_anchor)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            anchors,
+            steps: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Проверить ответ апстрима. Возвращает ответ с битом AD, выставленным только
+    /// для подтверждённых данных; ошибка означает поддельный или непроверяемый ответ.
+    pub async fn validate(&self, resolver: &ProxyResolver, request: &Message, response: &[u8]) -> Result<Vec<u8>> {
+        let mut message = Message::from_vec(response).context("Malformed answer")?;
+        let query = request.query().context("Query has no question")?;
+        let now = unix_now();
+
+        let mut secure = true;
+        let mut wildcards = Vec::new();
+        let dnames: Vec<Name> = message
+            .answers()
+            .iter()
+            .filter(|r| u16::from(r.record_type()) == DNAME_TYPE)
+            .map(|r| r.name().clone())
+            .collect();
+
+        for (name, record_type) in rrset_keys(message.answers()) {
+            let (records, sigs) = rrset(message.answers(), &name, record_type);
+            // CNAME, синтезированный из подписанного DNAME, подписи не имеет (RFC 6672 §5.3.1)
+            if record_type == RecordType::CNAME && sigs.is_empty() && dnames.iter().any(|d| d.zone_of(&name)) {
+                continue;
+            }
+            secure &= self.verify_rrset(resolver, &name, record_type, &records, &sigs, now).await?;
+            // Подпись с меньшим числом меток — ответ развёрнут из wildcard (RFC 4035 §5.3.4)
+            if let Some(sig) = sigs.iter().find(|sig| sig.num_labels() < name.num_labels()) {
+                wildcards.push(name.trim_to(sig.num_labels() as usize + 1));
+            }
+        }
+
+        if secure && !wildcards.is_empty() {
+            let authority = message.name_servers();
+            for (name, record_type) in rrset_keys(authority) {
+                if matches!(record_type, RecordType::NSEC | RecordType::NSEC3) {
+                    let (records, sigs) = rrset(authority, &name, record_type);
+                    self.verify_rrset(resolver, &name, record_type, &records, &sigs, now).await?;
+                }
+            }
+            let denial = Denial::collect(authority);
+            for next_closer in wildcards {
+                if !denial.covers(&next_closer) {
+                    anyhow::bail!("Wildcard answer has no proof that {} does not exist", next_closer);
+                }
+            }
+        }
+
+        let target = follow_cnames(query.name(), message.answers());
+        let answered = message
+            .answers()
+            .iter()
+            .any(|r| *r.name() == target && (r.record_type() == query.query_type() || query.query_type() == RecordType::ANY));
+        let nxdomain = message.response_code() == ResponseCode::NXDomain;
+        if !answered && (nxdomain || message.response_code() == ResponseCode::NoError) {
+            secure &= self
+                .verify_denial(resolver, &message, &target, query.query_type(), nxdomain, now)
+                .await?;
+        }
+
+        message.set_authentic_data(secure);
+        Ok(message.to_vec()?)
+    }
+
+    /// Проверить RRset ответа; `false` — данные из неподписанной зоны
+    async fn verify_rrset(
+        &self,
+        resolver: &ProxyResolver,
+        name: &Name,
+        record_type: RecordType,
+        records: &[Record],
+        sigs: &[RRSIG],
+        now: u32,
+    ) -> Result<bool> {
+        let Some(signer) = sigs.first().map(|sig| sig.signer_name().clone()) else {
+            return match self.zone_for(resolver, name).await? {
+                (_, ZoneState::Insecure) => Ok(false),
+                (zone, ZoneState::Secure(_)) => {
+                    anyhow::bail!("{} {} from signed zone {} has no signatures", name, record_type, zone)
+                }
+            };
+        };
+
+        if !signer.zone_of(name) {
+            anyhow::bail!("{} {} is signed by {}, which is not its zone", name, record_type, signer);
+        }
+
+        let (zone, state) = self.zone_for(resolver, &signer).await?;
+        let ZoneState::Secure(keys) = state else {
+            return Ok(false);
+        };
+        if zone != signer {
+            anyhow::bail!("{} {} is signed by {}, but the enclosing zone is {}", name, record_type, signer, zone);
+        }
+
+        verify_signatures(name, record_type, records, sigs, &keys, now)?;
+        Ok(true)
+    }
+
+    /// Проверить доказательство отсутствия имени (NXDOMAIN) или типа (NODATA)
+    async fn verify_denial(
+        &self,
+        resolver: &ProxyResolver,
+        message: &Message,
+        target: &Name,
+        query_type: RecordType,
+        nxdomain: bool,
+        now: u32,
+    ) -> Result<bool> {
+        let authority = message.name_servers();
+        let soa_owner = authority
+            .iter()
+            .find(|r| r.record_type() == RecordType::SOA)
+            .map(|r| r.name().clone());
+
+        let (zone, state) = self.zone_for(resolver, soa_owner.as_ref().unwrap_or(target)).await?;
+        let ZoneState::Secure(keys) = state else {
+            return Ok(false);
+        };
+        if soa_owner.as_ref() != Some(&zone) {
+            anyhow::bail!("Negative answer for {} {} has no SOA record of signed zone {}", target, query_type, zone);
+        }
+
+        for (name, record_type) in rrset_keys(authority) {
+            if matches!(record_type, RecordType::SOA | RecordType::NSEC | RecordType::NSEC3) {
+                let (records, sigs) = rrset(authority, &name, record_type);
+                verify_signatures(&name, record_type, &records, &sigs, &keys, now)?;
+            }
+        }
+
+        let denial = Denial::collect(authority);
+        if denial.nsec3.iter().any(|(_, nsec3)| nsec3.iterations() > MAX_NSEC3_ITERATIONS) {
+            debug!("NSEC3 of {} uses too many iterations, treating it as unsigned", zone);
+            return Ok(false);
+        }
+
+        let proven = if nxdomain {
+            denial.proves_nxdomain(target, &zone)
+        } else {
+            denial.proves_nodata(target, query_type)
+        };
+        if !proven {
+            anyhow::bail!("No valid proof that {} {} does not exist", target, query_type);
+        }
+        Ok(true)
+    }
+
+    /// Ближайшая зона, содержащая `name`, и её состояние в цепочке доверия
+    async fn zone_for(&self, resolver: &ProxyResolver, name: &Name) -> Result<(Name, ZoneState)> {
+        let Some((anchor_zone, _)) = self
+            .anchors
+            .iter()
+            .filter(|(zone, _)| zone.zone_of(name))
+            .max_by_key(|(zone, _)| zone.num_labels())
+        else {
+            return Ok((Name::root(), ZoneState::Insecure));
+        };
+
+        let mut zone = anchor_zone.clone();
+        let mut state = match self.cached_step(&zone) {
+            Some(Step::Zone(state)) => state,
+            _ => {
+                let ds: Vec<DS> = self
+                    .anchors
+                    .iter()
+                    .filter(|(anchor, _)| *anchor == zone)
+                    .map(|(_, ds)| ds.clone())
+                    .collect();
+                let (state, ttl) = self.zone_keys(resolver, &zone, &ds).await?;
+                self.store_step(&zone, Step::Zone(state.clone()), ttl);
+                state
+            }
+        };
+
+        for depth in zone.num_labels() + 1..=name.num_labels() {
+            let ZoneState::Secure(keys) = &state else {
+                break;
+            };
+            let child = name.trim_to(depth as usize);
+
+            let step = match self.cached_step(&child) {
+                Some(step) => step,
+                None => {
+                    let (step, ttl) = self.descend(resolver, &zone, keys, &child).await?;
+                    self.store_step(&child, step.clone(), ttl);
+                    step
+                }
+            };
+
+            match step {
+                Step::Zone(next) => {
+                    zone = child;
+                    state = next;
+                }
+                Step::NotACut => {}
+                Step::End => break,
+            }
+        }
+
+        Ok((zone, state))
+    }
+
+    /// Узнать у родительской зоны, является ли `child` подписанным разрезом
+    async fn descend(&self, resolver: &ProxyResolver, parent: &Name, keys: &[DNSKEY], child: &Name) -> Result<(Step, u32)> {
+        let response = resolver.dnssec_lookup(child, RecordType::DS).await?;
+        let now = unix_now();
+        let ttl = min_ttl(response.answers().iter().chain(response.name_servers()));
+
+        let (records, sigs) = rrset(response.answers(), child, RecordType::DS);
+        if !records.is_empty() {
+            verify_signatures(child, RecordType::DS, &records, &sigs, keys, now)
+                .with_context(|| format!("DS of {} is not signed by {}", child, parent))?;
+            let ds: Vec<DS> = records
+                .iter()
+                .filter_map(|r| match r.data() {
+                    Some(RData::DNSSEC(DNSSECRData::DS(ds))) => Some(ds.clone()),
+                    _ => None,
+                })
+                .collect();
+            let (state, key_ttl) = self.zone_keys(resolver, child, &ds).await?;
+            return Ok((Step::Zone(state), ttl.min(key_ttl)));
+        }
+
+        if response.response_code() == ResponseCode::NXDomain
+            || response.answers().iter().any(|r| r.record_type() == RecordType::CNAME)
+        {
+            return Ok((Step::End, ttl));
+        }
+
+        let authority = response.name_servers();
+        for (name, record_type) in rrset_keys(authority) {
+            if matches!(record_type, RecordType::NSEC | RecordType::NSEC3) {
+                let (records, sigs) = rrset(authority, &name, record_type);
+                verify_signatures(&name, record_type, &records, &sigs, keys, now)
+                    .with_context(|| format!("Denial of DS for {} is not signed by {}", child, parent))?;
+            }
+        }
+
+        let step = Denial::collect(authority)
+            .ds_absence(child)
+            .with_context(|| format!("{} did not prove that {} has no DS record", parent, child))?;
+        Ok((step, ttl))
+    }
+
+    /// Проверить DNSKEY зоны по её DS-записям. Если ни один алгоритм DS не
+    /// поддерживается, зона считается неподписанной (RFC 4035 §5.2).
+    async fn zone_keys(&self, resolver: &ProxyResolver, zone: &Name, ds: &[DS]) -> Result<(ZoneState, u32)> {
+        let supported: Vec<&DS> = ds
+            .iter()
+            .filter(|ds| is_supported(ds.algorithm()))
+            .filter(|ds| matches!(ds.digest_type(), DigestType::SHA1 | DigestType::SHA256 | DigestType::SHA384))
+            .collect();
+        if supported.is_empty() {
+            debug!("No supported DS algorithm for {}, treating it as unsigned", zone);
+            return Ok((ZoneState::Insecure, MAX_STEP_TTL));
+        }
+
+        let response = resolver.dnssec_lookup(zone, RecordType::DNSKEY).await?;
+        let (records, sigs) = rrset(response.answers(), zone, RecordType::DNSKEY);
+        let keys: Vec<DNSKEY> = records
+            .iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::DNSSEC(DNSSECRData::DNSKEY(key))) if key.zone_key() && !key.revoke() => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let entry_keys: Vec<DNSKEY> = keys
+            .iter()
+            .filter(|key| {
+                supported.iter().any(|ds| {
+                    key.algorithm() == ds.algorithm()
+                        && key.calculate_key_tag().ok() == Some(ds.key_tag())
+                        && ds.covers(zone, key).unwrap_or(false)
+                })
+            })
+            .cloned()
+            .collect();
+        if entry_keys.is_empty() {
+            anyhow::bail!("No DNSKEY of {} matches its DS records", zone);
+        }
+
+        verify_signatures(zone, RecordType::DNSKEY, &records, &sigs, &entry_keys, unix_now())
+            .with_context(|| format!("DNSKEY set of {} is not signed by its key-signing key", zone))?;
+        Ok((ZoneState::Secure(Arc::new(keys)), min_ttl(records.iter())))
+    }
+
+    fn cached_step(&self, name: &Name) -> Option<Step> {
+        let steps = self.steps.lock().ok()?;
+        steps
+            .get(name)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(step, _)| step.clone())
+    }
+
+    fn store_step(&self, name: &Name, step: Step, ttl: u32) {
+        if let Ok(mut steps) = self.steps.lock() {
+            let expires = Instant::now() + Duration::from_secs(ttl.min(MAX_STEP_TTL) as u64);
+            steps.retain(|_, (_, expires)| *expires > Instant::now());
+            steps.insert(name.clone(), (step, expires));
+        }
+    }
+}
+
+/// Доказательства отсутствия из секции авторитета
+struct Denial {
+    nsec: Vec<(Name, NSEC)>,
+    nsec3: Vec<(Name, NSEC3)>,
+}
+
+impl Denial {
+    fn collect(authority: &[Record]) -> Self {
+        let mut denial = Self {
+            nsec: Vec::new(),
+            nsec3: Vec::new(),
+        };
+        for record in authority {
+            match record.data() {
+                Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) => denial.nsec.push((record.name().clone(), nsec.clone())),
+                Some(RData::DNSSEC(DNSSECRData::NSEC3(nsec3))) => {
+                    denial.nsec3.push((record.name().clone(), nsec3.clone()))
+                }
+                _ => {}
+            }
+        }
+        denial
+    }
+
+    /// Типы, существующие у имени, если для него есть точная запись NSEC или NSEC3
+    fn types_at(&self, name: &Name) -> Option<&[RecordType]> {
+        if let Some((_, nsec)) = self.nsec.iter().find(|(owner, _)| owner == name) {
+            return Some(nsec.type_bit_maps());
+        }
+        self.nsec3
+            .iter()
+            .find(|(owner, nsec3)| nsec3_hash(nsec3, name).is_some_and(|hash| owner_hash(owner) == hash))
+            .map(|(_, nsec3)| nsec3.type_bit_maps())
+    }
+
+    fn proves_nodata(&self, name: &Name, query_type: RecordType) -> bool {
+        self.types_at(name)
+            .is_some_and(|types| !types.contains(&query_type) && !types.contains(&RecordType::CNAME))
+    }
+
+    /// Есть ли NSEC или NSEC3, покрывающая несуществующее имя
+    fn covers(&self, name: &Name) -> bool {
+        self.covering_nsec(name).is_some() || self.nsec3_covers(name).is_some()
+    }
+
+    /// NSEC, интервал которой содержит имя
+    fn covering_nsec(&self, name: &Name) -> Option<&(Name, NSEC)> {
+        self.nsec
+            .iter()
+            .find(|(owner, nsec)| nsec_covers(owner, nsec.next_domain_name(), name))
+    }
+
+    /// NXDOMAIN доказан, если нет ни самого имени, ни wildcard у его
+    /// ближайшего существующего предка (RFC 4035 §5.4, RFC 5155 §8.4)
+    fn proves_nxdomain(&self, name: &Name, zone: &Name) -> bool {
+        if let Some((owner, nsec)) = self.covering_nsec(name) {
+            // Ближайший предок — самый длинный общий предок имени и границ интервала
+            let encloser = [owner, nsec.next_domain_name()]
+                .into_iter()
+                .map(|bound| common_ancestor(name, bound))
+                .max_by_key(Name::num_labels)
+                .unwrap_or_else(Name::root);
+            return zone.zone_of(&encloser) && wildcard_of(&encloser).is_some_and(|w| self.covering_nsec(&w).is_some());
+        }
+
+        // NSEC3: ближайший существующий предок задан точной записью, следующее за ним имя покрыто
+        let mut encloser = name.base_name();
+        while zone.zone_of(&encloser) {
+            if self.types_at(&encloser).is_some() {
+                let next_closer = name.trim_to(encloser.num_labels() as usize + 1);
+                return self.nsec3_covers(&next_closer).is_some()
+                    && wildcard_of(&encloser).is_some_and(|w| self.nsec3_covers(&w).is_some());
+            }
+            if encloser.is_root() {
+                break;
+            }
+            encloser = encloser.base_name();
+        }
+        false
+    }
+
+    /// NSEC3, хеш владельца которого покрывает хеш имени
+    fn nsec3_covers(&self, name: &Name) -> Option<&NSEC3> {
+        self.nsec3.iter().find_map(|(owner, nsec3)| {
+            let hash = nsec3_hash(nsec3, name)?;
+            let (owner, next) = (owner_hash(owner), encode_base32hex(nsec3.next_hashed_owner_name()));
+            let covered = if owner < next {
+                owner < hash && hash < next
+            } else {
+                hash > owner || hash < next
+            };
+            covered.then_some(nsec3)
+        })
+    }
+
+    /// Что доказывает ответ без DS: небезопасное делегирование или имя внутри зоны
+    fn ds_absence(&self, child: &Name) -> Result<Step> {
+        if let Some(types) = self.types_at(child) {
+            if types.contains(&RecordType::DS) {
+                anyhow::bail!("The denial lists a DS record for {}", child);
+            }
+            let delegation = types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA);
+            return Ok(if delegation { Step::Zone(ZoneState::Insecure) } else { Step::NotACut });
+        }
+
+        if self.covering_nsec(child).is_some() {
+            return Ok(Step::NotACut);
+        }
+
+        if self.nsec3.iter().any(|(_, nsec3)| nsec3.iterations() > MAX_NSEC3_ITERATIONS) {
+            return Ok(Step::Zone(ZoneState::Insecure));
+        }
+        match self.nsec3_covers(child) {
+            // Opt-out: в покрытом интервале могут быть неподписанные делегирования
+            Some(nsec3) if nsec3.opt_out() => Ok(Step::Zone(ZoneState::Insecure)),
+            Some(_) => Ok(Step::NotACut),
+            None => anyhow::bail!("No NSEC or NSEC3 record covers {}", child),
+        }
+    }
+}
+
+/// Проверить RRset подписями, сделанными одним из ключей `keys`
+fn verify_signatures(
+    name: &Name,
+    record_type: RecordType,
+    records: &[Record],
+    sigs: &[RRSIG],
+    keys: &[DNSKEY],
+    now: u32,
+) -> Result<()> {
+    let mut failure = format!("{} {} has no signature from a trusted key", name, record_type);
+
+    for sig in sigs {
+        // Меток в подписи не может быть больше, чем у владельца (RFC 4035 §5.3.1)
+        if sig.num_labels() > name.num_labels() {
+            failure = format!("Signature over {} {} claims more labels than its owner has", name, record_type);
+            continue;
+        }
+        if now < sig.sig_inception() || now > sig.sig_expiration() {
+            failure = format!("Signature over {} {} is expired or not yet valid", name, record_type);
+            continue;
+        }
+        let candidates = keys
+            .iter()
+            .filter(|key| key.algorithm() == sig.algorithm() && key.calculate_key_tag().ok() == Some(sig.key_tag()));
+        for key in candidates {
+            match key.verify_rrsig(name, DNSClass::IN, sig, records) {
+                Ok(()) => return Ok(()),
+                Err(e) => failure = format!("Signature over {} {} does not verify: {}", name, record_type, e),
+            }
+        }
+    }
+
+    anyhow::bail!(failure)
+}
+
+/// Записи RRset и подписи над ним
+fn rrset(records: &[Record], name: &Name, record_type: RecordType) -> (Vec<Record>, Vec<RRSIG>) {
+    let mut rrset = Vec::new();
+    let mut sigs = Vec::new();
+    for record in records.iter().filter(|r| r.name() == name) {
+        match record.data() {
+            Some(RData::DNSSEC(DNSSECRData::RRSIG(sig))) if sig.type_covered() == record_type => sigs.push(sig.clone()),
+            _ if record.record_type() == record_type => rrset.push(record.clone()),
+            _ => {}
+        }
+    }
+    (rrset, sigs)
+}
+
+/// Различные пары (имя, тип) среди записей, кроме самих подписей
+fn rrset_keys(records: &[Record]) -> Vec<(Name, RecordType)> {
+    let mut keys: Vec<(Name, RecordType)> = Vec::new();
+    for record in records.iter().filter(|r| r.record_type() != RecordType::RRSIG) {
+        let key = (record.name().clone(), record.record_type());
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Имя, к которому ведёт цепочка CNAME из ответа
+fn follow_cnames(name: &Name, answers: &[Record]) -> Name {
+    let mut current = name.clone();
+    for _ in 0..answers.len() {
+        let next = answers.iter().find_map(|r| match r.data() {
+            Some(RData::CNAME(target)) if *r.name() == current => Some(target.0.clone()),
+            _ => None,
+        });
+        match next {
+            Some(target) => current = target,
+            None => break,
+        }
+    }
+    current
+}
+
+/// Лежит ли `name` строго между `owner` и `next` в каноническом порядке (RFC 4034 §6.1)
+fn nsec_covers(owner: &Name, next: &Name, name: &Name) -> bool {
+    if owner < next {
+        owner < name && name < next
+    } else {
+        // Последняя NSEC зоны указывает обратно на её вершину
+        name > owner || name < next
+    }
+}
+
+/// Самый длинный общий предок двух имён
+fn common_ancestor(name: &Name, other: &Name) -> Name {
+    let mut ancestor = name.clone();
+    while !ancestor.zone_of(other) && !ancestor.is_root() {
+        ancestor = ancestor.base_name();
+    }
+    ancestor
+}
+
+/// Имя `*.<encloser>`
+fn wildcard_of(encloser: &Name) -> Option<Name> {
+    Name::from_ascii("*").ok()?.append_domain(encloser).ok()
+}
+
+fn nsec3_hash(nsec3: &NSEC3, name: &Name) -> Option<String> {
+    if nsec3.iterations() > MAX_NSEC3_ITERATIONS {
+        return None;
+    }
+    let digest = nsec3
+        .hash_algorithm()
+        .hash(nsec3.salt(), name, nsec3.iterations())
+        .ok()?;
+    Some(encode_base32hex(digest.as_ref()))
+}
+
+/// Хеш из первой метки владельца NSEC3
+fn owner_hash(owner: &Name) -> String {
+    owner
+        .iter()
+        .next()
+        .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Base32 с алфавитом «extended hex» без дополнения (RFC 4648 §7), как в именах NSEC3
+fn encode_base32hex(data: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32HEX[((buffer >> bits) & 0x1F) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32HEX[((buffer << (5 - bits)) & 0x1F) as usize] as char);
+    }
+    out
+}
+
+fn is_supported(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::RSASHA256 | Algorithm::RSASHA512 | Algorithm::ECDSAP256SHA256 | Algorithm::ECDSAP384SHA384 | Algorithm::ED25519
+    )
+}
+
+fn min_ttl<'a>(records: impl Iterator<Item = &'a Record>) -> u32 {
+    records.map(Record::ttl).min().unwrap_or(MAX_STEP_TTL)
+}
+
+fn unix_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+/// Запрос с битами DO и CD: апстрим должен вернуть подписи и не отбрасывать
+/// ответы, которые сам считает поддельными — проверка выполняется здесь
+pub fn dnssec_query(request: &Message) -> Result<Vec<u8>> {
+    let mut query = request.clone();
+    query.set_checking_disabled(true);
+    let edns = query.extensions_mut().get_or_insert_with(Edns::new);
+    edns.set_dnssec_ok(true);
+    edns.set_max_payload(edns.max_payload().max(DNSSEC_PAYLOAD));
+    Ok(query.to_vec()?)
+}
+
+/// Снять бит AD с ответа, который прокси не проверял: AD выставляет только валидатор
+pub fn without_authentic_data(mut response: Vec<u8>) -> Vec<u8> {
+    // AD — бит 0x20 четвёртого байта заголовка (RFC 4035 §3.1.6)
+    if let Some(flags) = response.get_mut(3) {
+        *flags &= !0x20;
+    }
+    response
+}
+
+/// Привести проверенный ответ к запросу клиента: без DO клиент не получает
+/// подписей и бита AD (RFC 4035 §3.2.1, RFC 6840 §5.8), без EDNS — записи OPT
+pub fn for_client(request: &Message, response: Vec<u8>) -> Vec<u8> {
+    let Ok(mut message) = Message::from_vec(&response) else {
+        return response;
+    };
+
+    let dnssec_ok = request.extensions().as_ref().is_some_and(|edns| edns.dnssec_ok());
+    if !dnssec_ok {
+        let query_type = request.query().map(|q| q.query_type());
+        let keep = |record: &Record| {
+            !matches!(record.record_type(), RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3)
+                || Some(record.record_type()) == query_type
+        };
+        message.answers_mut().retain(keep);
+        message.name_servers_mut().retain(keep);
+        message.additionals_mut().retain(keep);
+        if !request.authentic_data() {
+            message.set_authentic_data(false);
+        }
+    }
+    if request.extensions().is_none() {
+        *message.extensions_mut() = None;
+    }
+    message.set_checking_disabled(request.checking_disabled());
+
+    message.to_vec().unwrap_or(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::proxy::blocklist::Blocklists;
+    use crate::proxy::cache::CacheConfig;
+    use crate::proxy::querylog::QueryLogConfig;
+    use crate::proxy::upstream::Upstream;
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::dnssec::{tbs, KeyFormat, KeyPair, Nsec3HashAlgorithm, Private};
+    use hickory_proto::rr::rdata::A;
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+
+    fn name(value: &str) -> Name {
+        Name::from_ascii(value).unwrap()
+    }
+
+    /// Подписанная зона с одним ключом ED25519, она же якорь доверия
+    struct Zone {
+        apex: Name,
+        key: KeyPair<Private>,
+        dnskey: DNSKEY,
+        tag: u16,
+    }
+
+    impl Zone {
+        fn new(apex: &str) -> Self {
+            let pkcs8 = KeyPair::<Private>::generate_pkcs8(Algorithm::ED25519).unwrap();
+            let key = KeyFormat::Pkcs8.decode_key(&pkcs8, None, Algorithm::ED25519).unwrap();
+            let dnskey = key.to_dnskey(Algorithm::ED25519).unwrap();
+            let tag = dnskey.calculate_key_tag().unwrap();
+            Self {
+                apex: name(apex),
+                key,
+                dnskey,
+                tag,
+            }
+        }
+
+        fn anchor(&self) -> String {
+            let digest = self.dnskey.to_digest(&self.apex, DigestType::SHA256).unwrap();
+            let digest: String = digest.as_ref().iter().map(|b| format!("{:02X}", b)).collect();
+            format!("{} {} 15 2 {}", self.apex, self.tag, digest)
+        }
+
+        fn dnskey(&self) -> Record {
+            Record::from_rdata(self.apex.clone(), 3600, RData::DNSSEC(DNSSECRData::DNSKEY(self.dnskey.clone())))
+        }
+
+        /// RRSIG над RRset с заданным полем Labels
+        fn sign(&self, records: &[Record], labels: u8) -> Record {
+            let first = &records[0];
+            let now = unix_now();
+            let (inception, expiration) = (now - 3600, now + 3600);
+            let tbs = tbs::rrset_tbs(
+                first.name(),
+                DNSClass::IN,
+                labels,
+                first.record_type(),
+                Algorithm::ED25519,
+                first.ttl(),
+                expiration,
+                inception,
+                self.tag,
+                &self.apex,
+                records,
+            )
+            .unwrap();
+            let signature = self.key.sign(Algorithm::ED25519, &tbs).unwrap();
+            let sig = RRSIG::new(
+                first.record_type(),
+                Algorithm::ED25519,
+                labels,
+                first.ttl(),
+                expiration,
+                inception,
+                self.tag,
+                self.apex.clone(),
+                signature,
+            );
+            Record::from_rdata(first.name().clone(), first.ttl(), RData::DNSSEC(DNSSECRData::RRSIG(sig)))
+        }
+
+        /// Резолвер с проверкой DNSSEC; его апстрим на loopback отдаёт ключи зоны
+        /// на запрос DNSKEY и `answers` на всё остальное
+        async fn resolver(&self, answers: Vec<Record>) -> Arc<ProxyResolver> {
+            let dnskey = self.dnskey();
+            let keys = vec![dnskey.clone(), self.sign(&[dnskey], self.apex.num_labels())];
+
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = socket.local_addr().unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                    let request = Message::from_vec(&buf[..len]).unwrap();
+                    let query = request.queries()[0].clone();
+                    let records = if query.query_type() == RecordType::DNSKEY { &keys } else { &answers };
+                    let mut response = Message::new();
+                    response
+                        .set_id(request.id())
+                        .set_message_type(MessageType::Response)
+                        .add_query(query)
+                        .add_answers(records.clone());
+                    socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+                }
+            });
+
+            let resolver = Arc::new(ProxyResolver::new(
+                Duration::from_secs(1),
+                CacheConfig::default(),
+                QueryLogConfig::default(),
+                Arc::new(Blocklists::new()),
+            ));
+            resolver.set_upstreams(vec![Upstream::udp(addr)]);
+            resolver.set_dnssec(&DnssecConfig {
+                enabled: true,
+                trust_anchors: vec![self.anchor()],
+            });
+            resolver
+        }
+    }
+
+    /// Запрос A с битом AD: клиент без DO получает AD только так (RFC 6840 §5.7)
+    fn request(owner: &Name) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(0x0d5e)
+            .set_recursion_desired(true)
+            .set_authentic_data(true)
+            .add_query(Query::query(owner.clone(), RecordType::A));
+        message
+    }
+
+    async fn ask(resolver: &Arc<ProxyResolver>, request: &Message) -> Message {
+        let client: SocketAddr = "127.0.0.1:53000".parse().unwrap();
+        let response = resolver.handle(&request.to_vec().unwrap(), client).await.unwrap();
+        Message::from_vec(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn validated_chain_sets_ad() {
+        let _dir = config::use_temp_app_dir();
+        let zone = Zone::new("example.");
+        let www = name("www.example.");
+        let a = Record::from_rdata(www.clone(), 300, RData::A(A::new(192, 0, 2, 1)));
+        let resolver = zone.resolver(vec![a.clone(), zone.sign(&[a], 2)]).await;
+
+        let response = ask(&resolver, &request(&www)).await;
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.authentic_data());
+        // Клиент без DO не получает подписей
+        assert!(response.answers().iter().all(|r| r.record_type() == RecordType::A));
+    }
+
+    #[tokio::test]
+    async fn bogus_signature_is_servfail() {
+        let _dir = config::use_temp_app_dir();
+        let zone = Zone::new("example.");
+        let www = name("www.example.");
+        let signed = Record::from_rdata(www.clone(), 300, RData::A(A::new(192, 0, 2, 1)));
+        let forged = Record::from_rdata(www.clone(), 300, RData::A(A::new(203, 0, 113, 66)));
+        let answers = vec![forged, zone.sign(&[signed], 2)];
+        let resolver = zone.resolver(answers.clone()).await;
+        let request = request(&www);
+
+        assert_eq!(ask(&resolver, &request).await.response_code(), ResponseCode::ServFail);
+
+        // Эту причину резолвер пишет в журнал вместе с SERVFAIL
+        let mut upstream_answer = Message::new();
+        upstream_answer
+            .set_message_type(MessageType::Response)
+            .add_query(request.queries()[0].clone())
+            .add_answers(answers);
+        let validator = DnssecValidator::new(&DnssecConfig {
+            enabled: true,
+            trust_anchors: vec![zone.anchor()],
+        })
+        .unwrap();
+        let error = validator
+            .validate(&resolver, &request, &upstream_answer.to_vec().unwrap())
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("does not verify"), "{:#}", error);
+    }
+
+    #[test]
+    fn signatures_with_too_many_labels_are_rejected() {
+        let zone = Zone::new("example.");
+        let www = name("www.example.");
+        let a = Record::from_rdata(www.clone(), 300, RData::A(A::new(192, 0, 2, 1)));
+        let Some(RData::DNSSEC(DNSSECRData::RRSIG(valid))) = zone.sign(std::slice::from_ref(&a), 2).data().cloned() else {
+            unreachable!()
+        };
+        let inflated = RRSIG::new(
+            RecordType::A,
+            Algorithm::ED25519,
+            3,
+            valid.original_ttl(),
+            valid.sig_expiration(),
+            valid.sig_inception(),
+            valid.key_tag(),
+            valid.signer_name().clone(),
+            valid.sig().to_vec(),
+        );
+        let keys = [zone.dnskey.clone()];
+
+        let error = verify_signatures(&www, RecordType::A, std::slice::from_ref(&a), &[inflated], &keys, unix_now()).unwrap_err();
+        assert!(error.to_string().contains("more labels"), "{}", error);
+        verify_signatures(&www, RecordType::A, &[a], &[valid], &keys, unix_now()).unwrap();
+    }
+
+    fn nsec(owner: &str, next: &str, types: &[RecordType]) -> Record {
+        let nsec = NSEC::new(name(next), types.to_vec());
+        Record::from_rdata(name(owner), 300, RData::DNSSEC(DNSSECRData::NSEC(nsec)))
+    }
+
+    /// Хеш NSEC3 без соли и итераций как число из 20 байт
+    fn hashed(owner: &str) -> Vec<u8> {
+        Nsec3HashAlgorithm::SHA1.hash(&[], &name(owner), 0).unwrap().as_ref().to_vec()
+    }
+
+    /// Соседний хеш: `delta` прибавляется к последнему байту с переносом
+    fn step(hash: &[u8], delta: i16) -> Vec<u8> {
+        let mut out = hash.to_vec();
+        let mut carry = delta;
+        for byte in out.iter_mut().rev() {
+            let value = *byte as i16 + carry;
+            *byte = value.rem_euclid(256) as u8;
+            carry = value.div_euclid(256);
+            if carry == 0 {
+                break;
+            }
+        }
+        out
+    }
+
+    fn nsec3(owner_hash: &[u8], next_hash: Vec<u8>, types: &[RecordType]) -> Record {
+        let owner = name(&format!("{}.example.", encode_base32hex(owner_hash)));
+        let nsec3 = NSEC3::new(Nsec3HashAlgorithm::SHA1, false, 0, Vec::new(), next_hash, types.to_vec());
+        Record::from_rdata(owner, 300, RData::DNSSEC(DNSSECRData::NSEC3(nsec3)))
+    }
+
+    /// NSEC3, совпадающая с именем
+    fn nsec3_at(owner: &str, types: &[RecordType]) -> Record {
+        let hash = hashed(owner);
+        nsec3(&hash, step(&hash, 1), types)
+    }
+
+    /// NSEC3 с узким интервалом вокруг хеша имени
+    fn nsec3_around(owner: &str) -> Record {
+        let hash = hashed(owner);
+        nsec3(&step(&hash, -1), step(&hash, 1), &[RecordType::A])
+    }
+
+    #[test]
+    fn nsec_nxdomain_needs_a_missing_wildcard() {
+        let zone = name("example.");
+        let covering = nsec("a.example.", "c.example.", &[RecordType::A, RecordType::NSEC]);
+        // *.example. лежит между вершиной и a.example. в каноническом порядке
+        let no_wildcard = nsec("example.", "a.example.", &[RecordType::SOA, RecordType::NS, RecordType::NSEC]);
+
+        let proof = Denial::collect(&[covering.clone(), no_wildcard]);
+        assert!(proof.proves_nxdomain(&name("b.example."), &zone));
+        assert!(!proof.proves_nxdomain(&name("a.example."), &zone));
+
+        // Без доказательства отсутствия wildcard имя могло быть синтезировано
+        let partial = Denial::collect(&[covering]);
+        assert!(!partial.proves_nxdomain(&name("b.example."), &zone));
+    }
+
+    #[test]
+    fn nsec_nodata_checks_the_type_bitmap() {
+        let denial = Denial::collect(&[nsec("www.example.", "z.example.", &[RecordType::A, RecordType::NSEC])]);
+
+        assert!(denial.proves_nodata(&name("www.example."), RecordType::AAAA));
+        assert!(!denial.proves_nodata(&name("www.example."), RecordType::A));
+        assert!(!denial.proves_nodata(&name("mail.example."), RecordType::AAAA));
+
+        let alias = Denial::collect(&[nsec("www.example.", "z.example.", &[RecordType::CNAME])]);
+        assert!(!alias.proves_nodata(&name("www.example."), RecordType::AAAA));
+    }
+
+    #[test]
+    fn nsec3_nxdomain_needs_encloser_next_closer_and_wildcard() {
+        let zone = name("example.");
+        let encloser = nsec3_at("example.", &[RecordType::SOA, RecordType::NS]);
+        let next_closer = nsec3_around("y.example.");
+        let wildcard = nsec3_around("*.example.");
+
+        let proof = Denial::collect(&[encloser.clone(), next_closer.clone(), wildcard.clone()]);
+        assert!(proof.proves_nxdomain(&name("x.y.example."), &zone));
+
+        for incomplete in [
+            vec![encloser.clone(), next_closer.clone()],
+            vec![encloser, wildcard.clone()],
+            vec![next_closer, wildcard],
+        ] {
+            assert!(!Denial::collect(&incomplete).proves_nxdomain(&name("x.y.example."), &zone));
+        }
+    }
+
+    #[test]
+    fn nsec3_nodata_checks_the_type_bitmap() {
+        let denial = Denial::collect(&[nsec3_at("www.example.", &[RecordType::A, RecordType::RRSIG])]);
+
+        assert!(denial.proves_nodata(&name("www.example."), RecordType::AAAA));
+        assert!(!denial.proves_nodata(&name("www.example."), RecordType::A));
+        assert!(!denial.proves_nodata(&name("mail.example."), RecordType::AAAA));
+    }
+
+    #[test]
+    fn last_nsec_wraps_around_to_the_apex() {
+        let (last, apex) = (name("m.example."), name("example."));
+
+        assert!(nsec_covers(&last, &apex, &name("z.example.")));
+        assert!(nsec_covers(&last, &apex, &name("a.z.example.")));
+        assert!(!nsec_covers(&last, &apex, &name("b.example.")));
+        assert!(!nsec_covers(&last, &apex, &apex));
+        assert!(!nsec_covers(&last, &apex, &last));
+    }
+
+    #[test]
+    fn base32hex_matches_rfc_vectors() {
+        // RFC 4648 §10 без дополнения и в нижнем регистре
+        let vectors = [
+            ("", ""),
+            ("f", "co"),
+            ("fo", "cpng"),
+            ("foo", "cpnmu"),
+            ("foob", "cpnmuog"),
+            ("fooba", "cpnmuoj1"),
+            ("foobar", "cpnmuoj1e8"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(encode_base32hex(input.as_bytes()), expected);
+        }
+
+        // RFC 5155, приложение A: соль aabbccdd, 12 итераций
+        let digest = Nsec3HashAlgorithm::SHA1
+            .hash(&[0xaa, 0xbb, 0xcc, 0xdd], &name("example."), 12)
+            .unwrap();
+        assert_eq!(encode_base32hex(digest.as_ref()), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+    }
+
+    #[test]
+    fn unvalidated_answer_loses_only_ad() {
+        let mut message = Message::new();
+        message
+            .set_id(7)
+            .set_message_type(MessageType::Response)
+            .set_recursion_desired(true)
+            .set_recursion_available(true)
+            .set_checking_disabled(true)
+            .set_authentic_data(true);
+
+        let cleared = Message::from_vec(&without_authentic_data(message.to_vec().unwrap())).unwrap();
+        assert!(!cleared.authentic_data());
+        assert!(cleared.checking_disabled() && cleared.recursion_available() && cleared.recursion_desired());
+        assert_eq!(cleared.id(), 7);
+    }
+}
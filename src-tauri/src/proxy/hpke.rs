@@ -0,0 +1,222 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// HPKE (RFC 9180) в режиме base для единственного набора, который нужен ODoH:
+// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM. Только сторона отправителя.
+pub const KEM_X25519_HKDF_SHA256: u16 = 0x0020;
+pub const KDF_HKDF_SHA256: u16 = 0x0001;
+pub const AEAD_AES_128_GCM: u16 = 0x0001;
+
+/// Длина ключа AEAD
+pub const NK: usize = 16;
+/// Длина nonce AEAD
+pub const NN: usize = 12;
+/// Длина выхода хеша KDF
+const NH: usize = 32;
+
+const MODE_BASE: u8 = 0x00;
+const VERSION_LABEL: &[u8] = b"HPKE-v1";
+
+fn kem_suite_id() -> Vec<u8> {
+    [b"KEM".as_slice(), &KEM_X25519_HKDF_SHA256.to_be_bytes()].concat()
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    [
+        b"HPKE".as_slice(),
+        &KEM_X25519_HKDF_SHA256.to_be_bytes(),
+        &KDF_HKDF_SHA256.to_be_bytes(),
+        &AEAD_AES_128_GCM.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let labeled_ikm = [VERSION_LABEL, suite_id, label, ikm].concat();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm);
+    prk.to_vec()
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>> {
+    let length = u16::try_from(len).context("HPKE output too long")?.to_be_bytes();
+    let labeled_info = [length.as_slice(), VERSION_LABEL, suite_id, label, info].concat();
+
+    let mut okm = vec![0u8; len];
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|_| anyhow::anyhow!("Invalid HPKE pseudorandom key"))?
+        .expand(&labeled_info, &mut okm)
+        .map_err(|_| anyhow::anyhow!("HPKE output too long"))?;
+    Ok(okm)
+}
+
+/// Контекст отправителя: шифрует сообщения и выводит секреты экспорта
+pub struct SenderContext {
+    aead: Aes128Gcm,
+    base_nonce: [u8; NN],
+    exporter_secret: Vec<u8>,
+    seq: u64,
+}
+
+impl SenderContext {
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = self.base_nonce;
+        for (byte, seq) in nonce[NN - 8..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *byte ^= seq;
+        }
+        self.seq = self.seq.checked_add(1).context("HPKE sequence number overflow")?;
+
+        self.aead
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| anyhow::anyhow!("HPKE encryption failed"))
+    }
+
+    pub fn export(&self, exporter_context: &[u8], len: usize) -> Result<Vec<u8>> {
+        labeled_expand(&hpke_suite_id(), &self.exporter_secret, b"sec", exporter_context, len)
+    }
+}
+
+/// SetupBaseS: инкапсулировать общий секрет для `public_key` получателя.
+/// Возвращает `enc` (эфемерный открытый ключ) и контекст отправителя.
+pub fn setup_base_sender(public_key: &[u8; 32], info: &[u8]) -> Result<(Vec<u8>, SenderContext)> {
+    setup_base_sender_with(StaticSecret::random_from_rng(OsRng), public_key, info)
+}
+
+fn setup_base_sender_with(
+    ephemeral: StaticSecret,
+    public_key: &[u8; 32],
+    info: &[u8],
+) -> Result<(Vec<u8>, SenderContext)> {
+    let enc = PublicKey::from(&ephemeral).to_bytes();
+    let dh = ephemeral.diffie_hellman(&PublicKey::from(*public_key));
+    if !dh.was_contributory() {
+        anyhow::bail!("HPKE recipient key is invalid");
+    }
+
+    let kem_context = [enc.as_slice(), public_key].concat();
+    let eae_prk = labeled_extract(&kem_suite_id(), b"", b"eae_prk", dh.as_bytes());
+    let shared_secret = labeled_expand(&kem_suite_id(), &eae_prk, b"shared_secret", &kem_context, NH)?;
+
+    Ok((enc.to_vec(), key_schedule(&shared_secret, info)?))
+}
+
+fn key_schedule(shared_secret: &[u8], info: &[u8]) -> Result<SenderContext> {
+    let suite_id = hpke_suite_id();
+
+    let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+    let context = [[MODE_BASE].as_slice(), &psk_id_hash, &info_hash].concat();
+
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+    let key = labeled_expand(&suite_id, &secret, b"key", &context, NK)?;
+    let base_nonce = labeled_expand(&suite_id, &secret, b"base_nonce", &context, NN)?;
+    let exporter_secret = labeled_expand(&suite_id, &secret, b"exp", &context, NH)?;
+
+    Ok(SenderContext {
+        aead: Aes128Gcm::new_from_slice(&key).map_err(|_| anyhow::anyhow!("Invalid HPKE key"))?,
+        base_nonce: base_nonce.try_into().map_err(|_| anyhow::anyhow!("Invalid HPKE nonce"))?,
+        exporter_secret,
+        seq: 0,
+    })
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(value: &str) -> [u8; 32] {
+        hex(value).try_into().unwrap()
+    }
+
+    /// Сторона получателя (SetupBaseR) для тестов: расшифровать первое сообщение
+    /// и вернуть контекст, из которого получатель выводит секреты экспорта
+    pub fn open_base(
+        secret: &StaticSecret,
+        enc: &[u8],
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<(Vec<u8>, SenderContext)> {
+        let enc: [u8; 32] = enc.try_into().context("HPKE enc must be 32 bytes")?;
+        let public_key = PublicKey::from(secret).to_bytes();
+        let dh = secret.diffie_hellman(&PublicKey::from(enc));
+
+        let kem_context = [enc.as_slice(), &public_key].concat();
+        let eae_prk = labeled_extract(&kem_suite_id(), b"", b"eae_prk", dh.as_bytes());
+        let shared_secret = labeled_expand(&kem_suite_id(), &eae_prk, b"shared_secret", &kem_context, NH)?;
+
+        let context = key_schedule(&shared_secret, info)?;
+        let plaintext = context
+            .aead
+            .decrypt(Nonce::from_slice(&context.base_nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| anyhow::anyhow!("HPKE decryption failed"))?;
+        Ok((plaintext, context))
+    }
+
+    /// RFC 9180, приложение A.1.1: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM, base
+    #[test]
+    fn matches_rfc9180_base_vectors() {
+        let info = hex("4f6465206f6e2061204772656369616e2055726e");
+        let ephemeral = StaticSecret::from(key("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736"));
+        let recipient = StaticSecret::from(key("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8"));
+        let public_key = PublicKey::from(&recipient).to_bytes();
+        assert_eq!(public_key.to_vec(), hex("3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d"));
+
+        let (enc, mut context) = setup_base_sender_with(ephemeral, &public_key, &info).unwrap();
+        assert_eq!(enc, hex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431"));
+        assert_eq!(context.base_nonce.to_vec(), hex("56d890e5accaaf011cff4b7d"));
+        assert_eq!(
+            context.exporter_secret,
+            hex("45ff1c2e220db587171952c0592d5f5ebe103f1561a2614e38f2ffd47e99e3f8")
+        );
+
+        let plaintext = hex("4265617574792069732074727574682c20747275746820626561757479");
+        assert_eq!(
+            context.seal(b"Count-0", &plaintext).unwrap(),
+            hex("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a96d8770ac83d07bea87e13c512a")
+        );
+        assert_eq!(
+            context.seal(b"Count-1", &plaintext).unwrap(),
+            hex("af2d7e9ac9ae7e270f46ba1f975be53c09f8d875bdc8535458c2494e8a6eab251c03d0c22a56b8ca42c2063b84")
+        );
+
+        assert_eq!(
+            context.export(b"", 32).unwrap(),
+            hex("3853fe2b4035195a573ffc53856e77058e15d9ea064de3e59f4961d0095250ee")
+        );
+        assert_eq!(
+            context.export(b"TestContext", 32).unwrap(),
+            hex("e9e43065102c3836401bed8c3c3c75ae46be1639869391d62c61f1ec7af54931")
+        );
+    }
+
+    #[test]
+    fn recipient_opens_what_sender_sealed() {
+        let recipient = StaticSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(&recipient).to_bytes();
+
+        let (enc, mut sender) = setup_base_sender(&public_key, b"odoh query").unwrap();
+        let sealed = sender.seal(b"aad", b"query").unwrap();
+
+        let (plaintext, receiver) = open_base(&recipient, &enc, b"odoh query", b"aad", &sealed).unwrap();
+        assert_eq!(plaintext, b"query");
+        assert_eq!(receiver.export(b"odoh response", NK).unwrap(), sender.export(b"odoh response", NK).unwrap());
+        assert!(open_base(&recipient, &enc, b"odoh query", b"other aad", &sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_low_order_recipient_key() {
+        assert!(setup_base_sender(&[0u8; 32], b"").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use super::blocklist::Blocklists;
+use super::resolver::ProxyResolver;
+use super::strategy::{LatencyHistogram, LATENCY_BUCKETS};
+use anyhow::{Context, Result};
+use hickory_proto::op::ResponseCode;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// Заголовки запроса длиннее этого не читаются
+const MAX_REQUEST: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Отдавать метрики в формате OpenMetrics по HTTP
+    pub enabled: bool,
+    /// Только loopback: метрики раскрывают имена апстримов и адаптеров
+    pub listen_address: SocketAddr,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 9153),
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.listen_address.ip().is_loopback() {
+            anyhow::bail!("Metrics endpoint must listen on a loopback address, got {}", self.listen_address);
+        }
+        Ok(())
+    }
+}
+
+/// Источники данных для метрик
+#[derive(Clone)]
+pub struct MetricsSource {
+    pub resolver: Arc<ProxyResolver>,
+    pub blocklists: Arc<Blocklists>,
+    /// Активный пресет по имени адаптера
+    pub active_presets: Arc<Mutex<BTreeMap<String, String>>>,
+    /// Гистограммы задержек проверок по адресу сервера
+    pub probe_latencies: Arc<Mutex<BTreeMap<String, LatencyHistogram>>>,
+}
+
+impl MetricsSource {
+    /// Текст в формате OpenMetrics
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        family(&mut out, "dns_switcher_upstream_latency_seconds", "histogram", "Latency of successful upstream exchanges.");
+        for (upstream, latencies) in self.resolver.upstream_latencies() {
+            histogram(&mut out, "dns_switcher_upstream_latency_seconds", "upstream", &upstream, &latencies);
+        }
+
+        family(&mut out, "dns_switcher_probe_latency_seconds", "histogram", "Latency of successful DNS server tests.");
+        if let Ok(probes) = self.probe_latencies.lock() {
+            for (server, latencies) in probes.iter() {
+                histogram(&mut out, "dns_switcher_probe_latency_seconds", "server", server, latencies);
+            }
+        }
+
+        family(&mut out, "dns_switcher_proxy_queries", "counter", "Queries answered by the local proxy.");
+        for (key, count) in self.resolver.query_log().counters() {
+            let _ = writeln!(
+                out,
+                "dns_switcher_proxy_queries_total{{rcode=\"{}\",upstream=\"{}\",cache=\"{}\"}} {}",
+                rcode_label(&key.rcode),
+                escape(&key.upstream),
+                key.cache,
+                count
+            );
+        }
+
+        let blocklists = self.blocklists.status();
+        family(&mut out, "dns_switcher_blocklist_domains", "gauge", "Domains in the combined blocklist.");
+        let _ = writeln!(out, "dns_switcher_blocklist_domains {}", blocklists.domains);
+        family(&mut out, "dns_switcher_blocklist_source_entries", "gauge", "Unique blocked domains loaded from each blocklist source.");
+        for source in &blocklists.sources {
+            let _ = writeln!(
+                out,
+                "dns_switcher_blocklist_source_entries{{source=\"{}\",name=\"{}\",enabled=\"{}\"}} {}",
+                escape(&source.id),
+                escape(&source.name),
+                source.enabled,
+                source.entries
+            );
+        }
+        family(&mut out, "dns_switcher_blocked_queries", "counter", "Queries answered from the blocklist.");
+        let _ = writeln!(out, "dns_switcher_blocked_queries_total {}", blocklists.blocked_queries);
+
+        family(&mut out, "dns_switcher_active_preset", "info", "Preset applied to each network adapter.");
+        if let Ok(presets) = self.active_presets.lock() {
+            for (adapter, preset) in presets.iter() {
+                let _ = writeln!(
+                    out,
+                    "dns_switcher_active_preset_info{{adapter=\"{}\",preset=\"{}\"}} 1",
+                    escape(adapter),
+                    escape(preset)
+                );
+            }
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+}
+
+/// Серия гистограммы: накопительные корзины, `+Inf`, сумма и количество
+fn histogram(out: &mut String, name: &str, label: &str, value: &str, histogram: &LatencyHistogram) {
+    let value = escape(value);
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+        cumulative += count;
+        let _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}", name, label, value, bound, cumulative);
+    }
+    let _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"+Inf\"}} {}", name, label, value, histogram.count);
+    let _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, label, value, histogram.sum_secs);
+    let _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, label, value, histogram.count);
+}
+
+/// Мнемоника кода ответа (`NOERROR`, `NXDOMAIN`) по тексту из журнала запросов
+fn rcode_label(text: &str) -> String {
+    (0..=15u8)
+        .map(ResponseCode::from_low)
+        .find(|code| code.to_str() == text)
+        .map(|code| format!("{:?}", code).to_uppercase())
+        .unwrap_or_else(|| escape(text))
+}
+
+/// Экранирование значения метки (OpenMetrics: `\`, `"` и перевод строки)
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// HTTP-сервер метрик: `GET /metrics`
+pub struct MetricsServer {
+    task: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    pub async fn start(address: SocketAddr, source: MetricsSource) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to bind metrics endpoint on {}", address))?;
+        info!("📈 Metrics endpoint listening on http://{}/metrics", address);
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::serve(stream, source.clone()));
+                    }
+                    Err(e) => debug!("Metrics accept error: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { task })
+    }
+
+    pub async fn stop(self) {
+        self.task.abort();
+        let _ = self.task.await;
+    }
+
+    async fn serve(mut stream: TcpStream, source: MetricsSource) {
+        let request = match tokio::time::timeout(REQUEST_TIMEOUT, Self::read_head(&mut stream)).await {
+            Ok(Some(request)) => request,
+            _ => return,
+        };
+
+        let mut parts = request.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let path = path.split('?').next().unwrap_or_default();
+
+        let (status, content_type, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", CONTENT_TYPE, source.render()),
+            ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        if let Err(e) = stream.write_all(response.as_bytes()).await {
+            debug!("Failed to send metrics: {}", e);
+        }
+        let _ = stream.shutdown().await;
+    }
+
+    /// Прочитать заголовки запроса; тело у `GET` не ожидается
+    async fn read_head(stream: &mut TcpStream) -> Option<String> {
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut buf).await.ok()?;
+            if read == 0 || head.len() + read > MAX_REQUEST {
+                return None;
+            }
+            head.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(head).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::cache::CacheConfig;
+    use crate::proxy::querylog::{QueryLogConfig, QueryLogEntry};
+    use crate::proxy::upstream::Upstream;
+
+    fn source() -> MetricsSource {
+        let blocklists = Arc::new(Blocklists::new());
+        let resolver = Arc::new(ProxyResolver::new(
+            Duration::from_secs(1),
+            CacheConfig::default(),
+            QueryLogConfig {
+                enabled: false,
+                ..QueryLogConfig::default()
+            },
+            blocklists.clone(),
+        ));
+        MetricsSource {
+            resolver,
+            blocklists,
+            active_presets: Arc::new(Mutex::new(BTreeMap::new())),
+            probe_latencies: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    fn lines<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+        text.lines().filter(|line| line.starts_with(prefix)).collect()
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let source = source();
+        let upstream = Upstream::udp("127.0.0.1:5353".parse().unwrap());
+        // 3 мс, 40 мс, 40 мс и 10 с — последний замер попадает только в +Inf
+        for ms in [3, 40, 40, 10_000] {
+            upstream.health().record_success(Duration::from_millis(ms));
+        }
+        source.resolver.set_upstreams(vec![upstream]);
+
+        let text = source.render();
+        let buckets: Vec<&str> = lines(&text, "dns_switcher_upstream_latency_seconds_bucket")
+            .into_iter()
+            .map(|line| line.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(buckets, ["1", "1", "1", "3", "3", "3", "3", "3", "3", "3", "4"]);
+        assert!(text.contains("dns_switcher_upstream_latency_seconds_bucket{upstream=\"udp://127.0.0.1:5353\",le=\"0.005\"} 1\n"));
+        assert!(text.contains("dns_switcher_upstream_latency_seconds_bucket{upstream=\"udp://127.0.0.1:5353\",le=\"+Inf\"} 4\n"));
+        assert!(text.contains("dns_switcher_upstream_latency_seconds_count{upstream=\"udp://127.0.0.1:5353\"} 4\n"));
+        let sum = lines(&text, "dns_switcher_upstream_latency_seconds_sum")[0].rsplit(' ').next().unwrap();
+        assert!((sum.parse::<f64>().unwrap() - 10.083).abs() < 1e-9, "{}", sum);
+    }
+
+    #[test]
+    fn families_have_suffixed_samples_and_the_text_ends_with_eof() {
+        let source = source();
+        source.resolver.query_log().record(QueryLogEntry {
+            timestamp: chrono::Utc::now(),
+            client: "127.0.0.1".to_string(),
+            name: "example.com.".to_string(),
+            record_type: "A".to_string(),
+            upstream: Some("udp://192.0.2.1:53".to_string()),
+            rcode: ResponseCode::NXDomain.to_str().to_string(),
+            latency_ms: 12.0,
+            cached: false,
+            stale: false,
+            blocked: false,
+        });
+        source.active_presets.lock().unwrap().insert("Ethernet".to_string(), "cloudflare".to_string());
+        source.probe_latencies.lock().unwrap().entry("1.1.1.1".to_string()).or_default().observe(Duration::from_millis(20));
+
+        let text = source.render();
+
+        assert!(text.contains(
+            "dns_switcher_proxy_queries_total{rcode=\"NXDOMAIN\",upstream=\"udp://192.0.2.1:53\",cache=\"miss\"} 1\n"
+        ));
+        assert!(text.contains("dns_switcher_blocked_queries_total 0\n"));
+        assert!(text.contains("dns_switcher_active_preset_info{adapter=\"Ethernet\",preset=\"cloudflare\"} 1\n"));
+        assert!(text.contains("dns_switcher_probe_latency_seconds_bucket{server=\"1.1.1.1\",le=\"0.025\"} 1\n"));
+        // Семейства объявляются без суффиксов, сэмплы — с ними
+        for (name, kind) in [
+            ("dns_switcher_proxy_queries", "counter"),
+            ("dns_switcher_blocked_queries", "counter"),
+            ("dns_switcher_active_preset", "info"),
+            ("dns_switcher_probe_latency_seconds", "histogram"),
+        ] {
+            assert!(text.contains(&format!("# TYPE {} {}\n", name, kind)), "{}", name);
+        }
+        assert!(!text.contains("# TYPE dns_switcher_proxy_queries_total"));
+        assert!(text.ends_with("\n# EOF\n"));
+        assert_eq!(text.matches("# EOF").count(), 1);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("C:\\Wi-Fi \"home\"\nnext"), "C:\\\\Wi-Fi \\\"home\\\"\\nnext");
+
+        let source = source();
+        source.active_presets.lock().unwrap().insert("Wi-Fi \"2\"".to_string(), "a\\b".to_string());
+        let text = source.render();
+        assert!(text.contains("dns_switcher_active_preset_info{adapter=\"Wi-Fi \\\"2\\\"\",preset=\"a\\\\b\"} 1\n"));
+    }
+
+    #[test]
+    fn rcode_labels_are_mnemonics() {
+        assert_eq!(rcode_label(ResponseCode::NoError.to_str()), "NOERROR");
+        assert_eq!(rcode_label(ResponseCode::ServFail.to_str()), "SERVFAIL");
+        assert_eq!(rcode_label("odd \"code\""), "odd \\\"code\\\"");
+    }
+}
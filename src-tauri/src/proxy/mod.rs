@@ -0,0 +1,635 @@
+mod access;
+mod blocklist;
+mod cache;
+mod dnscrypt;
+mod dnssec;
+mod hpke;
+mod metrics;
+mod odoh;
+mod pinning;
+mod privacy;
+mod querylog;
+mod records;
+mod resolver;
+mod rules;
+mod server;
+mod strategy;
+mod upstream;
+
+use crate::config;
+use crate::types::DnsTestResult;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub use access::{AccessStats, LanConfig};
+pub use blocklist::{BlocklistSettings, BlocklistSource, BlocklistStatus};
+use blocklist::Blocklists;
+pub use cache::{CacheConfig, CacheStats};
+pub use dnssec::DnssecConfig;
+pub use metrics::MetricsConfig;
+use metrics::{MetricsServer, MetricsSource};
+pub use privacy::{EcsPolicy, PrivacyConfig, PrivacyOptions};
+pub use querylog::{QueryLogConfig, QueryLogEntry, QueryLogPage, QueryStats};
+pub use records::{LocalRecord, RecordsManager};
+use records::LocalZone;
+use resolver::ProxyResolver;
+pub use rules::{find_preset, ForwardingRule, RulesManager};
+use rules::{preset_upstreams, RuleSet};
+use server::ProxyServer;
+pub use strategy::{BreakerConfig, UpstreamHealthStatus, UpstreamStrategy};
+use strategy::LatencyHistogram;
+pub use upstream::{strip_uri_template, Upstream};
+use upstream::UpstreamContext;
+
+const CONFIG_FILE: &str = "proxy.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Never route adapters through the proxy
+    Disabled,
+    /// Use the proxy when the preset is encrypted but the OS has no native DoH
+    Auto,
+    /// Route every preset through the proxy
+    Always,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    pub listen_addresses: Vec<IpAddr>,
+    /// Адаптеры Windows можно направить только на порт 53
+    pub port: u16,
+    pub upstream_timeout_ms: u64,
+    pub strategy: UpstreamStrategy,
+    pub breaker: BreakerConfig,
+    pub cache: CacheConfig,
+    pub query_log: QueryLogConfig,
+    /// Релей Oblivious DoH (RFC 9230) для ODoH-апстримов: URL или стамп `sdns://`
+    pub odoh_relay: Option<String>,
+    /// ECS, дополнение запросов и 0x20 — для всех апстримов и для отдельных
+    pub privacy: PrivacyConfig,
+    /// Собственная проверка подписей DNSSEC
+    pub dnssec: DnssecConfig,
+    /// Доступ к прокси для других устройств локальной сети
+    pub lan: LanConfig,
+    /// HTTP-эндпоинт с метриками в формате OpenMetrics
+    pub metrics: MetricsConfig,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            mode: ProxyMode::Auto,
+            listen_addresses: vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)],
+            port: 53,
+            upstream_timeout_ms: 5000,
+            strategy: UpstreamStrategy::Strict,
+            breaker: BreakerConfig::default(),
+            cache: CacheConfig::default(),
+            query_log: QueryLogConfig::default(),
+            odoh_relay: None,
+            privacy: PrivacyConfig::default(),
+            dnssec: DnssecConfig::default(),
+            lan: LanConfig::default(),
+            metrics: MetricsConfig::default(),
+        }
+    }
+}
+
+/// Апстримы пресета, через который прокси обслуживает адаптеры
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RoutedUpstreams {
+    ipv4_servers: Vec<String>,
+    ipv6_servers: Vec<String>,
+    doh_template: Option<String>,
+    dot_hostname: Option<String>,
+    stamp: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyStatus {
+    pub running: bool,
+    pub listening: Vec<String>,
+    pub upstreams: Vec<String>,
+    pub upstream_health: Vec<UpstreamHealthStatus>,
+    pub adapters: Vec<String>,
+    /// Отказы клиентам локальной сети
+    pub access: AccessStats,
+}
+
+/// Встроенный DNS-прокси: слушает loopback и пересылает запросы в DoH/DoT
+/// апстрим пресета, так что шифрованный DNS работает без поддержки со стороны ОС
+pub struct ProxyService {
+    config: RwLock<ProxyConfig>,
+    resolver: Arc<ProxyResolver>,
+    blocklists: Arc<Blocklists>,
+    server: tokio::sync::Mutex<Option<ProxyServer>>,
+    adapters: Mutex<BTreeSet<String>>,
+    /// Апстримы, на которые направлены адаптеры. Все адаптеры обращаются к одному
+    /// адресу 127.0.0.1:53, поэтому различить их прокси не может
+    routed: Mutex<Option<RoutedUpstreams>>,
+    /// Plain DNS servers of the routed preset, used to resolve DoH/DoT hostnames in rules
+    bootstrap: Mutex<Vec<IpAddr>>,
+    /// Пресет, применённый к каждому адаптеру, — для метрик
+    active_presets: Arc<Mutex<BTreeMap<String, String>>>,
+    /// Задержки проверок серверов (`test_dns`, проверка пресетов) — для метрик
+    probe_latencies: Arc<Mutex<BTreeMap<String, LatencyHistogram>>>,
+    metrics: tokio::sync::Mutex<Option<MetricsServer>>,
+}
+
+impl ProxyService {
+    pub fn new() -> Self {
+        let config: ProxyConfig = config::load_json(CONFIG_FILE).unwrap_or_else(|e| {
+            warn!("⚠️  Failed to load proxy config, using defaults: {}", e);
+            ProxyConfig::default()
+        });
+        let blocklists = Arc::new(Blocklists::new());
+        let resolver = ProxyResolver::new(
+            Duration::from_millis(config.upstream_timeout_ms),
+            config.cache.clone(),
+            config.query_log.clone(),
+            blocklists.clone(),
+        );
+        resolver.set_strategy(config.strategy, config.breaker.clone());
+        resolver.set_privacy(config.privacy.clone());
+        resolver.set_dnssec(&config.dnssec);
+        resolver.set_lan(config.lan.clone());
+
+        let service = Self {
+            config: RwLock::new(config),
+            resolver: Arc::new(resolver),
+            blocklists,
+            server: tokio::sync::Mutex::new(None),
+            adapters: Mutex::new(BTreeSet::new()),
+            routed: Mutex::new(None),
+            bootstrap: Mutex::new(Vec::new()),
+            active_presets: Arc::new(Mutex::new(BTreeMap::new())),
+            probe_latencies: Arc::new(Mutex::new(BTreeMap::new())),
+            metrics: tokio::sync::Mutex::new(None),
+        };
+        service.reload_records();
+        service
+    }
+
+    pub fn config(&self) -> ProxyConfig {
+        self.config.read().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    /// Сохранить настройки; работающий прокси перезапускается на новых адресах
+    pub async fn update_config(&self, new_config: ProxyConfig) -> Result<()> {
+        new_config.privacy.validate()?;
+        new_config.dnssec.validate()?;
+        new_config.lan.validate()?;
+        new_config.metrics.validate()?;
+        let previous = self.config();
+        // Сначала занимаем новые адреса: если это не удалось, настройки не сохраняются
+        self.sync_server(&previous, &new_config).await?;
+
+        let metrics_changed = new_config.metrics != previous.metrics;
+        config::save_json(CONFIG_FILE, &new_config)?;
+        self.resolver.set_timeout(Duration::from_millis(new_config.upstream_timeout_ms));
+        self.resolver.cache().configure(new_config.cache.clone());
+        self.resolver.query_log().configure(new_config.query_log.clone());
+        self.resolver.set_strategy(new_config.strategy, new_config.breaker.clone());
+        self.resolver.set_privacy(new_config.privacy.clone());
+        self.resolver.set_lan(new_config.lan.clone());
+        if new_config.dnssec != self.config().dnssec {
+            self.resolver.set_dnssec(&new_config.dnssec);
+        }
+        if let Ok(mut current) = self.config.write() {
+            *current = new_config;
+        }
+
+        if metrics_changed {
+            self.apply_metrics().await?;
+        }
+        if self.config().lan != previous.lan {
+            self.apply_lan_upstreams().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Запустить или остановить прокси по текущим настройкам: он работает, пока
+    /// на него направлены адаптеры или включён доступ из локальной сети
+    pub async fn apply_server(&self) -> Result<()> {
+        let lan_applied = self.apply_lan_upstreams().await.unwrap_or_else(|e| {
+            warn!("⚠️  Failed to set up upstreams for LAN clients: {:#}", e);
+            false
+        });
+        // Правила пересылки нужны с первого запроса, а не с первого переключения адаптера
+        if !lan_applied {
+            self.reload_rules().await;
+        }
+        let config = self.config();
+        self.sync_server(&config, &config).await
+    }
+
+    /// Направить прокси на пресет для локальной сети, если ни один адаптер
+    /// не направлен на него со своим пресетом. Возвращает `true`, если апстримы
+    /// и правила пересылки заданы заново.
+    async fn apply_lan_upstreams(&self) -> Result<bool> {
+        let config = self.config();
+        let routed = self.routed.lock().map(|r| r.is_some()).unwrap_or(false);
+        let Some(preset_id) = config.lan.preset_id.as_deref().filter(|_| config.lan.enabled && !routed) else {
+            return Ok(false);
+        };
+
+        let preset = find_preset(preset_id).with_context(|| format!("Preset {} not found", preset_id))?;
+        let upstreams = preset_upstreams(&preset, config.odoh_relay.as_deref()).await?;
+        info!(
+            "🛰️  LAN upstreams ({}): {:?}",
+            preset.name,
+            upstreams.iter().map(|u| u.name()).collect::<Vec<_>>()
+        );
+        self.resolver.set_upstreams(upstreams);
+
+        if let Ok(mut bootstrap) = self.bootstrap.lock() {
+            *bootstrap = preset
+                .servers_ipv4
+                .iter()
+                .chain(&preset.servers_ipv6)
+                .filter_map(|s| s.parse().ok())
+                .collect();
+        }
+        self.reload_rules().await;
+        Ok(true)
+    }
+
+    /// Привести прокси в соответствие с `config`: запустить, остановить или
+    /// перевести на новые адреса. Если новые адреса занять не удалось, прокси
+    /// возвращается на прежние: адаптеры, направленные на 127.0.0.1, не должны
+    /// остаться без DNS.
+    async fn sync_server(&self, previous: &ProxyConfig, config: &ProxyConfig) -> Result<()> {
+        let addrs = Self::listen_addrs(config);
+        let previous_addrs = Self::listen_addrs(previous);
+        let routed = self.adapters.lock().map(|a| !a.is_empty()).unwrap_or(false);
+        let wanted = routed || config.lan.enabled;
+
+        let mut server = self.server.lock().await;
+        let running = match server.take() {
+            Some(running) if !wanted => {
+                running.stop().await;
+                return Ok(());
+            }
+            Some(running) if addrs == previous_addrs => {
+                *server = Some(running);
+                return Ok(());
+            }
+            Some(running) => running,
+            None if wanted => {
+                *server = Some(ProxyServer::start(&addrs, self.resolver.clone()).await?);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+
+        running.stop().await;
+        match ProxyServer::start(&addrs, self.resolver.clone()).await {
+            Ok(started) => {
+                *server = Some(started);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("⚠️  Proxy could not move to the new addresses, restoring the previous ones: {}", e);
+                let restored = ProxyServer::start(&previous_addrs, self.resolver.clone())
+                    .await
+                    .context("Local DNS proxy could not be restarted on its previous addresses")?;
+                *server = Some(restored);
+                Err(e.context("New proxy listen addresses could not be bound; settings were not changed"))
+            }
+        }
+    }
+
+    /// Запустить, перезапустить или остановить эндпоинт метрик по текущим настройкам
+    pub async fn apply_metrics(&self) -> Result<()> {
+        let config = self.config().metrics;
+        let mut metrics = self.metrics.lock().await;
+        if let Some(running) = metrics.take() {
+            running.stop().await;
+        }
+
+        if config.enabled {
+            let source = MetricsSource {
+                resolver: self.resolver.clone(),
+                blocklists: self.blocklists.clone(),
+                active_presets: self.active_presets.clone(),
+                probe_latencies: self.probe_latencies.clone(),
+            };
+            *metrics = Some(MetricsServer::start(config.listen_address, source).await?);
+        }
+        Ok(())
+    }
+
+    /// Запомнить пресет адаптера; `None` — адаптер сброшен или настроен вручную
+    pub fn set_active_preset(&self, adapter_name: &str, preset_id: Option<&str>) {
+        if let Ok(mut presets) = self.active_presets.lock() {
+            match preset_id {
+                Some(preset_id) => presets.insert(adapter_name.to_string(), preset_id.to_string()),
+                None => presets.remove(adapter_name),
+            };
+        }
+    }
+
+    /// Нужно ли направлять адаптер через прокси для данного пресета
+    pub fn should_route(&self, has_encrypted_upstream: bool, native_doh: bool) -> bool {
+        match self.config().mode {
+            ProxyMode::Disabled => false,
+            ProxyMode::Auto => has_encrypted_upstream && !native_doh,
+            ProxyMode::Always => true,
+        }
+    }
+
+    /// Запустить прокси с апстримами пресета и закрепить за ним адаптер.
+    /// Возвращает IPv4/IPv6 адреса, которые нужно прописать адаптеру.
+    pub async fn route_adapter(
+        &self,
+        adapter_name: &str,
+        ipv4_servers: &[String],
+        ipv6_servers: &[String],
+        doh_template: Option<&str>,
+        dot_hostname: Option<&str>,
+        stamp: Option<&str>,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let config = self.config();
+        if config.port != 53 {
+            anyhow::bail!("Adapters can only be pointed at a proxy listening on port 53");
+        }
+
+        let routed = RoutedUpstreams {
+            ipv4_servers: ipv4_servers.to_vec(),
+            ipv6_servers: ipv6_servers.to_vec(),
+            doh_template: doh_template.map(str::to_string),
+            dot_hostname: dot_hostname.map(str::to_string),
+            stamp: stamp.map(str::to_string),
+        };
+        let others: Vec<String> = self
+            .adapters
+            .lock()
+            .map(|adapters| adapters.iter().filter(|a| *a != adapter_name).cloned().collect())
+            .unwrap_or_default();
+        let current = self.routed.lock().ok().and_then(|r| r.clone());
+        if !others.is_empty() && current.as_ref().is_some_and(|current| *current != routed) {
+            anyhow::bail!(
+                "{} already uses the local DNS proxy with another preset. The proxy serves one preset \
+                 for all adapters: apply the same preset here or reset the other adapter first",
+                others.join(", ")
+            );
+        }
+
+        let upstreams = Upstream::from_preset(
+            ipv4_servers,
+            ipv6_servers,
+            doh_template,
+            dot_hostname,
+            stamp,
+            config.odoh_relay.as_deref(),
+        )
+        .await?;
+        info!(
+            "🛰️  Proxy upstreams: {:?}",
+            upstreams.iter().map(|u| u.name()).collect::<Vec<_>>()
+        );
+        self.resolver.set_upstreams(upstreams);
+        if let Ok(mut current) = self.routed.lock() {
+            *current = Some(routed);
+        }
+
+        if let Ok(mut bootstrap) = self.bootstrap.lock() {
+            *bootstrap = ipv4_servers
+                .iter()
+                .chain(ipv6_servers)
+                .filter_map(|s| s.parse().ok())
+                .collect();
+        }
+        self.reload_rules().await;
+
+        let mut server = self.server.lock().await;
+        if server.is_none() {
+            *server = Some(ProxyServer::start(&Self::listen_addrs(&config), self.resolver.clone()).await?);
+        }
+
+        // Адаптеры этого компьютера не направляются на адреса для локальной сети
+        let lan_only: Vec<IpAddr> = config
+            .lan
+            .listen_addresses
+            .iter()
+            .filter(|ip| !config.listen_addresses.contains(ip))
+            .copied()
+            .collect();
+        let bound: Vec<IpAddr> = server
+            .as_ref()
+            .map(|s| {
+                s.bound_addresses()
+                    .iter()
+                    .map(|a| a.ip())
+                    .filter(|ip| !lan_only.contains(ip))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let Ok(mut adapters) = self.adapters.lock() {
+            adapters.insert(adapter_name.to_string());
+        }
+
+        Ok(Self::system_servers(&bound))
+    }
+
+    /// Отвязать адаптер; когда адаптеров не осталось, прокси останавливается,
+    /// если он не нужен для локальной сети
+    pub async fn release_adapter(&self, adapter_name: &str) {
+        let remaining = match self.adapters.lock() {
+            Ok(mut adapters) => {
+                if !adapters.remove(adapter_name) {
+                    return;
+                }
+                adapters.len()
+            }
+            Err(_) => return,
+        };
+
+        if remaining == 0 {
+            if let Ok(mut routed) = self.routed.lock() {
+                *routed = None;
+            }
+            if self.config().lan.enabled {
+                // Сеть снова обслуживается своим пресетом
+                if let Err(e) = self.apply_lan_upstreams().await {
+                    warn!("⚠️  Failed to set up upstreams for LAN clients: {:#}", e);
+                }
+                return;
+            }
+            if let Some(server) = self.server.lock().await.take() {
+                server.stop().await;
+            }
+        }
+    }
+
+    /// Перечитать правила условной пересылки и применить их к резолверу
+    pub async fn reload_rules(&self) {
+        let rules = RulesManager::load_rules().unwrap_or_else(|e| {
+            warn!("⚠️  Failed to load forwarding rules: {}", e);
+            Vec::new()
+        });
+        let context = UpstreamContext {
+            bootstrap: self.bootstrap.lock().map(|b| b.clone()).unwrap_or_default(),
+            odoh_relay: self.config().odoh_relay,
+        };
+
+        let compiled = RuleSet::compile(&rules, &context).await;
+        if !compiled.is_empty() {
+            info!("🔀 Loaded {} forwarding rule(s)", compiled.len());
+        }
+        self.resolver.set_rules(compiled);
+    }
+
+    /// Перечитать локальные DNS-записи
+    pub fn reload_records(&self) {
+        let records = RecordsManager::load_records().unwrap_or_else(|e| {
+            warn!("⚠️  Failed to load local records: {}", e);
+            Vec::new()
+        });
+        self.resolver.set_local_records(LocalZone::compile(&records));
+    }
+
+    pub async fn status(&self) -> ProxyStatus {
+        let server = self.server.lock().await;
+        ProxyStatus {
+            running: server.is_some(),
+            listening: server
+                .as_ref()
+                .map(|s| s.bound_addresses().iter().map(|a| a.to_string()).collect())
+                .unwrap_or_default(),
+            upstreams: self.resolver.upstream_names(),
+            upstream_health: self.resolver.upstream_health(),
+            adapters: self
+                .adapters
+                .lock()
+                .map(|a| a.iter().cloned().collect())
+                .unwrap_or_default(),
+            access: self.resolver.access().stats(),
+        }
+    }
+
+    /// Проверить апстрим (DoH/DoT/ODoH-адрес или стамп) с текущими настройками прокси
+    pub async fn probe_upstream(&self, spec: &str) -> DnsTestResult {
+        let config = self.config();
+        let result = upstream::probe_upstream(spec, config.odoh_relay.as_deref(), &config.privacy.defaults).await;
+        self.record_probe(&result);
+        result
+    }
+
+    /// Проверить несколько апстримов одновременно; результаты в порядке `specs`
+    pub async fn probe_upstreams(&self, specs: &[String]) -> Vec<DnsTestResult> {
+        let config = self.config();
+        let tasks: Vec<_> = specs
+            .iter()
+            .map(|spec| {
+                let spec = spec.clone();
+                let relay = config.odoh_relay.clone();
+                let options = config.privacy.defaults.clone();
+                tokio::spawn(async move { upstream::probe_upstream(&spec, relay.as_deref(), &options).await })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for (spec, task) in specs.iter().zip(tasks) {
+            results.push(task.await.unwrap_or_else(|e| DnsTestResult {
+                server: spec.clone(),
+                latency_ms: None,
+                is_available: false,
+                error: Some(e.to_string()),
+            }));
+        }
+        for result in &results {
+            self.record_probe(result);
+        }
+        results
+    }
+
+    /// Учесть задержку удачной проверки сервера в гистограмме для метрик
+    pub fn record_probe(&self, result: &DnsTestResult) {
+        let Some(latency_ms) = result.latency_ms.filter(|_| result.is_available) else {
+            return;
+        };
+        if let Ok(mut probes) = self.probe_latencies.lock() {
+            probes
+                .entry(result.server.clone())
+                .or_default()
+                .observe(Duration::from_millis(latency_ms));
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.resolver.cache().stats()
+    }
+
+    pub fn clear_cache(&self) {
+        self.resolver.cache().clear();
+    }
+
+    pub fn query_log(&self, offset: usize, limit: usize, search: Option<&str>) -> QueryLogPage {
+        self.resolver.query_log().page(offset, limit, search)
+    }
+
+    pub fn query_stats(&self, top: usize) -> QueryStats {
+        self.resolver.query_log().stats(top)
+    }
+
+    pub fn clear_query_log(&self) -> Result<()> {
+        self.resolver.query_log().clear()
+    }
+
+    /// Поток новых записей журнала запросов
+    pub fn subscribe_queries(&self) -> tokio::sync::broadcast::Receiver<QueryLogEntry> {
+        self.resolver.query_log().subscribe()
+    }
+
+    pub fn blocklists(&self) -> Arc<Blocklists> {
+        self.blocklists.clone()
+    }
+
+    /// Адреса для прослушивания: loopback из настроек и, если включено, адреса для сети
+    fn listen_addrs(config: &ProxyConfig) -> Vec<SocketAddr> {
+        let lan = if config.lan.enabled {
+            config.lan.listen_addresses.as_slice()
+        } else {
+            &[]
+        };
+
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+        for ip in config.listen_addresses.iter().chain(lan) {
+            let addr = SocketAddr::new(*ip, config.port);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    /// Адреса для настроек адаптера: wildcard-адреса заменяются на loopback
+    fn system_servers(bound: &[IpAddr]) -> (Vec<String>, Vec<String>) {
+        let mut ipv4 = Vec::new();
+        let mut ipv6 = Vec::new();
+
+        for ip in bound {
+            match ip {
+                IpAddr::V4(v4) if v4.is_unspecified() => ipv4.push(Ipv4Addr::LOCALHOST.to_string()),
+                IpAddr::V4(v4) => ipv4.push(v4.to_string()),
+                IpAddr::V6(v6) if v6.is_unspecified() => ipv6.push(Ipv6Addr::LOCALHOST.to_string()),
+                IpAddr::V6(v6) => ipv6.push(v6.to_string()),
+            }
+        }
+
+        ipv4.dedup();
+        ipv6.dedup();
+        (ipv4, ipv6)
+    }
+}
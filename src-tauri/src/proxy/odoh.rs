@@ -0,0 +1,524 @@
+use super::hpke::{self, SenderContext};
+use super::upstream::bootstrap_or_fallback;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use log::{info, warn};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const CONTENT_TYPE: &str = "application/oblivious-dns-message";
+const CONFIGS_PATH: &str = "/.well-known/odohconfigs";
+const ODOH_VERSION: u16 = 0x0001;
+
+const MESSAGE_QUERY: u8 = 0x01;
+const MESSAGE_RESPONSE: u8 = 0x02;
+
+/// Запрос дополняется нулями до кратной длины, чтобы размер не выдавал имя
+const PADDING_BLOCK: usize = 128;
+/// Ключ цели перечитывается не реже раза в час
+const CONFIG_REFRESH: Duration = Duration::from_secs(3600);
+
+/// Открытый ключ цели из ObliviousDoHConfig
+struct TargetConfig {
+    public_key: [u8; 32],
+    key_id: Vec<u8>,
+    fetched_at: Instant,
+}
+
+impl TargetConfig {
+    /// Выбрать из ObliviousDoHConfigs первую конфигурацию с поддерживаемым набором HPKE
+    fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(body);
+        let mut configs = Reader::new(reader.vector()?);
+
+        while !configs.is_empty() {
+            let version = configs.u16()?;
+            let contents = configs.vector()?;
+            if version != ODOH_VERSION {
+                continue;
+            }
+
+            let mut fields = Reader::new(contents);
+            let suite = (fields.u16()?, fields.u16()?, fields.u16()?);
+            let public_key = fields.vector()?;
+            if suite != (hpke::KEM_X25519_HKDF_SHA256, hpke::KDF_HKDF_SHA256, hpke::AEAD_AES_128_GCM) {
+                continue;
+            }
+
+            // key_id = Expand(Extract("", config), "odoh key id", Nh)
+            let (_, hkdf) = Hkdf::<Sha256>::extract(Some(&[]), contents);
+            let mut key_id = vec![0u8; 32];
+            hkdf.expand(b"odoh key id", &mut key_id)
+                .map_err(|_| anyhow::anyhow!("Failed to derive ODoH key id"))?;
+
+            return Ok(Self {
+                public_key: public_key.try_into().context("ODoH public key must be 32 bytes")?,
+                key_id,
+                fetched_at: Instant::now(),
+            });
+        }
+
+        anyhow::bail!("Target offers no supported ODoH configuration")
+    }
+}
+
+/// Отправленный запрос: нужен, чтобы расшифровать ответ на него
+struct PendingQuery {
+    plaintext: Vec<u8>,
+    context: SenderContext,
+}
+
+/// Клиент Oblivious DoH (RFC 9230): запросы шифруются HPKE-ключом цели и
+/// отправляются через релей, так что ни релей, ни цель не видят вместе IP и имя
+pub struct OdohClient {
+    target: reqwest::Url,
+    relay: Option<reqwest::Url>,
+    client: reqwest::Client,
+    config: Mutex<Option<Arc<TargetConfig>>>,
+}
+
+impl OdohClient {
+    /// `target` — URL DoH-эндпоинта цели, `relay` — URL релея. Имена хостов
+    /// разрешаются через `bootstrap`, а не через системный резолвер.
+    pub async fn new(target: &str, relay: Option<&str>, bootstrap: &[IpAddr]) -> Result<Self> {
+        let target = reqwest::Url::parse(target).context("Invalid ODoH target URL")?;
+        let relay = relay
+            .map(reqwest::Url::parse)
+            .transpose()
+            .context("Invalid ODoH relay URL")?;
+
+        if relay.is_none() {
+            warn!(
+                "⚠️  ODoH target {} has no relay: queries are encrypted but not oblivious",
+                target
+            );
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .no_proxy();
+
+        for url in std::iter::once(&target).chain(relay.as_ref()) {
+            let host = url.host_str().context("ODoH URL has no host")?;
+            if host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() {
+                continue;
+            }
+
+            let port = url.port_or_known_default().unwrap_or(443);
+            let addrs: Vec<SocketAddr> = bootstrap_or_fallback(host, bootstrap)
+                .await
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect();
+            if addrs.is_empty() {
+                anyhow::bail!("Could not bootstrap ODoH host {}", host);
+            }
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+
+        Ok(Self {
+            target,
+            relay,
+            client: builder.build().context("Failed to build ODoH client")?,
+            config: Mutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        match &self.relay {
+            Some(relay) => format!("odoh://{}{} via {}", self.target_host(), self.target.path(), relay),
+            None => format!("odoh://{}{}", self.target_host(), self.target.path()),
+        }
+    }
+
+    pub async fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let config = self.config().await?;
+
+        let result = self.exchange_with(&config, query).await;
+        if result.is_err() {
+            // Цель могла сменить ключ (на устаревший key_id она отвечает 401)
+            if let Ok(mut current) = self.config.lock() {
+                *current = None;
+            }
+        }
+        result
+    }
+
+    async fn exchange_with(&self, config: &TargetConfig, query: &[u8]) -> Result<Vec<u8>> {
+        let (message, pending) = encrypt_query(config, query)?;
+
+        let url = match &self.relay {
+            Some(relay) => {
+                let mut url = relay.clone();
+                url.query_pairs_mut()
+                    .append_pair("targethost", &self.target_host())
+                    .append_pair("targetpath", self.target.path());
+                url
+            }
+            None => self.target.clone(),
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .header("content-type", CONTENT_TYPE)
+            .header("accept", CONTENT_TYPE)
+            .body(message)
+            .send()
+            .await
+            .context("ODoH request failed")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("ODoH server returned HTTP {}", response.status());
+        }
+
+        decrypt_response(pending, &response.bytes().await?)
+    }
+
+    /// Хост цели с портом, если он не стандартный
+    fn target_host(&self) -> String {
+        let host = self.target.host_str().unwrap_or_default();
+        match self.target.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        }
+    }
+
+    async fn config(&self) -> Result<Arc<TargetConfig>> {
+        let cached = self.config.lock().ok().and_then(|c| c.clone());
+        if let Some(config) = cached.filter(|c| c.fetched_at.elapsed() < CONFIG_REFRESH) {
+            return Ok(config);
+        }
+
+        let mut url = self.target.clone();
+        url.set_path(CONFIGS_PATH);
+        url.set_query(None);
+
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to fetch ODoH target configuration")?;
+        if !response.status().is_success() {
+            anyhow::bail!("ODoH target returned HTTP {} for its configuration", response.status());
+        }
+
+        let config = Arc::new(TargetConfig::parse(&response.bytes().await?)?);
+        info!("🔐 Fetched ODoH configuration for {}", self.target_host());
+
+        if let Ok(mut current) = self.config.lock() {
+            *current = Some(config.clone());
+        }
+        Ok(config)
+    }
+}
+
+/// Зашифровать DNS-запрос в ObliviousDoHMessage (RFC 9230 §6.1)
+fn encrypt_query(config: &TargetConfig, query: &[u8]) -> Result<(Vec<u8>, PendingQuery)> {
+    let padding = (PADDING_BLOCK - query.len() % PADDING_BLOCK) % PADDING_BLOCK;
+    let mut plaintext = Vec::with_capacity(query.len() + padding + 4);
+    put_vector(&mut plaintext, query)?;
+    put_vector(&mut plaintext, &vec![0u8; padding])?;
+
+    let (enc, mut context) = hpke::setup_base_sender(&config.public_key, b"odoh query")?;
+
+    let mut aad = vec![MESSAGE_QUERY];
+    put_vector(&mut aad, &config.key_id)?;
+    let sealed = context.seal(&aad, &plaintext)?;
+
+    let mut message = aad;
+    put_vector(&mut message, &[enc, sealed].concat())?;
+
+    Ok((message, PendingQuery { plaintext, context }))
+}
+
+/// Расшифровать ответ цели ключом, выведенным из контекста запроса (RFC 9230 §6.2)
+fn decrypt_response(pending: PendingQuery, body: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(body);
+    if reader.u8()? != MESSAGE_RESPONSE {
+        anyhow::bail!("Not an ODoH response");
+    }
+    let response_nonce = reader.vector()?;
+    let sealed = reader.vector()?;
+
+    let secret = pending.context.export(b"odoh response", hpke::NK)?;
+
+    let mut salt = pending.plaintext;
+    put_vector(&mut salt, response_nonce)?;
+    let (_, hkdf) = Hkdf::<Sha256>::extract(Some(&salt), &secret);
+
+    let mut key = [0u8; hpke::NK];
+    let mut nonce = [0u8; hpke::NN];
+    hkdf.expand(b"odoh key", &mut key)
+        .and_then(|_| hkdf.expand(b"odoh nonce", &mut nonce))
+        .map_err(|_| anyhow::anyhow!("Failed to derive ODoH response key"))?;
+
+    let mut aad = vec![MESSAGE_RESPONSE];
+    put_vector(&mut aad, response_nonce)?;
+
+    let plaintext = Aes128Gcm::new_from_slice(&key)
+        .map_err(|_| anyhow::anyhow!("Invalid ODoH response key"))?
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: sealed, aad: &aad })
+        .map_err(|_| anyhow::anyhow!("ODoH response failed to decrypt"))?;
+
+    Ok(Reader::new(&plaintext).vector()?.to_vec())
+}
+
+/// Записать вектор с 16-битной длиной
+fn put_vector(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    let len = u16::try_from(data.len()).context("ODoH message too large")?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("ODoH message is truncated");
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn vector(&mut self) -> Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+    use rand::RngCore;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    type Handler = Arc<dyn Fn(String, Vec<u8>) -> Pin<Box<dyn Future<Output = Vec<u8>> + Send>> + Send + Sync>;
+
+    /// Простейший HTTP/1.1-сервер на 127.0.0.1: один запрос на соединение
+    async fn serve(handler: Handler) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    let header_end = loop {
+                        let read = stream.read(&mut chunk).await.unwrap();
+                        assert!(read > 0, "connection closed before the request ended");
+                        request.extend_from_slice(&chunk[..read]);
+                        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break end + 4;
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&request[..header_end]).to_string();
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                        .unwrap_or(0usize);
+                    while request.len() < header_end + length {
+                        let read = stream.read(&mut chunk).await.unwrap();
+                        request.extend_from_slice(&chunk[..read]);
+                    }
+
+                    let request_line = head.lines().next().unwrap_or_default().to_string();
+                    let body = handler(request_line, request[header_end..].to_vec()).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        CONTENT_TYPE,
+                        body.len()
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    stream.write_all(&body).await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    fn target_config(public_key: &[u8; 32]) -> Vec<u8> {
+        let mut contents = Vec::new();
+        contents.extend_from_slice(&hpke::KEM_X25519_HKDF_SHA256.to_be_bytes());
+        contents.extend_from_slice(&hpke::KDF_HKDF_SHA256.to_be_bytes());
+        contents.extend_from_slice(&hpke::AEAD_AES_128_GCM.to_be_bytes());
+        put_vector(&mut contents, public_key).unwrap();
+
+        let mut config = ODOH_VERSION.to_be_bytes().to_vec();
+        put_vector(&mut config, &contents).unwrap();
+        let mut configs = Vec::new();
+        put_vector(&mut configs, &config).unwrap();
+        configs
+    }
+
+    /// Цель: расшифровать запрос своим ключом и зашифровать ответ (RFC 9230 §6)
+    fn answer_query(secret: &StaticSecret, body: &[u8]) -> Vec<u8> {
+        let mut reader = Reader::new(body);
+        assert_eq!(reader.u8().unwrap(), MESSAGE_QUERY);
+        let key_id = reader.vector().unwrap();
+        let encrypted = reader.vector().unwrap();
+        let (enc, sealed) = encrypted.split_at(32);
+
+        let mut aad = vec![MESSAGE_QUERY];
+        put_vector(&mut aad, key_id).unwrap();
+        let (plaintext, context) = hpke::tests::open_base(secret, enc, b"odoh query", &aad, sealed).unwrap();
+
+        let mut fields = Reader::new(&plaintext);
+        let query = fields.vector().unwrap().to_vec();
+        let padding = fields.vector().unwrap();
+        assert!(padding.iter().all(|b| *b == 0));
+        assert_eq!((query.len() + padding.len()) % PADDING_BLOCK, 0);
+
+        // Ответ — тот же запрос с флагом QR
+        let mut answer = query;
+        answer[2] |= 0x80;
+        let mut answer_plaintext = Vec::new();
+        put_vector(&mut answer_plaintext, &answer).unwrap();
+        put_vector(&mut answer_plaintext, &[]).unwrap();
+
+        let mut response_nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut response_nonce);
+        let secret = context.export(b"odoh response", hpke::NK).unwrap();
+        let mut salt = plaintext.clone();
+        put_vector(&mut salt, &response_nonce).unwrap();
+        let (_, hkdf) = Hkdf::<Sha256>::extract(Some(&salt), &secret);
+        let mut key = [0u8; hpke::NK];
+        let mut nonce = [0u8; hpke::NN];
+        hkdf.expand(b"odoh key", &mut key).unwrap();
+        hkdf.expand(b"odoh nonce", &mut nonce).unwrap();
+
+        let mut message = vec![MESSAGE_RESPONSE];
+        put_vector(&mut message, &response_nonce).unwrap();
+        let sealed = Aes128Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &answer_plaintext, aad: &message })
+            .unwrap();
+        put_vector(&mut message, &sealed).unwrap();
+        message
+    }
+
+    #[tokio::test]
+    async fn query_goes_through_relay_and_target() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let configs = target_config(&PublicKey::from(&secret).to_bytes());
+
+        let target_queries = Arc::new(AtomicUsize::new(0));
+        let target = {
+            let target_queries = target_queries.clone();
+            let handler: Handler = Arc::new(move |request_line, body| {
+                let response = if request_line.starts_with(&format!("GET {}", CONFIGS_PATH)) {
+                    configs.clone()
+                } else {
+                    assert!(request_line.starts_with("POST /dns-query "), "{}", request_line);
+                    target_queries.fetch_add(1, Ordering::SeqCst);
+                    answer_query(&secret, &body)
+                };
+                Box::pin(async move { response })
+            });
+            serve(handler).await
+        };
+
+        let relayed = Arc::new(AtomicUsize::new(0));
+        let relay = {
+            let relayed = relayed.clone();
+            let handler: Handler = Arc::new(move |request_line, body| {
+                let relayed = relayed.clone();
+                Box::pin(async move {
+                    let expected = format!(
+                        "POST /proxy?targethost=127.0.0.1%3A{}&targetpath=%2Fdns-query ",
+                        target.port()
+                    );
+                    assert!(request_line.starts_with(&expected), "{}", request_line);
+                    // Релей видит только зашифрованное сообщение
+                    assert!(!body.windows(7).any(|w| w == b"example"));
+                    relayed.fetch_add(1, Ordering::SeqCst);
+
+                    reqwest::Client::new()
+                        .post(format!("http://{}/dns-query", target))
+                        .header("content-type", CONTENT_TYPE)
+                        .body(body)
+                        .send()
+                        .await
+                        .unwrap()
+                        .bytes()
+                        .await
+                        .unwrap()
+                        .to_vec()
+                })
+            });
+            serve(handler).await
+        };
+
+        let client = OdohClient::new(
+            &format!("http://{}/dns-query", target),
+            Some(&format!("http://{}/proxy", relay)),
+            &[],
+        )
+        .await
+        .unwrap();
+
+        // Запрос example.com A с id 0x1234
+        let query = [
+            &[0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..],
+            b"\x07example\x03com\x00",
+            &[0x00, 0x01, 0x00, 0x01],
+        ]
+        .concat();
+        let response = client.exchange(&query).await.unwrap();
+
+        assert_eq!(response[..2], query[..2]);
+        assert_eq!(response[2] & 0x80, 0x80);
+        assert_eq!(response[3..], query[3..]);
+        assert_eq!(relayed.load(Ordering::SeqCst), 1);
+        assert_eq!(target_queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn response_with_wrong_key_is_rejected() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let config = TargetConfig::parse(&target_config(&PublicKey::from(&secret).to_bytes())).unwrap();
+        let (message, _) = encrypt_query(&config, b"query").unwrap();
+        let response = answer_query(&secret, &message);
+
+        // Ответ на другой запрос расшифровываться не должен
+        let (_, other) = encrypt_query(&config, b"query").unwrap();
+        assert!(decrypt_response(other, &response).is_err());
+    }
+}
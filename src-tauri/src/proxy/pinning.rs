@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+
+/// Проверка сертификата с хэшами из DNS-стампа: кроме обычной проверки цепочки
+/// хотя бы один её сертификат должен совпасть с хэшем. Как и в dnscrypt-proxy,
+/// хэшируется `tbsCertificate` (RFC 5280), обычно промежуточного сертификата.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    hashes: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| tbs_hash(cert))
+            .any(|hash| self.hashes.iter().any(|pin| pin[..] == hash[..]));
+        if !pinned {
+            return Err(Error::General(
+                "server certificate chain does not match the hashes pinned in the DNS stamp".to_string(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn roots() -> RootCertStore {
+    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+}
+
+/// Клиентская конфигурация TLS; с хэшами из стампа цепочка сертификатов
+/// сервера должна им соответствовать
+pub(super) fn client_config(hashes: &[Vec<u8>]) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .context("TLS provider does not support the default protocol versions")?;
+    if hashes.is_empty() {
+        return Ok(builder.with_root_certificates(roots()).with_no_client_auth());
+    }
+
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots()), provider())
+        .build()
+        .context("Failed to build the TLS certificate verifier")?;
+    let verifier = PinnedVerifier {
+        inner,
+        hashes: hashes.to_vec(),
+    };
+    Ok(builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// SHA-256 от `tbsCertificate` — первого элемента SEQUENCE сертификата, вместе с заголовком
+fn tbs_hash(cert: &[u8]) -> Option<[u8; 32]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (tbs, _, _) = der_element(certificate)?;
+    Some(Sha256::digest(tbs).into())
+}
+
+/// Разобрать один элемент DER: (элемент целиком, содержимое, остаток)
+fn der_element(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let first = *data.get(1)?;
+    let (header, len) = if first < 0x80 {
+        (2, first as usize)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        (2 + count, bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize))
+    };
+    let end = header.checked_add(len)?;
+    let element = data.get(..end)?;
+    Some((element, &element[header..], &data[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Обёртка DER с заданным тегом и длиной в нужной форме
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    #[test]
+    fn tbs_hash_covers_the_whole_first_element() {
+        // Короткая и длинная форма длины
+        for size in [3, 300] {
+            let tbs = der(0x30, &vec![0x02; size]);
+            let signature_algorithm = der(0x30, &[0x06, 0x00]);
+            let signature = der(0x03, &[0x00, 0xff]);
+            let cert = der(0x30, &[tbs.clone(), signature_algorithm, signature].concat());
+
+            let expected: [u8; 32] = Sha256::digest(&tbs).into();
+            assert_eq!(tbs_hash(&cert), Some(expected));
+        }
+    }
+
+    #[test]
+    fn truncated_certificates_have_no_hash() {
+        let cert = der(0x30, &der(0x30, &[0x02, 0x01, 0x01]));
+
+        assert!(tbs_hash(&cert[..cert.len() - 1]).is_none());
+        assert!(tbs_hash(&[0x30]).is_none());
+        assert!(tbs_hash(&[0x30, 0x85, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+use anyhow::{Context, Result};
+use hickory_proto::op::{Edns, Message};
+use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+use ipnet::IpNet;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Range;
+
+/// Запросы дополняются до кратной длины (RFC 8467, Block-Length Padding)
+const PADDING_BLOCK: usize = 128;
+/// Имя в секции вопроса начинается сразу за 12-байтным заголовком
+const QUESTION_OFFSET: usize = 12;
+/// Размер UDP-буфера, объявляемый апстриму, если клиент пришёл без EDNS
+const EDNS_PAYLOAD: u16 = 1232;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EcsPolicy {
+    /// Передавать ECS клиента без изменений
+    Forward,
+    /// Убрать ECS клиента и попросить апстрим не подставлять наш адрес (префикс /0)
+    Strip,
+    /// Сообщать апстриму заданную подсеть, например `203.0.113.0/24`
+    Override(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyOptions {
+    pub ecs: EcsPolicy,
+    /// Дополнять запросы по DoH и DoT до длины, кратной 128 байтам (RFC 7830/8467)
+    pub padding: bool,
+    /// Случайный регистр букв имени в запросах по UDP (0x20) — защита от подмены ответов
+    pub randomize_case: bool,
+}
+
+impl Default for PrivacyOptions {
+    fn default() -> Self {
+        Self {
+            ecs: EcsPolicy::Strip,
+            padding: true,
+            randomize_case: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Настройки для всех апстримов
+    pub defaults: PrivacyOptions,
+    /// Настройки отдельных апстримов по имени из статуса прокси (`udp://…`, `https://…`)
+    pub upstreams: HashMap<String, PrivacyOptions>,
+}
+
+impl PrivacyConfig {
+    pub fn for_upstream(&self, name: &str) -> &PrivacyOptions {
+        self.upstreams.get(name).unwrap_or(&self.defaults)
+    }
+
+    /// Проверить подсети ECS до сохранения настроек
+    pub fn validate(&self) -> Result<()> {
+        for options in std::iter::once(&self.defaults).chain(self.upstreams.values()) {
+            if let EcsPolicy::Override(subnet) = &options.ecs {
+                parse_subnet(subnet)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_subnet(subnet: &str) -> Result<ClientSubnet> {
+    let net = subnet
+        .trim()
+        .parse::<IpNet>()
+        .with_context(|| format!("Invalid ECS subnet: {}", subnet))?;
+    // Биты адреса за пределами префикса должны быть нулевыми (RFC 7871 §6)
+    Ok(ClientSubnet::from(net.trunc()))
+}
+
+/// Запрос в том виде, в каком он уходит апстриму
+pub struct PreparedQuery {
+    pub packet: Vec<u8>,
+    /// Исходное имя и его место в пакете, если регистр был перемешан
+    original_name: Option<(Range<usize>, Vec<u8>)>,
+    /// EDNS добавлен прокси: клиент без EDNS не должен получить OPT в ответе (RFC 6891 §7)
+    added_edns: bool,
+}
+
+impl PreparedQuery {
+    /// Проверить, что апстрим вернул имя в том же регистре, и вернуть клиенту исходный
+    pub fn restore(&self, mut response: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some((range, original)) = &self.original_name {
+            if response.get(range.clone()) != self.packet.get(range.clone()) {
+                anyhow::bail!("Answer does not match the 0x20-encoded query name");
+            }
+            response[range.clone()].copy_from_slice(original);
+        }
+
+        if self.added_edns {
+            let mut message = Message::from_vec(&response).context("Malformed answer")?;
+            if message.extensions_mut().take().is_some() {
+                response = message.to_vec()?;
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Применить к запросу политику ECS, дополнение и 0x20. `pad` и `randomize_case`
+/// уже учитывают транспорт: дополнение нужно только шифрованным, 0x20 — UDP.
+pub fn prepare(query: &[u8], ecs: &EcsPolicy, pad: bool, randomize_case: bool) -> Result<PreparedQuery> {
+    let (mut packet, added_edns) = if *ecs == EcsPolicy::Forward && !pad {
+        (query.to_vec(), false)
+    } else {
+        rewrite_edns(query, ecs, pad)?
+    };
+
+    let original_name = if randomize_case {
+        randomize_name_case(&mut packet)
+    } else {
+        None
+    };
+
+    Ok(PreparedQuery {
+        packet,
+        original_name,
+        added_edns,
+    })
+}
+
+/// Переписать опции EDNS; второй элемент — был ли EDNS добавлен прокси
+fn rewrite_edns(query: &[u8], ecs: &EcsPolicy, pad: bool) -> Result<(Vec<u8>, bool)> {
+    let mut message = Message::from_vec(query).context("Malformed query")?;
+    let added_edns = message.extensions().is_none();
+    let edns = message.extensions_mut().get_or_insert_with(|| {
+        let mut edns = Edns::new();
+        edns.set_max_payload(EDNS_PAYLOAD);
+        edns
+    });
+
+    match ecs {
+        EcsPolicy::Forward => {}
+        EcsPolicy::Strip => {
+            edns.options_mut().remove(EdnsCode::Subnet);
+            edns.options_mut().insert(EdnsOption::Subnet(ClientSubnet::new(
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                0,
+                0,
+            )));
+        }
+        EcsPolicy::Override(subnet) => {
+            edns.options_mut().remove(EdnsCode::Subnet);
+            edns.options_mut().insert(EdnsOption::Subnet(parse_subnet(subnet)?));
+        }
+    }
+
+    if !pad {
+        return Ok((message.to_vec()?, added_edns));
+    }
+
+    if let Some(edns) = message.extensions_mut() {
+        edns.options_mut().remove(EdnsCode::Padding);
+    }
+    // Сама опция занимает 4 байта заголовка плюс нули дополнения
+    let unpadded = message.to_vec()?.len() + 4;
+    let padding = (PADDING_BLOCK - unpadded % PADDING_BLOCK) % PADDING_BLOCK;
+    if let Some(edns) = message.extensions_mut() {
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(u16::from(EdnsCode::Padding), vec![0; padding]));
+    }
+
+    Ok((message.to_vec()?, added_edns))
+}
+
+/// Перемешать регистр букв имени в вопросе; возвращает место имени и исходные байты
+fn randomize_name_case(packet: &mut [u8]) -> Option<(Range<usize>, Vec<u8>)> {
+    let mut pos = QUESTION_OFFSET;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            break;
+        }
+        // Сжатие в вопросе не встречается; такой пакет оставляем как есть
+        if len & 0xC0 != 0 || pos + 1 + len > packet.len() {
+            return None;
+        }
+        pos += 1 + len;
+    }
+
+    let range = QUESTION_OFFSET..pos + 1;
+    let original = packet[range.clone()].to_vec();
+
+    let mut rng = rand::thread_rng();
+    let mut label_end = QUESTION_OFFSET;
+    for i in range.clone() {
+        if i == label_end {
+            // Байт длины метки
+            label_end = i + 1 + packet[i] as usize;
+        } else if packet[i].is_ascii_alphabetic() && rng.gen::<bool>() {
+            packet[i] ^= 0x20;
+        }
+    }
+
+    Some((range, original))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RecordType};
+
+    fn query(name: &str, subnet: Option<ClientSubnet>) -> Vec<u8> {
+        let mut message = Message::new();
+        message
+            .set_id(0x4242)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        if let Some(subnet) = subnet {
+            let mut edns = Edns::new();
+            edns.set_max_payload(4096);
+            edns.options_mut().insert(EdnsOption::Subnet(subnet));
+            message.set_edns(edns);
+        }
+        message.to_vec().unwrap()
+    }
+
+    fn client_subnet() -> ClientSubnet {
+        ClientSubnet::new("198.51.100.0".parse().unwrap(), 24, 0)
+    }
+
+    fn subnet_of(packet: &[u8]) -> Option<EdnsOption> {
+        let message = Message::from_vec(packet).unwrap();
+        message.extensions().as_ref()?.options().get(EdnsCode::Subnet).cloned()
+    }
+
+    #[test]
+    fn strip_replaces_client_subnet_with_zero_prefix() {
+        let prepared = prepare(&query("example.com.", Some(client_subnet())), &EcsPolicy::Strip, false, false).unwrap();
+        assert_eq!(
+            subnet_of(&prepared.packet),
+            Some(EdnsOption::Subnet(ClientSubnet::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0, 0)))
+        );
+    }
+
+    #[test]
+    fn override_sends_configured_prefix() {
+        let ecs = EcsPolicy::Override("203.0.113.77/24".to_string());
+        let prepared = prepare(&query("example.com.", Some(client_subnet())), &ecs, false, false).unwrap();
+        assert_eq!(
+            subnet_of(&prepared.packet),
+            Some(EdnsOption::Subnet(ClientSubnet::new("203.0.113.0".parse().unwrap(), 24, 0)))
+        );
+    }
+
+    #[test]
+    fn forward_keeps_client_subnet_untouched() {
+        let original = query("example.com.", Some(client_subnet()));
+        let prepared = prepare(&original, &EcsPolicy::Forward, false, false).unwrap();
+        assert_eq!(prepared.packet, original);
+    }
+
+    #[test]
+    fn padding_rounds_query_up_to_block() {
+        for name in ["a.", "example.com.", "a-much-longer-label-to-change-the-length.example.org."] {
+            for subnet in [None, Some(client_subnet())] {
+                let prepared = prepare(&query(name, subnet), &EcsPolicy::Forward, true, false).unwrap();
+                assert_eq!(prepared.packet.len() % PADDING_BLOCK, 0, "{}", name);
+
+                let message = Message::from_vec(&prepared.packet).unwrap();
+                let edns = message.extensions().as_ref().unwrap();
+                assert!(edns.options().get(EdnsCode::Padding).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn edns_added_by_proxy_is_removed_from_answer() {
+        let prepared = prepare(&query("example.com.", None), &EcsPolicy::Strip, true, false).unwrap();
+        let mut answer = Message::from_vec(&prepared.packet).unwrap();
+        answer.set_message_type(hickory_proto::op::MessageType::Response);
+
+        let restored = prepared.restore(answer.to_vec().unwrap()).unwrap();
+        assert!(Message::from_vec(&restored).unwrap().extensions().is_none());
+    }
+
+    #[test]
+    fn randomized_case_is_restored_in_answer() {
+        let original = query("www.example-with-many-letters.com.", None);
+        let prepared = prepare(&original, &EcsPolicy::Forward, false, true).unwrap();
+        let (range, _) = prepared.original_name.clone().unwrap();
+        assert!(prepared.packet[range.clone()].eq_ignore_ascii_case(&original[range.clone()]));
+
+        let mut answer = prepared.packet.clone();
+        answer[2] |= 0x80;
+        let restored = prepared.restore(answer).unwrap();
+        assert_eq!(restored[range.clone()], original[range.clone()]);
+        assert_eq!(restored[3..], original[3..]);
+    }
+
+    #[test]
+    fn answer_with_other_case_is_rejected() {
+        let prepared = prepare(&query("example.com.", None), &EcsPolicy::Forward, false, true).unwrap();
+        let (range, _) = prepared.original_name.clone().unwrap();
+
+        let mut answer = prepared.packet.clone();
+        answer[2] |= 0x80;
+        // Первая буква имени — сразу за байтом длины метки
+        answer[range.start + 1] ^= 0x20;
+        assert!(prepared.restore(answer).is_err());
+    }
+
+    #[test]
+    fn compressed_question_is_left_alone() {
+        let mut packet = query("example.com.", None);
+        packet[QUESTION_OFFSET] = 0xC0;
+        assert!(randomize_name_case(&mut packet).is_none());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tokio::sync::broadcast;
+
+const LOG_FILE: &str = "query_log.jsonl";
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+/// Сколько записей может ждать записи на диск; лишние остаются только в памяти
+const WRITER_QUEUE: usize = 4096;
+/// Сколько записей сбрасывается на диск за один раз
+const WRITER_BATCH: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryLogConfig {
+    pub enabled: bool,
+    /// Сколько последних запросов держать в памяти
+    pub capacity: usize,
+    /// Дописывать записи в `query_log.jsonl` и восстанавливать их при запуске
+    pub persist: bool,
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: 10_000,
+            persist: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub client: String,
+    pub name: String,
+    pub record_type: String,
+    pub upstream: Option<String>,
+    pub rcode: String,
+    pub latency_ms: f64,
+    pub cached: bool,
+    /// Отдан просроченный ответ из кэша, потому что апстрим не ответил вовремя
+    #[serde(default)]
+    pub stale: bool,
+    pub blocked: bool,
+}
+
+/// Ключ счётчика запросов для метрик
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QueryCounterKey {
+    pub rcode: String,
+    pub upstream: String,
+    /// `hit`, `stale` или `miss`
+    pub cache: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryLogPage {
+    pub total: usize,
+    /// Записи от новых к старым
+    pub entries: Vec<QueryLogEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopEntry {
+    pub name: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryStats {
+    pub total: u64,
+    pub blocked: u64,
+    pub cached: u64,
+    pub avg_latency_ms: Option<f64>,
+    pub top_domains: Vec<TopEntry>,
+    pub top_blocked: Vec<TopEntry>,
+    pub top_clients: Vec<TopEntry>,
+}
+
+/// Журнал запросов прокси: кольцевой буфер в памяти, необязательная запись
+/// на диск и поток событий для живого просмотра
+pub struct QueryLog {
+    config: Mutex<QueryLogConfig>,
+    entries: Arc<Mutex<VecDeque<QueryLogEntry>>>,
+    writer: Mutex<Option<LogWriter>>,
+    events: broadcast::Sender<QueryLogEntry>,
+    /// Счётчики с запуска; ведутся и при выключенном журнале
+    counters: Mutex<HashMap<QueryCounterKey, u64>>,
+}
+
+impl QueryLog {
+    pub fn new(config: QueryLogConfig) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let log = Self {
+            config: Mutex::new(QueryLogConfig::default()),
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            writer: Mutex::new(None),
+            events,
+            counters: Mutex::new(HashMap::new()),
+        };
+        log.configure(config);
+        log
+    }
+
+    /// Применить настройки; при включении записи на диск журнал восстанавливается из файла
+    pub fn configure(&self, config: QueryLogConfig) {
+        let was_persisting = self.config.lock().map(|c| c.persist).unwrap_or(false);
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if config.persist && !was_persisting {
+                match Self::load_tail(config.capacity) {
+                    Ok(restored) => *entries = restored,
+                    Err(e) => warn!("⚠️  Failed to restore query log: {:#}", e),
+                }
+            }
+            while entries.len() > config.capacity {
+                entries.pop_front();
+            }
+        }
+
+        // Прежний писатель дописывает свою очередь до того, как новый перепишет файл.
+        // Ждать его под замком нельзя: он сам берёт замок буфера, а record держит оба.
+        let previous = self.writer.lock().ok().and_then(|mut writer| writer.take());
+        drop(previous);
+
+        if config.enabled && config.persist {
+            let writer = Self::file_path()
+                .and_then(|path| LogWriter::spawn(path, self.entries.clone(), config.capacity.max(1000)));
+            match writer {
+                Ok(started) => {
+                    if let Ok(mut writer) = self.writer.lock() {
+                        *writer = Some(started);
+                    }
+                }
+                Err(e) => warn!("⚠️  Failed to open query log file: {:#}", e),
+            }
+        }
+
+        if let Ok(mut current) = self.config.lock() {
+            *current = config;
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QueryLogEntry> {
+        self.events.subscribe()
+    }
+
+    pub fn record(&self, entry: QueryLogEntry) {
+        self.count(&entry);
+        let config = match self.config.lock() {
+            Ok(config) if config.enabled => config.clone(),
+            _ => return,
+        };
+
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push_back(entry.clone());
+            while entries.len() > config.capacity {
+                entries.pop_front();
+            }
+
+            // В очередь под замком буфера: тогда снимок буфера при перезаписи
+            // файла содержит ровно те записи, что уже стоят в очереди
+            if let Ok(mut writer) = self.writer.lock() {
+                if writer.as_ref().is_some_and(|w| !w.send(WriterCommand::Append(entry.clone()))) {
+                    *writer = None;
+                }
+            }
+        }
+
+        // Ошибка означает только, что сейчас никто не слушает
+        let _ = self.events.send(entry);
+    }
+
+    fn count(&self, entry: &QueryLogEntry) {
+        let key = QueryCounterKey {
+            rcode: entry.rcode.clone(),
+            upstream: match (&entry.upstream, entry.blocked) {
+                (Some(upstream), _) => upstream.clone(),
+                (None, true) => "blocked".to_string(),
+                (None, false) => "none".to_string(),
+            },
+            cache: match (entry.stale, entry.cached) {
+                (true, _) => "stale",
+                (false, true) => "hit",
+                (false, false) => "miss",
+            },
+        };
+        if let Ok(mut counters) = self.counters.lock() {
+            *counters.entry(key).or_default() += 1;
+        }
+    }
+
+    /// Число запросов по коду ответа, апстриму и состоянию кэша с запуска
+    pub fn counters(&self) -> Vec<(QueryCounterKey, u64)> {
+        let mut counters: Vec<(QueryCounterKey, u64)> = self
+            .counters
+            .lock()
+            .map(|c| c.iter().map(|(key, count)| (key.clone(), *count)).collect())
+            .unwrap_or_default();
+        counters.sort();
+        counters
+    }
+
+    /// Страница журнала, от новых к старым, с фильтром по подстроке имени или клиента
+    pub fn page(&self, offset: usize, limit: usize, search: Option<&str>) -> QueryLogPage {
+        let entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return QueryLogPage { total: 0, entries: Vec::new() },
+        };
+
+        let search = search.map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty());
+        let matching: Vec<&QueryLogEntry> = entries
+            .iter()
+            .rev()
+            .filter(|entry| match &search {
+                Some(search) => entry.name.contains(search.as_str()) || entry.client.contains(search.as_str()),
+                None => true,
+            })
+            .collect();
+
+        QueryLogPage {
+            total: matching.len(),
+            entries: matching.into_iter().skip(offset).take(limit).cloned().collect(),
+        }
+    }
+
+    /// Статистика по записям в буфере
+    pub fn stats(&self, top: usize) -> QueryStats {
+        let entries = self.entries.lock().map(|e| e.clone()).unwrap_or_default();
+
+        let mut domains = HashMap::new();
+        let mut blocked_domains = HashMap::new();
+        let mut clients = HashMap::new();
+        let (mut blocked, mut cached, mut latency_sum, mut forwarded) = (0, 0, 0.0, 0);
+
+        for entry in &entries {
+            *domains.entry(entry.name.as_str()).or_insert(0) += 1;
+            *clients.entry(entry.client.as_str()).or_insert(0) += 1;
+
+            if entry.blocked {
+                blocked += 1;
+                *blocked_domains.entry(entry.name.as_str()).or_insert(0) += 1;
+            } else if entry.cached {
+                cached += 1;
+            } else {
+                latency_sum += entry.latency_ms;
+                forwarded += 1;
+            }
+        }
+
+        QueryStats {
+            total: entries.len() as u64,
+            blocked,
+            cached,
+            avg_latency_ms: (forwarded > 0).then(|| latency_sum / forwarded as f64),
+            top_domains: Self::top(domains, top),
+            top_blocked: Self::top(blocked_domains, top),
+            top_clients: Self::top(clients, top),
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+
+        // Ждать места в очереди можно только без замков: поток записи сам берёт замок буфера
+        let queue = self.writer.lock().ok().and_then(|writer| writer.as_ref()?.queue.clone());
+        if let Some(queue) = queue {
+            if queue.send(WriterCommand::Rewrite).is_err() {
+                anyhow::bail!("Query log writer has stopped");
+            }
+        }
+        Ok(())
+    }
+
+    fn top(counts: HashMap<&str, u64>, n: usize) -> Vec<TopEntry> {
+        let mut counts: Vec<(&str, u64)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts
+            .into_iter()
+            .take(n)
+            .map(|(name, count)| TopEntry {
+                name: name.to_string(),
+                count,
+            })
+            .collect()
+    }
+
+    fn file_path() -> Result<PathBuf> {
+        Ok(config::app_dir()?.join(LOG_FILE))
+    }
+
+    fn load_tail(capacity: usize) -> Result<VecDeque<QueryLogEntry>> {
+        let path = Self::file_path()?;
+        let mut entries = VecDeque::new();
+        if !path.exists() {
+            return Ok(entries);
+        }
+
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let Ok(entry) = serde_json::from_str(&line?) else {
+                continue;
+            };
+            entries.push_back(entry);
+            if entries.len() > capacity {
+                entries.pop_front();
+            }
+        }
+        Ok(entries)
+    }
+}
+
+enum WriterCommand {
+    Append(QueryLogEntry),
+    /// Переписать файл содержимым буфера, например после очистки
+    Rewrite,
+}
+
+/// Фоновая запись журнала на диск: резолвер только ставит записи в очередь,
+/// а отдельный поток сбрасывает их в файл пачками
+struct LogWriter {
+    queue: Option<SyncSender<WriterCommand>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LogWriter {
+    /// Запустить поток записи. Файл сначала переписывается из буфера, а затем
+    /// каждый раз, когда в нём набирается `compact_after` дописанных записей.
+    fn spawn(path: PathBuf, entries: Arc<Mutex<VecDeque<QueryLogEntry>>>, compact_after: usize) -> Result<Self> {
+        let (queue, commands) = mpsc::sync_channel(WRITER_QUEUE);
+        let thread = thread::Builder::new()
+            .name("query-log-writer".to_string())
+            .spawn(move || Self::run(&path, &entries, compact_after, commands))
+            .context("Failed to start the query log writer")?;
+        Ok(Self {
+            queue: Some(queue),
+            thread: Some(thread),
+        })
+    }
+
+    /// Поставить команду в очередь; `false`, если поток записи остановился.
+    /// Переполненная очередь не задерживает запрос — запись остаётся только в памяти.
+    fn send(&self, command: WriterCommand) -> bool {
+        let Some(queue) = self.queue.as_ref() else {
+            return false;
+        };
+        !matches!(queue.try_send(command), Err(TrySendError::Disconnected(_)))
+    }
+
+    fn run(
+        path: &Path,
+        entries: &Mutex<VecDeque<QueryLogEntry>>,
+        compact_after: usize,
+        commands: Receiver<WriterCommand>,
+    ) {
+        let mut file = match Self::rewrite(path, entries, &commands) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("⚠️  Failed to write query log: {:#}", e);
+                return;
+            }
+        };
+        let mut appended = 0;
+
+        while let Ok(command) = commands.recv() {
+            let mut batch = vec![command];
+            batch.extend(commands.try_iter().take(WRITER_BATCH - 1));
+
+            let rewrite = appended + batch.len() > compact_after
+                || batch.iter().any(|command| matches!(command, WriterCommand::Rewrite));
+            let written = if rewrite {
+                // Записи пачки уже есть в снимке буфера
+                appended = 0;
+                Self::rewrite(path, entries, &commands).map(|rewritten| file = rewritten)
+            } else {
+                appended += batch.len();
+                Self::append(&mut file, &batch)
+            };
+
+            if let Err(e) = written {
+                warn!("⚠️  Failed to write query log: {:#}", e);
+                return;
+            }
+        }
+    }
+
+    fn append(file: &mut BufWriter<File>, batch: &[WriterCommand]) -> Result<()> {
+        for command in batch {
+            if let WriterCommand::Append(entry) = command {
+                serde_json::to_writer(&mut *file, entry)?;
+                file.write_all(b"\n")?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Переписать файл содержимым буфера и открыть его на дозапись. Очередь
+    /// очищается под замком буфера: всё, что в ней было, уже попало в снимок.
+    fn rewrite(
+        path: &Path,
+        entries: &Mutex<VecDeque<QueryLogEntry>>,
+        commands: &Receiver<WriterCommand>,
+    ) -> Result<BufWriter<File>> {
+        let snapshot: Vec<QueryLogEntry> = match entries.lock() {
+            Ok(entries) => {
+                commands.try_iter().for_each(drop);
+                entries.iter().cloned().collect()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            for entry in &snapshot {
+                serde_json::to_writer(&mut file, entry)?;
+                file.write_all(b"\n")?;
+            }
+            file.flush()?;
+        }
+        fs::rename(&tmp, path)?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .context("Failed to open query log")?;
+        Ok(BufWriter::new(file))
+    }
+}
+
+impl Drop for LogWriter {
+    /// Закрытая очередь останавливает поток, когда он допишет то, что в ней осталось
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, client: &str) -> QueryLogEntry {
+        QueryLogEntry {
+            timestamp: Utc::now(),
+            client: client.to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            upstream: Some("udp://192.0.2.1:53".to_string()),
+            rcode: "NoError".to_string(),
+            latency_ms: 10.0,
+            cached: false,
+            stale: false,
+            blocked: false,
+        }
+    }
+
+    fn names(page: &QueryLogPage) -> Vec<&str> {
+        page.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    fn log_config(capacity: usize, persist: bool) -> QueryLogConfig {
+        QueryLogConfig {
+            enabled: true,
+            capacity,
+            persist,
+        }
+    }
+
+    #[test]
+    fn ring_buffer_keeps_the_newest_entries() {
+        let log = QueryLog::new(log_config(3, false));
+        for name in ["a", "b", "c", "d", "e"] {
+            log.record(entry(name, "127.0.0.1"));
+        }
+
+        let page = log.page(0, 10, None);
+        assert_eq!(page.total, 3);
+        assert_eq!(names(&page), ["e", "d", "c"]);
+
+        // Уменьшение ёмкости отбрасывает самые старые
+        log.configure(log_config(2, false));
+        assert_eq!(names(&log.page(0, 10, None)), ["e", "d"]);
+    }
+
+    #[test]
+    fn pages_go_from_newest_to_oldest_and_filter() {
+        let log = QueryLog::new(log_config(100, false));
+        for i in 0..7 {
+            log.record(entry(&format!("host{}.example", i), if i % 2 == 0 { "10.0.0.2" } else { "10.0.0.3" }));
+        }
+
+        let page = log.page(2, 3, None);
+        assert_eq!(page.total, 7);
+        assert_eq!(names(&page), ["host4.example", "host3.example", "host2.example"]);
+        assert!(log.page(7, 3, None).entries.is_empty());
+
+        let page = log.page(0, 10, Some(" 10.0.0.3 "));
+        assert_eq!(names(&page), ["host5.example", "host3.example", "host1.example"]);
+        assert_eq!(log.page(0, 10, Some("HOST6")).total, 1);
+        assert_eq!(log.page(0, 10, Some("")).total, 7);
+    }
+
+    #[test]
+    fn stats_rank_domains_and_clients() {
+        let log = QueryLog::new(log_config(100, false));
+        for (name, client) in [
+            ("b.example", "10.0.0.2"),
+            ("a.example", "10.0.0.2"),
+            ("a.example", "10.0.0.3"),
+            ("b.example", "10.0.0.2"),
+            ("c.example", "10.0.0.4"),
+        ] {
+            log.record(entry(name, client));
+        }
+        let mut blocked = entry("ads.example", "10.0.0.3");
+        blocked.blocked = true;
+        blocked.upstream = None;
+        log.record(blocked);
+        let mut cached = entry("c.example", "10.0.0.4");
+        cached.cached = true;
+        log.record(cached);
+
+        let stats = log.stats(2);
+        let top = |entries: &[TopEntry]| -> Vec<(String, u64)> {
+            entries.iter().map(|e| (e.name.clone(), e.count)).collect()
+        };
+        assert_eq!((stats.total, stats.blocked, stats.cached), (7, 1, 1));
+        assert_eq!(stats.avg_latency_ms, Some(10.0));
+        // При равенстве счётчиков порядок по имени
+        assert_eq!(top(&stats.top_domains), [("a.example".into(), 2), ("b.example".into(), 2)]);
+        assert_eq!(top(&stats.top_clients), [("10.0.0.2".into(), 3), ("10.0.0.3".into(), 2)]);
+        assert_eq!(top(&stats.top_blocked), [("ads.example".into(), 1)]);
+    }
+
+    #[test]
+    fn persisted_log_is_restored_and_cleared() {
+        let dir = config::use_temp_app_dir();
+        let log = QueryLog::new(log_config(3, true));
+        for name in ["a", "b", "c", "d"] {
+            log.record(entry(name, "127.0.0.1"));
+        }
+        // Сброс на диск дожидается остановки потока записи
+        drop(log);
+
+        let log = QueryLog::new(log_config(3, true));
+        assert_eq!(names(&log.page(0, 10, None)), ["d", "c", "b"]);
+
+        log.clear().unwrap();
+        log.record(entry("e", "127.0.0.1"));
+        drop(log);
+        let saved = fs::read_to_string(dir.path().join(LOG_FILE)).unwrap();
+        assert_eq!(saved.lines().count(), 1);
+
+        let log = QueryLog::new(log_config(3, true));
+        assert_eq!(names(&log.page(0, 10, None)), ["e"]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::config;
+use anyhow::{Context, Result};
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, CNAME, PTR, TXT};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const RECORDS_FILE: &str = "local_records.json";
+/// Предел длины цепочки локальных CNAME
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LocalRecordType {
+    A,
+    Aaaa,
+    Cname,
+    Txt,
+    Ptr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalRecord {
+    pub id: String,
+    /// Имя записи; `*.example.test` покрывает все поддомены. Для PTR можно указать IP-адрес.
+    pub name: String,
+    pub record_type: LocalRecordType,
+    pub value: String,
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_ttl() -> u32 {
+    300
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Ответ из локальных записей
+pub struct LocalAnswer {
+    pub response: Message,
+    /// CNAME ведёт за пределы локальных записей — цель нужно спросить у апстрима
+    pub unresolved_target: Option<Name>,
+}
+
+/// Скомпилированные локальные записи: точные имена и wildcard-суффиксы
+#[derive(Default)]
+pub struct LocalZone {
+    exact: HashMap<Name, Vec<Record>>,
+    wildcard: HashMap<Name, Vec<Record>>,
+}
+
+impl LocalZone {
+    pub fn compile(records: &[LocalRecord]) -> Self {
+        let mut zone = Self::default();
+
+        for record in records.iter().filter(|r| r.enabled) {
+            let (owner, wildcard) = match parse_owner(record) {
+                Ok(owner) => owner,
+                Err(e) => {
+                    warn!("⚠️  Skipping local record {}: {:#}", record.id, e);
+                    continue;
+                }
+            };
+            let rdata = match parse_rdata(record.record_type, &record.value) {
+                Ok(rdata) => rdata,
+                Err(e) => {
+                    warn!("⚠️  Skipping local record {}: {:#}", record.id, e);
+                    continue;
+                }
+            };
+
+            let map = if wildcard { &mut zone.wildcard } else { &mut zone.exact };
+            map.entry(owner.clone())
+                .or_default()
+                .push(Record::from_rdata(owner, record.ttl, rdata));
+        }
+
+        zone
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.wildcard.is_empty()
+    }
+
+    /// Записи для имени: точное совпадение важнее wildcard, среди wildcard —
+    /// самый длинный суффикс. Владелец записей заменяется на запрошенное имя.
+    fn lookup(&self, name: &Name) -> Option<Vec<Record>> {
+        if let Some(records) = self.exact.get(name) {
+            return Some(records.clone());
+        }
+
+        let mut parent = name.base_name();
+        loop {
+            if let Some(records) = self.wildcard.get(&parent) {
+                return Some(
+                    records
+                        .iter()
+                        .map(|r| {
+                            let mut record = r.clone();
+                            record.set_name(name.clone());
+                            record
+                        })
+                        .collect(),
+                );
+            }
+            if parent.is_root() {
+                return None;
+            }
+            parent = parent.base_name();
+        }
+    }
+
+    /// Ответить на запрос из локальных записей; `None`, если имя здесь не описано
+    pub fn answer(&self, request: &Message) -> Option<LocalAnswer> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let query = request.query()?;
+        let query_type = query.query_type();
+        let mut name = query.name().to_lowercase();
+        name.set_fqdn(true);
+
+        let mut records = self.lookup(&name)?;
+        let mut answers = Vec::new();
+        let mut unresolved_target = None;
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let matching: Vec<Record> = records
+                .iter()
+                .filter(|r| r.record_type() == query_type || query_type == RecordType::ANY)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                answers.extend(matching);
+                break;
+            }
+
+            let Some(cname) = records.iter().find(|r| r.record_type() == RecordType::CNAME) else {
+                break;
+            };
+            answers.push(cname.clone());
+
+            let Some(RData::CNAME(target)) = cname.data() else {
+                break;
+            };
+            let target = target.0.to_lowercase();
+            match self.lookup(&target) {
+                Some(next) => records = next,
+                None => {
+                    unresolved_target = Some(target);
+                    break;
+                }
+            }
+        }
+
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_authoritative(true)
+            .set_recursion_desired(request.recursion_desired())
+            .set_recursion_available(true)
+            .set_response_code(ResponseCode::NoError)
+            .add_queries(request.queries().to_vec())
+            .add_answers(answers);
+
+        Some(LocalAnswer {
+            response,
+            unresolved_target,
+        })
+    }
+}
+
+/// Имя владельца записи и признак wildcard
+fn parse_owner(record: &LocalRecord) -> Result<(Name, bool)> {
+    let raw = record.name.trim();
+
+    let (raw, wildcard) = match raw.strip_prefix("*.") {
+        Some(rest) => (rest.to_string(), true),
+        None => (raw.to_string(), false),
+    };
+
+    let mut name = match raw.parse::<IpAddr>() {
+        Ok(ip) if record.record_type == LocalRecordType::Ptr && !wildcard => Name::from(ip),
+        _ => Name::from_utf8(&raw).with_context(|| format!("Invalid record name: {}", record.name))?,
+    }
+    .to_lowercase();
+    name.set_fqdn(true);
+
+    Ok((name, wildcard))
+}
+
+fn parse_rdata(record_type: LocalRecordType, value: &str) -> Result<RData> {
+    let value = value.trim();
+    let parse_name = |value: &str| -> Result<Name> {
+        let mut name = Name::from_utf8(value).with_context(|| format!("Invalid target name: {}", value))?;
+        name.set_fqdn(true);
+        Ok(name)
+    };
+
+    Ok(match record_type {
+        LocalRecordType::A => RData::A(A::from(
+            value.parse::<Ipv4Addr>().context("A record needs an IPv4 address")?,
+        )),
+        LocalRecordType::Aaaa => RData::AAAA(AAAA::from(
+            value.parse::<Ipv6Addr>().context("AAAA record needs an IPv6 address")?,
+        )),
+        LocalRecordType::Cname => RData::CNAME(CNAME(parse_name(value)?)),
+        LocalRecordType::Ptr => RData::PTR(PTR(parse_name(value)?)),
+        LocalRecordType::Txt => {
+            // Строки TXT ограничены 255 байтами — длинное значение режется на части
+            let chunks: Vec<String> = value
+                .as_bytes()
+                .chunks(255)
+                .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+                .collect();
+            RData::TXT(TXT::new(chunks))
+        }
+    })
+}
+
+pub struct RecordsManager;
+
+impl RecordsManager {
+    /// Загрузить локальные записи
+    pub fn load_records() -> Result<Vec<LocalRecord>> {
+        config::load_json(RECORDS_FILE)
+    }
+
+    /// Сохранить локальные записи
+    pub fn save_records(records: &[LocalRecord]) -> Result<()> {
+        config::save_json(RECORDS_FILE, records)
+    }
+
+    /// Добавить запись; пустой ID генерируется автоматически
+    pub fn add_record(mut record: LocalRecord) -> Result<LocalRecord> {
+        Self::validate(&record)?;
+        let mut records = Self::load_records()?;
+
+        if record.id.is_empty() {
+            record.id = format!("record-{}", chrono::Utc::now().timestamp_millis());
+        }
+
+        if records.iter().any(|r| r.id == record.id) {
+            anyhow::bail!("Record with this ID already exists");
+        }
+
+        records.push(record.clone());
+        Self::save_records(&records)?;
+
+        Ok(record)
+    }
+
+    /// Обновить запись
+    pub fn update_record(record: LocalRecord) -> Result<()> {
+        Self::validate(&record)?;
+        let mut records = Self::load_records()?;
+
+        if let Some(existing) = records.iter_mut().find(|r| r.id == record.id) {
+            *existing = record;
+            Self::save_records(&records)
+        } else {
+            anyhow::bail!("Record not found");
+        }
+    }
+
+    /// Удалить запись по ID
+    pub fn delete_record(id: &str) -> Result<()> {
+        let mut records = Self::load_records()?;
+        records.retain(|r| r.id != id);
+        Self::save_records(&records)
+    }
+
+    fn validate(record: &LocalRecord) -> Result<()> {
+        let owner = parse_owner(record)?;
+        parse_rdata(record.record_type, &record.value)?;
+
+        // CNAME не может соседствовать с другими записями того же имени (RFC 1034 §3.6.2)
+        let conflict = Self::load_records()?.iter().any(|other| {
+            other.id != record.id
+                && other.enabled
+                && (record.record_type == LocalRecordType::Cname || other.record_type == LocalRecordType::Cname)
+                && parse_owner(other).is_ok_and(|other_owner| other_owner == owner)
+        });
+        if record.enabled && conflict {
+            anyhow::bail!("A CNAME record can't share its name with other records");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+
+    fn record(id: &str, name: &str, record_type: LocalRecordType, value: &str) -> LocalRecord {
+        LocalRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl: 300,
+            enabled: true,
+        }
+    }
+
+    fn query(name: &str, record_type: RecordType) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(7)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message
+    }
+
+    /// Ответ зоны: владелец и данные каждой записи
+    fn answers(zone: &LocalZone, name: &str, record_type: RecordType) -> Option<Vec<(String, String)>> {
+        let answer = zone.answer(&query(name, record_type))?;
+        Some(
+            answer
+                .response
+                .answers()
+                .iter()
+                .map(|r| (r.name().to_string(), r.data().map(|d| d.to_string()).unwrap_or_default()))
+                .collect(),
+        )
+    }
+
+    fn pair(name: &str, data: &str) -> (String, String) {
+        (name.to_string(), data.to_string())
+    }
+
+    #[test]
+    fn exact_name_beats_wildcard_and_longest_wildcard_wins() {
+        let zone = LocalZone::compile(&[
+            record("1", "*.home.test", LocalRecordType::A, "192.0.2.1"),
+            record("2", "*.lab.home.test", LocalRecordType::A, "192.0.2.2"),
+            record("3", "nas.lab.home.test", LocalRecordType::A, "192.0.2.3"),
+            record("4", "off.home.test", LocalRecordType::A, "192.0.2.4"),
+            record("5", "bad name..test", LocalRecordType::A, "192.0.2.5"),
+            record("6", "bad.home.test", LocalRecordType::A, "not an ip"),
+        ]);
+        let a = RecordType::A;
+
+        assert_eq!(answers(&zone, "NAS.Lab.Home.Test.", a), Some(vec![pair("nas.lab.home.test.", "192.0.2.3")]));
+        // Владелец wildcard-записи заменяется на запрошенное имя
+        assert_eq!(answers(&zone, "pc.lab.home.test.", a), Some(vec![pair("pc.lab.home.test.", "192.0.2.2")]));
+        assert_eq!(answers(&zone, "a.b.home.test.", a), Some(vec![pair("a.b.home.test.", "192.0.2.1")]));
+        // Wildcard не покрывает сам домен; непригодные записи пропущены
+        assert_eq!(answers(&zone, "home.test.", a), None);
+        assert_eq!(answers(&zone, "bad.home.test.", a), Some(vec![pair("bad.home.test.", "192.0.2.1")]));
+        assert_eq!(answers(&zone, "example.com.", a), None);
+
+        let mut disabled = record("4", "off.home.test", LocalRecordType::A, "192.0.2.4");
+        disabled.enabled = false;
+        assert!(LocalZone::compile(&[disabled]).is_empty());
+    }
+
+    #[test]
+    fn other_types_of_a_known_name_get_an_empty_authoritative_answer() {
+        let zone = LocalZone::compile(&[record("1", "nas.home.test", LocalRecordType::A, "192.0.2.1")]);
+
+        let answer = zone.answer(&query("nas.home.test.", RecordType::AAAA)).unwrap();
+        assert!(answer.response.answers().is_empty());
+        assert_eq!(answer.response.response_code(), ResponseCode::NoError);
+        assert!(answer.response.authoritative());
+        assert_eq!(answer.response.id(), 7);
+        assert_eq!(answers(&zone, "nas.home.test.", RecordType::ANY).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn ptr_records_accept_ip_addresses_and_txt_values_are_split() {
+        let long = "v".repeat(300);
+        let zone = LocalZone::compile(&[
+            record("1", "192.0.2.10", LocalRecordType::Ptr, "nas.home.test"),
+            record("2", "2001:db8::10", LocalRecordType::Ptr, "nas.home.test."),
+            record("3", "nas.home.test", LocalRecordType::Txt, &long),
+        ]);
+
+        assert_eq!(
+            answers(&zone, "10.2.0.192.in-addr.arpa.", RecordType::PTR),
+            Some(vec![pair("10.2.0.192.in-addr.arpa.", "nas.home.test.")])
+        );
+        let v6 = Name::from("2001:db8::10".parse::<IpAddr>().unwrap()).to_string();
+        assert_eq!(answers(&zone, &v6, RecordType::PTR).unwrap()[0].1, "nas.home.test.");
+
+        let answer = zone.answer(&query("nas.home.test.", RecordType::TXT)).unwrap();
+        let Some(RData::TXT(txt)) = answer.response.answers()[0].data() else {
+            panic!("expected a TXT record");
+        };
+        let chunks: Vec<usize> = txt.txt_data().iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(chunks, [255, 45]);
+    }
+
+    #[test]
+    fn cname_chains_are_followed_until_they_leave_the_zone() {
+        let zone = LocalZone::compile(&[
+            record("1", "www.home.test", LocalRecordType::Cname, "web.home.test"),
+            record("2", "web.home.test", LocalRecordType::A, "192.0.2.1"),
+            record("3", "cdn.home.test", LocalRecordType::Cname, "cdn.example.com"),
+        ]);
+
+        let answer = zone.answer(&query("www.home.test.", RecordType::A)).unwrap();
+        assert_eq!(answer.response.answers().len(), 2);
+        assert_eq!(answer.unresolved_target, None);
+
+        let answer = zone.answer(&query("cdn.home.test.", RecordType::A)).unwrap();
+        assert_eq!(answer.response.answers().len(), 1);
+        assert_eq!(answer.unresolved_target, Some(Name::from_ascii("cdn.example.com.").unwrap()));
+
+        // Запрос самой CNAME-записи цепочку не раскрывает
+        assert_eq!(answers(&zone, "cdn.home.test.", RecordType::CNAME).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn records_are_validated_on_add_and_update() {
+        let _dir = config::use_temp_app_dir();
+
+        let added = RecordsManager::add_record(record("", "nas.home.test", LocalRecordType::A, "192.0.2.1")).unwrap();
+        assert!(added.id.starts_with("record-"));
+        assert!(RecordsManager::add_record(record(&added.id, "pc.home.test", LocalRecordType::A, "192.0.2.2")).is_err());
+        assert!(RecordsManager::add_record(record("v6", "nas.home.test", LocalRecordType::Aaaa, "192.0.2.1")).is_err());
+        assert!(RecordsManager::add_record(record("bad", "bad name..test", LocalRecordType::A, "192.0.2.1")).is_err());
+
+        // CNAME не уживается с другими записями того же имени, в любую сторону
+        assert!(RecordsManager::add_record(record("alias", "NAS.home.test.", LocalRecordType::Cname, "pc.home.test")).is_err());
+        RecordsManager::add_record(record("www", "www.home.test", LocalRecordType::Cname, "nas.home.test")).unwrap();
+        assert!(RecordsManager::add_record(record("txt", "www.home.test", LocalRecordType::Txt, "hello")).is_err());
+        let mut disabled = record("txt", "www.home.test", LocalRecordType::Txt, "hello");
+        disabled.enabled = false;
+        RecordsManager::add_record(disabled).unwrap();
+
+        // Запись не конфликтует сама с собой
+        let mut www = record("www", "www.home.test", LocalRecordType::Cname, "pc.home.test");
+        RecordsManager::update_record(www.clone()).unwrap();
+        www.id = "missing".to_string();
+        assert!(RecordsManager::update_record(www).is_err());
+
+        RecordsManager::delete_record("txt").unwrap();
+        let ids: Vec<String> = RecordsManager::load_records().unwrap().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, [added.id, "www".to_string()]);
+        assert_eq!(RecordsManager::load_records().unwrap()[1].value, "pc.home.test");
+    }
+}
@@ -0,0 +1,912 @@
+use super::access::{AccessControl, Admission, LanConfig};
+use super::blocklist::{BlockResponse, Blocklists};
+use super::cache::{CacheConfig, ResponseCache, StaleAnswer};
+use super::dnssec::{self, DnssecConfig, DnssecValidator};
+use super::privacy::PrivacyConfig;
+use super::querylog::{QueryLog, QueryLogConfig, QueryLogEntry};
+use super::records::{LocalAnswer, LocalZone};
+use super::rules::RuleSet;
+use super::strategy::{BreakerConfig, LatencyHistogram, UpstreamHealthStatus, UpstreamStrategy};
+use super::upstream::Upstream;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::{debug, warn};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// TTL ответов-заглушек для заблокированных доменов
+const BLOCKED_TTL: u32 = 10;
+
+/// Обработчик запросов локального прокси: разбор, кэш, пересылка в апстрим, ответ
+pub struct ProxyResolver {
+    upstreams: RwLock<Arc<Vec<Upstream>>>,
+    rules: RwLock<Arc<RuleSet>>,
+    local: RwLock<Arc<LocalZone>>,
+    timeout: RwLock<Duration>,
+    strategy: RwLock<UpstreamStrategy>,
+    breaker: RwLock<BreakerConfig>,
+    privacy: RwLock<Arc<PrivacyConfig>>,
+    /// Проверка DNSSEC; `None`, если выключена
+    dnssec: RwLock<Option<Arc<DnssecValidator>>>,
+    next_upstream: AtomicUsize,
+    cache: ResponseCache,
+    blocklists: Arc<Blocklists>,
+    query_log: QueryLog,
+    access: AccessControl,
+}
+
+/// Как был получен ответ — для журнала запросов
+enum Outcome {
+    Local,
+    Blocked,
+    /// Запрос ANY от клиента сети отклонён
+    Refused,
+    Cached,
+    /// Просроченный ответ из кэша (RFC 8767)
+    Stale,
+    /// Имя апстрима, ответившего на запрос; `None`, если не ответил никто
+    Forwarded(Option<String>),
+}
+
+impl ProxyResolver {
+    pub fn new(
+        timeout: Duration,
+        cache_config: CacheConfig,
+        query_log_config: QueryLogConfig,
+        blocklists: Arc<Blocklists>,
+    ) -> Self {
+        Self {
+            upstreams: RwLock::new(Arc::new(Vec::new())),
+            rules: RwLock::new(Arc::new(RuleSet::default())),
+            local: RwLock::new(Arc::new(LocalZone::default())),
+            timeout: RwLock::new(timeout),
+            strategy: RwLock::new(UpstreamStrategy::Strict),
+            breaker: RwLock::new(BreakerConfig::default()),
+            privacy: RwLock::new(Arc::new(PrivacyConfig::default())),
+            dnssec: RwLock::new(None),
+            next_upstream: AtomicUsize::new(0),
+            cache: ResponseCache::new(cache_config),
+            blocklists,
+            query_log: QueryLog::new(query_log_config),
+            access: AccessControl::new(),
+        }
+    }
+
+    pub fn set_upstreams(&self, upstreams: Vec<Upstream>) {
+        if let Ok(mut current) = self.upstreams.write() {
+            *current = Arc::new(upstreams);
+        }
+        // Ответы прежнего апстрима больше не актуальны
+        self.cache.clear_entries();
+    }
+
+    pub fn set_rules(&self, rules: RuleSet) {
+        if let Ok(mut current) = self.rules.write() {
+            *current = Arc::new(rules);
+        }
+        self.cache.clear_entries();
+    }
+
+    pub fn set_local_records(&self, zone: LocalZone) {
+        if let Ok(mut current) = self.local.write() {
+            *current = Arc::new(zone);
+        }
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        if let Ok(mut current) = self.timeout.write() {
+            *current = timeout;
+        }
+    }
+
+    pub fn set_strategy(&self, strategy: UpstreamStrategy, breaker: BreakerConfig) {
+        if let Ok(mut current) = self.strategy.write() {
+            *current = strategy;
+        }
+        if let Ok(mut current) = self.breaker.write() {
+            *current = breaker;
+        }
+    }
+
+    pub fn set_privacy(&self, privacy: PrivacyConfig) {
+        if let Ok(mut current) = self.privacy.write() {
+            *current = Arc::new(privacy);
+        }
+    }
+
+    pub fn set_dnssec(&self, config: &DnssecConfig) {
+        let validator = match config.enabled.then(|| DnssecValidator::new(config)) {
+            Some(Ok(validator)) => Some(Arc::new(validator)),
+            Some(Err(e)) => {
+                warn!("⚠️  DNSSEC validation disabled: {:#}", e);
+                None
+            }
+            None => None,
+        };
+        if let Ok(mut current) = self.dnssec.write() {
+            *current = validator;
+        }
+        // В кэше могут быть ответы, проверенные по-другому или не проверенные вовсе
+        self.cache.clear_entries();
+    }
+
+    fn validator(&self) -> Option<Arc<DnssecValidator>> {
+        self.dnssec.read().ok().and_then(|v| v.clone())
+    }
+
+    pub fn set_lan(&self, config: LanConfig) {
+        self.access.configure(config);
+    }
+
+    pub fn upstream_health(&self) -> Vec<UpstreamHealthStatus> {
+        self.upstreams
+            .read()
+            .map(|upstreams| upstreams.iter().map(|u| u.health().status(u.name())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Гистограммы задержек апстримов пресета по имени
+    pub fn upstream_latencies(&self) -> Vec<(String, LatencyHistogram)> {
+        self.upstreams
+            .read()
+            .map(|upstreams| {
+                upstreams
+                    .iter()
+                    .map(|u| (u.name().to_string(), u.health().latency_histogram()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn cache(&self) -> &ResponseCache {
+        &self.cache
+    }
+
+    pub fn query_log(&self) -> &QueryLog {
+        &self.query_log
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    pub fn upstream_names(&self) -> Vec<String> {
+        self.upstreams
+            .read()
+            .map(|upstreams| upstreams.iter().map(|u| u.name().to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Обработать сырой DNS-запрос клиента. `None` — пакет не похож на DNS и отбрасывается.
+    pub async fn handle(self: &Arc<Self>, packet: &[u8], client: SocketAddr) -> Option<Vec<u8>> {
+        // Чужим и слишком частым клиентам не отвечаем вовсе, чтобы не усиливать отражённый трафик
+        let admission = self.access.admit(client.ip());
+        if matches!(admission, Admission::Denied | Admission::RateLimited) {
+            debug!("Dropping query from {}: {:?}", client, admission);
+            return None;
+        }
+
+        let request = match Message::from_vec(packet) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("Dropping malformed packet from {}: {}", client, e);
+                return None;
+            }
+        };
+
+        if request.message_type() != MessageType::Query
+            || request.op_code() != OpCode::Query
+            || request.queries().len() != 1
+        {
+            return Some(error_response(&request, ResponseCode::FormErr));
+        }
+
+        let started = Instant::now();
+        let refuse_any = admission == Admission::Lan
+            && self.access.refuses_any()
+            && request.query().is_some_and(|q| q.query_type() == RecordType::ANY);
+        let (response, outcome) = if refuse_any {
+            self.access.any_refused();
+            (error_response(&request, ResponseCode::Refused), Outcome::Refused)
+        } else {
+            self.resolve(packet, &request).await
+        };
+        self.log_query(&request, client, &response, outcome, started);
+        Some(response)
+    }
+
+    async fn resolve(self: &Arc<Self>, packet: &[u8], request: &Message) -> (Vec<u8>, Outcome) {
+        if let Some(answer) = self.answer_locally(request).await {
+            return answer;
+        }
+        self.resolve_remote(packet, request).await
+    }
+
+    /// Блоклист, затем кэш или апстрим
+    async fn resolve_remote(self: &Arc<Self>, packet: &[u8], request: &Message) -> (Vec<u8>, Outcome) {
+        if request.query().is_some_and(|q| self.blocklists.is_blocked(q.name())) {
+            return (blocked_response(request, self.blocklists.response()), Outcome::Blocked);
+        }
+
+        let (response, outcome) = self.resolve_upstream(packet, request).await;
+        match self.validator() {
+            Some(_) => (dnssec::for_client(request, response), outcome),
+            None => (response, outcome),
+        }
+    }
+
+    /// Ответ из кэша или от апстрима
+    async fn resolve_upstream(self: &Arc<Self>, packet: &[u8], request: &Message) -> (Vec<u8>, Outcome) {
+        if let Some(cached) = self.cache.get(request) {
+            if cached.prefetch {
+                self.spawn_prefetch(packet.to_vec(), request.clone());
+            }
+            return (cached.response, Outcome::Cached);
+        }
+
+        if let Some(stale) = self.cache.get_stale(request) {
+            return self.refresh_or_serve_stale(packet, request, stale).await;
+        }
+
+        match self.fetch(packet, request).await {
+            Some((response, upstream)) => {
+                if self.cacheable(request) {
+                    self.cache.insert(request, &response);
+                }
+                (response, Outcome::Forwarded(Some(upstream)))
+            }
+            None => (error_response(request, ResponseCode::ServFail), Outcome::Forwarded(None)),
+        }
+    }
+
+    /// RFC 8767: ждать апстрим не дольше `client_timeout`, иначе отдать просроченный
+    /// ответ. Обновление кэша продолжается в фоне и после ответа клиенту.
+    async fn refresh_or_serve_stale(
+        self: &Arc<Self>,
+        packet: &[u8],
+        request: &Message,
+        stale: StaleAnswer,
+    ) -> (Vec<u8>, Outcome) {
+        let resolver = self.clone();
+        let (packet, request_copy) = (packet.to_vec(), request.clone());
+        let mut refresh = tokio::spawn(async move {
+            let answer = resolver.fetch(&packet, &request_copy).await;
+            match &answer {
+                Some((response, _)) if !is_server_failure(response) && resolver.cacheable(&request_copy) => {
+                    resolver.cache.insert(&request_copy, response);
+                }
+                _ => debug!("Refresh failed, keeping stale answer for {:?}", request_copy.query()),
+            }
+            answer
+        });
+
+        match tokio::time::timeout(stale.client_timeout, &mut refresh).await {
+            Ok(Ok(Some((response, upstream)))) if !is_server_failure(&response) => {
+                (response, Outcome::Forwarded(Some(upstream)))
+            }
+            _ => {
+                self.cache.stale_served();
+                (stale.response, Outcome::Stale)
+            }
+        }
+    }
+
+    /// Ответ из локальных записей. Если CNAME ведёт наружу, цель разрешается как
+    /// обычный запрос: через блоклист, кэш и апстрим.
+    async fn answer_locally(self: &Arc<Self>, request: &Message) -> Option<(Vec<u8>, Outcome)> {
+        let zone = self.local.read().map(|z| z.clone()).ok()?;
+        let LocalAnswer {
+            mut response,
+            unresolved_target,
+        } = zone.answer(request)?;
+
+        let mut outcome = Outcome::Local;
+        if let (Some(target), Some(query)) = (unresolved_target, request.query()) {
+            let mut follow = Message::new();
+            let mut target_query = Query::query(target, query.query_type());
+            target_query.set_query_class(query.query_class());
+            follow
+                .set_id(request.id())
+                .set_recursion_desired(true)
+                .add_query(target_query);
+
+            let target_answer = match follow.to_vec() {
+                Ok(packet) => {
+                    let (bytes, target_outcome) = self.resolve_remote(&packet, &follow).await;
+                    if matches!(target_outcome, Outcome::Blocked) {
+                        outcome = Outcome::Blocked;
+                    }
+                    Message::from_vec(&bytes).ok()
+                }
+                Err(_) => None,
+            };
+            match target_answer {
+                Some(mut answer) => {
+                    response
+                        .set_authoritative(false)
+                        .set_response_code(answer.response_code())
+                        .add_answers(answer.take_answers());
+                }
+                None => {
+                    response.set_authoritative(false).set_response_code(ResponseCode::ServFail);
+                }
+            }
+        }
+
+        Some((response.to_vec().ok()?, outcome))
+    }
+
+    fn log_query(&self, request: &Message, client: SocketAddr, response: &[u8], outcome: Outcome, started: Instant) {
+        let Some(query) = request.query() else {
+            return;
+        };
+        let rcode = response
+            .get(3)
+            .map(|flags| ResponseCode::from_low(flags & 0x0F))
+            .unwrap_or(ResponseCode::ServFail);
+
+        self.query_log.record(QueryLogEntry {
+            timestamp: Utc::now(),
+            client: client.ip().to_string(),
+            name: query.name().to_lowercase().to_ascii().trim_end_matches('.').to_string(),
+            record_type: query.query_type().to_string(),
+            upstream: match &outcome {
+                Outcome::Forwarded(upstream) => upstream.clone(),
+                Outcome::Local => Some("local".to_string()),
+                _ => None,
+            },
+            rcode: rcode.to_str().to_string(),
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+            cached: matches!(outcome, Outcome::Cached | Outcome::Stale),
+            stale: matches!(outcome, Outcome::Stale),
+            blocked: matches!(outcome, Outcome::Blocked),
+        });
+    }
+
+    /// Апстримы для запроса: правила условной пересылки имеют приоритет над пресетом
+    fn upstreams_for(&self, request: &Message) -> Arc<Vec<Upstream>> {
+        let rules = self.rules.read().map(|r| r.clone()).unwrap_or_default();
+        if let Some((rule_id, upstreams)) = request.query().and_then(|q| rules.find(q.name())) {
+            debug!("Query matched forwarding rule {}", rule_id);
+            return upstreams;
+        }
+
+        self.upstreams.read().map(|u| u.clone()).unwrap_or_default()
+    }
+
+    /// Переслать запрос и, если включена проверка DNSSEC, проверить ответ.
+    /// Не прошедший проверку ответ заменяется на SERVFAIL.
+    async fn fetch(&self, packet: &[u8], request: &Message) -> Option<(Vec<u8>, String)> {
+        let Some(validator) = self.validator() else {
+            return self.forward(packet, request).await;
+        };
+        // С битом CD клиент проверяет подписи сам (RFC 4035 §3.2.2). Прокси ответ
+        // не проверял, поэтому AD апстрима клиенту не передаётся (RFC 6840 §5.7)
+        if request.checking_disabled() {
+            let (response, upstream) = self.forward(packet, request).await?;
+            return Some((dnssec::without_authentic_data(response), upstream));
+        }
+
+        let query = dnssec::dnssec_query(request).ok()?;
+        let (response, upstream) = self.forward(&query, request).await?;
+        if is_server_failure(&response) {
+            return Some((response, upstream));
+        }
+
+        match validator.validate(self, request, &response).await {
+            Ok(validated) => Some((validated, upstream)),
+            Err(e) => {
+                let name = request.query().map(|q| q.name().to_string()).unwrap_or_default();
+                warn!("⚠️  DNSSEC validation failed for {}: {:#}", name, e);
+                Some((error_response(request, ResponseCode::ServFail), upstream))
+            }
+        }
+    }
+
+    /// Непроверенные ответы на запросы с CD не должны попасть в кэш к остальным клиентам
+    fn cacheable(&self, request: &Message) -> bool {
+        !request.checking_disabled() || self.validator().is_none()
+    }
+
+    /// Запрос записей для цепочки доверия DNSSEC; подписи проверяет вызывающий
+    pub(super) async fn dnssec_lookup(&self, name: &Name, record_type: RecordType) -> Result<Message> {
+        let mut request = Message::new();
+        request
+            .set_id(rand::random())
+            .set_recursion_desired(true)
+            .add_query(Query::query(name.clone(), record_type));
+        let packet = dnssec::dnssec_query(&request)?;
+
+        let (response, _) = self
+            .forward(&packet, &request)
+            .await
+            .with_context(|| format!("No upstream answered {} {}", name, record_type))?;
+        let response = Message::from_vec(&response).context("Malformed answer")?;
+        if !matches!(response.response_code(), ResponseCode::NoError | ResponseCode::NXDomain) {
+            anyhow::bail!("{} {} lookup failed with {}", name, record_type, response.response_code());
+        }
+        Ok(response)
+    }
+
+    /// Переслать запрос согласно стратегии; ID ответа совпадает с ID запроса
+    async fn forward(&self, packet: &[u8], request: &Message) -> Option<(Vec<u8>, String)> {
+        let id = request.id();
+        let upstreams = self.upstreams_for(request);
+        let timeout = self.timeout.read().map(|t| *t).unwrap_or(Duration::from_secs(5));
+        let strategy = self.strategy.read().map(|s| *s).unwrap_or(UpstreamStrategy::Strict);
+        let breaker = self.breaker.read().map(|b| b.clone()).unwrap_or_default();
+        let privacy = self.privacy.read().map(|p| p.clone()).unwrap_or_default();
+        let order = self.upstream_order(&upstreams, strategy);
+
+        if strategy == UpstreamStrategy::Race {
+            return Self::race(upstreams, order, packet, id, timeout, breaker, privacy).await;
+        }
+
+        let mut attempted = false;
+        for &index in &order {
+            let upstream = &upstreams[index];
+            if !upstream.health().try_acquire() {
+                continue;
+            }
+            attempted = true;
+            if let Some(response) = exchange_with(upstream, packet, id, timeout, &breaker, &privacy).await {
+                return Some((response, upstream.name().to_string()));
+            }
+        }
+
+        // Все апстримы выведены размыкателем — лучше попробовать их, чем сразу отдать SERVFAIL
+        if !attempted {
+            for &index in &order {
+                let upstream = &upstreams[index];
+                if let Some(response) = exchange_with(upstream, packet, id, timeout, &breaker, &privacy).await {
+                    return Some((response, upstream.name().to_string()));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Порядок опроса апстримов для стратегии
+    fn upstream_order(&self, upstreams: &[Upstream], strategy: UpstreamStrategy) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..upstreams.len()).collect();
+
+        match strategy {
+            UpstreamStrategy::Strict | UpstreamStrategy::Race => {}
+            UpstreamStrategy::RoundRobin => {
+                if !order.is_empty() {
+                    let start = self.next_upstream.fetch_add(1, Ordering::Relaxed) % order.len();
+                    order.rotate_left(start);
+                }
+            }
+            UpstreamStrategy::LowestLatency => {
+                // Ещё не измеренные апстримы идут первыми, чтобы получить замер
+                let latency = |i: &usize| upstreams[*i].health().latency_ms().unwrap_or(0.0);
+                order.sort_by(|a, b| latency(a).total_cmp(&latency(b)));
+            }
+        }
+
+        order
+    }
+
+    /// Опросить все доступные апстримы одновременно; побеждает первый корректный ответ
+    async fn race(
+        upstreams: Arc<Vec<Upstream>>,
+        order: Vec<usize>,
+        packet: &[u8],
+        id: u16,
+        timeout: Duration,
+        breaker: BreakerConfig,
+        privacy: Arc<PrivacyConfig>,
+    ) -> Option<(Vec<u8>, String)> {
+        let mut admitted: Vec<usize> = order
+            .iter()
+            .copied()
+            .filter(|&i| upstreams[i].health().try_acquire())
+            .collect();
+        if admitted.is_empty() {
+            admitted = order;
+        }
+
+        let mut tasks = JoinSet::new();
+        for index in admitted {
+            let upstreams = upstreams.clone();
+            let packet = packet.to_vec();
+            let breaker = breaker.clone();
+            let privacy = privacy.clone();
+            tasks.spawn(async move {
+                let upstream = &upstreams[index];
+                exchange_with(upstream, &packet, id, timeout, &breaker, &privacy)
+                    .await
+                    .map(|response| (response, upstream.name().to_string()))
+            });
+        }
+
+        // Оставшиеся запросы отменяются вместе с JoinSet
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(Some(answer)) = result {
+                return Some(answer);
+            }
+        }
+
+        None
+    }
+
+    /// Обновить популярную запись кэша до истечения её TTL
+    fn spawn_prefetch(self: &Arc<Self>, packet: Vec<u8>, request: Message) {
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            debug!("Prefetching {:?}", request.query().map(|q| q.name().to_string()));
+            match resolver.fetch(&packet, &request).await {
+                Some((response, _)) if resolver.cacheable(&request) => resolver.cache.insert(&request, &response),
+                Some(_) => {}
+                None => resolver.cache.prefetch_failed(&request),
+            }
+        });
+    }
+}
+
+/// Один запрос к апстриму с учётом его здоровья; ID ответа заменяется на `id`
+async fn exchange_with(
+    upstream: &Upstream,
+    packet: &[u8],
+    id: u16,
+    timeout: Duration,
+    breaker: &BreakerConfig,
+    privacy: &PrivacyConfig,
+) -> Option<Vec<u8>> {
+    let started = Instant::now();
+    match upstream.exchange(packet, timeout, privacy.for_upstream(upstream.name())).await {
+        Ok(mut response) if Message::from_vec(&response).is_ok() => {
+            upstream.health().record_success(started.elapsed());
+            response[..2].copy_from_slice(&id.to_be_bytes());
+            Some(response)
+        }
+        Ok(_) => {
+            warn!("⚠️  Malformed answer from {}", upstream.name());
+            upstream.health().record_failure(breaker);
+            None
+        }
+        Err(e) => {
+            warn!("⚠️  Upstream {} failed: {:#}", upstream.name(), e);
+            upstream.health().record_failure(breaker);
+            None
+        }
+    }
+}
+
+/// SERVFAIL или REFUSED от апстрима не заменяют просроченный ответ (RFC 8767 §5)
+fn is_server_failure(response: &[u8]) -> bool {
+    response
+        .get(3)
+        .map(|flags| ResponseCode::from_low(flags & 0x0F))
+        .is_some_and(|code| matches!(code, ResponseCode::ServFail | ResponseCode::Refused))
+}
+
+/// Ответ с кодом ошибки и исходным вопросом
+pub fn error_response(request: &Message, code: ResponseCode) -> Vec<u8> {
+    let mut response = Message::error_msg(request.id(), request.op_code(), code);
+    response
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(request.queries().to_vec());
+    response.to_vec().unwrap_or_default()
+}
+
+/// Ответ на запрос к заблокированному домену
+pub fn blocked_response(request: &Message, mode: BlockResponse) -> Vec<u8> {
+    let query = match (mode, request.query()) {
+        (BlockResponse::NullIp, Some(query)) => query,
+        (BlockResponse::Refused, _) => return error_response(request, ResponseCode::Refused),
+        _ => return error_response(request, ResponseCode::NXDomain),
+    };
+
+    let mut response = Message::error_msg(request.id(), request.op_code(), ResponseCode::NoError);
+    response
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(request.queries().to_vec());
+
+    let rdata = match query.query_type() {
+        RecordType::A => Some(RData::A(A::new(0, 0, 0, 0))),
+        RecordType::AAAA => Some(RData::AAAA(AAAA::new(0, 0, 0, 0, 0, 0, 0, 0))),
+        _ => None,
+    };
+    if let Some(rdata) = rdata {
+        response.add_answer(Record::from_rdata(query.name().clone(), BLOCKED_TTL, rdata));
+    }
+
+    response.to_vec().unwrap_or_default()
+}
+
+/// Обрезать ответ под UDP-лимит клиента (512 байт без EDNS), выставив TC
+pub fn fit_udp_response(request: &Message, response: Vec<u8>) -> Vec<u8> {
+    let limit = request
+        .extensions()
+        .as_ref()
+        .map(|edns| edns.max_payload().max(512))
+        .unwrap_or(512) as usize;
+
+    if response.len() <= limit {
+        return response;
+    }
+    truncated(response)
+}
+
+/// Ответ по TCP не может быть длиннее 65535 байт (RFC 1035 §4.2.2): больший
+/// заменяется пустым с битом TC
+pub fn fit_tcp_response(response: Vec<u8>) -> Vec<u8> {
+    if response.len() <= u16::MAX as usize {
+        return response;
+    }
+    truncated(response)
+}
+
+/// Заголовок и вопрос ответа с выставленным TC
+fn truncated(response: Vec<u8>) -> Vec<u8> {
+    match Message::from_vec(&response) {
+        Ok(full) => {
+            let mut truncated = Message::new();
+            truncated
+                .set_header(*full.header())
+                .set_truncated(true)
+                .add_queries(full.queries().to_vec());
+            truncated.to_vec().unwrap_or(response)
+        }
+        Err(_) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::proxy::blocklist::{BlocklistSettings, BlocklistSource};
+    use crate::proxy::records::{LocalRecord, LocalRecordType};
+
+    fn query(name: &str) -> Message {
+        let mut message = Message::new();
+        message
+            .set_id(0x5151)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        message
+    }
+
+    fn resolver() -> Arc<ProxyResolver> {
+        Arc::new(ProxyResolver::new(
+            Duration::from_secs(1),
+            CacheConfig::default(),
+            QueryLogConfig::default(),
+            Arc::new(Blocklists::new()),
+        ))
+    }
+
+    /// Апстрим на loopback, отвечающий адресом `answer` через `delay`
+    async fn upstream(answer: [u8; 4], delay: Duration) -> Upstream {
+        let socket = Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let socket = socket.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let query = request.queries()[0].clone();
+                    let mut response = Message::new();
+                    response
+                        .set_id(request.id())
+                        .set_message_type(MessageType::Response)
+                        .add_query(query.clone())
+                        .add_answer(Record::from_rdata(query.name().clone(), 60, RData::A(A::from(std::net::Ipv4Addr::from(answer)))));
+                    socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+                });
+            }
+        });
+        Upstream::udp(addr)
+    }
+
+    #[tokio::test]
+    async fn round_robin_rotates_the_first_upstream() {
+        let resolver = resolver();
+        let upstreams = vec![
+            upstream([192, 0, 2, 1], Duration::ZERO).await,
+            upstream([192, 0, 2, 2], Duration::ZERO).await,
+            upstream([192, 0, 2, 3], Duration::ZERO).await,
+        ];
+
+        let orders: Vec<Vec<usize>> = (0..4)
+            .map(|_| resolver.upstream_order(&upstreams, UpstreamStrategy::RoundRobin))
+            .collect();
+        assert_eq!(orders, [vec![0, 1, 2], vec![1, 2, 0], vec![2, 0, 1], vec![0, 1, 2]]);
+        assert_eq!(resolver.upstream_order(&upstreams, UpstreamStrategy::Strict), [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn lowest_latency_orders_by_average() {
+        let resolver = resolver();
+        let upstreams = vec![
+            upstream([192, 0, 2, 1], Duration::ZERO).await,
+            upstream([192, 0, 2, 2], Duration::ZERO).await,
+            upstream([192, 0, 2, 3], Duration::ZERO).await,
+        ];
+        upstreams[0].health().record_success(Duration::from_millis(80));
+        upstreams[1].health().record_success(Duration::from_millis(20));
+
+        // Ещё не измеренный апстрим идёт первым, чтобы получить замер
+        let order = resolver.upstream_order(&upstreams, UpstreamStrategy::LowestLatency);
+        assert_eq!(order, [2, 1, 0]);
+
+        // Среднее сдвигается после новых замеров
+        upstreams[2].health().record_success(Duration::from_millis(200));
+        upstreams[1].health().record_success(Duration::from_millis(500));
+        let order = resolver.upstream_order(&upstreams, UpstreamStrategy::LowestLatency);
+        assert_eq!(order, [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn race_returns_the_first_answer() {
+        let _dir = config::use_temp_app_dir();
+        let resolver = resolver();
+        let slow = upstream([192, 0, 2, 1], Duration::from_millis(500)).await;
+        let fast = upstream([192, 0, 2, 2], Duration::ZERO).await;
+        let fast_name = fast.name().to_string();
+        resolver.set_upstreams(vec![slow, fast]);
+        resolver.set_strategy(UpstreamStrategy::Race, BreakerConfig::default());
+
+        let request = query("race.example.");
+        let started = Instant::now();
+        let (response, upstream) = resolver.forward(&request.to_vec().unwrap(), &request).await.unwrap();
+
+        assert_eq!(upstream, fast_name);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        let response = Message::from_vec(&response).unwrap();
+        assert_eq!(response.id(), request.id());
+        assert!(matches!(response.answers()[0].data(), Some(RData::A(a)) if *a == A::new(192, 0, 2, 2)));
+    }
+
+    /// Резолвер с одним апстримом и просроченным ответом 192.0.2.9 в кэше
+    fn resolver_with_stale(request: &Message, upstream: Upstream) -> Arc<ProxyResolver> {
+        let resolver = Arc::new(ProxyResolver::new(
+            Duration::from_secs(1),
+            CacheConfig {
+                stale_answer_ttl: 7,
+                stale_client_timeout_ms: 200,
+                ..CacheConfig::default()
+            },
+            QueryLogConfig::default(),
+            Arc::new(Blocklists::new()),
+        ));
+        resolver.set_upstreams(vec![upstream]);
+
+        let mut old = request.clone();
+        old.set_message_type(MessageType::Response).add_answer(Record::from_rdata(
+            request.queries()[0].name().clone(),
+            60,
+            RData::A(A::new(192, 0, 2, 9)),
+        ));
+        resolver.cache.insert(request, &old.to_vec().unwrap());
+        resolver.cache.age_entries(Duration::from_secs(120));
+        resolver
+    }
+
+    /// Адрес и TTL первой записи ответа
+    fn first_answer(response: &[u8]) -> (A, u32) {
+        let response = Message::from_vec(response).unwrap();
+        let record = &response.answers()[0];
+        match record.data() {
+            Some(RData::A(a)) => (*a, record.ttl()),
+            other => panic!("expected an A record, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_answer_is_served_when_the_refresh_is_slow() {
+        let _dir = config::use_temp_app_dir();
+        let request = query("stale.example.");
+        let resolver = resolver_with_stale(&request, upstream([192, 0, 2, 1], Duration::from_millis(600)).await);
+
+        let started = Instant::now();
+        let (response, outcome) = resolver.resolve_upstream(&request.to_vec().unwrap(), &request).await;
+
+        assert!(matches!(outcome, Outcome::Stale));
+        assert!(started.elapsed() < Duration::from_millis(600));
+        assert_eq!(first_answer(&response), (A::new(192, 0, 2, 9), 7));
+        assert_eq!(resolver.cache.stats().stale_served, 1);
+
+        // Обновление продолжается в фоне и заменяет запись в кэше
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        let cached = resolver.cache.get(&request).unwrap();
+        assert_eq!(first_answer(&cached.response).0, A::new(192, 0, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn refresh_that_beats_the_client_timeout_wins() {
+        let _dir = config::use_temp_app_dir();
+        let request = query("stale.example.");
+        let resolver = resolver_with_stale(&request, upstream([192, 0, 2, 2], Duration::ZERO).await);
+
+        let (response, outcome) = resolver.resolve_upstream(&request.to_vec().unwrap(), &request).await;
+
+        assert!(matches!(outcome, Outcome::Forwarded(Some(_))));
+        assert_eq!(first_answer(&response), (A::new(192, 0, 2, 2), 60));
+        assert_eq!(resolver.cache.stats().stale_served, 0);
+        assert!(resolver.cache.get(&request).is_some());
+    }
+
+    #[test]
+    fn oversized_tcp_answers_are_truncated() {
+        let request = query("big.example.");
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .add_queries(request.queries().to_vec());
+        // hickory сам не соберёт такой ответ, но апстрим DoH может прислать его
+        let mut full = response.to_vec().unwrap();
+        full.resize(70_000, 0);
+
+        let fitted = Message::from_vec(&fit_tcp_response(full)).unwrap();
+        assert!(fitted.truncated());
+        assert!(fitted.answers().is_empty());
+        assert_eq!(fitted.queries(), request.queries());
+
+        let small = blocked_response(&request, BlockResponse::NullIp);
+        assert_eq!(fit_tcp_response(small.clone()), small);
+    }
+
+    #[tokio::test]
+    async fn local_cname_to_a_blocked_target_is_blocked() {
+        let dir = config::use_temp_app_dir();
+        let list = dir.path().join("hosts.txt");
+        std::fs::write(&list, "0.0.0.0 ads.example\n").unwrap();
+
+        let blocklists = Arc::new(Blocklists::new());
+        blocklists
+            .update_settings(BlocklistSettings {
+                sources: vec![BlocklistSource {
+                    id: "local".to_string(),
+                    name: "Local".to_string(),
+                    location: list.to_string_lossy().into_owned(),
+                    enabled: true,
+                    last_updated: None,
+                    entries: 0,
+                    last_error: None,
+                }],
+                ..BlocklistSettings::default()
+            })
+            .await
+            .unwrap();
+
+        let resolver = Arc::new(ProxyResolver::new(
+            Duration::from_secs(1),
+            CacheConfig::default(),
+            QueryLogConfig::default(),
+            blocklists,
+        ));
+        resolver.set_local_records(LocalZone::compile(&[LocalRecord {
+            id: "alias".to_string(),
+            name: "alias.home".to_string(),
+            record_type: LocalRecordType::Cname,
+            value: "ads.example".to_string(),
+            ttl: 300,
+            enabled: true,
+        }]));
+
+        let request = query("alias.home.");
+        let (response, outcome) = resolver.resolve(&request.to_vec().unwrap(), &request).await;
+        let response = Message::from_vec(&response).unwrap();
+
+        // Без блоклиста цель ушла бы к апстриму; апстримов нет, так что был бы SERVFAIL
+        assert!(matches!(outcome, Outcome::Blocked));
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(matches!(response.answers()[0].data(), Some(RData::CNAME(_))));
+    }
+}
@@ -0,0 +1,375 @@
+use super::upstream::{Upstream, UpstreamContext};
+use crate::config;
+use crate::custom_presets::CustomPresetsManager;
+use crate::preset_params::PresetParameters;
+use crate::presets;
+use crate::types::DnsPreset;
+use anyhow::{Context, Result};
+use hickory_proto::rr::Name;
+use ipnet::IpNet;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+const RULES_FILE: &str = "forwarding_rules.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum RuleTarget {
+    /// Upstream addresses in any form accepted by `Upstream::parse`
+    Servers(Vec<String>),
+    /// ID встроенного или пользовательского пресета
+    Preset(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardingRule {
+    pub id: String,
+    /// Суффикс домена (`corp.example`, `*.corp.example`) или сеть для
+    /// обратной зоны (`10.0.0.0/8`, `fd00::/8`)
+    pub pattern: String,
+    pub target: RuleTarget,
+    pub enabled: bool,
+}
+
+enum Matcher {
+    Suffix(Name),
+    Network(IpNet),
+}
+
+impl Matcher {
+    fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+
+        if let Ok(net) = pattern.parse::<IpNet>() {
+            return Ok(Self::Network(net.trunc()));
+        }
+
+        let domain = pattern.trim_start_matches("*.").trim_start_matches('.');
+        let mut name = Name::from_utf8(domain)
+            .with_context(|| format!("Invalid rule pattern: {}", pattern))?
+            .to_lowercase();
+        name.set_fqdn(true);
+        Ok(Self::Suffix(name))
+    }
+}
+
+struct CompiledRule {
+    id: String,
+    matcher: Matcher,
+    upstreams: Arc<Vec<Upstream>>,
+}
+
+/// Скомпилированные правила условной пересылки (split-horizon)
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    /// Построить апстримы для всех включённых правил. Правила с ошибками
+    /// пропускаются, чтобы одна опечатка не ломала остальные.
+    pub async fn compile(rules: &[ForwardingRule], context: &UpstreamContext) -> Self {
+        let mut compiled = Vec::new();
+
+        for rule in rules.iter().filter(|r| r.enabled) {
+            match Self::compile_rule(rule, context).await {
+                Ok(rule) => compiled.push(rule),
+                Err(e) => warn!("⚠️  Skipping forwarding rule {}: {:#}", rule.id, e),
+            }
+        }
+
+        Self { rules: compiled }
+    }
+
+    async fn compile_rule(rule: &ForwardingRule, context: &UpstreamContext) -> Result<CompiledRule> {
+        let matcher = Matcher::parse(&rule.pattern)?;
+
+        let upstreams = match &rule.target {
+            RuleTarget::Servers(servers) => {
+                let mut upstreams = Vec::new();
+                for server in servers {
+                    upstreams.extend(Upstream::parse(server, context).await?);
+                }
+                upstreams
+            }
+            RuleTarget::Preset(id) => {
+                let preset = find_preset(id).with_context(|| format!("Preset {} not found", id))?;
+                preset_upstreams(&preset, context.odoh_relay.as_deref()).await?
+            }
+        };
+
+        if upstreams.is_empty() {
+            anyhow::bail!("Rule has no upstream servers");
+        }
+
+        Ok(CompiledRule {
+            id: rule.id.clone(),
+            matcher,
+            upstreams: Arc::new(upstreams),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Самое специфичное правило для имени: для обратных зон — сеть с самым
+    /// длинным префиксом, иначе — самый длинный совпавший суффикс
+    pub fn find(&self, name: &Name) -> Option<(&str, Arc<Vec<Upstream>>)> {
+        let name = name.to_lowercase();
+
+        let by_network = reverse_prefix(&name).and_then(|(addr, len)| {
+            self.rules
+                .iter()
+                .filter_map(|rule| match &rule.matcher {
+                    Matcher::Network(net) if net.prefix_len() <= len && net.contains(&addr) => {
+                        Some((net.prefix_len(), rule))
+                    }
+                    _ => None,
+                })
+                .max_by_key(|(len, _)| *len)
+        });
+
+        let best = by_network.map(|(_, rule)| rule).or_else(|| {
+            self.rules
+                .iter()
+                .filter_map(|rule| match &rule.matcher {
+                    Matcher::Suffix(suffix) if suffix.zone_of(&name) => Some((suffix.num_labels(), rule)),
+                    _ => None,
+                })
+                .max_by_key(|(labels, _)| *labels)
+                .map(|(_, rule)| rule)
+        })?;
+
+        Some((best.id.as_str(), best.upstreams.clone()))
+    }
+}
+
+/// Адрес и длина префикса, закодированные в имени `*.in-addr.arpa` / `*.ip6.arpa`
+fn reverse_prefix(name: &Name) -> Option<(IpAddr, u8)> {
+    let labels: Vec<String> = name
+        .iter()
+        .map(|label| String::from_utf8_lossy(label).to_string())
+        .collect();
+
+    let n = labels.len();
+    if n >= 2 && labels[n - 2] == "in-addr" && labels[n - 1] == "arpa" {
+        let octets = &labels[..n - 2];
+        if octets.is_empty() || octets.len() > 4 {
+            return None;
+        }
+        let mut addr = [0u8; 4];
+        for (i, octet) in octets.iter().rev().enumerate() {
+            addr[i] = octet.parse().ok()?;
+        }
+        return Some((IpAddr::V4(Ipv4Addr::from(addr)), (octets.len() * 8) as u8));
+    }
+
+    if n >= 2 && labels[n - 2] == "ip6" && labels[n - 1] == "arpa" {
+        let nibbles = &labels[..n - 2];
+        if nibbles.is_empty() || nibbles.len() > 32 {
+            return None;
+        }
+        let mut addr = [0u8; 16];
+        for (i, nibble) in nibbles.iter().rev().enumerate() {
+            let value = u8::from_str_radix(nibble, 16).ok().filter(|_| nibble.len() == 1)?;
+            addr[i / 2] |= if i % 2 == 0 { value << 4 } else { value };
+        }
+        return Some((IpAddr::V6(Ipv6Addr::from(addr)), (nibbles.len() * 4) as u8));
+    }
+
+    None
+}
+
+/// Апстримы пресета с подставленными значениями параметров
+pub(super) async fn preset_upstreams(preset: &DnsPreset, odoh_relay: Option<&str>) -> Result<Vec<Upstream>> {
+    let preset = PresetParameters::resolve(preset)?;
+    Upstream::from_preset(
+        &preset.servers_ipv4,
+        &preset.servers_ipv6,
+        preset.doh_template.as_deref(),
+        preset.dot_hostname.as_deref(),
+        preset.stamp.as_deref(),
+        odoh_relay,
+    )
+    .await
+}
+
+/// Найти пресет среди встроенных и пользовательских
+pub fn find_preset(id: &str) -> Option<DnsPreset> {
+    presets::get_preset_by_id(id).or_else(|| {
+        CustomPresetsManager::load_custom_presets()
+            .ok()?
+            .into_iter()
+            .find(|p| p.id == id)
+    })
+}
+
+pub struct RulesManager;
+
+impl RulesManager {
+    /// Загрузить правила пересылки
+    pub fn load_rules() -> Result<Vec<ForwardingRule>> {
+        config::load_json(RULES_FILE)
+    }
+
+    /// Сохранить правила пересылки
+    pub fn save_rules(rules: &[ForwardingRule]) -> Result<()> {
+        config::save_json(RULES_FILE, rules)
+    }
+
+    /// Добавить правило; пустой ID генерируется автоматически
+    pub fn add_rule(mut rule: ForwardingRule) -> Result<ForwardingRule> {
+        Self::validate(&rule)?;
+        let mut rules = Self::load_rules()?;
+
+        if rule.id.is_empty() {
+            rule.id = format!("rule-{}", chrono::Utc::now().timestamp_millis());
+        }
+
+        if rules.iter().any(|r| r.id == rule.id) {
+            anyhow::bail!("Rule with this ID already exists");
+        }
+
+        rules.push(rule.clone());
+        Self::save_rules(&rules)?;
+
+        Ok(rule)
+    }
+
+    /// Обновить правило
+    pub fn update_rule(rule: ForwardingRule) -> Result<()> {
+        Self::validate(&rule)?;
+        let mut rules = Self::load_rules()?;
+
+        if let Some(existing) = rules.iter_mut().find(|r| r.id == rule.id) {
+            *existing = rule;
+            Self::save_rules(&rules)
+        } else {
+            anyhow::bail!("Rule not found");
+        }
+    }
+
+    /// Удалить правило по ID
+    pub fn delete_rule(id: &str) -> Result<()> {
+        let mut rules = Self::load_rules()?;
+        rules.retain(|r| r.id != id);
+        Self::save_rules(&rules)
+    }
+
+    fn validate(rule: &ForwardingRule) -> Result<()> {
+        Matcher::parse(&rule.pattern)?;
+
+        match &rule.target {
+            RuleTarget::Servers(servers) if servers.iter().all(|s| s.trim().is_empty()) => {
+                anyhow::bail!("Rule needs at least one upstream server")
+            }
+            RuleTarget::Preset(id) if find_preset(id).is_none() => {
+                anyhow::bail!("Preset {} not found", id)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn name(name: &str) -> Name {
+        Name::from_ascii(name).unwrap()
+    }
+
+    fn prefix(reverse: &str) -> Option<(String, u8)> {
+        reverse_prefix(&name(reverse)).map(|(addr, len)| (addr.to_string(), len))
+    }
+
+    fn rules(patterns: &[&str]) -> RuleSet {
+        let rules = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| CompiledRule {
+                id: pattern.to_string(),
+                matcher: Matcher::parse(pattern).unwrap(),
+                upstreams: Arc::new(vec![Upstream::udp(SocketAddr::from(([192, 0, 2, i as u8 + 1], 53)))]),
+            })
+            .collect();
+        RuleSet { rules }
+    }
+
+    fn find(rules: &RuleSet, query: &str) -> Option<String> {
+        rules.find(&name(query)).map(|(id, _)| id.to_string())
+    }
+
+    #[test]
+    fn ipv4_reverse_names_give_a_prefix_per_octet() {
+        assert_eq!(prefix("10.in-addr.arpa."), Some(("10.0.0.0".into(), 8)));
+        assert_eq!(prefix("2.1.10.in-addr.arpa."), Some(("10.1.2.0".into(), 24)));
+        assert_eq!(prefix("4.3.2.1.in-addr.arpa."), Some(("1.2.3.4".into(), 32)));
+
+        assert_eq!(prefix("in-addr.arpa."), None);
+        assert_eq!(prefix("5.4.3.2.1.in-addr.arpa."), None);
+        assert_eq!(prefix("300.in-addr.arpa."), None);
+        assert_eq!(prefix("x.in-addr.arpa."), None);
+    }
+
+    #[test]
+    fn ipv6_reverse_names_give_a_prefix_per_nibble() {
+        assert_eq!(prefix("8.b.d.0.1.0.0.2.ip6.arpa."), Some(("2001:db8::".into(), 32)));
+        // Нечётное число полубайтов: префикс не кратен байту
+        assert_eq!(prefix("d.f.ip6.arpa."), Some(("fd00::".into(), 8)));
+        assert_eq!(prefix("0.d.f.ip6.arpa."), Some(("fd00::".into(), 12)));
+        let full = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa.";
+        assert_eq!(prefix(full), Some(("2001:db8::1".into(), 128)));
+
+        assert_eq!(prefix("ip6.arpa."), None);
+        assert_eq!(prefix("db8.ip6.arpa."), None);
+        assert_eq!(prefix(&format!("0.{}", full)), None);
+    }
+
+    #[test]
+    fn longest_suffix_wins() {
+        let rules = rules(&["example", "*.corp.example", "lab.corp.example"]);
+
+        assert_eq!(find(&rules, "www.example.").as_deref(), Some("example"));
+        assert_eq!(find(&rules, "Host.CORP.example.").as_deref(), Some("*.corp.example"));
+        assert_eq!(find(&rules, "corp.example.").as_deref(), Some("*.corp.example"));
+        assert_eq!(find(&rules, "a.lab.corp.example.").as_deref(), Some("lab.corp.example"));
+        assert_eq!(find(&rules, "example.org."), None);
+        assert_eq!(find(&rules, "notexample."), None);
+    }
+
+    #[test]
+    fn longest_network_wins_for_reverse_zones() {
+        let rules = rules(&["10.0.0.0/8", "10.1.0.0/16", "10.16.0.0/12", "fd00::/8", "fd12:3400::/30"]);
+
+        assert_eq!(find(&rules, "4.3.2.10.in-addr.arpa.").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(find(&rules, "4.3.1.10.in-addr.arpa.").as_deref(), Some("10.1.0.0/16"));
+        // Префикс /12 не совпадает с границей октета
+        assert_eq!(find(&rules, "17.10.in-addr.arpa.").as_deref(), Some("10.16.0.0/12"));
+        assert_eq!(find(&rules, "32.10.in-addr.arpa.").as_deref(), Some("10.0.0.0/8"));
+        // Зона шире правила: 10.in-addr.arpa не целиком внутри 10.1.0.0/16
+        assert_eq!(find(&rules, "10.in-addr.arpa.").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(find(&rules, "1.168.192.in-addr.arpa."), None);
+
+        assert_eq!(find(&rules, "1.0.0.0.4.3.2.1.d.f.ip6.arpa.").as_deref(), Some("fd12:3400::/30"));
+        assert_eq!(find(&rules, "7.3.2.1.d.f.ip6.arpa.").as_deref(), Some("fd00::/8"));
+        // Семь полубайтов — это /28, правило /30 для такой зоны слишком узкое
+        assert_eq!(find(&rules, "0.4.3.2.1.d.f.ip6.arpa.").as_deref(), Some("fd00::/8"));
+    }
+
+    #[test]
+    fn network_rules_take_precedence_over_suffix_rules() {
+        let rules = rules(&["in-addr.arpa", "10.0.0.0/8"]);
+
+        assert_eq!(find(&rules, "1.10.in-addr.arpa.").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(find(&rules, "1.11.in-addr.arpa.").as_deref(), Some("in-addr.arpa"));
+    }
+}
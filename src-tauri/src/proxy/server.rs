@@ -0,0 +1,183 @@
+use super::resolver::{fit_tcp_response, fit_udp_response, ProxyResolver};
+use anyhow::Result;
+use hickory_proto::op::Message;
+use log::{debug, info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::timeout;
+
+/// Сколько готовых UDP-ответов может ждать отправки
+const UDP_RESPONSE_QUEUE: usize = 1024;
+/// Сколько UDP-запросов одного слушателя обрабатывается одновременно; лишние отбрасываются
+const MAX_UDP_IN_FLIGHT: usize = 512;
+/// Сколько TCP-соединений одного слушателя открыто одновременно; лишние закрываются
+const MAX_TCP_CONNECTIONS: usize = 128;
+/// Соединение, по которому столько времени не пришёл запрос, закрывается (RFC 7766 §6.2.3)
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Запущенные UDP/TCP слушатели прокси
+pub struct ProxyServer {
+    bound: Vec<SocketAddr>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ProxyServer {
+    /// Поднять слушатели на всех адресах. Адреса, которые не удалось занять
+    /// (например, `::1` при отключённом IPv6), пропускаются.
+    pub async fn start(addrs: &[SocketAddr], resolver: Arc<ProxyResolver>) -> Result<Self> {
+        let mut bound = Vec::new();
+        let mut tasks = Vec::new();
+
+        for addr in addrs {
+            let udp = match UdpSocket::bind(addr).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("⚠️  Could not bind UDP {}: {}", addr, e);
+                    continue;
+                }
+            };
+            let tcp = match TcpListener::bind(addr).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!("⚠️  Could not bind TCP {}: {}", addr, e);
+                    continue;
+                }
+            };
+
+            info!("🛰️  Local DNS proxy listening on {}", addr);
+            bound.push(*addr);
+            tasks.push(tokio::spawn(Self::serve_udp(udp, resolver.clone())));
+            tasks.push(tokio::spawn(Self::serve_tcp(tcp, resolver.clone())));
+        }
+
+        if bound.is_empty() {
+            anyhow::bail!("Local DNS proxy could not bind any listen address");
+        }
+
+        Ok(Self { bound, tasks })
+    }
+
+    pub fn bound_addresses(&self) -> &[SocketAddr] {
+        &self.bound
+    }
+
+    /// Остановить прокси; возвращается, когда сокеты уже закрыты и адреса можно занять снова.
+    /// Запросы и соединения в работе принадлежат задачам слушателей и прерываются вместе с ними.
+    pub async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for task in self.tasks {
+            let _ = task.await;
+        }
+        info!("🛑 Local DNS proxy stopped");
+    }
+
+    async fn serve_udp(socket: UdpSocket, resolver: Arc<ProxyResolver>) {
+        // Ответы отправляются из этой же задачи, чтобы сокетом владела только она
+        let (tx, mut rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(UDP_RESPONSE_QUEUE);
+        let mut buf = vec![0u8; 4096];
+        // При остановке слушателя набор удаляется и прерывает все запросы в работе
+        let mut in_flight = JoinSet::new();
+        let permits = Arc::new(Semaphore::new(MAX_UDP_IN_FLIGHT));
+
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let (len, peer) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            // На Windows ICMP port unreachable приходит как ошибка recv_from
+                            debug!("UDP receive error: {}", e);
+                            continue;
+                        }
+                    };
+                    let Ok(permit) = permits.clone().try_acquire_owned() else {
+                        debug!("Dropping UDP query from {}: too many queries in flight", peer);
+                        continue;
+                    };
+
+                    let packet = buf[..len].to_vec();
+                    let tx = tx.clone();
+                    let resolver = resolver.clone();
+                    in_flight.spawn(async move {
+                        let _permit = permit;
+                        if let Some(response) = resolver.handle(&packet, peer).await {
+                            let response = match Message::from_vec(&packet) {
+                                Ok(request) => fit_udp_response(&request, response),
+                                Err(_) => response,
+                            };
+                            let _ = tx.send((response, peer)).await;
+                        }
+                    });
+                }
+                Some((response, peer)) = rx.recv() => {
+                    if let Err(e) = socket.send_to(&response, peer).await {
+                        debug!("Failed to answer {}: {}", peer, e);
+                    }
+                }
+                Some(_) = in_flight.join_next() => {}
+            }
+        }
+    }
+
+    async fn serve_tcp(listener: TcpListener, resolver: Arc<ProxyResolver>) {
+        let mut connections = JoinSet::new();
+        let permits = Arc::new(Semaphore::new(MAX_TCP_CONNECTIONS));
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let Ok(permit) = permits.clone().try_acquire_owned() else {
+                            debug!("Closing TCP connection from {}: too many open connections", peer);
+                            continue;
+                        };
+                        let resolver = resolver.clone();
+                        connections.spawn(async move {
+                            let _permit = permit;
+                            Self::serve_tcp_connection(stream, peer, resolver).await;
+                        });
+                    }
+                    Err(e) => debug!("TCP accept error: {}", e),
+                },
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    }
+
+    async fn serve_tcp_connection(mut stream: TcpStream, peer: SocketAddr, resolver: Arc<ProxyResolver>) {
+        loop {
+            let mut len_buf = [0u8; 2];
+            if !matches!(timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut len_buf)).await, Ok(Ok(_))) {
+                return;
+            }
+
+            let mut packet = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+            if !matches!(timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut packet)).await, Ok(Ok(_))) {
+                return;
+            }
+
+            let Some(response) = resolver.handle(&packet, peer).await else {
+                return;
+            };
+            let response = fit_tcp_response(response);
+            let Ok(len) = u16::try_from(response.len()) else {
+                debug!("Closing TCP connection from {}: answer does not fit in 65535 bytes", peer);
+                return;
+            };
+
+            let mut framed = Vec::with_capacity(response.len() + 2);
+            framed.extend_from_slice(&len.to_be_bytes());
+            framed.extend_from_slice(&response);
+            if stream.write_all(&framed).await.is_err() {
+                return;
+            }
+        }
+    }
+}
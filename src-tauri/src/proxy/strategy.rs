@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Вес нового замера в скользящем среднем задержки
+const EWMA_ALPHA: f64 = 0.3;
+/// Верхние границы корзин гистограммы задержек, в секундах
+pub const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStrategy {
+    /// Always try upstreams in the listed order
+    Strict,
+    /// Start from the next upstream on every query
+    RoundRobin,
+    /// Prefer the upstream with the lowest average latency
+    LowestLatency,
+    /// Query all upstreams at once, the first answer wins
+    Race,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Сколько ошибок подряд выводят апстрим из ротации
+    pub failure_threshold: u32,
+    /// Через сколько секунд выведенный апстрим получает пробный запрос
+    pub cooldown_secs: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamHealthStatus {
+    pub name: String,
+    pub latency_ms: Option<f64>,
+    pub failures: u32,
+    pub ejected: bool,
+}
+
+/// Гистограмма задержек успешных запросов к апстриму
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// Число замеров в каждой корзине `LATENCY_BUCKETS` (не накопительное)
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+    pub count: u64,
+    pub sum_secs: f64,
+}
+
+impl LatencyHistogram {
+    pub(super) fn observe(&mut self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum_secs += secs;
+    }
+}
+
+#[derive(Default)]
+struct HealthState {
+    latency_ms: Option<f64>,
+    histogram: LatencyHistogram,
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+    cooldown: Duration,
+}
+
+/// Состояние апстрима: EWMA задержки и размыкатель цепи
+/// (closed → open после серии ошибок → пробный запрос после паузы)
+#[derive(Default)]
+pub struct UpstreamHealth {
+    state: Mutex<HealthState>,
+}
+
+impl UpstreamHealth {
+    /// Можно ли отправить запрос. После паузы выведенный апстрим получает
+    /// один пробный запрос, следующая проба — не раньше чем через ещё одну паузу.
+    pub fn try_acquire(&self) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return true;
+        };
+
+        match state.ejected_until {
+            None => true,
+            Some(until) if Instant::now() >= until => {
+                state.ejected_until = Some(Instant::now() + state.cooldown);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    pub fn record_success(&self, latency: Duration) {
+        if let Ok(mut state) = self.state.lock() {
+            let sample = latency.as_secs_f64() * 1000.0;
+            state.latency_ms = Some(match state.latency_ms {
+                Some(avg) => avg + EWMA_ALPHA * (sample - avg),
+                None => sample,
+            });
+            state.histogram.observe(latency);
+            state.consecutive_failures = 0;
+            state.ejected_until = None;
+        }
+    }
+
+    pub fn record_failure(&self, config: &BreakerConfig) {
+        if let Ok(mut state) = self.state.lock() {
+            state.consecutive_failures += 1;
+            if state.consecutive_failures >= config.failure_threshold.max(1) {
+                state.cooldown = Duration::from_secs(config.cooldown_secs);
+                state.ejected_until = Some(Instant::now() + state.cooldown);
+            }
+        }
+    }
+
+    pub fn latency_ms(&self) -> Option<f64> {
+        self.state.lock().ok().and_then(|s| s.latency_ms)
+    }
+
+    pub fn latency_histogram(&self) -> LatencyHistogram {
+        self.state.lock().map(|s| s.histogram.clone()).unwrap_or_default()
+    }
+
+    pub fn status(&self, name: &str) -> UpstreamHealthStatus {
+        let state = self.state.lock();
+        UpstreamHealthStatus {
+            name: name.to_string(),
+            latency_ms: state.as_ref().ok().and_then(|s| s.latency_ms),
+            failures: state.as_ref().map(|s| s.consecutive_failures).unwrap_or(0),
+            ejected: state
+                .as_ref()
+                .map(|s| s.ejected_until.is_some_and(|until| Instant::now() < until))
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_is_an_exponential_moving_average() {
+        let health = UpstreamHealth::default();
+        assert_eq!(health.latency_ms(), None);
+
+        health.record_success(Duration::from_millis(100));
+        assert_eq!(health.latency_ms(), Some(100.0));
+
+        // Новый замер входит с весом EWMA_ALPHA
+        health.record_success(Duration::from_millis(10));
+        let latency = health.latency_ms().unwrap();
+        assert!((latency - 73.0).abs() < 1e-9, "{}", latency);
+        assert_eq!(health.latency_histogram().count, 2);
+    }
+
+    #[test]
+    fn breaker_opens_probes_and_closes() {
+        let health = UpstreamHealth::default();
+        let config = BreakerConfig {
+            failure_threshold: 2,
+            cooldown_secs: 30,
+        };
+
+        health.record_failure(&config);
+        assert!(health.try_acquire());
+        health.record_failure(&config);
+        // Открыт: запросы не пропускаются до конца паузы
+        assert!(health.status("u").ejected);
+        assert!(!health.try_acquire());
+
+        // Пауза прошла: полуоткрыт, пропускается ровно один пробный запрос
+        health.state.lock().unwrap().ejected_until = Some(Instant::now() - Duration::from_secs(1));
+        assert!(health.try_acquire());
+        assert!(!health.try_acquire());
+
+        // Неудачная проба оставляет его открытым
+        health.record_failure(&config);
+        assert!(!health.try_acquire());
+
+        // Удачная проба закрывает его
+        health.state.lock().unwrap().ejected_until = Some(Instant::now() - Duration::from_secs(1));
+        assert!(health.try_acquire());
+        health.record_success(Duration::from_millis(20));
+        let status = health.status("u");
+        assert!(!status.ejected);
+        assert_eq!(status.failures, 0);
+        assert!(health.try_acquire() && health.try_acquire());
+    }
+}
@@ -0,0 +1,767 @@
+use super::dnscrypt::DnsCryptClient;
+use super::odoh::OdohClient;
+use super::pinning;
+use super::privacy::{self, PrivacyOptions};
+use super::strategy::UpstreamHealth;
+use crate::stamp::{self, DnsStamp};
+use crate::types::DnsTestResult;
+use anyhow::{Context, Result};
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use log::{debug, warn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::TlsConnector;
+
+/// Максимум простаивающих TLS-соединений на один DoT-апстрим
+const MAX_IDLE_TLS_CONNECTIONS: usize = 4;
+
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(3);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bootstrap-серверы для проверки апстрима вне пресета
+const PROBE_BOOTSTRAP: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+    IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9)),
+];
+/// Порт DNSCrypt по умолчанию, если в стампе он не указан
+const DNSCRYPT_PORT: u16 = 443;
+
+enum Transport {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Https {
+        url: String,
+        client: reqwest::Client,
+    },
+    Tls {
+        addr: SocketAddr,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+        idle: Mutex<Vec<TlsStream<TcpStream>>>,
+    },
+    DnsCrypt(DnsCryptClient),
+    Odoh(OdohClient),
+}
+
+/// Общие параметры построения апстримов
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamContext {
+    /// Обычные DNS-серверы, через которые разрешаются имена DoH/DoT/ODoH-апстримов
+    pub bootstrap: Vec<IpAddr>,
+    /// Релей Oblivious DoH: URL или стамп `sdns://`
+    pub odoh_relay: Option<String>,
+}
+
+/// A single upstream resolver the local proxy forwards queries to
+pub struct Upstream {
+    name: String,
+    health: UpstreamHealth,
+    transport: Transport,
+}
+
+impl Upstream {
+    pub fn udp(addr: SocketAddr) -> Self {
+        Self {
+            name: format!("udp://{}", addr),
+            health: UpstreamHealth::default(),
+            transport: Transport::Udp(addr),
+        }
+    }
+
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self {
+            name: format!("tcp://{}", addr),
+            health: UpstreamHealth::default(),
+            transport: Transport::Tcp(addr),
+        }
+    }
+
+    /// DoH upstream. The host of `template` is resolved through `bootstrap`
+    /// servers so the proxy never asks the system resolver (which may be itself).
+    pub async fn https(template: &str, bootstrap: &[IpAddr]) -> Result<Self> {
+        Self::https_pinned(template, bootstrap, &[]).await
+    }
+
+    /// DoH upstream whose certificate chain must match `hashes` from a DNS stamp
+    async fn https_pinned(template: &str, bootstrap: &[IpAddr], hashes: &[Vec<u8>]) -> Result<Self> {
+        let url = strip_uri_template(template);
+        let parsed = reqwest::Url::parse(&url).context("Invalid DoH URL")?;
+        let host = parsed.host_str().context("DoH URL has no host")?.to_string();
+        let port = parsed.port_or_known_default().unwrap_or(443);
+
+        let mut addrs = Vec::new();
+        if host.parse::<IpAddr>().is_err() {
+            addrs = bootstrap_or_fallback(&host, bootstrap)
+                .await
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect();
+
+            if addrs.is_empty() {
+                anyhow::bail!("Could not bootstrap DoH host {}", host);
+            }
+        }
+
+        Self::https_resolved(url, &addrs, hashes)
+    }
+
+    /// DoH upstream whose host is already known to live at `addrs`. With
+    /// `hashes` from a DNS stamp the server's certificate chain must match them.
+    fn https_resolved(url: String, addrs: &[SocketAddr], hashes: &[Vec<u8>]) -> Result<Self> {
+        let parsed = reqwest::Url::parse(&url).context("Invalid DoH URL")?;
+        let host = parsed.host_str().context("DoH URL has no host")?;
+
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .no_proxy();
+        if !addrs.is_empty() {
+            builder = builder.resolve_to_addrs(host, addrs);
+        }
+        if !hashes.is_empty() {
+            let mut config = pinning::client_config(hashes)?;
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            builder = builder.use_preconfigured_tls(config);
+        }
+
+        let client = builder.build().context("Failed to build DoH client")?;
+
+        Ok(Self {
+            name: url.clone(),
+            health: UpstreamHealth::default(),
+            transport: Transport::Https { url, client },
+        })
+    }
+
+    /// DoT upstreams on port 853, authenticated against `hostname`: one per
+    /// bootstrapped address, so the strategy can fail over between them
+    pub async fn tls(hostname: &str, bootstrap: &[IpAddr]) -> Result<Vec<Self>> {
+        Self::tls_bootstrapped(hostname, 853, bootstrap, &[]).await
+    }
+
+    /// DoT upstreams for every address `hostname` resolves to through `bootstrap`
+    async fn tls_bootstrapped(hostname: &str, port: u16, bootstrap: &[IpAddr], hashes: &[Vec<u8>]) -> Result<Vec<Self>> {
+        if let Ok(ip) = hostname.parse::<IpAddr>() {
+            return Ok(vec![Self::tls_at(hostname, SocketAddr::new(ip, port), hashes)?]);
+        }
+
+        let ips = bootstrap_or_fallback(hostname, bootstrap).await;
+        if ips.is_empty() {
+            anyhow::bail!("Could not bootstrap DoT host {}", hostname);
+        }
+        let several = ips.len() > 1;
+        ips.into_iter()
+            .map(|ip| {
+                let mut upstream = Self::tls_at(hostname, SocketAddr::new(ip, port), hashes)?;
+                // Имя различает адреса в статистике здоровья и метриках
+                if several {
+                    upstream.name = format!("tls://{} ({})", hostname, ip);
+                }
+                Ok(upstream)
+            })
+            .collect()
+    }
+
+    /// DoT upstream at `addr`; `hashes` from a DNS stamp pin its certificate chain
+    fn tls_at(hostname: &str, addr: SocketAddr, hashes: &[Vec<u8>]) -> Result<Self> {
+        let server_name = ServerName::try_from(hostname.to_string())
+            .context("Invalid DoT hostname")?;
+        let config = if hashes.is_empty() {
+            tls_config()
+        } else {
+            Arc::new(pinning::client_config(hashes)?)
+        };
+
+        Ok(Self {
+            name: format!("tls://{}", hostname),
+            health: UpstreamHealth::default(),
+            transport: Transport::Tls {
+                addr,
+                server_name,
+                config,
+                idle: Mutex::new(Vec::new()),
+            },
+        })
+    }
+
+    /// Oblivious DoH: запросы к `target` идут через релей из контекста
+    pub async fn odoh(target: &str, context: &UpstreamContext) -> Result<Self> {
+        let relay = match context.odoh_relay.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+            Some(relay) if stamp::is_stamp(relay) => match DnsStamp::parse(relay)? {
+                DnsStamp::ODoHRelay { hostname, path, .. } => Some(format!("https://{}{}", hostname, path)),
+                _ => anyhow::bail!("ODoH relay stamp must describe an ODoH relay"),
+            },
+            Some(relay) => Some(relay.to_string()),
+            None => None,
+        };
+
+        let client = OdohClient::new(target, relay.as_deref(), &context.bootstrap).await?;
+        Ok(Self {
+            name: client.name(),
+            health: UpstreamHealth::default(),
+            transport: Transport::Odoh(client),
+        })
+    }
+
+    /// Апстримы из DNS-стампа. Адрес из стампа используется напрямую,
+    /// без него имя хоста разрешается через bootstrap-серверы контекста и стампа
+    /// (для DoT — апстрим на каждый найденный адрес).
+    pub async fn from_stamp(stamp: &DnsStamp, context: &UpstreamContext) -> Result<Vec<Self>> {
+        let with_stamp_bootstrap = |servers: &[String]| -> Vec<IpAddr> {
+            context
+                .bootstrap
+                .iter()
+                .copied()
+                .chain(servers.iter().filter_map(|s| parse_socket_addr(s, 53).ok().map(|a| a.ip())))
+                .collect()
+        };
+
+        match stamp {
+            DnsStamp::Plain { addr, .. } => Ok(vec![Self::udp(parse_socket_addr(addr, 53)?)]),
+            DnsStamp::DnsCrypt {
+                addr,
+                public_key,
+                provider_name,
+                ..
+            } => {
+                let addr = parse_socket_addr(addr, DNSCRYPT_PORT)?;
+                let client = DnsCryptClient::new(addr, provider_name, public_key)?;
+                Ok(vec![Self {
+                    name: format!("dnscrypt://{}", client.provider_name()),
+                    health: UpstreamHealth::default(),
+                    transport: Transport::DnsCrypt(client),
+                }])
+            }
+            DnsStamp::DoH {
+                addr,
+                hashes,
+                hostname,
+                path,
+                bootstrap: servers,
+                ..
+            } => {
+                let url = format!("https://{}{}", hostname, path);
+                // Клиент DoH сам перебирает все адреса хоста
+                if addr.is_empty() {
+                    return Ok(vec![Self::https_pinned(&url, &with_stamp_bootstrap(servers), hashes).await?]);
+                }
+                let port = reqwest::Url::parse(&url)
+                    .ok()
+                    .and_then(|u| u.port_or_known_default())
+                    .unwrap_or(443);
+                Ok(vec![Self::https_resolved(url, &[parse_socket_addr(addr, port)?], hashes)?])
+            }
+            DnsStamp::DoT {
+                addr,
+                hashes,
+                hostname,
+                bootstrap: servers,
+                ..
+            } => {
+                let (host, port) = split_host_port(hostname);
+                let port = port.unwrap_or(853);
+                if addr.is_empty() {
+                    return Self::tls_bootstrapped(host, port, &with_stamp_bootstrap(servers), hashes).await;
+                }
+                Ok(vec![Self::tls_at(host, parse_socket_addr(addr, port)?, hashes)?])
+            }
+            DnsStamp::ODoHTarget { hostname, path, .. } => {
+                Ok(vec![Self::odoh(&format!("https://{}{}", hostname, path), context).await?])
+            }
+            _ => anyhow::bail!("This kind of DNS stamp can't be used as an upstream"),
+        }
+    }
+
+    /// Построить апстримы из параметров пресета: стамп, DoH, затем DoT, а для
+    /// пресетов без шифрования — обычный DNS на каждый сервер. Если шифрованный
+    /// апстрим построить не удалось, это ошибка: открытый DNS вместо него не подставляется.
+    pub async fn from_preset(
+        ipv4_servers: &[String],
+        ipv6_servers: &[String],
+        doh_template: Option<&str>,
+        dot_hostname: Option<&str>,
+        stamp: Option<&str>,
+        odoh_relay: Option<&str>,
+    ) -> Result<Vec<Upstream>> {
+        let plain: Vec<IpAddr> = ipv4_servers
+            .iter()
+            .chain(ipv6_servers.iter())
+            .filter_map(|s| s.trim().parse().ok())
+            .collect();
+        let context = UpstreamContext {
+            bootstrap: plain.clone(),
+            odoh_relay: odoh_relay.map(str::to_string),
+        };
+
+        let mut upstreams = Vec::new();
+        let mut failures = Vec::new();
+
+        if let Some(stamp) = stamp.filter(|s| !s.is_empty()) {
+            let built = match DnsStamp::parse(stamp) {
+                Ok(stamp) => Self::from_stamp(&stamp, &context).await,
+                Err(e) => Err(e),
+            };
+            match built {
+                Ok(built) => upstreams.extend(built),
+                Err(e) => {
+                    warn!("⚠️  Stamp upstream unavailable: {}", e);
+                    failures.push(format!("stamp: {:#}", e));
+                }
+            }
+        }
+
+        if upstreams.is_empty() {
+            if let Some(template) = doh_template.filter(|t| !t.is_empty()) {
+                match Self::https(template, &plain).await {
+                    Ok(upstream) => upstreams.push(upstream),
+                    Err(e) => {
+                        warn!("⚠️  DoH upstream unavailable: {}", e);
+                        failures.push(format!("DoH: {:#}", e));
+                    }
+                }
+            }
+        }
+
+        if upstreams.is_empty() {
+            if let Some(hostname) = dot_hostname.filter(|h| !h.is_empty()) {
+                match Self::tls(hostname, &plain).await {
+                    Ok(built) => upstreams.extend(built),
+                    Err(e) => {
+                        warn!("⚠️  DoT upstream unavailable: {}", e);
+                        failures.push(format!("DoT: {:#}", e));
+                    }
+                }
+            }
+        }
+
+        if upstreams.is_empty() && !failures.is_empty() {
+            anyhow::bail!(
+                "Encrypted upstream unavailable, not falling back to plain DNS ({})",
+                failures.join("; ")
+            );
+        }
+
+        if upstreams.is_empty() {
+            upstreams.extend(plain.iter().map(|ip| Self::udp(SocketAddr::new(*ip, 53))));
+        }
+
+        if upstreams.is_empty() {
+            anyhow::bail!("Preset has no usable upstream servers");
+        }
+
+        Ok(upstreams)
+    }
+
+    /// Разобрать адрес апстрима: `10.0.0.1`, `10.0.0.1:5353`, `udp://…`, `tcp://…`,
+    /// `tls://dns.example`, `https://dns.example/dns-query`, `odoh://target.example/dns-query`
+    /// или DNS-стамп `sdns://…`. Хост DoT даёт апстрим на каждый свой адрес.
+    pub async fn parse(spec: &str, context: &UpstreamContext) -> Result<Vec<Self>> {
+        let spec = spec.trim();
+
+        if stamp::is_stamp(spec) {
+            return Self::from_stamp(&DnsStamp::parse(spec)?, context).await;
+        }
+
+        if spec.starts_with("https://") {
+            return Ok(vec![Self::https(spec, &context.bootstrap).await?]);
+        }
+
+        if let Some(target) = spec.strip_prefix("odoh://") {
+            return Ok(vec![Self::odoh(&format!("https://{}", target), context).await?]);
+        }
+
+        if let Some(host) = spec.strip_prefix("tls://") {
+            return Self::tls(host, &context.bootstrap).await;
+        }
+
+        if let Some(addr) = spec.strip_prefix("tcp://") {
+            return Ok(vec![Self::tcp(parse_socket_addr(addr, 53)?)]);
+        }
+
+        let addr = spec.strip_prefix("udp://").unwrap_or(spec);
+        Ok(vec![Self::udp(parse_socket_addr(addr, 53)?)])
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn health(&self) -> &UpstreamHealth {
+        &self.health
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        matches!(
+            self.transport,
+            Transport::Https { .. } | Transport::Tls { .. } | Transport::DnsCrypt(_) | Transport::Odoh(_)
+        )
+    }
+
+    /// Отправить запрос в апстрим с учётом настроек приватности и вернуть сырой ответ
+    pub async fn exchange(&self, query: &[u8], timeout: Duration, options: &PrivacyOptions) -> Result<Vec<u8>> {
+        let prepared = privacy::prepare(
+            query,
+            &options.ecs,
+            options.padding && matches!(self.transport, Transport::Https { .. } | Transport::Tls { .. }),
+            options.randomize_case && matches!(self.transport, Transport::Udp(_)),
+        )?;
+
+        let response = tokio::time::timeout(timeout, self.exchange_inner(&prepared.packet))
+            .await
+            .with_context(|| format!("{} timed out", self.name))??;
+        prepared.restore(response)
+    }
+
+    async fn exchange_inner(&self, query: &[u8]) -> Result<Vec<u8>> {
+        match &self.transport {
+            Transport::Udp(addr) => {
+                let response = exchange_udp(*addr, query).await?;
+                if response.len() > 2 && response[2] & 0x02 != 0 {
+                    debug!("Truncated UDP answer from {}, retrying over TCP", addr);
+                    let mut stream = TcpStream::connect(addr).await?;
+                    return exchange_stream(&mut stream, query).await;
+                }
+                Ok(response)
+            }
+            Transport::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr).await
+                    .with_context(|| format!("Failed to connect to {}", addr))?;
+                exchange_stream(&mut stream, query).await
+            }
+            Transport::Https { url, client } => {
+                let response = client
+                    .post(url)
+                    .header("content-type", "application/dns-message")
+                    .header("accept", "application/dns-message")
+                    .body(query.to_vec())
+                    .send()
+                    .await
+                    .context("DoH request failed")?;
+
+                if !response.status().is_success() {
+                    anyhow::bail!("DoH server returned HTTP {}", response.status());
+                }
+
+                Ok(response.bytes().await?.to_vec())
+            }
+            Transport::Tls { addr, server_name, config, idle } => {
+                let pooled = idle.lock().ok().and_then(|mut pool| pool.pop());
+
+                if let Some(mut stream) = pooled {
+                    if let Ok(response) = exchange_stream(&mut stream, query).await {
+                        Self::return_tls(idle, stream);
+                        return Ok(response);
+                    }
+                    debug!("Idle DoT connection to {} went stale, reconnecting", addr);
+                }
+
+                let tcp = TcpStream::connect(addr).await
+                    .with_context(|| format!("Failed to connect to {}", addr))?;
+                let mut stream = TlsConnector::from(config.clone())
+                    .connect(server_name.clone(), tcp)
+                    .await
+                    .context("TLS handshake failed")?;
+
+                let response = exchange_stream(&mut stream, query).await?;
+                Self::return_tls(idle, stream);
+                Ok(response)
+            }
+            Transport::DnsCrypt(client) => client.exchange(query).await,
+            Transport::Odoh(client) => client.exchange(query).await,
+        }
+    }
+
+    fn return_tls(idle: &Mutex<Vec<TlsStream<TcpStream>>>, stream: TlsStream<TcpStream>) {
+        if let Ok(mut pool) = idle.lock() {
+            if pool.len() < MAX_IDLE_TLS_CONNECTIONS {
+                pool.push(stream);
+            }
+        }
+    }
+}
+
+/// IP с необязательным портом; IPv6 с портом — в виде `[::1]:53`
+fn parse_socket_addr(value: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid upstream address: {}", value))?;
+    Ok(SocketAddr::new(ip, default_port))
+}
+
+/// Отделить порт от имени хоста вида `dns.example:853`
+fn split_host_port(value: &str) -> (&str, Option<u16>) {
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (value, None),
+        },
+        _ => (value, None),
+    }
+}
+
+/// Убрать RFC 6570 суффикс вида `{?dns}` из DoH-шаблона
+pub fn strip_uri_template(template: &str) -> String {
+    match template.find('{') {
+        Some(idx) => template[..idx].to_string(),
+        None => template.to_string(),
+    }
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    CONFIG
+        .get_or_init(|| Arc::new(pinning::client_config(&[]).expect("ring provider supports default TLS versions")))
+        .clone()
+}
+
+pub(super) async fn exchange_udp(addr: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
+    let bind_addr: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+
+    let socket = UdpSocket::bind(bind_addr).await
+        .context("Failed to bind UDP socket")?;
+    socket.connect(addr).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        // Отбрасываем ответы с чужим ID
+        if len >= 2 && query.len() >= 2 && buf[..2] == query[..2] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+pub(super) async fn exchange_stream<S>(stream: &mut S, query: &[u8]) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let len = u16::try_from(query.len()).context("Query too large")?;
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+    stream.flush().await?;
+
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf).await?;
+    let mut response = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut response).await?;
+
+    Ok(response)
+}
+
+/// Как `bootstrap_resolve`, но при неудаче использует сами серверы пресета:
+/// у большинства провайдеров DoH/DoT отвечают на тех же адресах
+pub(super) async fn bootstrap_or_fallback(host: &str, servers: &[IpAddr]) -> Vec<IpAddr> {
+    let resolved = bootstrap_resolve(host, servers).await;
+    if resolved.is_empty() {
+        debug!("Bootstrap for {} failed, falling back to preset addresses", host);
+        return servers.to_vec();
+    }
+    resolved
+}
+
+/// Разрешить имя хоста апстрима через обычный DNS серверов пресета: сначала
+/// A, затем AAAA, чтобы хосты только с IPv6 тоже находились
+pub async fn bootstrap_resolve(host: &str, servers: &[IpAddr]) -> Vec<IpAddr> {
+    let name = match Name::from_ascii(host) {
+        Ok(name) => name,
+        Err(e) => {
+            warn!("⚠️  Invalid bootstrap hostname {}: {}", host, e);
+            return Vec::new();
+        }
+    };
+
+    for server in servers {
+        let addr = SocketAddr::new(*server, 53);
+        let (ipv4, ipv6) = tokio::join!(
+            bootstrap_query(addr, &name, RecordType::A),
+            bootstrap_query(addr, &name, RecordType::AAAA),
+        );
+        if ipv4.is_none() && ipv6.is_none() {
+            debug!("Bootstrap server {} did not answer for {}", server, host);
+            continue;
+        }
+
+        let ips: Vec<IpAddr> = ipv4.into_iter().chain(ipv6).flatten().collect();
+        if !ips.is_empty() {
+            debug!("Bootstrapped {} -> {:?} via {}", host, ips, server);
+            return ips;
+        }
+    }
+
+    Vec::new()
+}
+
+/// Один запрос к bootstrap-серверу; `None`, если сервер не ответил
+async fn bootstrap_query(addr: SocketAddr, name: &Name, record_type: RecordType) -> Option<Vec<IpAddr>> {
+    let mut message = Message::new();
+    message
+        .set_id(rand::random())
+        .set_recursion_desired(true)
+        .add_query(Query::query(name.clone(), record_type));
+    let query = message.to_vec().ok()?;
+
+    let response = tokio::time::timeout(BOOTSTRAP_TIMEOUT, exchange_udp(addr, &query))
+        .await
+        .ok()?
+        .ok()?;
+
+    let ips = Message::from_vec(&response)
+        .map(|msg| {
+            msg.answers()
+                .iter()
+                .filter_map(|record| match record.data() {
+                    Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                    Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ips)
+}
+
+/// Проверить апстрим, заданный адресом или стампом, одним A-запросом
+pub async fn probe_upstream(spec: &str, odoh_relay: Option<&str>, options: &PrivacyOptions) -> DnsTestResult {
+    let started = Instant::now();
+    let result = async {
+        let context = UpstreamContext {
+            bootstrap: PROBE_BOOTSTRAP.to_vec(),
+            odoh_relay: odoh_relay.map(str::to_string),
+        };
+        let upstreams = Upstream::parse(spec, &context).await?;
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii("google.com.")?, RecordType::A));
+        let query = message.to_vec()?;
+
+        // Апстрим доступен, если ответил хотя бы один из его адресов
+        let mut failure = anyhow::anyhow!("Upstream has no addresses");
+        for upstream in &upstreams {
+            match upstream.exchange(&query, PROBE_TIMEOUT, options).await {
+                Ok(response) => {
+                    Message::from_vec(&response).context("Upstream returned a malformed answer")?;
+                    return anyhow::Ok(());
+                }
+                Err(e) => failure = e,
+            }
+        }
+        Err(failure)
+    }
+    .await;
+
+    match result {
+        Ok(()) => DnsTestResult {
+            server: spec.to_string(),
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+            is_available: true,
+            error: None,
+        },
+        Err(e) => {
+            warn!("❌ Upstream {} probe failed: {:#}", spec, e);
+            DnsTestResult {
+                server: spec.to_string(),
+                latency_ms: None,
+                is_available: false,
+                error: Some(format!("{:#}", e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::MessageType;
+    use hickory_proto::rr::rdata::{A, AAAA};
+    use hickory_proto::rr::Record;
+
+    /// Локальный DNS-сервер, отвечающий одним адресом на каждый A и AAAA запрос
+    async fn bootstrap_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let data = match query.query_type() {
+                    RecordType::A => RData::A(A::new(192, 0, 2, 1)),
+                    _ => RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_query(query.clone())
+                    .add_answer(Record::from_rdata(query.name().clone(), 60, data));
+                socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn bootstrap_queries_both_address_families() {
+        let server = bootstrap_server().await;
+        let name = Name::from_ascii("dns.example.").unwrap();
+
+        let ipv4 = bootstrap_query(server, &name, RecordType::A).await;
+        let ipv6 = bootstrap_query(server, &name, RecordType::AAAA).await;
+
+        assert_eq!(ipv4, Some(vec!["192.0.2.1".parse::<IpAddr>().unwrap()]));
+        assert_eq!(ipv6, Some(vec!["2001:db8::1".parse::<IpAddr>().unwrap()]));
+    }
+
+    #[tokio::test]
+    async fn dot_preset_gets_an_upstream_per_address() {
+        // На loopback нет DNS: bootstrap не удаётся, и используются адреса пресета
+        let servers = vec!["127.0.0.1".to_string(), "127.0.0.2".to_string()];
+        let upstreams = Upstream::from_preset(&servers, &[], None, Some("dns.example"), None, None)
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = upstreams.iter().map(Upstream::name).collect();
+        assert_eq!(names, ["tls://dns.example (127.0.0.1)", "tls://dns.example (127.0.0.2)"]);
+        assert!(upstreams.iter().all(Upstream::is_encrypted));
+    }
+
+    #[tokio::test]
+    async fn broken_encrypted_preset_does_not_fall_back_to_plain_dns() {
+        let servers = vec!["192.0.2.1".to_string()];
+
+        let error = Upstream::from_preset(&servers, &[], Some("not a url"), None, None, None)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("not falling back"), "{}", error);
+
+        // Без шифрования обычный DNS — единственный вариант
+        let plain = Upstream::from_preset(&servers, &[], None, None, None, None).await.unwrap();
+        assert_eq!(plain.iter().map(Upstream::name).collect::<Vec<_>>(), ["udp://192.0.2.1:53"]);
+    }
+}
@@ -0,0 +1,511 @@
+use anyhow::{Context, Result};
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+const STAMP_PREFIX: &str = "sdns://";
+
+/// Стамп кодируется base64url без паддинга, но встречаются и стампы с `=`
+const STAMP_ENGINE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Свойства сервера, заявленные в стампе
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StampProps {
+    pub dnssec: bool,
+    pub no_logs: bool,
+    pub no_filter: bool,
+}
+
+impl StampProps {
+    fn from_bits(bits: u64) -> Self {
+        Self {
+            dnssec: bits & 1 != 0,
+            no_logs: bits & 2 != 0,
+            no_filter: bits & 4 != 0,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        self.dnssec as u64 | (self.no_logs as u64) << 1 | (self.no_filter as u64) << 2
+    }
+}
+
+/// Разобранный DNS-стамп (`sdns://…`), см. https://dnscrypt.info/stamps-specifications
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsStamp {
+    Plain {
+        props: StampProps,
+        addr: String,
+    },
+    DnsCrypt {
+        props: StampProps,
+        addr: String,
+        /// Ed25519-ключ провайдера, которым подписаны сертификаты резолвера
+        public_key: [u8; 32],
+        provider_name: String,
+    },
+    DoH {
+        props: StampProps,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        path: String,
+        bootstrap: Vec<String>,
+    },
+    DoT {
+        props: StampProps,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<String>,
+    },
+    DoQ {
+        props: StampProps,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<String>,
+    },
+    ODoHTarget {
+        props: StampProps,
+        hostname: String,
+        path: String,
+    },
+    DnsCryptRelay {
+        addr: String,
+    },
+    ODoHRelay {
+        props: StampProps,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        path: String,
+        bootstrap: Vec<String>,
+    },
+}
+
+impl DnsStamp {
+    pub fn parse(stamp: &str) -> Result<Self> {
+        let encoded = stamp
+            .trim()
+            .strip_prefix(STAMP_PREFIX)
+            .context("DNS stamp must start with sdns://")?;
+        let bytes = STAMP_ENGINE
+            .decode(encoded)
+            .context("DNS stamp is not valid base64url")?;
+
+        let mut reader = StampReader::new(&bytes);
+        let protocol = reader.byte()?;
+
+        let stamp = match protocol {
+            0x00 => Self::Plain {
+                props: reader.props()?,
+                addr: reader.string()?,
+            },
+            0x01 => {
+                let props = reader.props()?;
+                let addr = reader.string()?;
+                let public_key = reader
+                    .bytes()?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("DNSCrypt provider key must be 32 bytes"))?;
+                Self::DnsCrypt {
+                    props,
+                    addr,
+                    public_key,
+                    provider_name: reader.string()?,
+                }
+            }
+            0x02 => Self::DoH {
+                props: reader.props()?,
+                addr: reader.string()?,
+                hashes: reader.bytes_set()?,
+                hostname: reader.string()?,
+                path: reader.string()?,
+                bootstrap: reader.optional_string_set()?,
+            },
+            0x03 | 0x04 => {
+                let props = reader.props()?;
+                let addr = reader.string()?;
+                let hashes = reader.bytes_set()?;
+                let hostname = reader.string()?;
+                let bootstrap = reader.optional_string_set()?;
+                if protocol == 0x03 {
+                    Self::DoT { props, addr, hashes, hostname, bootstrap }
+                } else {
+                    Self::DoQ { props, addr, hashes, hostname, bootstrap }
+                }
+            }
+            0x05 => Self::ODoHTarget {
+                props: reader.props()?,
+                hostname: reader.string()?,
+                path: reader.string()?,
+            },
+            0x81 => Self::DnsCryptRelay {
+                addr: reader.string()?,
+            },
+            0x85 => Self::ODoHRelay {
+                props: reader.props()?,
+                addr: reader.string()?,
+                hashes: reader.bytes_set()?,
+                hostname: reader.string()?,
+                path: reader.string()?,
+                bootstrap: reader.optional_string_set()?,
+            },
+            other => anyhow::bail!("Unsupported DNS stamp protocol 0x{:02x}", other),
+        };
+
+        if !reader.is_empty() {
+            anyhow::bail!("DNS stamp has trailing data");
+        }
+        Ok(stamp)
+    }
+
+    /// Закодировать стамп обратно в `sdns://…`. Поля длиннее, чем позволяет
+    /// формат (255 байт, элементы наборов — 127), не обрезаются, а дают ошибку.
+    pub fn encode(&self) -> Result<String> {
+        let mut writer = StampWriter::default();
+        match self {
+            Self::Plain { props, addr } => {
+                writer.byte(0x00).props(*props).string("address", addr)?;
+            }
+            Self::DnsCrypt { props, addr, public_key, provider_name } => {
+                writer
+                    .byte(0x01)
+                    .props(*props)
+                    .string("address", addr)?
+                    .bytes("public key", public_key)?
+                    .string("provider name", provider_name)?;
+            }
+            Self::DoH { props, addr, hashes, hostname, path, bootstrap } => {
+                writer
+                    .byte(0x02)
+                    .props(*props)
+                    .string("address", addr)?
+                    .bytes_set("hashes", hashes)?
+                    .string("hostname", hostname)?
+                    .string("path", path)?
+                    .optional_string_set("bootstrap", bootstrap)?;
+            }
+            Self::DoT { props, addr, hashes, hostname, bootstrap } => {
+                writer
+                    .byte(0x03)
+                    .props(*props)
+                    .string("address", addr)?
+                    .bytes_set("hashes", hashes)?
+                    .string("hostname", hostname)?
+                    .optional_string_set("bootstrap", bootstrap)?;
+            }
+            Self::DoQ { props, addr, hashes, hostname, bootstrap } => {
+                writer
+                    .byte(0x04)
+                    .props(*props)
+                    .string("address", addr)?
+                    .bytes_set("hashes", hashes)?
+                    .string("hostname", hostname)?
+                    .optional_string_set("bootstrap", bootstrap)?;
+            }
+            Self::ODoHTarget { props, hostname, path } => {
+                writer.byte(0x05).props(*props).string("hostname", hostname)?.string("path", path)?;
+            }
+            Self::DnsCryptRelay { addr } => {
+                writer.byte(0x81).string("address", addr)?;
+            }
+            Self::ODoHRelay { props, addr, hashes, hostname, path, bootstrap } => {
+                writer
+                    .byte(0x85)
+                    .props(*props)
+                    .string("address", addr)?
+                    .bytes_set("hashes", hashes)?
+                    .string("hostname", hostname)?
+                    .string("path", path)?
+                    .optional_string_set("bootstrap", bootstrap)?;
+            }
+        }
+        Ok(format!("{}{}", STAMP_PREFIX, STAMP_ENGINE.encode(writer.data)))
+    }
+
+    /// Может ли локальный прокси обращаться к серверу из этого стампа
+    pub fn check_upstream(&self) -> Result<()> {
+        match self {
+            Self::DoQ { .. } => {
+                anyhow::bail!("DNS-over-QUIC stamps are not supported: the local DNS proxy can't connect to DoQ servers")
+            }
+            Self::DnsCryptRelay { .. } | Self::ODoHRelay { .. } => {
+                anyhow::bail!("Relay stamps can't be used as a preset on their own")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn props(&self) -> StampProps {
+        match self {
+            Self::Plain { props, .. }
+            | Self::DnsCrypt { props, .. }
+            | Self::DoH { props, .. }
+            | Self::DoT { props, .. }
+            | Self::DoQ { props, .. }
+            | Self::ODoHTarget { props, .. }
+            | Self::ODoHRelay { props, .. } => *props,
+            Self::DnsCryptRelay { .. } => StampProps::default(),
+        }
+    }
+}
+
+/// Является ли строка DNS-стампом
+pub fn is_stamp(value: &str) -> bool {
+    value.trim_start().starts_with(STAMP_PREFIX)
+}
+
+struct StampReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StampReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let (&first, rest) = self.data.split_first().context("DNS stamp is truncated")?;
+        self.data = rest;
+        Ok(first)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("DNS stamp is truncated");
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn props(&mut self) -> Result<StampProps> {
+        let raw: [u8; 8] = self.take(8)?.try_into()?;
+        Ok(StampProps::from_bits(u64::from_le_bytes(raw)))
+    }
+
+    /// LP(x): байт длины и сами данные
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.byte()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?).context("DNS stamp contains invalid UTF-8")
+    }
+
+    /// VLP(x1..xn): старший бит длины означает, что за элементом следует ещё один
+    fn bytes_set(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut items = Vec::new();
+        loop {
+            let len = self.byte()?;
+            let item = self.take((len & 0x7f) as usize)?.to_vec();
+            if !item.is_empty() {
+                items.push(item);
+            }
+            if len & 0x80 == 0 {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn optional_string_set(&mut self) -> Result<Vec<String>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.bytes_set()?
+            .into_iter()
+            .map(|item| String::from_utf8(item).context("DNS stamp contains invalid UTF-8"))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct StampWriter {
+    data: Vec<u8>,
+}
+
+impl StampWriter {
+    fn byte(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    fn props(&mut self, props: StampProps) -> &mut Self {
+        self.data.extend_from_slice(&props.bits().to_le_bytes());
+        self
+    }
+
+    /// LP(x); длина поля в стампе не больше 255 байт
+    fn bytes(&mut self, field: &str, value: &[u8]) -> Result<&mut Self> {
+        if value.len() > 0xff {
+            anyhow::bail!("DNS stamp {} is {} bytes long, at most 255 fit", field, value.len());
+        }
+        self.data.push(value.len() as u8);
+        self.data.extend_from_slice(value);
+        Ok(self)
+    }
+
+    fn string(&mut self, field: &str, value: &str) -> Result<&mut Self> {
+        self.bytes(field, value.as_bytes())
+    }
+
+    /// VLP(x1..xn); пустой набор — один нулевой байт, элемент не длиннее 127 байт
+    fn bytes_set<T: AsRef<[u8]>>(&mut self, field: &str, items: &[T]) -> Result<&mut Self> {
+        if items.is_empty() {
+            return Ok(self.byte(0));
+        }
+        for (index, item) in items.iter().enumerate() {
+            let item = item.as_ref();
+            if item.len() > 0x7f {
+                anyhow::bail!("DNS stamp {} entry is {} bytes long, at most 127 fit", field, item.len());
+            }
+            let more = if index + 1 < items.len() { 0x80 } else { 0 };
+            self.data.push(item.len() as u8 | more);
+            self.data.extend_from_slice(item);
+        }
+        Ok(self)
+    }
+
+    /// Необязательный последний VLP: пустой набор не записывается
+    fn optional_string_set(&mut self, field: &str, items: &[String]) -> Result<&mut Self> {
+        if !items.is_empty() {
+            self.bytes_set(field, items)?;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOUDFLARE_DOH: &str = "sdns://AgcAAAAAAAAABzEuMC4wLjEAEmRucy5jbG91ZGZsYXJlLmNvbQovZG5zLXF1ZXJ5";
+
+    fn props() -> StampProps {
+        StampProps { dnssec: true, no_logs: true, no_filter: false }
+    }
+
+    #[test]
+    fn parses_and_reencodes_published_stamp() {
+        let stamp = DnsStamp::parse(CLOUDFLARE_DOH).unwrap();
+        assert_eq!(
+            stamp,
+            DnsStamp::DoH {
+                props: StampProps { dnssec: true, no_logs: true, no_filter: true },
+                addr: "1.0.0.1".to_string(),
+                hashes: Vec::new(),
+                hostname: "dns.cloudflare.com".to_string(),
+                path: "/dns-query".to_string(),
+                bootstrap: Vec::new(),
+            }
+        );
+        assert_eq!(stamp.encode().unwrap(), CLOUDFLARE_DOH);
+    }
+
+    #[test]
+    fn every_protocol_round_trips() {
+        let stamps = [
+            DnsStamp::Plain { props: props(), addr: "[2001:db8::1]:5353".to_string() },
+            DnsStamp::DnsCrypt {
+                props: props(),
+                addr: "198.51.100.1:8443".to_string(),
+                public_key: [7; 32],
+                provider_name: "2.dnscrypt-cert.example".to_string(),
+            },
+            DnsStamp::DoH {
+                props: props(),
+                addr: String::new(),
+                hashes: vec![vec![1; 32], vec![2; 32]],
+                hostname: "doh.example:8443".to_string(),
+                path: "/dns-query?x=1".to_string(),
+                bootstrap: vec!["9.9.9.9".to_string(), "1.1.1.1".to_string()],
+            },
+            DnsStamp::DoT {
+                props: props(),
+                addr: "192.0.2.1".to_string(),
+                hashes: Vec::new(),
+                hostname: "dot.example".to_string(),
+                bootstrap: Vec::new(),
+            },
+            DnsStamp::DoQ {
+                props: props(),
+                addr: "192.0.2.2".to_string(),
+                hashes: vec![vec![3; 32]],
+                hostname: "doq.example".to_string(),
+                bootstrap: vec!["8.8.8.8".to_string()],
+            },
+            DnsStamp::ODoHTarget {
+                props: props(),
+                hostname: "odoh.example".to_string(),
+                path: "/dns-query".to_string(),
+            },
+            DnsStamp::DnsCryptRelay { addr: "203.0.113.5:443".to_string() },
+            DnsStamp::ODoHRelay {
+                props: props(),
+                addr: String::new(),
+                hashes: Vec::new(),
+                hostname: "relay.example".to_string(),
+                path: "/proxy".to_string(),
+                bootstrap: Vec::new(),
+            },
+        ];
+
+        for stamp in stamps {
+            let encoded = stamp.encode().unwrap();
+            assert!(is_stamp(&encoded));
+            assert_eq!(DnsStamp::parse(&encoded).unwrap(), stamp, "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn accepts_padding_and_rejects_malformed_stamps() {
+        let encoded_len = CLOUDFLARE_DOH.len() - STAMP_PREFIX.len();
+        let padded = format!("{}{}", CLOUDFLARE_DOH, "=".repeat((4 - encoded_len % 4) % 4));
+        assert_eq!(DnsStamp::parse(&padded).unwrap(), DnsStamp::parse(CLOUDFLARE_DOH).unwrap());
+        assert!(DnsStamp::parse("https://dns.cloudflare.com").is_err());
+        assert!(DnsStamp::parse("sdns://not base64!").is_err());
+
+        // У обычного DNS нет необязательных полей в конце, лишний байт — ошибка
+        let plain = DnsStamp::Plain { props: props(), addr: "192.0.2.1".to_string() }.encode().unwrap();
+        let mut bytes = STAMP_ENGINE.decode(&plain[STAMP_PREFIX.len()..]).unwrap();
+        bytes.push(0);
+        let trailing = format!("{}{}", STAMP_PREFIX, STAMP_ENGINE.encode(bytes));
+        assert!(DnsStamp::parse(&trailing).is_err());
+    }
+
+    #[test]
+    fn encode_rejects_fields_that_do_not_fit() {
+        let long_host = DnsStamp::DoT {
+            props: props(),
+            addr: String::new(),
+            hashes: Vec::new(),
+            hostname: "a".repeat(256),
+            bootstrap: Vec::new(),
+        };
+        assert!(long_host.encode().is_err());
+
+        let long_hash = DnsStamp::DoH {
+            props: props(),
+            addr: String::new(),
+            hashes: vec![vec![0; 128]],
+            hostname: "doh.example".to_string(),
+            path: "/dns-query".to_string(),
+            bootstrap: Vec::new(),
+        };
+        assert!(long_hash.encode().is_err());
+    }
+}
@@ -37,8 +37,124 @@ pub struct DnsPreset {
     pub supports_doh: bool,
     pub supports_dot: bool,
     pub icon: String,
+    /// Фронтенд назначает цвет сам и может его не передавать
+    #[serde(default)]
     pub color: String,
     pub website: Option<String>,
+    /// DNS-стамп (`sdns://…`), например для DNSCrypt-серверов
+    #[serde(default)]
+    pub stamp: Option<String>,
+    /// Итог последней проверки серверов (для пользовательских пресетов)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<PresetVerification>,
+    #[serde(default)]
+    pub capabilities: PresetCapabilities,
+    /// Личные параметры (ID профиля и т.п.), подставляемые в адреса как `{name}`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<PresetParameter>,
+}
+
+/// Пресет для тестов: один DoH-сервер, без параметров и метаданных
+#[cfg(test)]
+pub fn test_preset(id: &str, name: &str) -> DnsPreset {
+    DnsPreset {
+        id: id.to_string(),
+        name: name.to_string(),
+        description: String::new(),
+        category: "custom".to_string(),
+        servers_ipv4: vec!["192.0.2.1".to_string()],
+        servers_ipv6: Vec::new(),
+        doh_template: Some("https://dns.example/dns-query{?dns}".to_string()),
+        dot_hostname: None,
+        supports_doh: true,
+        supports_dot: false,
+        icon: String::new(),
+        color: String::new(),
+        website: None,
+        stamp: None,
+        verification: None,
+        capabilities: PresetCapabilities::default(),
+        parameters: Vec::new(),
+    }
+}
+
+/// Параметр пресета: значение задаёт пользователь, а `doh_template` и
+/// `dot_hostname` ссылаются на него как на `{name}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresetParameter {
+    pub name: String,
+    pub label: String,
+    #[serde(default)]
+    pub description: String,
+    /// Регулярное выражение, которому значение должно соответствовать целиком
+    pub pattern: String,
+    #[serde(default)]
+    pub example: Option<String>,
+}
+
+/// Политика и возможности резолвера. `None` и `Unknown` — сведений нет.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetCapabilities {
+    pub logging: LoggingPolicy,
+    /// Резолвер проверяет DNSSEC
+    pub dnssec: Option<bool>,
+    /// Резолвер передаёт подсеть клиента (EDNS Client Subnet) авторитативным серверам
+    pub ecs: Option<bool>,
+    pub filtering: FilteringType,
+    /// Страна оператора, ISO 3166-1 alpha-2 (`CH`, `US`)
+    pub jurisdiction: Option<String>,
+    pub anycast: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoggingPolicy {
+    /// Запросы не журналируются
+    None,
+    /// Журналы удаляются в течение суток-двух
+    Temporary,
+    /// Хранится только обезличенная статистика
+    Anonymized,
+    Full,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilteringType {
+    None,
+    /// Вредоносные и фишинговые домены
+    Malware,
+    /// Реклама и трекеры (обычно вместе с вредоносными)
+    Ads,
+    /// Контент для взрослых (обычно вместе с вредоносными)
+    Family,
+    #[default]
+    Unknown,
+}
+
+/// Протоколы, которыми можно обратиться к резолверу
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Do53,
+    Doh,
+    Dot,
+    Doq,
+    Dnscrypt,
+    Odoh,
+}
+
+/// Когда и с каким итогом проверялись серверы пресета
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetVerification {
+    pub verified_at: chrono::DateTime<chrono::Utc>,
+    pub passed: bool,
+    /// Адреса, которые не ответили
+    #[serde(default)]
+    pub failed: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,4 +171,32 @@ pub struct WindowsVersion {
     pub minor: u32,
     pub build: u32,
     pub supports_doh: bool, // Windows 11+ (build >= 22000)
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkStats {
+    pub samples: u32,
+    pub successes: u32,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub median_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkRun {
+    pub preset_id: Option<String>,
+    pub server: String,
+    pub protocol: String,
+    pub stats: BenchmarkStats,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Markdown,
+}
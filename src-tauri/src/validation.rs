@@ -0,0 +1,406 @@
+use crate::stamp::DnsStamp;
+use crate::types::{DnsPreset, PresetParameter};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+pub const CATEGORIES: [&str; 7] = ["public", "security", "privacy", "gaming", "family", "adblock", "custom"];
+
+/// Операторы выражений RFC 6570
+const TEMPLATE_OPERATORS: [char; 7] = ['+', '#', '.', '/', ';', '?', '&'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Обязательное поле пустое
+    Required,
+    /// Значение не разбирается
+    InvalidFormat,
+    /// Адрес не той версии IP
+    WrongFamily,
+    Duplicate,
+    /// Поля противоречат друг другу
+    Inconsistent,
+}
+
+/// Ошибка в конкретном поле; элементы списков адресуются как `servers_ipv4[1]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    fn push(&mut self, field: impl Into<String>, kind: ErrorKind, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            kind,
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Проверить пресет сам по себе: синтаксис полей и их согласованность
+pub fn validate_preset(preset: &DnsPreset) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    if preset.id.trim().is_empty() {
+        errors.push("id", ErrorKind::Required, "must not be empty");
+    } else if !preset.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        errors.push("id", ErrorKind::InvalidFormat, "may contain only letters, digits, '-', '_' and '.'");
+    }
+    if preset.name.trim().is_empty() {
+        errors.push("name", ErrorKind::Required, "must not be empty");
+    }
+    if !CATEGORIES.contains(&preset.category.as_str()) {
+        errors.push(
+            "category",
+            ErrorKind::InvalidFormat,
+            format!("unknown category '{}', expected one of {}", preset.category, CATEGORIES.join(", ")),
+        );
+    }
+
+    check_servers(&mut errors, "servers_ipv4", &preset.servers_ipv4, false);
+    check_servers(&mut errors, "servers_ipv6", &preset.servers_ipv6, true);
+    // Без IPv4 адаптер настроить нечем; DNS-стамп обслуживает локальный прокси
+    if preset.servers_ipv4.is_empty() && preset.stamp.is_none() {
+        errors.push("servers_ipv4", ErrorKind::Required, "at least one IPv4 server is required");
+    }
+
+    match &preset.doh_template {
+        Some(template) => {
+            if let Err(message) = check_doh_template(template, &preset.parameters) {
+                errors.push("doh_template", ErrorKind::InvalidFormat, message);
+            }
+        }
+        None if preset.supports_doh => {
+            errors.push("supports_doh", ErrorKind::Inconsistent, "DoH is enabled but doh_template is not set");
+        }
+        None => {}
+    }
+    match &preset.dot_hostname {
+        Some(hostname) if !is_hostname(&fill_parameters(hostname, &preset.parameters)) => {
+            errors.push("dot_hostname", ErrorKind::InvalidFormat, format!("'{}' is not a valid host name", hostname));
+        }
+        Some(_) => {}
+        None if preset.supports_dot => {
+            errors.push("supports_dot", ErrorKind::Inconsistent, "DoT is enabled but dot_hostname is not set");
+        }
+        None => {}
+    }
+
+    check_parameters(&mut errors, preset);
+
+    if !preset.color.is_empty() && !is_color(&preset.color) {
+        errors.push("color", ErrorKind::InvalidFormat, format!("'{}' must look like #rrggbb", preset.color));
+    }
+    if let Some(website) = &preset.website {
+        let valid = Url::parse(website)
+            .map(|url| url.scheme() == "https" || url.scheme() == "http")
+            .unwrap_or(false);
+        if !valid {
+            errors.push("website", ErrorKind::InvalidFormat, format!("'{}' is not an http(s) URL", website));
+        }
+    }
+    if let Some(stamp) = &preset.stamp {
+        // Стамп обслуживает локальный прокси — он должен уметь к нему обращаться
+        match DnsStamp::parse(stamp) {
+            Ok(parsed) => {
+                if let Err(e) = parsed.check_upstream() {
+                    errors.push("stamp", ErrorKind::InvalidFormat, e.to_string());
+                }
+            }
+            Err(e) => errors.push("stamp", ErrorKind::InvalidFormat, e.to_string()),
+        }
+    }
+    if let Some(country) = &preset.capabilities.jurisdiction {
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+            errors.push(
+                "capabilities.jurisdiction",
+                ErrorKind::InvalidFormat,
+                format!("'{}' must be an ISO 3166-1 alpha-2 country code like CH", country),
+            );
+        }
+    }
+
+    errors.into_result()
+}
+
+/// Проверить пресет перед сохранением рядом со встроенными и пользовательскими
+/// пресетами: имена не должны повторяться (пользовательский пресет с тем же
+/// `id` — это он сам до изменения)
+pub fn validate_against(preset: &DnsPreset, builtin: &[DnsPreset], custom: &[DnsPreset]) -> Result<(), ValidationErrors> {
+    let mut errors = validate_preset(preset).err().unwrap_or_default();
+
+    let name = preset.name.trim();
+    let same_name = |other: &DnsPreset| other.name.trim().eq_ignore_ascii_case(name);
+    if !name.is_empty() {
+        if builtin.iter().any(same_name) {
+            errors.push("name", ErrorKind::Duplicate, format!("a built-in preset named '{}' already exists", name));
+        } else if custom.iter().any(|other| other.id != preset.id && same_name(other)) {
+            errors.push("name", ErrorKind::Duplicate, format!("a preset named '{}' already exists", name));
+        }
+    }
+
+    errors.into_result()
+}
+
+fn check_servers(errors: &mut ValidationErrors, field: &str, servers: &[String], ipv6: bool) {
+    for (index, server) in servers.iter().enumerate() {
+        let field = format!("{}[{}]", field, index);
+        match server.trim().parse::<IpAddr>() {
+            Ok(ip) if ip.is_ipv6() != ipv6 => {
+                let (got, expected) = if ipv6 { ("IPv4", "IPv6") } else { ("IPv6", "IPv4") };
+                errors.push(
+                    field,
+                    ErrorKind::WrongFamily,
+                    format!("'{}' is an {} address, expected {}", server, got, expected),
+                );
+            }
+            Ok(_) if servers[..index].iter().any(|s| s.trim() == server.trim()) => {
+                errors.push(field, ErrorKind::Duplicate, format!("'{}' is listed twice", server));
+            }
+            Ok(_) => {}
+            Err(_) => {
+                errors.push(field, ErrorKind::InvalidFormat, format!("'{}' is not an IP address", server));
+            }
+        }
+    }
+}
+
+/// Параметры: имя — идентификатор, шаблон компилируется, и каждый параметр
+/// используется в `doh_template` или `dot_hostname`
+fn check_parameters(errors: &mut ValidationErrors, preset: &DnsPreset) {
+    for (index, parameter) in preset.parameters.iter().enumerate() {
+        let field = format!("parameters[{}]", index);
+        let name = &parameter.name;
+
+        if !is_parameter_name(name) {
+            errors.push(
+                format!("{}.name", field),
+                ErrorKind::InvalidFormat,
+                format!("'{}' may contain only lowercase letters, digits and '_'", name),
+            );
+        } else if name == "dns" {
+            errors.push(format!("{}.name", field), ErrorKind::InvalidFormat, "'dns' is reserved for the DoH query");
+        } else if preset.parameters[..index].iter().any(|p| &p.name == name) {
+            errors.push(format!("{}.name", field), ErrorKind::Duplicate, format!("'{}' is declared twice", name));
+        }
+        if parameter.label.trim().is_empty() {
+            errors.push(format!("{}.label", field), ErrorKind::Required, "must not be empty");
+        }
+        match Regex::new(&format!("^(?:{})$", parameter.pattern)) {
+            Ok(pattern) => {
+                if let Some(example) = parameter.example.as_deref().filter(|e| !pattern.is_match(e)) {
+                    errors.push(
+                        format!("{}.example", field),
+                        ErrorKind::Inconsistent,
+                        format!("'{}' does not match the pattern", example),
+                    );
+                }
+            }
+            Err(e) => errors.push(format!("{}.pattern", field), ErrorKind::InvalidFormat, e.to_string()),
+        }
+
+        let placeholder = format!("{{{}}}", name);
+        let used = [&preset.doh_template, &preset.dot_hostname]
+            .iter()
+            .any(|value| value.as_deref().is_some_and(|v| v.contains(&placeholder)));
+        if !used {
+            errors.push(
+                format!("{}.name", field),
+                ErrorKind::Inconsistent,
+                format!("'{}' is used neither in doh_template nor in dot_hostname", placeholder),
+            );
+        }
+    }
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Подставить вместо `{name}` пробное значение, чтобы проверить остальное
+fn fill_parameters(value: &str, parameters: &[PresetParameter]) -> String {
+    parameters
+        .iter()
+        .fold(value.to_string(), |value, p| value.replace(&format!("{{{}}}", p.name), "param"))
+}
+
+/// Шаблон DoH (RFC 8484): https-URL, в котором допустимы только выражения
+/// RFC 6570 с переменной `dns`, например `https://dns.example/dns-query{?dns}`,
+/// и простые подстановки `{name}` параметров пресета
+pub fn check_doh_template(template: &str, parameters: &[PresetParameter]) -> Result<(), String> {
+    let mut base = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let literal = &rest[..start];
+        if literal.contains('}') {
+            return Err("unmatched '}' in URI template".to_string());
+        }
+        base.push_str(literal);
+
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| "unclosed '{' in URI template".to_string())?;
+        let expression = &rest[start + 1..end];
+        if parameters.iter().any(|p| p.name == expression) {
+            base.push_str("param");
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let variables = expression.strip_prefix(&TEMPLATE_OPERATORS[..]).unwrap_or(expression);
+        if variables.is_empty() {
+            return Err("empty expression in URI template".to_string());
+        }
+        for variable in variables.split(',') {
+            // Модификаторы: `dns*` (explode) и `dns:100` (префикс)
+            let name = match variable.split_once(':') {
+                Some((name, length)) if !length.is_empty() && length.chars().all(|c| c.is_ascii_digit()) => name,
+                Some(_) => return Err(format!("invalid prefix modifier in '{{{}}}'", expression)),
+                None => variable.strip_suffix('*').unwrap_or(variable),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%') {
+                return Err(format!("invalid variable name in '{{{}}}'", expression));
+            }
+            if name != "dns" {
+                return Err(format!("unsupported variable '{}', DoH templates may only use 'dns'", name));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}') {
+        return Err("unmatched '}' in URI template".to_string());
+    }
+    base.push_str(rest);
+
+    let url = Url::parse(&base).map_err(|e| format!("'{}' is not a valid URL: {}", template, e))?;
+    if url.scheme() != "https" {
+        return Err(format!("'{}' must be an https:// URL", template));
+    }
+    if matches!(url.host_str(), None | Some("")) {
+        return Err(format!("'{}' has no host", template));
+    }
+    Ok(())
+}
+
+/// Имя хоста по RFC 1123; IP-адрес тоже допустим
+pub fn is_hostname(name: &str) -> bool {
+    if name.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+pub fn is_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::test_preset as preset;
+
+    fn parameter(name: &str) -> PresetParameter {
+        PresetParameter {
+            name: name.to_string(),
+            label: "Profile ID".to_string(),
+            description: String::new(),
+            pattern: "[a-z0-9]+".to_string(),
+            example: None,
+        }
+    }
+
+    #[test]
+    fn doh_templates_accept_the_dns_variable_and_parameters() {
+        for template in [
+            "https://dns.example/dns-query",
+            "https://dns.example/dns-query{?dns}",
+            "https://dns.example:8443/q{?dns*}",
+            "https://dns.example/q{?dns:100}",
+        ] {
+            assert_eq!(check_doh_template(template, &[]), Ok(()), "{}", template);
+        }
+        assert_eq!(check_doh_template("https://dns.example/{profile}{?dns}", &[parameter("profile")]), Ok(()));
+        assert_eq!(check_doh_template("https://{profile}.dns.example/", &[parameter("profile")]), Ok(()));
+    }
+
+    #[test]
+    fn doh_templates_reject_bad_syntax_and_urls() {
+        for (template, expected) in [
+            ("https://dns.example/{?dns", "unclosed '{'"),
+            ("https://dns.example/}", "unmatched '}'"),
+            ("https://dns.example/{}", "empty expression"),
+            ("https://dns.example/{?name}", "unsupported variable 'name'"),
+            ("https://dns.example/{?dns:x}", "invalid prefix modifier"),
+            ("https://dns.example/{profile}", "unsupported variable 'profile'"),
+            ("http://dns.example/dns-query", "must be an https:// URL"),
+            ("dns.example/dns-query", "is not a valid URL"),
+        ] {
+            let message = check_doh_template(template, &[]).unwrap_err();
+            assert!(message.contains(expected), "{}: {}", template, message);
+        }
+    }
+
+    #[test]
+    fn names_must_differ_from_built_in_and_other_custom_presets() {
+        let builtin = [preset("cloudflare", "Cloudflare")];
+        let custom = [preset("custom-1", "Home")];
+
+        let errors = validate_against(&preset("custom-2", " cloudflare "), &builtin, &custom).unwrap_err();
+        assert_eq!(errors.errors[0].field, "name");
+        assert_eq!(errors.errors[0].kind, ErrorKind::Duplicate);
+        assert!(errors.errors[0].message.contains("built-in"));
+
+        assert!(validate_against(&preset("custom-2", "HOME"), &builtin, &custom).is_err());
+        // Тот же пресет до изменения — не дубликат
+        assert!(validate_against(&preset("custom-1", "Home"), &builtin, &custom).is_ok());
+    }
+}
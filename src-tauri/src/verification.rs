@@ -0,0 +1,336 @@
+use crate::proxy::ProxyService;
+use crate::types::{DnsPreset, DnsTestResult, PresetVerification};
+use crate::validation::{self, ValidationErrors};
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// Что именно проверялось
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    Ipv4,
+    Ipv6,
+    Doh,
+    Dot,
+    Stamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub kind: ProbeKind,
+    #[serde(flatten)]
+    pub result: DnsTestResult,
+}
+
+/// Отчёт о проверке серверов пресета
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub verified_at: chrono::DateTime<Utc>,
+    /// Ответили все проверенные адреса
+    pub passed: bool,
+    pub probes: Vec<ProbeResult>,
+}
+
+impl VerificationReport {
+    /// Краткий итог, который хранится вместе с пресетом
+    pub fn summary(&self) -> PresetVerification {
+        PresetVerification {
+            verified_at: self.verified_at,
+            passed: self.passed,
+            failed: self
+                .probes
+                .iter()
+                .filter(|probe| !probe.result.is_available)
+                .map(|probe| probe.result.server.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Итог сохранения пресета с необязательной проверкой
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetSaveResult {
+    /// Пресет сохранён; при ошибках в полях или неудачной проверке он не
+    /// сохраняется, и пользователь может исправить адреса или сохранить его с `save_anyway`
+    pub saved: bool,
+    pub report: Option<VerificationReport>,
+    /// Ошибки по полям, из-за которых пресет не сохранён
+    #[serde(default)]
+    pub errors: ValidationErrors,
+}
+
+impl PresetSaveResult {
+    pub fn saved(report: Option<VerificationReport>) -> Self {
+        Self {
+            saved: true,
+            report,
+            errors: ValidationErrors::default(),
+        }
+    }
+
+    pub fn rejected(report: Option<VerificationReport>, errors: ValidationErrors) -> Self {
+        Self {
+            saved: false,
+            report,
+            errors,
+        }
+    }
+}
+
+/// Совпадают ли адреса, которые проверяет `verify_with`: иначе прежний итог
+/// проверки к пресету уже не относится
+pub fn same_servers(a: &DnsPreset, b: &DnsPreset) -> bool {
+    a.servers_ipv4 == b.servers_ipv4
+        && a.servers_ipv6 == b.servers_ipv6
+        && a.doh_template == b.doh_template
+        && a.dot_hostname == b.dot_hostname
+        && a.stamp == b.stamp
+}
+
+/// Проверить пресет перед сохранением, если это запрошено. Итог проверки
+/// записывает только сервер: без новой проверки у пресета остаётся прежний
+/// итог, если его серверы не менялись, иначе итог сбрасывается. `Err` — пресет
+/// сохранять нельзя: в полях ошибки или проверка не прошла без `save_anyway`.
+pub async fn check_before_save(
+    preset: &mut DnsPreset,
+    previous: Option<&DnsPreset>,
+    verify: Option<bool>,
+    save_anyway: Option<bool>,
+    proxy: &ProxyService,
+) -> Result<Option<VerificationReport>, PresetSaveResult> {
+    check_before_save_with(preset, previous, verify, save_anyway, |specs| async move {
+        proxy.probe_upstreams(&specs).await
+    })
+    .await
+}
+
+async fn check_before_save_with<F, Fut>(
+    preset: &mut DnsPreset,
+    previous: Option<&DnsPreset>,
+    verify: Option<bool>,
+    save_anyway: Option<bool>,
+    probe: F,
+) -> Result<Option<VerificationReport>, PresetSaveResult>
+where
+    F: FnOnce(Vec<String>) -> Fut,
+    Fut: Future<Output = Vec<DnsTestResult>>,
+{
+    if !verify.unwrap_or(false) {
+        preset.verification = previous
+            .filter(|previous| same_servers(previous, preset))
+            .and_then(|previous| previous.verification.clone());
+        return Ok(None);
+    }
+    // Опрашивать заведомо неверные адреса незачем
+    if let Err(errors) = validation::validate_preset(preset) {
+        return Err(PresetSaveResult::rejected(None, errors));
+    }
+
+    let report = verify_with(preset, probe).await;
+    preset.verification = Some(report.summary());
+    if report.passed {
+        return Ok(Some(report));
+    }
+    if save_anyway.unwrap_or(false) {
+        warn!("⚠️  Custom preset {} failed verification and is saved anyway", preset.name);
+        return Ok(Some(report));
+    }
+    warn!("⚠️  Custom preset {} failed verification and was not saved", preset.name);
+    Err(PresetSaveResult::rejected(Some(report), ValidationErrors::default()))
+}
+
+/// Опросить каждый сервер пресета: IPv4 и IPv6 по UDP, шаблон DoH, имя DoT и
+/// стамп. `probe` получает адреса и возвращает результаты в том же порядке.
+async fn verify_with<F, Fut>(preset: &DnsPreset, probe: F) -> VerificationReport
+where
+    F: FnOnce(Vec<String>) -> Fut,
+    Fut: Future<Output = Vec<DnsTestResult>>,
+{
+    let mut targets = Vec::new();
+    targets.extend(preset.servers_ipv4.iter().map(|server| (ProbeKind::Ipv4, server.clone())));
+    targets.extend(preset.servers_ipv6.iter().map(|server| (ProbeKind::Ipv6, server.clone())));
+    if let Some(template) = &preset.doh_template {
+        targets.push((ProbeKind::Doh, template.clone()));
+    }
+    if let Some(hostname) = &preset.dot_hostname {
+        targets.push((ProbeKind::Dot, format!("tls://{}", hostname)));
+    }
+    if let Some(stamp) = &preset.stamp {
+        targets.push((ProbeKind::Stamp, stamp.clone()));
+    }
+
+    let specs: Vec<String> = targets.iter().map(|(_, spec)| spec.clone()).collect();
+    let probes: Vec<ProbeResult> = targets
+        .into_iter()
+        .zip(probe(specs).await)
+        .map(|((kind, _), result)| ProbeResult { kind, result })
+        .collect();
+
+    let passed = probes.iter().all(|probe| probe.result.is_available);
+    info!(
+        "🔎 Verified preset {}: {}/{} servers responded",
+        preset.name,
+        probes.iter().filter(|probe| probe.result.is_available).count(),
+        probes.len()
+    );
+
+    VerificationReport {
+        verified_at: Utc::now(),
+        passed,
+        probes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::test_preset;
+
+    fn result(server: &str, is_available: bool) -> DnsTestResult {
+        DnsTestResult {
+            server: server.to_string(),
+            latency_ms: is_available.then_some(10),
+            is_available,
+            error: (!is_available).then(|| "Timeout".to_string()),
+        }
+    }
+
+    /// Опрос, при котором не отвечают адреса из `down`
+    fn probe(down: &'static [&'static str]) -> impl FnOnce(Vec<String>) -> std::future::Ready<Vec<DnsTestResult>> {
+        move |specs| std::future::ready(specs.iter().map(|spec| result(spec, !down.contains(&spec.as_str()))).collect())
+    }
+
+    fn preset() -> DnsPreset {
+        DnsPreset {
+            servers_ipv6: vec!["2001:db8::1".to_string()],
+            dot_hostname: Some("dns.example".to_string()),
+            supports_dot: true,
+            ..test_preset("home", "Home")
+        }
+    }
+
+    fn verified(passed: bool) -> Option<PresetVerification> {
+        Some(PresetVerification {
+            verified_at: Utc::now(),
+            passed,
+            failed: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn same_servers_ignores_everything_but_addresses() {
+        let base = preset();
+        let renamed = DnsPreset {
+            name: "Renamed".to_string(),
+            description: "Other".to_string(),
+            verification: verified(true),
+            ..base.clone()
+        };
+        assert!(same_servers(&base, &renamed));
+
+        let changes: [fn(&mut DnsPreset); 5] = [
+            |p| p.servers_ipv4.push("192.0.2.2".to_string()),
+            |p| p.servers_ipv6.clear(),
+            |p| p.doh_template = Some("https://other.example/dns-query".to_string()),
+            |p| p.dot_hostname = None,
+            |p| p.stamp = Some("sdns://AAcAAAAAAAAABzEuMS4xLjE".to_string()),
+        ];
+        for change in changes {
+            let mut changed = base.clone();
+            change(&mut changed);
+            assert!(!same_servers(&base, &changed), "{:?}", changed);
+        }
+    }
+
+    #[tokio::test]
+    async fn report_covers_every_server_and_summary_lists_failures() {
+        let report = verify_with(&preset(), probe(&["2001:db8::1", "tls://dns.example"])).await;
+
+        let kinds: Vec<ProbeKind> = report.probes.iter().map(|probe| probe.kind).collect();
+        assert_eq!(kinds, [ProbeKind::Ipv4, ProbeKind::Ipv6, ProbeKind::Doh, ProbeKind::Dot]);
+        assert_eq!(report.probes[2].result.server, "https://dns.example/dns-query{?dns}");
+        assert!(!report.passed);
+
+        let summary = report.summary();
+        assert!(!summary.passed);
+        assert_eq!(summary.verified_at, report.verified_at);
+        assert_eq!(summary.failed, ["2001:db8::1", "tls://dns.example"]);
+
+        let report = verify_with(&preset(), probe(&[])).await;
+        assert!(report.passed && report.summary().failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn without_verification_the_previous_result_is_kept_only_for_the_same_servers() {
+        let previous = DnsPreset {
+            verification: verified(true),
+            ..preset()
+        };
+
+        let mut unchanged = DnsPreset {
+            name: "Renamed".to_string(),
+            ..preset()
+        };
+        let report = check_before_save_with(&mut unchanged, Some(&previous), None, None, probe(&[])).await;
+        assert!(matches!(report, Ok(None)));
+        assert!(unchanged.verification.is_some_and(|v| v.passed));
+
+        // Итог для прежних адресов к новым не относится
+        let mut moved = DnsPreset {
+            servers_ipv4: vec!["192.0.2.2".to_string()],
+            verification: verified(true),
+            ..preset()
+        };
+        let report = check_before_save_with(&mut moved, Some(&previous), Some(false), None, probe(&[])).await;
+        assert!(matches!(report, Ok(None)));
+        assert!(moved.verification.is_none());
+
+        let mut new = DnsPreset {
+            verification: verified(true),
+            ..preset()
+        };
+        assert!(check_before_save_with(&mut new, None, None, None, probe(&[])).await.is_ok());
+        assert!(new.verification.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_verification_blocks_saving_unless_saved_anyway() {
+        let mut rejected = preset();
+        let Err(result) = check_before_save_with(&mut rejected, None, Some(true), None, probe(&["192.0.2.1"])).await
+        else {
+            panic!("a failed verification must not be saved");
+        };
+        assert!(!result.saved);
+        assert!(result.errors.errors.is_empty());
+        assert!(result.report.is_some_and(|report| !report.passed));
+        assert_eq!(rejected.verification.unwrap().failed, ["192.0.2.1"]);
+
+        let mut forced = preset();
+        let report = check_before_save_with(&mut forced, None, Some(true), Some(true), probe(&["192.0.2.1"])).await;
+        assert!(report.is_ok_and(|report| report.is_some_and(|r| !r.passed)));
+        assert!(forced.verification.is_some_and(|v| !v.passed));
+
+        let mut passing = preset();
+        let report = check_before_save_with(&mut passing, None, Some(true), None, probe(&[])).await;
+        assert!(report.is_ok_and(|report| report.is_some_and(|r| r.passed)));
+        assert!(passing.verification.is_some_and(|v| v.passed && v.failed.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn invalid_presets_are_not_probed() {
+        let mut invalid = DnsPreset {
+            servers_ipv4: vec!["not an ip".to_string()],
+            ..preset()
+        };
+        let not_called = |_: Vec<String>| -> std::future::Ready<Vec<DnsTestResult>> { panic!("invalid preset was probed") };
+
+        let Err(result) = check_before_save_with(&mut invalid, None, Some(true), Some(true), not_called).await else {
+            panic!("an invalid preset must not be saved");
+        };
+        assert!(!result.saved && result.report.is_none());
+        assert_eq!(result.errors.errors[0].field, "servers_ipv4[0]");
+        assert!(invalid.verification.is_none());
+    }
+}
@@ -1,10 +1,34 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import type {
 	NetworkAdapter,
 	DnsConfiguration,
 	DnsPreset,
 	DnsTestResult,
 	WindowsVersion,
+	BenchmarkRun,
+	ExportFormat,
+	ProxyConfig,
+	ProxyStatus,
+	CacheStats,
+	ForwardingRule,
+	LocalRecord,
+	QueryLogEntry,
+	QueryLogPage,
+	QueryStats,
+	BlocklistSettings,
+	BlocklistSource,
+	BlocklistStatus,
+	CatalogSource,
+	CatalogUpdate,
+	CatalogDiff,
+	FieldError,
+	PresetSaveResult,
+	PresetQuery,
+	StampImport,
+	ImportFormat,
+	ImportPreview,
+	ImportResult,
 } from './types';
 import { getPresetColor } from './presetUI';
 
@@ -21,7 +45,9 @@ export async function setDns(
 	ipv4Servers: string[],
 	ipv6Servers: string[] = [],
 	dohTemplate: string | null = null,
-	dotHostname: string | null = null
+	dotHostname: string | null = null,
+	stamp: string | null = null,
+	presetId: string | null = null
 ): Promise<void> {
 	return invoke('set_dns', {
 		adapterName,
@@ -29,6 +55,8 @@ export async function setDns(
 		ipv6Servers,
 		dohTemplate,
 		dotHostname,
+		stamp,
+		presetId,
 	});
 }
 
@@ -59,6 +87,87 @@ export async function getPresetById(id: string): Promise<DnsPreset | null> {
 	return null;
 }
 
+export async function queryPresets(query: PresetQuery): Promise<DnsPreset[]> {
+	const presets = await invoke<DnsPreset[]>('query_presets', { query });
+	return presets.map((preset) => ({
+		...preset,
+		color: getPresetColor(preset.id, preset.category === 'custom'),
+	}));
+}
+
+export async function getPresetParameters(presetId: string): Promise<Record<string, string>> {
+	return await invoke('get_preset_parameters', { presetId });
+}
+
+export async function setPresetParameters(presetId: string, values: Record<string, string>): Promise<void> {
+	return await invoke('set_preset_parameters', { presetId, values });
+}
+
+/** Ask for personal values (e.g. a NextDNS profile ID) that aren't saved yet; false if the user cancels */
+export async function ensurePresetParameters(preset: DnsPreset): Promise<boolean> {
+	const parameters = preset.parameters || [];
+	if (parameters.length === 0) return true;
+
+	const values = await getPresetParameters(preset.id);
+	const missing = parameters.filter((p) => !values[p.name]);
+	if (missing.length === 0) return true;
+
+	for (const parameter of missing) {
+		const hint = parameter.example ? ` (e.g. ${parameter.example})` : '';
+		const value = window.prompt(`${preset.name}: ${parameter.label}${hint}\n${parameter.description}`);
+		if (!value) return false;
+		values[parameter.name] = value.trim();
+	}
+	await setPresetParameters(preset.id, values);
+	return true;
+}
+
+export async function exportPresetStamps(presetId: string): Promise<string[]> {
+	return await invoke('export_preset_stamps', { presetId });
+}
+
+/** Import stamps pasted as text, or from a stamp list file when `path` is given */
+export async function importPresetStamps(text: string | null, path: string | null = null): Promise<StampImport> {
+	return await invoke('import_preset_stamps', { text, path });
+}
+
+/**
+ * Parse a dnscrypt-proxy.toml, public-resolvers.md or AdGuard Home YAML list and
+ * report duplicates of existing presets. Nothing is saved; the format is guessed
+ * from the file extension when omitted.
+ */
+export async function previewPresetImport(
+	format: ImportFormat | null,
+	text: string | null,
+	path: string | null = null
+): Promise<ImportPreview> {
+	return await invoke('preview_preset_import', { format, text, path });
+}
+
+export async function importPresets(presets: DnsPreset[]): Promise<ImportResult> {
+	return await invoke('import_presets', { presets });
+}
+
+export async function getCatalogSource(): Promise<CatalogSource> {
+	return await invoke('get_catalog_source');
+}
+
+export async function setCatalogSource(source: CatalogSource): Promise<void> {
+	return await invoke('set_catalog_source', { source });
+}
+
+export async function updatePresetCatalog(): Promise<CatalogUpdate> {
+	return await invoke('update_preset_catalog');
+}
+
+export async function getPresetCatalogDiff(): Promise<CatalogDiff> {
+	return await invoke('get_preset_catalog_diff');
+}
+
+export async function onPresetCatalogUpdated(callback: (update: CatalogUpdate) => void): Promise<UnlistenFn> {
+	return listen<CatalogUpdate>('preset-catalog-updated', (event) => callback(event.payload));
+}
+
 export async function flushDnsCache(): Promise<void> {
 	return await invoke('flush_dns_cache');
 }
@@ -67,6 +176,15 @@ export async function getWindowsVersion(): Promise<WindowsVersion> {
 	return await invoke('get_windows_version');
 }
 
+export async function exportTestResults(
+	runs: BenchmarkRun[],
+	format: ExportFormat,
+	path: string,
+	adapterName: string | null = null
+): Promise<void> {
+	return invoke('export_test_results', { runs, format, path, adapterName });
+}
+
 export async function getCustomPresets(): Promise<DnsPreset[]> {
 	const presets = await invoke<DnsPreset[]>('get_custom_presets');
 	return presets.map((preset) => ({
@@ -75,16 +193,30 @@ export async function getCustomPresets(): Promise<DnsPreset[]> {
 	}));
 }
 
-export async function addCustomPreset(preset: Omit<DnsPreset, 'color'>): Promise<void> {
-	return await invoke('add_custom_preset', { preset });
+/** `saveAnyway` saves the preset with its failed verification instead of rejecting it */
+export async function addCustomPreset(
+	preset: Omit<DnsPreset, 'color'>,
+	verify = false,
+	saveAnyway = false
+): Promise<PresetSaveResult> {
+	return await invoke('add_custom_preset', { preset, verify, saveAnyway });
 }
 
 export async function deleteCustomPreset(id: string): Promise<void> {
 	return await invoke('delete_custom_preset', { id });
 }
 
-export async function updateCustomPreset(preset: Omit<DnsPreset, 'color'>): Promise<void> {
-	return await invoke('update_custom_preset', { preset });
+/** `saveAnyway` saves the preset with its failed verification instead of rejecting it */
+export async function updateCustomPreset(
+	preset: Omit<DnsPreset, 'color'>,
+	verify = false,
+	saveAnyway = false
+): Promise<PresetSaveResult> {
+	return await invoke('update_custom_preset', { preset, verify, saveAnyway });
+}
+
+export async function validatePreset(preset: Omit<DnsPreset, 'color'>): Promise<FieldError[]> {
+	return await invoke('validate_preset', { preset });
 }
 
 // Mini window operations
@@ -117,4 +249,97 @@ export async function getSelectedAdapter(): Promise<string | null> {
 
 export async function setSelectedAdapter(adapterName: string): Promise<void> {
 	return invoke('set_selected_adapter', { adapterName });
-}
\ No newline at end of file
+}
+
+// Local DNS proxy
+export async function getProxyConfig(): Promise<ProxyConfig> {
+	return invoke('get_proxy_config');
+}
+
+export async function updateProxyConfig(config: ProxyConfig): Promise<void> {
+	return invoke('update_proxy_config', { config });
+}
+
+export async function getProxyStatus(): Promise<ProxyStatus> {
+	return invoke('get_proxy_status');
+}
+
+export async function getProxyCacheStats(): Promise<CacheStats> {
+	return invoke('get_proxy_cache_stats');
+}
+
+export async function clearProxyCache(): Promise<void> {
+	return invoke('clear_proxy_cache');
+}
+
+export async function getForwardingRules(): Promise<ForwardingRule[]> {
+	return invoke('get_forwarding_rules');
+}
+
+export async function addForwardingRule(rule: ForwardingRule): Promise<ForwardingRule> {
+	return invoke('add_forwarding_rule', { rule });
+}
+
+export async function updateForwardingRule(rule: ForwardingRule): Promise<void> {
+	return invoke('update_forwarding_rule', { rule });
+}
+
+export async function deleteForwardingRule(id: string): Promise<void> {
+	return invoke('delete_forwarding_rule', { id });
+}
+
+export async function getBlocklistSettings(): Promise<BlocklistSettings> {
+	return invoke('get_blocklist_settings');
+}
+
+export async function updateBlocklistSettings(settings: BlocklistSettings): Promise<void> {
+	return invoke('update_blocklist_settings', { settings });
+}
+
+export async function addBlocklistSource(source: BlocklistSource): Promise<BlocklistSource> {
+	return invoke('add_blocklist_source', { source });
+}
+
+export async function deleteBlocklistSource(id: string): Promise<void> {
+	return invoke('delete_blocklist_source', { id });
+}
+
+export async function refreshBlocklists(): Promise<BlocklistStatus> {
+	return invoke('refresh_blocklists');
+}
+
+export async function getBlocklistStatus(): Promise<BlocklistStatus> {
+	return invoke('get_blocklist_status');
+}
+
+export async function getLocalRecords(): Promise<LocalRecord[]> {
+	return invoke('get_local_records');
+}
+
+export async function addLocalRecord(record: LocalRecord): Promise<LocalRecord> {
+	return invoke('add_local_record', { record });
+}
+
+export async function updateLocalRecord(record: LocalRecord): Promise<void> {
+	return invoke('update_local_record', { record });
+}
+
+export async function deleteLocalRecord(id: string): Promise<void> {
+	return invoke('delete_local_record', { id });
+}
+
+export async function getQueryLog(offset: number, limit: number, search?: string): Promise<QueryLogPage> {
+	return invoke('get_query_log', { offset, limit, search });
+}
+
+export async function getQueryStats(top?: number): Promise<QueryStats> {
+	return invoke('get_query_stats', { top });
+}
+
+export async function clearQueryLog(): Promise<void> {
+	return invoke('clear_query_log');
+}
+
+export async function onProxyQuery(callback: (entry: QueryLogEntry) => void): Promise<UnlistenFn> {
+	return listen<QueryLogEntry>('proxy-query', (event) => callback(event.payload));
+}
@@ -34,6 +34,150 @@ export interface DnsPreset {
   icon: string;
   color: string;
   website?: string | null;
+  stamp?: string | null;
+  verification?: PresetVerification | null;
+  capabilities?: PresetCapabilities;
+  parameters?: PresetParameter[];
+}
+
+/** Personal value substituted into doh_template / dot_hostname as {name} */
+export interface PresetParameter {
+  name: string;
+  label: string;
+  description: string;
+  pattern: string;
+  example: string | null;
+}
+
+export interface StampImportError {
+  line: number;
+  stamp: string;
+  message: string;
+}
+
+export interface StampImport {
+  imported: DnsPreset[];
+  errors: StampImportError[];
+}
+
+export type ImportFormat = 'dnscrypt_proxy' | 'public_resolvers' | 'adguard_home';
+
+export type DuplicateReason = 'name' | 'server' | 'doh_template' | 'dot_hostname' | 'stamp';
+
+export interface Duplicate {
+  id: string;
+  name: string;
+  source: 'built_in' | 'custom' | 'import';
+  reasons: DuplicateReason[];
+}
+
+export interface ImportCandidate {
+  preset: DnsPreset;
+  duplicates: Duplicate[];
+}
+
+export interface ImportIssue {
+  entry: string;
+  message: string;
+}
+
+export interface ImportPreview {
+  format: ImportFormat;
+  candidates: ImportCandidate[];
+  skipped: ImportIssue[];
+}
+
+export interface ImportResult {
+  imported: DnsPreset[];
+  errors: ImportIssue[];
+}
+
+export type LoggingPolicy = 'none' | 'anonymized' | 'temporary' | 'full' | 'unknown';
+
+export type FilteringType = 'none' | 'malware' | 'ads' | 'family' | 'unknown';
+
+export type Protocol = 'do53' | 'doh' | 'dot' | 'doq' | 'dnscrypt' | 'odoh';
+
+export interface PresetCapabilities {
+  logging: LoggingPolicy;
+  dnssec: boolean | null;
+  ecs: boolean | null;
+  filtering: FilteringType;
+  jurisdiction: string | null;
+  anycast: boolean | null;
+}
+
+export interface PresetQuery {
+  text?: string | null;
+  source?: 'all' | 'built_in' | 'custom';
+  categories?: string[];
+  protocols?: Protocol[];
+  logging?: LoggingPolicy[];
+  dnssec?: boolean | null;
+  ecs?: boolean | null;
+  filtering?: FilteringType[];
+  jurisdictions?: string[];
+  anycast?: boolean | null;
+  sort?: 'name' | 'category' | 'privacy' | 'jurisdiction';
+  descending?: boolean;
+}
+
+export interface PresetVerification {
+  verified_at: string;
+  passed: boolean;
+  failed: string[];
+}
+
+export type ProbeKind = 'ipv4' | 'ipv6' | 'doh' | 'dot' | 'stamp';
+
+export interface ProbeResult extends DnsTestResult {
+  kind: ProbeKind;
+}
+
+export interface VerificationReport {
+  verified_at: string;
+  passed: boolean;
+  probes: ProbeResult[];
+}
+
+export interface PresetSaveResult {
+  saved: boolean;
+  report: VerificationReport | null;
+  // Ошибки по полям, из-за которых пресет не сохранён
+  errors: FieldError[];
+}
+
+export interface CatalogSource {
+  url: string | null;
+  signature_url: string | null;
+  public_key: string | null;
+}
+
+export interface PresetChange {
+  id: string;
+  fields: string[];
+}
+
+export interface CatalogDiff {
+  from_version: number;
+  to_version: number;
+  added: string[];
+  removed: string[];
+  changed: PresetChange[];
+}
+
+export interface CatalogUpdate {
+  updated: boolean;
+  version: number;
+  diff: CatalogDiff;
+}
+
+export type ValidationErrorKind = 'required' | 'invalid_format' | 'wrong_family' | 'duplicate' | 'inconsistent';
+
+export interface FieldError {
+  field: string;
+  kind: ValidationErrorKind;
+  message: string;
 }
 
 // Расширенный тип для UI (с цветом)
@@ -55,6 +199,220 @@ export interface WindowsVersion {
   supports_doh: boolean;
 }
 
+export interface BenchmarkStats {
+  samples: number;
+  successes: number;
+  min_ms: number | null;
+  avg_ms: number | null;
+  median_ms: number | null;
+  max_ms: number | null;
+  jitter_ms: number | null;
+}
+
+export interface BenchmarkRun {
+  preset_id: string | null;
+  server: string;
+  protocol: string;
+  stats: BenchmarkStats;
+  timestamp: string;
+}
+
+export type ExportFormat = 'csv' | 'json' | 'markdown';
+
+export type ProxyMode = 'disabled' | 'auto' | 'always';
+
+export interface CacheConfig {
+  enabled: boolean;
+  capacity: number;
+  min_ttl: number;
+  max_ttl: number;
+  negative_max_ttl: number;
+  prefetch: boolean;
+  prefetch_window_percent: number;
+  prefetch_min_hits: number;
+  serve_stale: boolean;
+  stale_max_age_secs: number;
+  stale_answer_ttl: number;
+  stale_client_timeout_ms: number;
+}
+
+export interface CacheStats {
+  enabled: boolean;
+  capacity: number;
+  entries: number;
+  hits: number;
+  misses: number;
+  prefetches: number;
+  stale_served: number;
+  hit_rate: number;
+}
+
+export interface ProxyConfig {
+  mode: ProxyMode;
+  listen_addresses: string[];
+  port: number;
+  upstream_timeout_ms: number;
+  strategy: UpstreamStrategy;
+  breaker: BreakerConfig;
+  cache: CacheConfig;
+  query_log: QueryLogConfig;
+  odoh_relay: string | null;
+  privacy: PrivacyConfig;
+  dnssec: DnssecConfig;
+  lan: LanConfig;
+  metrics: MetricsConfig;
+}
+
+export interface MetricsConfig {
+  enabled: boolean;
+  listen_address: string;
+}
+
+export interface LanConfig {
+  enabled: boolean;
+  listen_addresses: string[];
+  allowed_networks: string[];
+  rate_limit_qps: number;
+  rate_limit_burst: number;
+  refuse_any: boolean;
+  preset_id: string | null;
+}
+
+export type EcsPolicy = 'forward' | 'strip' | { override: string };
+
+export interface PrivacyOptions {
+  ecs: EcsPolicy;
+  padding: boolean;
+  randomize_case: boolean;
+}
+
+export interface PrivacyConfig {
+  defaults: PrivacyOptions;
+  upstreams: Record<string, PrivacyOptions>;
+}
+
+export interface DnssecConfig {
+  enabled: boolean;
+  trust_anchors: string[];
+}
+
+export type UpstreamStrategy = 'strict' | 'round_robin' | 'lowest_latency' | 'race';
+
+export interface BreakerConfig {
+  failure_threshold: number;
+  cooldown_secs: number;
+}
+
+export interface UpstreamHealthStatus {
+  name: string;
+  latency_ms: number | null;
+  failures: number;
+  ejected: boolean;
+}
+
+export interface QueryLogConfig {
+  enabled: boolean;
+  capacity: number;
+  persist: boolean;
+}
+
+export interface QueryLogEntry {
+  timestamp: string;
+  client: string;
+  name: string;
+  record_type: string;
+  upstream: string | null;
+  rcode: string;
+  latency_ms: number;
+  cached: boolean;
+  stale: boolean;
+  blocked: boolean;
+}
+
+export interface QueryLogPage {
+  total: number;
+  entries: QueryLogEntry[];
+}
+
+export interface TopEntry {
+  name: string;
+  count: number;
+}
+
+export interface QueryStats {
+  total: number;
+  blocked: number;
+  cached: number;
+  avg_latency_ms: number | null;
+  top_domains: TopEntry[];
+  top_blocked: TopEntry[];
+  top_clients: TopEntry[];
+}
+
+export interface ProxyStatus {
+  running: boolean;
+  listening: string[];
+  upstreams: string[];
+  upstream_health: UpstreamHealthStatus[];
+  adapters: string[];
+  access: AccessStats;
+}
+
+export interface AccessStats {
+  denied: number;
+  rate_limited: number;
+  refused_any: number;
+}
+
+export type RuleTarget =
+  | { type: 'servers'; value: string[] }
+  | { type: 'preset'; value: string };
+
+export interface ForwardingRule {
+  id: string;
+  pattern: string;
+  target: RuleTarget;
+  enabled: boolean;
+}
+
+export type LocalRecordType = 'A' | 'AAAA' | 'CNAME' | 'TXT' | 'PTR';
+
+export interface LocalRecord {
+  id: string;
+  name: string;
+  record_type: LocalRecordType;
+  value: string;
+  ttl: number;
+  enabled: boolean;
+}
+
+export type BlockResponse = 'nxdomain' | 'null_ip' | 'refused';
+
+export interface BlocklistSource {
+  id: string;
+  name: string;
+  location: string;
+  enabled: boolean;
+  last_updated?: string | null;
+  entries?: number;
+  last_error?: string | null;
+}
+
+export interface BlocklistSettings {
+  enabled: boolean;
+  response: BlockResponse;
+  refresh_interval_hours: number;
+  sources: BlocklistSource[];
+  allowlist: string[];
+}
+
+export interface BlocklistStatus {
+  enabled: boolean;
+  domains: number;
+  blocked_queries: number;
+  sources: BlocklistSource[];
+}
+
 export interface AppState {
   adapters: NetworkAdapter[];
   selectedAdapter: string | null;